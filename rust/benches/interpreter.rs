@@ -0,0 +1,105 @@
+//! Interpreter benchmarks. Run with `cargo bench`.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+use chip8::decode_cache::DecodeCache;
+use chip8::instruction::parse_instruction;
+use chip8::random::SeededRandom;
+use chip8::threaded::BlockCache;
+use chip8::vm::{load_rom, processor_cycle, VM};
+
+/// A busy loop of arithmetic, like a game's main loop
+const LOOP_ROM: &[u8] = &[
+    0x70, 0x01, // 200: V0 += 1
+    0x81, 0x04, // 202: V1 += V0
+    0x82, 0x13, // 204: V2 ^= V1
+    0x30, 0x00, // 206: skip if V0 == 0
+    0x12, 0x00, // 208: jump to 200
+    0x12, 0x00, // 20A: jump to 200
+];
+
+/// Draws an 8x15 sprite all over the screen
+const DRAW_ROM: &[u8] = &[
+    0xA0, 0x50, // 200: I = font
+    0xD0, 0x1F, // 202: draw 15 rows at (V0, V1)
+    0x70, 0x05, // 204: V0 += 5
+    0x71, 0x03, // 206: V1 += 3
+    0x12, 0x02, // 208: jump to 202
+];
+
+/// Test ROMs that run for a while before settling into a loop
+const TEST_ROMS: &[(&str, &[u8])] = &[
+    ("ibm-logo", include_bytes!("../../roms/ibm-logo.ch8")),
+    ("bc_test", include_bytes!("../../roms/bc_test.ch8")),
+    (
+        "corex89_test_opcode",
+        include_bytes!("../../roms/corex89_test_opcode.ch8"),
+    ),
+];
+
+const CYCLES: usize = 10_000;
+
+/// How instructions are dispatched.
+#[derive(Clone, Copy)]
+enum Dispatch {
+    Uncached,
+    DecodeCache,
+    Threaded,
+}
+
+fn new_vm(rom: &[u8], dispatch: Dispatch) -> VM {
+    let mut vm = VM::new(Box::new(SeededRandom::new(0)));
+    load_rom(&mut vm.memory, rom).unwrap();
+    match dispatch {
+        Dispatch::Uncached => {}
+        Dispatch::DecodeCache => vm.decode_cache = Some(DecodeCache::new(vm.memory.len())),
+        Dispatch::Threaded => vm.block_cache = Some(BlockCache::new(vm.memory.len())),
+    }
+    vm
+}
+
+/// Benchmark running `CYCLES` instructions of `rom`.
+fn bench_rom(c: &mut Criterion, name: &str, rom: &[u8], dispatch: Dispatch) {
+    c.bench_function(name, |b| {
+        b.iter_batched_ref(
+            || new_vm(rom, dispatch),
+            |vm| {
+                for _ in 0..CYCLES {
+                    processor_cycle(vm).unwrap();
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn bench_parse_instruction(c: &mut Criterion) {
+    c.bench_function("parse_instruction", |b| {
+        b.iter(|| (0..=u16::MAX).filter(|&raw| parse_instruction(raw).is_ok()).count())
+    });
+}
+
+fn bench_dispatch(c: &mut Criterion) {
+    bench_rom(c, "processor_cycle/uncached", LOOP_ROM, Dispatch::Uncached);
+    bench_rom(c, "processor_cycle/cached", LOOP_ROM, Dispatch::DecodeCache);
+    bench_rom(c, "processor_cycle/threaded", LOOP_ROM, Dispatch::Threaded);
+}
+
+fn bench_test_roms(c: &mut Criterion) {
+    for (name, rom) in TEST_ROMS {
+        bench_rom(c, &format!("rom/{}", name), rom, Dispatch::Uncached);
+    }
+}
+
+fn bench_draw(c: &mut Criterion) {
+    bench_rom(c, "draw", DRAW_ROM, Dispatch::Uncached);
+}
+
+criterion_group!(
+    benches,
+    bench_parse_instruction,
+    bench_dispatch,
+    bench_test_roms,
+    bench_draw
+);
+criterion_main!(benches);
@@ -0,0 +1,23 @@
+//! With the `ffi` feature, generate the C header declaring the functions
+//! in `src/ffi.rs` into `OUT_DIR`. Build scripts shouldn't write to the
+//! source tree, so the copy in `include/chip8.h` is checked in, and
+//! `test_header` in `src/ffi.rs` checks it's up to date.
+
+fn main() {
+    #[cfg(feature = "ffi")]
+    {
+        println!("cargo:rerun-if-changed=src/ffi.rs");
+        let crate_dir = std::env::var("CARGO_MANIFEST_DIR").expect("cargo sets CARGO_MANIFEST_DIR");
+        cbindgen::Builder::new()
+            .with_src(format!("{}/src/ffi.rs", crate_dir))
+            .with_language(cbindgen::Language::C)
+            .with_include_guard("CHIP8_H")
+            .with_header("/* Generated from src/ffi.rs by build.rs. Don't edit. */")
+            .generate()
+            .expect("Unable to generate chip8.h")
+            .write_to_file(format!(
+                "{}/chip8.h",
+                std::env::var("OUT_DIR").expect("cargo sets OUT_DIR")
+            ));
+    }
+}
@@ -0,0 +1,58 @@
+//! Runs arbitrary bytes as a ROM, checking that the interpreter returns
+//! an error rather than panicking however broken the program is, and
+//! that the decode cache and threaded code agree with the interpreter.
+//! Run with `cargo fuzz run run_rom` (needs a nightly toolchain).
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use chip8::decode_cache::DecodeCache;
+use chip8::quirks::Quirks;
+use chip8::random::SeededRandom;
+use chip8::threaded::BlockCache;
+use chip8::vm::{load_rom, processor_cycle, CACHE_MISMATCH, VM};
+
+/// Stop after this many instructions, since most ROMs loop forever
+const CYCLE_LIMIT: u32 = 10_000;
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks the quirks, the second strict mode, the
+    // caches and a held key, and the rest is the ROM
+    let (quirks, flags, rom) = match data {
+        [quirks, flags, rom @ ..] => (*quirks, *flags, rom),
+        _ => return,
+    };
+
+    let mut vm = VM::new(Box::new(SeededRandom::new(0)));
+    vm.quirks = Quirks {
+        shift_uses_vy: quirks & 0x01 != 0,
+        display_wait: quirks & 0x02 != 0,
+        wrap_sprites: quirks & 0x04 != 0,
+        increment_i: quirks & 0x08 != 0,
+        index_overflow: quirks & 0x10 != 0,
+        jump_uses_vx: quirks & 0x20 != 0,
+        vf_reset: quirks & 0x40 != 0,
+        half_scroll: quirks & 0x80 != 0,
+    };
+    vm.strict = flags & 0x10 != 0;
+    vm.keys_pressed[(flags & 0x0F) as usize] = true;
+    if load_rom(&mut vm.memory, rom).is_err() {
+        return;
+    }
+    if flags & 0x20 != 0 {
+        vm.decode_cache = Some(DecodeCache::new(vm.memory.len()));
+    }
+    if flags & 0x40 != 0 {
+        vm.block_cache = Some(BlockCache::new(vm.memory.len()));
+    }
+    vm.verify_caches = true;
+
+    for _ in 0..CYCLE_LIMIT {
+        if let Err(err) = processor_cycle(&mut vm) {
+            assert!(!err.starts_with(CACHE_MISMATCH), "{}", err);
+            break;
+        }
+        vm.cycles += 1;
+    }
+});
@@ -0,0 +1,130 @@
+/* Generated from src/ffi.rs by build.rs. Don't edit. */
+
+#ifndef CHIP8_H
+#define CHIP8_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * An emulator. Its fields aren't part of the C API.
+ */
+typedef struct Chip8 Chip8;
+
+/**
+ * Create an emulator with nothing loaded, whose random numbers come
+ * from `seed`. Free it with `chip8_free`.
+ */
+struct Chip8 *chip8_new(uint64_t seed);
+
+/**
+ * Free an emulator. Does nothing if `chip8` is NULL.
+ *
+ * # Safety
+ *
+ * `chip8` must be NULL or a live emulator, which can't be used again.
+ */
+void chip8_free(struct Chip8 *chip8);
+
+/**
+ * Load the `len` bytes at `rom` and restart, as if the machine had just
+ * been turned on. Returns -1 if the ROM doesn't fit in memory.
+ *
+ * # Safety
+ *
+ * `chip8` must be a live emulator and `rom` must point to `len` bytes.
+ */
+int chip8_load_rom(struct Chip8 *chip8, const uint8_t *rom, uintptr_t len);
+
+/**
+ * Run one instruction. Returns -1 if the program hit an error.
+ *
+ * # Safety
+ *
+ * `chip8` must be a live emulator.
+ */
+int chip8_step(struct Chip8 *chip8);
+
+/**
+ * Run a frame's worth of instructions and tick the timers, to be
+ * called 60 times a second. Returns -1 if the program hit an error.
+ *
+ * # Safety
+ *
+ * `chip8` must be a live emulator.
+ */
+int chip8_run_frame(struct Chip8 *chip8);
+
+/**
+ * Whether the program has exited (with 00FD), after which running it
+ * does nothing.
+ *
+ * # Safety
+ *
+ * `chip8` must be a live emulator.
+ */
+bool chip8_exited(const struct Chip8 *chip8);
+
+/**
+ * Press (or release) keypad key `key`, 0 to 0xF. Other keys are
+ * ignored.
+ *
+ * # Safety
+ *
+ * `chip8` must be a live emulator.
+ */
+void chip8_set_key(struct Chip8 *chip8, uint8_t key, bool pressed);
+
+/**
+ * The display's width in pixels: 64, or 128 in hi-res.
+ *
+ * # Safety
+ *
+ * `chip8` must be a live emulator.
+ */
+uintptr_t chip8_display_width(const struct Chip8 *chip8);
+
+/**
+ * The display's height in pixels: 32, or 64 in hi-res.
+ *
+ * # Safety
+ *
+ * `chip8` must be a live emulator.
+ */
+uintptr_t chip8_display_height(const struct Chip8 *chip8);
+
+/**
+ * The display, width × height bytes row by row, one per pixel: 0 if
+ * it's off, and otherwise a bit mask of the planes it's lit in, 1 for
+ * the first (the only one before XO-CHIP). Only valid until the
+ * emulator next runs, as switching resolution moves it.
+ *
+ * # Safety
+ *
+ * `chip8` must be a live emulator.
+ */
+const uint8_t *chip8_framebuffer(const struct Chip8 *chip8);
+
+/**
+ * Whether the buzzer is sounding.
+ *
+ * # Safety
+ *
+ * `chip8` must be a live emulator.
+ */
+bool chip8_sound_on(const struct Chip8 *chip8);
+
+/**
+ * What went wrong the last time a function returned -1, as a
+ * NUL-terminated string that's valid until the next error, or NULL if
+ * nothing has.
+ *
+ * # Safety
+ *
+ * `chip8` must be a live emulator.
+ */
+const char *chip8_last_error(const struct Chip8 *chip8);
+
+#endif /* CHIP8_H */
@@ -0,0 +1,131 @@
+//! Static analysis of a ROM's control flow, shared by `chip8 decompile`
+//! and `chip8 lint`. Nothing is executed: every path from the entry
+//! point is followed instead, so both sides of each skip are taken.
+
+use std::collections::BTreeSet;
+
+use chip8::instruction::{parse_instruction, Instruction};
+
+/// ROMs are loaded at this address, which is also the entry point.
+pub(crate) const ROM_START: u16 = 0x200;
+
+/// Where execution can go after an instruction.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum Flow {
+    /// On to the next instruction
+    Next,
+    /// The next instruction, or the one after it
+    Skip,
+    Jump(u16),
+    /// Into a subroutine, and later back to the next instruction
+    Call(u16),
+    Return,
+    /// Nowhere: the program ends (`exit`)
+    Stop,
+    /// `jump0`: somewhere from the given address on, depending on V0
+    Computed(u16),
+}
+
+pub(crate) fn flow(instruction: &Instruction) -> Flow {
+    match *instruction {
+        Instruction::Jump { nnn } => Flow::Jump(nnn),
+        Instruction::SubroutineCall { nnn } => Flow::Call(nnn),
+        Instruction::SubroutineReturn => Flow::Return,
+        Instruction::Exit => Flow::Stop,
+        Instruction::JumpV0Nnn { nnn } => Flow::Computed(nnn),
+        Instruction::SkipVxEqNn { .. }
+        | Instruction::SkipVxNeqNn { .. }
+        | Instruction::SkipVxEqVy { .. }
+        | Instruction::SkipVxNeqVy { .. }
+        | Instruction::SkipIfVxPressed { .. }
+        | Instruction::SkipIfVxNotPressed { .. } => Flow::Skip,
+        _ => Flow::Next,
+    }
+}
+
+/// A ROM as loaded into memory.
+pub(crate) struct Program<'a> {
+    rom: &'a [u8],
+}
+
+impl<'a> Program<'a> {
+    pub(crate) fn new(rom: &'a [u8]) -> Program<'a> {
+        Program { rom }
+    }
+
+    /// The address just past the end of the ROM.
+    pub(crate) fn end(&self) -> u16 {
+        ROM_START + self.rom.len() as u16
+    }
+
+    pub(crate) fn contains(&self, addr: u16) -> bool {
+        (ROM_START..self.end()).contains(&addr)
+    }
+
+    pub(crate) fn byte(&self, addr: u16) -> u8 {
+        self.rom[(addr - ROM_START) as usize]
+    }
+
+    /// The instruction at `addr`, if it's inside the ROM and valid.
+    pub(crate) fn fetch(&self, addr: u16) -> Option<Instruction> {
+        if !self.contains(addr) || !self.contains(addr + 1) {
+            return None;
+        }
+        parse_instruction((self.byte(addr) as u16) << 8 | self.byte(addr + 1) as u16).ok()
+    }
+
+    /// Where execution can go from `addr` within the same subroutine:
+    /// calls are assumed to return.
+    pub(crate) fn local_successors(&self, addr: u16, flow: Flow) -> Vec<u16> {
+        match flow {
+            Flow::Next | Flow::Call(_) => vec![addr + 2],
+            Flow::Skip => vec![addr + 2, addr + 4],
+            Flow::Jump(target) => vec![target],
+            Flow::Return | Flow::Stop | Flow::Computed(_) => vec![],
+        }
+    }
+
+    /// The addresses of every instruction reachable from the entry
+    /// point, following calls into subroutines.
+    pub(crate) fn reachable(&self) -> BTreeSet<u16> {
+        let mut code = BTreeSet::new();
+        let mut to_visit = vec![ROM_START];
+        while let Some(addr) = to_visit.pop() {
+            if code.contains(&addr) {
+                continue;
+            }
+            let instruction = match self.fetch(addr) {
+                Some(instruction) => instruction,
+                None => continue,
+            };
+            code.insert(addr);
+            let flow = flow(&instruction);
+            if let Flow::Call(target) = flow {
+                to_visit.push(target);
+            }
+            to_visit.extend(self.local_successors(addr, flow));
+        }
+        code
+    }
+}
+
+#[test]
+fn test_reachable() {
+    let rom = [
+        0x22, 0x08, // 200: call 208
+        0x30, 0x00, // 202: skip if V0 == 0
+        0x12, 0x02, // 204: jump 202
+        0x12, 0x02, // 206: jump 202, skipped over
+        0x00, 0xEE, // 208: return
+        0xFF, 0xFF, // 20A: never executed
+    ];
+    let program = Program::new(&rom);
+    let reachable: Vec<u16> = program.reachable().into_iter().collect();
+    assert_eq!(reachable, [0x200, 0x202, 0x204, 0x206, 0x208]);
+    assert_eq!(program.fetch(0x20A), None);
+    assert_eq!(program.fetch(0x20C), None);
+
+    // Nothing runs after an exit
+    let program = Program::new(&[0x00, 0xFD, 0x12, 0x00]);
+    assert_eq!(program.reachable().into_iter().collect::<Vec<u16>>(), [0x200]);
+}
@@ -0,0 +1,186 @@
+//! A small two-pass assembler for the classic CHIP-8 mnemonics (as
+//! documented in Cowgod's technical reference), e.g.
+//!
+//! ```text
+//! start:
+//!     LD I, sprite     ; comments start with a semicolon
+//!     DRW V0, V1, 5
+//!     JP start
+//! sprite:
+//!     DB 0xF0, 0x90, 0xF0, 0x90, 0xF0
+//! ```
+//!
+//! Numbers can be written in decimal, hex (`0x` or `#`) or binary
+//! (`0b`), and anywhere an address is expected a label can be used.
+//! The XO-CHIP sound instructions are `AUDIO` (F002) and `PITCH VX`
+//! (FX3A).
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use chip8::instruction::{encode_instruction, parse_mnemonic, parse_number};
+use chip8::vm::{max_rom_bytes, XO_CHIP_MEMORY_BYTES};
+
+use crate::symbols::Symbols;
+
+/// Address programs are loaded at, and therefore where the first
+/// assembled byte lives.
+const ORIGIN: u16 = 0x200;
+
+/// A single line of source, after comments and labels are stripped.
+struct Statement<'a> {
+    /// Where the statement came from, for error messages
+    location: String,
+    mnemonic: String,
+    operands: Vec<&'a str>,
+}
+
+/// An assembled program and the addresses of its labels.
+pub(crate) struct Assembled {
+    pub(crate) rom: Vec<u8>,
+    pub(crate) symbols: Symbols,
+}
+
+/// Assemble several source files into one program, in order. Labels are
+/// shared between files, so e.g. sprite data can live in its own file.
+/// Each file is named by an optional path for error messages.
+pub(crate) fn assemble_files(sources: &[(Option<&str>, &str)]) -> Result<Assembled, String> {
+    // First pass: find the address of every label.
+    let mut labels = HashMap::new();
+    let mut statements = Vec::new();
+    // Counted in a usize, so an oversized program is an error rather
+    // than an overflow
+    let mut address = ORIGIN as usize;
+    let lines = sources.iter().flat_map(|(name, source)| {
+        source
+            .lines()
+            .enumerate()
+            .map(move |(num, line)| (*name, num + 1, line))
+    });
+    for (name, line_num, line) in lines {
+        let location = match name {
+            Some(name) => format!("{}:{}", name, line_num),
+            None => format!("line {}", line_num),
+        };
+        let mut line = line.split(';').next().unwrap_or("").trim();
+
+        if let Some(colon) = line.find(':') {
+            let label = line[..colon].trim();
+            if !is_identifier(label) {
+                return Err(format!("{}: invalid label {:?}", location, label));
+            }
+            // Only a label after a program that fills memory can be
+            // past the last address
+            let address = u16::try_from(address)
+                .map_err(|_| format!("{}: label {:?} is past the end of memory", location, label))?;
+            if labels.insert(label.to_string(), address).is_some() {
+                return Err(format!("{}: duplicate label {:?}", location, label));
+            }
+            line = line[colon + 1..].trim();
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, rest) = match line.find(char::is_whitespace) {
+            Some(space) => (&line[..space], line[space..].trim()),
+            None => (line, ""),
+        };
+        let operands: Vec<&str> = if rest.is_empty() {
+            Vec::new()
+        } else {
+            rest.split(',').map(str::trim).collect()
+        };
+        let mnemonic = mnemonic.to_ascii_uppercase();
+
+        address += if mnemonic == "DB" { operands.len() } else { 2 };
+        let max = max_rom_bytes(XO_CHIP_MEMORY_BYTES);
+        if address - ORIGIN as usize > max {
+            return Err(format!(
+                "{}: program too large, more than the {} bytes that fit in memory",
+                location, max
+            ));
+        }
+        statements.push(Statement {
+            location,
+            mnemonic,
+            operands,
+        });
+    }
+
+    // Second pass: encode everything now that labels are known.
+    let mut bytes = Vec::new();
+    for statement in &statements {
+        let located = |err: String| format!("{}: {}", statement.location, err);
+        if statement.mnemonic == "DB" {
+            for operand in &statement.operands {
+                match parse_number(operand).or_else(|| labels.get(*operand).copied()) {
+                    Some(value) if value <= 0xFF => bytes.push(value as u8),
+                    Some(_) => return Err(located(format!("DB operand {:?} is not a byte", operand))),
+                    None => return Err(located(format!("unknown label or invalid number {:?}", operand))),
+                }
+            }
+        } else {
+            let resolve = |label: &str| labels.get(label).copied();
+            let instruction = parse_mnemonic(&statement.mnemonic, &statement.operands, &resolve).map_err(located)?;
+            bytes.extend_from_slice(&encode_instruction(&instruction).to_be_bytes());
+        }
+    }
+
+    Ok(Assembled {
+        rom: bytes,
+        symbols: Symbols::new(labels.into_iter().collect()),
+    })
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[test]
+fn test_assemble() {
+    let assemble = |source| assemble_files(&[(None, source)]).map(|assembled| assembled.rom);
+    let source = "
+        ; Draw a sprite forever
+        start:  LD V0, 10
+                ld v1, #0A
+                LD I, sprite
+                DRW V0, V1, 2
+                JP start
+        sprite: DB 0b11110000, 0x90
+    ";
+    assert_eq!(
+        assemble(source),
+        Ok(vec![
+            0x60, 0x0A, 0x61, 0x0A, 0xA2, 0x0A, 0xD0, 0x12, 0x12, 0x00, 0xF0, 0x90
+        ])
+    );
+
+    assert!(assemble("LD V0, 256").unwrap_err().contains("line 1"));
+    assert!(assemble("JP nowhere").is_err());
+    assert!(assemble("a:\na:").is_err());
+    assert!(assemble("DRW V0, V1, 16").is_err());
+    assert_eq!(assemble("AUDIO\nPITCH VA"), Ok(vec![0xF0, 0x02, 0xFA, 0x3A]));
+    assert_eq!(assemble("EXIT"), Ok(vec![0x00, 0xFD]));
+
+    let symbols = assemble_files(&[(None, source)]).unwrap().symbols;
+    assert_eq!(symbols.address("start"), Some(0x200));
+    assert_eq!(symbols.address("sprite"), Some(0x20A));
+}
+
+#[test]
+fn test_assemble_too_large() {
+    // Just fits in XO-CHIP's memory
+    let source = "CLS\n".repeat(max_rom_bytes(XO_CHIP_MEMORY_BYTES) / 2);
+    let assembled = assemble_files(&[(None, &source)]).unwrap();
+    assert_eq!(assembled.rom.len(), 0xFE00);
+    assert!(assemble_files(&[(None, &(source + "end:"))]).is_err());
+
+    let source = "CLS\n".repeat(40_000);
+    assert_eq!(
+        assemble_files(&[(Some("big.asm"), &source)]).err(),
+        Some("big.asm:32513: program too large, more than the 65024 bytes that fit in memory".to_string())
+    );
+}
@@ -0,0 +1,292 @@
+//! The buzzer that sounds while the sound timer is non-zero. CHIP-8
+//! programs can only turn it on and off, so how it sounds is up to the
+//! user: `SampleGenerator` turns a `Tone` into samples for whatever
+//! audio output the frontend has. XO-CHIP programs can instead load
+//! their own `AudioPattern`, which replaces the tone's waveform.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    Noise,
+}
+
+impl Waveform {
+    pub const ALL: [Waveform; 4] = [Waveform::Square, Waveform::Sine, Waveform::Triangle, Waveform::Noise];
+
+    /// The waveform after this one, wrapping around, for cycling
+    /// through them.
+    pub fn next(self) -> Waveform {
+        let i = Waveform::ALL.iter().position(|&waveform| waveform == self).unwrap();
+        Waveform::ALL[(i + 1) % Waveform::ALL.len()]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Waveform::Square => "square",
+            Waveform::Sine => "sine",
+            Waveform::Triangle => "triangle",
+            Waveform::Noise => "noise",
+        }
+    }
+}
+
+impl fmt::Display for Waveform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Waveform, String> {
+        Waveform::ALL
+            .iter()
+            .copied()
+            .find(|waveform| waveform.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Waveform::ALL.iter().map(|waveform| waveform.name()).collect();
+                format!("Unknown waveform {:?}, expected one of: {}", s, names.join(", "))
+            })
+    }
+}
+
+/// How the buzzer sounds.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Tone {
+    pub waveform: Waveform,
+    pub pitch_hz: f32,
+    /// From 0 to 1
+    pub volume: f32,
+    /// Time to fade in when the buzzer starts, so it doesn't click
+    pub attack_ms: f32,
+    /// Time to fade out when it stops
+    pub release_ms: f32,
+}
+
+impl Default for Tone {
+    fn default() -> Tone {
+        Tone {
+            waveform: Waveform::Square,
+            pitch_hz: 440.0,
+            volume: 0.25,
+            attack_ms: 5.0,
+            release_ms: 20.0,
+        }
+    }
+}
+
+pub const AUDIO_PATTERN_BYTES: usize = 16;
+
+/// The XO-CHIP pitch register's initial value, which plays patterns at
+/// 4000 samples per second.
+pub const DEFAULT_PITCH: u8 = 64;
+
+/// An XO-CHIP audio pattern: 128 1-bit samples, played in a loop at a
+/// rate set by the pitch register.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct AudioPattern {
+    pub bits: [u8; AUDIO_PATTERN_BYTES],
+    pub pitch: u8,
+}
+
+impl AudioPattern {
+    const SAMPLES: usize = AUDIO_PATTERN_BYTES * 8;
+
+    /// Samples per second, doubling every 48 steps of pitch.
+    pub fn sample_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - DEFAULT_PITCH as f32) / 48.0)
+    }
+
+    fn bit(&self, i: usize) -> bool {
+        self.bits[i / 8] >> (7 - i % 8) & 1 == 1
+    }
+}
+
+/// Generates the buzzer's samples, one channel of f32s from -1 to 1.
+pub struct SampleGenerator {
+    tone: Tone,
+    pattern: Option<AudioPattern>,
+    sample_rate: u32,
+
+    /// Position within the current cycle of the waveform (or the
+    /// whole pattern), from 0 to 1
+    phase: f32,
+    /// Envelope level, from 0 (silent) to 1
+    level: f32,
+    /// xorshift state for the noise waveform
+    noise_state: u32,
+    /// The noise waveform holds each random value for a whole cycle, so
+    /// the pitch still changes how it sounds
+    noise_sample: f32,
+}
+
+impl SampleGenerator {
+    pub fn new(tone: Tone, sample_rate: u32) -> SampleGenerator {
+        SampleGenerator {
+            tone,
+            pattern: None,
+            sample_rate,
+            phase: 0.0,
+            level: 0.0,
+            noise_state: 0x2545_F491,
+            noise_sample: 0.0,
+        }
+    }
+
+    pub fn tone(&self) -> Tone {
+        self.tone
+    }
+
+    pub fn set_tone(&mut self, tone: Tone) {
+        self.tone = tone;
+    }
+
+    /// Play `pattern` instead of the tone's waveform, or go back to the
+    /// waveform if it's None. The tone's volume and envelope still
+    /// apply.
+    pub fn set_pattern(&mut self, pattern: Option<AudioPattern>) {
+        self.pattern = pattern;
+    }
+
+    /// Fill `out` with the next samples. `on` is whether the buzzer is
+    /// sounding; it fades in and out rather than cutting off.
+    pub fn fill(&mut self, out: &mut [f32], on: bool) {
+        let sample_rate = self.sample_rate as f32;
+        let attack_step = step(self.tone.attack_ms, sample_rate);
+        let release_step = step(self.tone.release_ms, sample_rate);
+        // Patterns are resampled to the output rate by holding each bit
+        // for as many output samples as it lasts
+        let cycles_per_second = match &self.pattern {
+            Some(pattern) => pattern.sample_rate() / AudioPattern::SAMPLES as f32,
+            None => self.tone.pitch_hz,
+        };
+        for sample in out.iter_mut() {
+            self.level = if on {
+                (self.level + attack_step).min(1.0)
+            } else {
+                (self.level - release_step).max(0.0)
+            };
+            if self.level == 0.0 {
+                // Start the next beep at the beginning of a cycle
+                self.phase = 0.0;
+                *sample = 0.0;
+                continue;
+            }
+
+            *sample = self.wave() * self.level * self.tone.volume;
+            self.phase += cycles_per_second / sample_rate;
+            if self.phase >= 1.0 {
+                self.phase -= self.phase.floor();
+                self.noise_sample = self.next_noise();
+            }
+        }
+    }
+
+    /// The waveform's value at the current phase.
+    fn wave(&self) -> f32 {
+        let phase = self.phase;
+        if let Some(pattern) = &self.pattern {
+            let i = ((phase * AudioPattern::SAMPLES as f32) as usize).min(AudioPattern::SAMPLES - 1);
+            return if pattern.bit(i) { 1.0 } else { -1.0 };
+        }
+        match self.tone.waveform {
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Sine => (phase * std::f32::consts::TAU).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Noise => self.noise_sample,
+        }
+    }
+
+    fn next_noise(&mut self) -> f32 {
+        let mut x = self.noise_state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.noise_state = x;
+        x as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
+
+/// How much the envelope changes per sample to fade over `ms`.
+fn step(ms: f32, sample_rate: f32) -> f32 {
+    let samples = ms / 1000.0 * sample_rate;
+    if samples < 1.0 {
+        1.0
+    } else {
+        1.0 / samples
+    }
+}
+
+#[test]
+fn test_waveform_names() {
+    for waveform in Waveform::ALL {
+        assert_eq!(waveform.to_string().parse(), Ok(waveform));
+    }
+    assert!("sawtooth".parse::<Waveform>().is_err());
+    assert_eq!(Waveform::Noise.next(), Waveform::Square);
+}
+
+#[test]
+fn test_sample_generator() {
+    let tone = Tone {
+        waveform: Waveform::Square,
+        pitch_hz: 1000.0,
+        volume: 0.5,
+        attack_ms: 0.0,
+        release_ms: 1.0,
+    };
+    let mut generator = SampleGenerator::new(tone, 8000);
+    let mut samples = [0.0; 8];
+
+    // One cycle of the square wave at full volume
+    generator.fill(&mut samples, true);
+    assert_eq!(samples, [0.5, 0.5, 0.5, 0.5, -0.5, -0.5, -0.5, -0.5]);
+
+    // The release fades it out over 8 samples, then it's silent
+    let mut samples = [0.0; 10];
+    generator.fill(&mut samples, false);
+    assert!(samples[0] > 0.0 && samples[0] < 0.5);
+    assert_eq!(&samples[7..], &[0.0; 3]);
+
+    for waveform in Waveform::ALL {
+        generator.set_tone(Tone { waveform, ..tone });
+        generator.fill(&mut samples, true);
+        assert!(samples.iter().all(|sample| sample.abs() <= 0.5));
+    }
+}
+
+#[test]
+fn test_audio_pattern() {
+    let mut bits = [0; AUDIO_PATTERN_BYTES];
+    bits[0] = 0b1010_0000;
+    let pattern = AudioPattern {
+        bits,
+        pitch: DEFAULT_PITCH,
+    };
+    assert_eq!(pattern.sample_rate(), 4000.0);
+    assert_eq!(AudioPattern { pitch: 112, ..pattern }.sample_rate(), 8000.0);
+
+    // At twice the pattern's rate, each bit lasts two samples
+    let tone = Tone {
+        volume: 1.0,
+        attack_ms: 0.0,
+        ..Tone::default()
+    };
+    let mut generator = SampleGenerator::new(tone, 8000);
+    generator.set_pattern(Some(pattern));
+    let mut samples = [0.0; 8];
+    generator.fill(&mut samples, true);
+    assert_eq!(samples, [1.0, 1.0, -1.0, -1.0, 1.0, 1.0, -1.0, -1.0]);
+}
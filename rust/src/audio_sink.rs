@@ -0,0 +1,161 @@
+//! Audio sinks are where the buzzer goes: the SDL frontend's speakers,
+//! a WAV file for `--record-audio`, or nowhere when running headless.
+//! The main loop only talks to the `AudioSink` trait.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use chip8::audio::{AudioPattern, SampleGenerator, Tone};
+use chip8::vm::FRAMES_PER_SECOND;
+
+pub(crate) trait AudioSink {
+    /// Start or stop the buzzer.
+    fn set_buzzer(&mut self, on: bool);
+
+    /// Change how the buzzer sounds.
+    fn set_tone(&mut self, tone: Tone);
+
+    /// Play an XO-CHIP audio pattern instead of the tone's waveform,
+    /// or go back to the waveform if it's None.
+    fn set_audio_pattern(&mut self, pattern: Option<AudioPattern>);
+
+    /// An emulated frame has passed. Sinks that play in real time ignore
+    /// this, while files write the frame's samples, so they keep time
+    /// with the program however fast it runs.
+    fn end_frame(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Play the buzzer `delay` after it's started and stopped, to line
+    /// it up with a display that lags (see src/av_sync.rs). Files keep
+    /// emulated time, so they ignore this.
+    fn set_delay(&mut self, _delay: Duration) {}
+
+    /// How long sound waits in the audio device's buffer before it's
+    /// heard, if the sink plays in real time.
+    fn latency(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Throws the buzzer away.
+pub(crate) struct NullSink;
+
+impl AudioSink for NullSink {
+    fn set_buzzer(&mut self, _on: bool) {}
+
+    fn set_tone(&mut self, _tone: Tone) {}
+
+    fn set_audio_pattern(&mut self, _pattern: Option<AudioPattern>) {}
+}
+
+const WAV_SAMPLE_RATE: u32 = 44_100;
+const WAV_HEADER_BYTES: u32 = 44;
+const WAV_FRAME_SAMPLES: usize = WAV_SAMPLE_RATE as usize / FRAMES_PER_SECOND as usize;
+
+/// Writes the buzzer to a 16-bit mono WAV file. The header's sizes are
+/// only right once `finish` has been called.
+pub(crate) struct WavSink<W: Write + Seek> {
+    out: W,
+    generator: SampleGenerator,
+    on: bool,
+    samples: u32,
+}
+
+impl WavSink<BufWriter<File>> {
+    pub(crate) fn create(path: &Path, tone: Tone) -> Result<WavSink<BufWriter<File>>, String> {
+        let file = File::create(path).map_err(|err| format!("Error creating {}: {}", path.display(), err))?;
+        WavSink::new(BufWriter::new(file), tone).map_err(|err| format!("Error writing {}: {}", path.display(), err))
+    }
+}
+
+impl<W: Write + Seek> WavSink<W> {
+    pub(crate) fn new(mut out: W, tone: Tone) -> io::Result<WavSink<W>> {
+        write_wav_header(&mut out, 0)?;
+        Ok(WavSink {
+            out,
+            generator: SampleGenerator::new(tone, WAV_SAMPLE_RATE),
+            on: false,
+            samples: 0,
+        })
+    }
+
+    /// Fill in the header now the length is known.
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        self.out.seek(SeekFrom::Start(0))?;
+        write_wav_header(&mut self.out, self.samples)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavSink<W> {
+    fn set_buzzer(&mut self, on: bool) {
+        self.on = on;
+    }
+
+    fn set_tone(&mut self, tone: Tone) {
+        self.generator.set_tone(tone);
+    }
+
+    fn set_audio_pattern(&mut self, pattern: Option<AudioPattern>) {
+        self.generator.set_pattern(pattern);
+    }
+
+    fn end_frame(&mut self) -> Result<(), String> {
+        let mut samples = [0.0; WAV_FRAME_SAMPLES];
+        self.generator.fill(&mut samples, self.on);
+        let mut bytes = Vec::with_capacity(samples.len() * 2);
+        for sample in samples {
+            bytes.extend_from_slice(&((sample * i16::MAX as f32) as i16).to_le_bytes());
+        }
+        self.out
+            .write_all(&bytes)
+            .map_err(|err| format!("Error writing audio: {}", err))?;
+        self.samples += samples.len() as u32;
+        Ok(())
+    }
+}
+
+/// The RIFF header of a WAV file holding `samples` 16-bit mono samples.
+fn write_wav_header(out: &mut impl Write, samples: u32) -> io::Result<()> {
+    let data_bytes = samples * 2;
+    out.write_all(b"RIFF")?;
+    out.write_all(&(WAV_HEADER_BYTES - 8 + data_bytes).to_le_bytes())?;
+    out.write_all(b"WAVEfmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    // PCM, one channel
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&WAV_SAMPLE_RATE.to_le_bytes())?;
+    // Bytes per second, bytes per sample and bits per sample
+    out.write_all(&(WAV_SAMPLE_RATE * 2).to_le_bytes())?;
+    out.write_all(&2u16.to_le_bytes())?;
+    out.write_all(&16u16.to_le_bytes())?;
+    out.write_all(b"data")?;
+    out.write_all(&data_bytes.to_le_bytes())
+}
+
+#[test]
+fn test_wav_sink() {
+    let tone = Tone {
+        attack_ms: 0.0,
+        ..Tone::default()
+    };
+    let mut wav = WavSink::new(io::Cursor::new(Vec::new()), tone).unwrap();
+    wav.end_frame().unwrap();
+    wav.set_buzzer(true);
+    wav.end_frame().unwrap();
+    let bytes = wav.finish().unwrap().into_inner();
+
+    assert_eq!(bytes.len(), WAV_HEADER_BYTES as usize + WAV_FRAME_SAMPLES * 2 * 2);
+    assert_eq!(&bytes[..4], b"RIFF");
+    assert_eq!(&bytes[4..8], &(bytes.len() as u32 - 8).to_le_bytes());
+    assert_eq!(&bytes[40..44], &(bytes.len() as u32 - WAV_HEADER_BYTES).to_le_bytes());
+    // Silent for the first frame, then the buzzer
+    let data = &bytes[WAV_HEADER_BYTES as usize..];
+    assert!(data[..WAV_FRAME_SAMPLES * 2].iter().all(|&byte| byte == 0));
+    assert!(data[WAV_FRAME_SAMPLES * 2..].iter().any(|&byte| byte != 0));
+}
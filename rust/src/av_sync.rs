@@ -0,0 +1,203 @@
+//! Lining up the buzzer with the display. Sound and pictures take
+//! different times to reach the player: sound waits in the audio
+//! device's buffer, and pictures wait for the display, which on some
+//! TVs takes 50 ms or more. The audio offset (`--audio-offset`, or
+//! `offset-ms` in the `[sound]` section of the config file) makes up the
+//! difference. Positive offsets delay the buzzer, and negative ones
+//! delay the picture, in whole frames.
+//!
+//! `chip8 calibrate` finds the offset: it flashes the screen and beeps
+//! together once a second, and the player moves the offset with Up and
+//! Down until they line up, then saves it with Enter.
+
+use std::collections::VecDeque;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use chip8::display::Display;
+use chip8::vm::FRAMES_PER_SECOND;
+
+use crate::config;
+use crate::frontend::{self, Frontend, FrontendKind, FrontendSettings, Hotkey, InputEvent};
+use crate::interrupt;
+
+/// The largest offset either way, a second, well past any real lag.
+pub(crate) const MAX_AUDIO_OFFSET_MS: i32 = 1000;
+
+/// How much Up and Down change the offset when calibrating.
+const CALIBRATION_STEP_MS: i32 = 5;
+
+/// How long the flash and the beep last when calibrating.
+const FLASH_FRAMES: u64 = 3;
+
+/// Shows the display a number of frames late.
+pub(crate) struct PictureDelay {
+    frames: usize,
+    /// Copies of the display from the last `frames` frames, oldest first
+    queue: VecDeque<Display>,
+}
+
+impl PictureDelay {
+    pub(crate) fn new(frames: usize) -> PictureDelay {
+        PictureDelay {
+            frames,
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// The display to paint now, given the current one: itself if
+    /// there's no delay, or otherwise a copy from `frames` calls ago.
+    /// Each copy only needs repainting if the display changed since the
+    /// copy before, so frontends still skip unchanged frames.
+    pub(crate) fn delay<'a>(&'a mut self, display: &'a mut Display) -> &'a mut Display {
+        if self.frames == 0 {
+            return display;
+        }
+        self.queue.push_back(display.clone());
+        display.mark_painted();
+        display.take_damage();
+        while self.queue.len() > self.frames + 1 {
+            self.queue.pop_front();
+        }
+        self.queue.front_mut().expect("the display was just added")
+    }
+}
+
+/// Apply an audio offset to the frontend's sound, returning the delay
+/// for the picture. Without sound there's nothing to line up.
+pub(crate) fn apply_offset(offset_ms: i32, frontend: &mut dyn Frontend) -> PictureDelay {
+    let audio = match frontend.audio() {
+        Some(audio) => audio,
+        None => return PictureDelay::new(0),
+    };
+    audio.set_delay(Duration::from_millis(offset_ms.max(0) as u64));
+    let frames = (-offset_ms).max(0) as f64 * FRAMES_PER_SECOND as f64 / 1000.0;
+    PictureDelay::new(frames.round() as usize)
+}
+
+/// Run the calibration pattern until the player saves an offset to the
+/// config file at `config_path` (or the default one) or quits.
+pub(crate) fn calibrate(config_path: Option<&Path>) -> Result<(), String> {
+    let settings = config::Settings::load(config_path)?;
+    let frontend_settings = FrontendSettings {
+        scale: frontend::DEFAULT_SCALE,
+        colors: settings.colors()?,
+        keymap: settings.keymap()?,
+        tone: settings.tone()?,
+        show_keypad: false,
+        vsync: false,
+        effects: None,
+        scale_mode: Default::default(),
+        fullscreen: false,
+    };
+    let mut frontend = frontend::create(FrontendKind::default(), frontend_settings)?;
+    let latency = match frontend.audio() {
+        Some(audio) => audio.latency(),
+        None => return Err("Calibrating needs sound, which this build's frontend can't play".to_string()),
+    };
+
+    // Sound usually lags by at least the audio buffer, so start from
+    // there
+    let mut offset_ms = match (settings.audio_offset()?, latency) {
+        (Some(offset_ms), _) => offset_ms,
+        (None, Some(latency)) => {
+            let ms = latency.as_millis() as i32;
+            -(ms + CALIBRATION_STEP_MS / 2) / CALIBRATION_STEP_MS * CALIBRATION_STEP_MS
+        }
+        (None, None) => 0,
+    };
+    if let Some(latency) = latency {
+        log::info!("The audio device buffers {} ms of sound", latency.as_millis());
+    }
+    log::info!(
+        "The screen flashes and beeps once a second. Press Up and Down until they happen together, \
+         then Enter to save the offset, or Esc to quit without saving"
+    );
+
+    let mut picture_delay = apply_offset(offset_ms, frontend.as_mut());
+    frontend.set_title(&calibration_title(offset_ms));
+    let mut display = Display::new();
+    let frame_duration = Duration::from_secs_f64(1.0 / FRAMES_PER_SECOND as f64);
+    let mut next_frame = Instant::now();
+    let mut frame = 0;
+    loop {
+        for event in frontend.poll_events() {
+            let step = match event {
+                InputEvent::Quit => return Ok(()),
+                InputEvent::Hotkey {
+                    hotkey: Hotkey::MenuSelect,
+                    pressed: true,
+                } => {
+                    let path = config::save_audio_offset(config_path, offset_ms)?;
+                    log::info!("Saved offset-ms = {} to {}", offset_ms, path.display());
+                    return Ok(());
+                }
+                InputEvent::Hotkey {
+                    hotkey: Hotkey::MenuUp,
+                    pressed: true,
+                } => CALIBRATION_STEP_MS,
+                InputEvent::Hotkey {
+                    hotkey: Hotkey::MenuDown,
+                    pressed: true,
+                } => -CALIBRATION_STEP_MS,
+                _ => continue,
+            };
+            offset_ms = (offset_ms + step).clamp(-MAX_AUDIO_OFFSET_MS, MAX_AUDIO_OFFSET_MS);
+            picture_delay = apply_offset(offset_ms, frontend.as_mut());
+            frontend.set_title(&calibration_title(offset_ms));
+        }
+        if interrupt::requested() {
+            return Ok(());
+        }
+
+        let flash = frame % FRAMES_PER_SECOND < FLASH_FRAMES;
+        if flash != display.get_pixel(0, 0) {
+            for y in 0..display.height() {
+                for x in 0..display.width() {
+                    display.set_pixel(x, y, flash);
+                }
+            }
+        }
+        if let Some(audio) = frontend.audio() {
+            audio.set_buzzer(flash);
+        }
+        frontend.paint(picture_delay.delay(&mut display));
+        frame += 1;
+
+        next_frame += frame_duration;
+        let now = Instant::now();
+        if next_frame > now {
+            std::thread::sleep(next_frame - now);
+        } else {
+            next_frame = now;
+        }
+    }
+}
+
+fn calibration_title(offset_ms: i32) -> String {
+    let meaning = match offset_ms {
+        0 => "no delay".to_string(),
+        ms if ms > 0 => format!("sound {} ms later", ms),
+        ms => format!("picture {} ms later", -ms),
+    };
+    format!(
+        "Audio offset {} ms ({}), Up/Down to change, Enter to save",
+        offset_ms, meaning
+    )
+}
+
+#[test]
+fn test_picture_delay() {
+    let mut display = Display::new();
+    assert!(std::ptr::eq(PictureDelay::new(0).delay(&mut display), &display));
+
+    let mut delay = PictureDelay::new(2);
+    let mut painted = Vec::new();
+    for frame in 0..5 {
+        display.set_pixel(frame, 0, true);
+        let shown = delay.delay(&mut display);
+        painted.push((0..5).filter(|&x| shown.get_pixel(x, 0)).count());
+    }
+    // Nothing to show two frames ago at first, so the first frame stays
+    assert_eq!(painted, [1, 1, 1, 2, 3]);
+}
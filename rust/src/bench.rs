@@ -0,0 +1,78 @@
+//! `chip8 bench`: run a ROM headless as fast as possible and report how
+//! many instructions per second the interpreter manages.
+
+use std::time::Instant;
+
+use chip8::decode_cache::DecodeCache;
+use chip8::random::SeededRandom;
+use chip8::threaded::BlockCache;
+use chip8::vm::{cycles_in_frame, processor_cycle, timer_ticks_due, DEFAULT_TIMER_HZ, PROCESSOR_SPEED_HZ, VM};
+
+use crate::audio_sink::{AudioSink, NullSink};
+use crate::interrupt;
+
+pub(crate) fn bench(rom: &[u8], cycles: u64, decode_cache: bool, threaded: bool) -> Result<String, String> {
+    let mut vm = VM::new(Box::new(SeededRandom::new(0)));
+    vm.reload(rom)?;
+    if decode_cache {
+        vm.decode_cache = Some(DecodeCache::new(vm.memory.len()));
+    }
+    if threaded {
+        vm.block_cache = Some(BlockCache::new(vm.memory.len()));
+    }
+
+    let start = Instant::now();
+    run(&mut vm, cycles, &mut NullSink)?;
+    let seconds = start.elapsed().as_secs_f64();
+
+    Ok(format!(
+        "{} instructions in {:.3}s: {:.0} instructions/second",
+        vm.cycles,
+        seconds,
+        vm.cycles as f64 / seconds
+    ))
+}
+
+/// Run `cycles` instructions headless, or fewer if the program exits,
+/// ticking the timers once per (emulated) frame, since ROMs often wait
+/// on them, and playing the buzzer into `audio`.
+pub(crate) fn run(vm: &mut VM, cycles: u64, audio: &mut dyn AudioSink) -> Result<(), String> {
+    let mut frames = 0;
+    let mut frame_cycles_left = cycles_in_frame(frames, PROCESSOR_SPEED_HZ);
+    for _ in 0..cycles {
+        if vm.exited {
+            break;
+        }
+        processor_cycle(vm).map_err(|err| format!("Error in processor cycle {}: {}", vm.cycles, err))?;
+        vm.cycles += 1;
+        frame_cycles_left -= 1;
+        if frame_cycles_left == 0 {
+            audio.set_audio_pattern(vm.audio_pattern());
+            audio.set_buzzer(vm.sound_timer > 0);
+            audio.end_frame()?;
+            frames += 1;
+            for _ in 0..timer_ticks_due(frames, DEFAULT_TIMER_HZ) {
+                vm.tick_timers();
+            }
+            frame_cycles_left = cycles_in_frame(frames, PROCESSOR_SPEED_HZ);
+            if interrupt::requested() {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_bench() {
+    // 1200: jump to itself
+    let report = bench(&[0x12, 0x00], 1000, true, false).unwrap();
+    assert!(report.starts_with("1000 instructions in "));
+    let report = bench(&[0x12, 0x00], 1000, false, true).unwrap();
+    assert!(report.starts_with("1000 instructions in "));
+    assert!(bench(&[0x00, 0x00], 1, false, false).is_err());
+
+    // 00FD: exit, so only one instruction runs
+    let report = bench(&[0x00, 0xFD], 1000, false, false).unwrap();
+    assert!(report.starts_with("1 instructions in "), "{}", report);
+}
@@ -0,0 +1,176 @@
+//! `chip8 cfg`: the control flow graph of a ROM as Graphviz DOT, for
+//! seeing the structure of a game at a glance. The code found by
+//! following every path from the entry point (see `analysis.rs`) is
+//! split into basic blocks, which end at jumps, calls, skips and
+//! returns, and start wherever one of those can lead.
+//!
+//! Render it with e.g. `dot -Tsvg rom.dot -o rom.svg`.
+
+use std::collections::BTreeSet;
+
+use crate::analysis::{flow, Flow, Program, ROM_START};
+use crate::disassembler::describe;
+use crate::symbols::Symbols;
+
+/// A run of instructions that always execute together, from the first
+/// to the last.
+#[derive(Debug, PartialEq)]
+struct Block {
+    start: u16,
+    /// The address of the last instruction
+    end: u16,
+    edges: Vec<Edge>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Edge {
+    target: u16,
+    kind: EdgeKind,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum EdgeKind {
+    /// Jumping or falling through to the next block
+    Flow,
+    /// The taken side of a skip
+    Skip,
+    Call,
+    /// `jump0`, to the start of the table it indexes into
+    Computed,
+}
+
+fn basic_blocks(program: &Program) -> Vec<Block> {
+    let code = program.reachable();
+
+    // Blocks start at the entry point and wherever control can go
+    // other than the next instruction
+    let mut leaders = BTreeSet::new();
+    leaders.insert(ROM_START);
+    for &addr in &code {
+        let flow = flow(&program.fetch(addr).expect("reachable code is valid"));
+        match flow {
+            Flow::Next => continue,
+            Flow::Call(target) | Flow::Computed(target) => {
+                leaders.insert(target);
+            }
+            _ => {}
+        }
+        leaders.extend(program.local_successors(addr, flow));
+    }
+
+    let mut blocks = Vec::new();
+    for &start in leaders.iter().filter(|addr| code.contains(addr)) {
+        let mut end = start;
+        loop {
+            let flow = flow(&program.fetch(end).expect("reachable code is valid"));
+            let next = end + 2;
+            if flow == Flow::Next && code.contains(&next) && !leaders.contains(&next) {
+                end = next;
+                continue;
+            }
+            let mut edges = Vec::new();
+            match flow {
+                Flow::Next => edges.push((next, EdgeKind::Flow)),
+                Flow::Skip => {
+                    edges.push((next, EdgeKind::Flow));
+                    edges.push((end + 4, EdgeKind::Skip));
+                }
+                Flow::Jump(target) => edges.push((target, EdgeKind::Flow)),
+                Flow::Call(target) => {
+                    edges.push((target, EdgeKind::Call));
+                    edges.push((next, EdgeKind::Flow));
+                }
+                Flow::Computed(target) => edges.push((target, EdgeKind::Computed)),
+                Flow::Return | Flow::Stop => {}
+            }
+            // Paths into data or off the end of the ROM aren't blocks
+            let edges = edges
+                .into_iter()
+                .filter(|(target, _)| code.contains(target))
+                .map(|(target, kind)| Edge { target, kind })
+                .collect();
+            blocks.push(Block { start, end, edges });
+            break;
+        }
+    }
+    blocks
+}
+
+pub(crate) fn cfg_dot(rom: &[u8], symbols: &Symbols) -> String {
+    let program = Program::new(rom);
+    let blocks = basic_blocks(&program);
+
+    let mut dot = String::from("digraph cfg {\n\tnode [shape=box fontname=monospace];\n");
+    for block in &blocks {
+        let mut label = String::new();
+        if let Some(name) = symbols.label(block.start) {
+            label += &format!("{}:\\l", name);
+        }
+        for addr in (block.start..=block.end).step_by(2) {
+            let instruction = program.fetch(addr).expect("blocks are made of valid code");
+            label += &format!("{:03X}: {}\\l", addr, escape(&describe(&instruction, symbols)));
+        }
+        dot += &format!("\tb{:03X} [label=\"{}\"];\n", block.start, label);
+    }
+
+    for block in &blocks {
+        for edge in &block.edges {
+            let style = match edge.kind {
+                EdgeKind::Flow => "",
+                EdgeKind::Skip => " [label=skip]",
+                EdgeKind::Call => " [style=dashed label=call]",
+                EdgeKind::Computed => " [style=dotted label=jump0]",
+            };
+            dot += &format!("\tb{:03X} -> b{:03X}{};\n", block.start, edge.target, style);
+        }
+    }
+    dot += "}\n";
+    dot
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[test]
+fn test_cfg() {
+    let rom = [
+        0x00, 0xE0, // 200: clear
+        0x22, 0x0A, // 202: call 20A
+        0x30, 0x00, // 204: skip if V0 == 0
+        0x12, 0x02, // 206: jump 202
+        0x12, 0x08, // 208: loop forever
+        0x70, 0x01, // 20A: V0 += 1
+        0x00, 0xEE, // 20C: return
+    ];
+    let program = Program::new(&rom);
+    let blocks = basic_blocks(&program);
+    let summary: Vec<String> = blocks
+        .iter()
+        .map(|block| {
+            let edges: Vec<String> = block
+                .edges
+                .iter()
+                .map(|edge| format!("{:?} {:03X}", edge.kind, edge.target))
+                .collect();
+            format!("{:03X}-{:03X}: {}", block.start, block.end, edges.join(", "))
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            "200-200: Flow 202",
+            "202-202: Call 20A, Flow 204",
+            "204-204: Flow 206, Skip 208",
+            "206-206: Flow 202",
+            "208-208: Flow 208",
+            "20A-20C: ",
+        ]
+    );
+
+    let symbols = Symbols::parse("20A step").unwrap();
+    let dot = cfg_dot(&rom, &symbols);
+    assert!(dot.contains("\tb20A [label=\"step:\\l20A: ADD V0, 0x01\\l20C: RET\\l\"];\n"));
+    assert!(dot.contains("\tb202 -> b20A [style=dashed label=call];\n"));
+    assert!(dot.contains("\tb204 -> b208 [label=skip];\n"));
+}
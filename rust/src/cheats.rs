@@ -0,0 +1,228 @@
+//! Cheats: Game Genie-style pokes into memory, read from a cheats file.
+//! Each line of the file is a cheat:
+//!
+//! ```text
+//! # NAME        WHEN    POKES                 [off]
+//! lives         always  3A0=09
+//! skip-intro    load    20A=1300
+//! fast-ship     always  3A4=04,3A5=04         off
+//! ```
+//!
+//! A poke writes a byte (two hex digits) or an instruction (four) to an
+//! address. `load` cheats are applied when the ROM is loaded, which
+//! suits patching code, and `always` cheats at the end of every frame
+//! too, which keeps a value pinned. Cheats marked `off` start disabled.
+
+use std::fmt;
+
+use crate::vm::MEMORY_BYTES;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CheatMode {
+    /// Applied when the ROM is loaded
+    Load,
+    /// Applied when the ROM is loaded and after every frame
+    Always,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Poke {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Cheat {
+    pub name: String,
+    pub mode: CheatMode,
+    pub pokes: Vec<Poke>,
+    pub enabled: bool,
+}
+
+impl Cheat {
+    pub fn apply(&self, memory: &mut [u8]) {
+        for poke in &self.pokes {
+            let start = poke.addr as usize;
+            memory[start..start + poke.bytes.len()].copy_from_slice(&poke.bytes);
+        }
+    }
+
+    /// The addresses the cheat writes to.
+    pub fn addresses(&self) -> impl Iterator<Item = usize> + '_ {
+        self.pokes
+            .iter()
+            .flat_map(|poke| poke.addr as usize..poke.addr as usize + poke.bytes.len())
+    }
+}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mode = match self.mode {
+            CheatMode::Load => "load",
+            CheatMode::Always => "always",
+        };
+        let pokes: Vec<String> = self
+            .pokes
+            .iter()
+            .map(|poke| {
+                let value: String = poke.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+                format!("{:03X}={}", poke.addr, value)
+            })
+            .collect();
+        write!(f, "{} {} {}", self.name, mode, pokes.join(","))?;
+        if !self.enabled {
+            write!(f, " off")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse the contents of a cheats file.
+pub fn parse_cheats(contents: &str) -> Result<Vec<Cheat>, String> {
+    let mut cheats = Vec::new();
+    for (num, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let cheat = parse_cheat(line).map_err(|err| format!("line {}: {}", num + 1, err))?;
+        cheats.push(cheat);
+    }
+    Ok(cheats)
+}
+
+fn parse_cheat(line: &str) -> Result<Cheat, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (name, mode, pokes, enabled) = match words.as_slice() {
+        [name, mode, pokes] => (name, mode, pokes, true),
+        [name, mode, pokes, "off"] => (name, mode, pokes, false),
+        _ => {
+            return Err(format!(
+                "invalid cheat {:?}, expected NAME load|always POKES [off]",
+                line
+            ))
+        }
+    };
+    let mode = match *mode {
+        "load" => CheatMode::Load,
+        "always" => CheatMode::Always,
+        _ => return Err(format!("invalid mode {:?}, expected load or always", mode)),
+    };
+    let pokes = pokes.split(',').map(parse_poke).collect::<Result<_, _>>()?;
+    Ok(Cheat {
+        name: name.to_string(),
+        mode,
+        pokes,
+        enabled,
+    })
+}
+
+/// Parse a poke like `3A0=09` or `20A=1300`.
+fn parse_poke(s: &str) -> Result<Poke, String> {
+    let invalid = || format!("invalid poke {:?}, expected e.g. 3A0=09 or 20A=1300", s);
+    let (addr, value) = s.split_once('=').ok_or_else(invalid)?;
+    let addr = u16::from_str_radix(addr, 16).map_err(|_| invalid())?;
+    if !value.is_ascii() || !matches!(value.len(), 2 | 4) {
+        return Err(invalid());
+    }
+    let bytes = (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&value[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| invalid())?;
+    if addr as usize + bytes.len() > MEMORY_BYTES {
+        return Err(format!("poke {:?} is outside of memory", s));
+    }
+    Ok(Poke { addr, bytes })
+}
+
+/// The cheats loaded for the running ROM.
+#[derive(Default)]
+pub struct CheatTable {
+    cheats: Vec<Cheat>,
+}
+
+impl CheatTable {
+    pub fn new(cheats: Vec<Cheat>) -> CheatTable {
+        CheatTable { cheats }
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    /// Enable or disable cheat number `index`, returning it.
+    pub fn toggle(&mut self, index: usize) -> Option<&Cheat> {
+        let cheat = self.cheats.get_mut(index)?;
+        cheat.enabled = !cheat.enabled;
+        Some(cheat)
+    }
+
+    /// Apply the enabled cheats with one of `modes`, returning the ones
+    /// applied.
+    pub fn apply(&self, memory: &mut [u8], modes: &[CheatMode]) -> Vec<&Cheat> {
+        let applied: Vec<&Cheat> = self
+            .cheats
+            .iter()
+            .filter(|cheat| cheat.enabled && modes.contains(&cheat.mode))
+            .collect();
+        for cheat in &applied {
+            cheat.apply(memory);
+        }
+        applied
+    }
+}
+
+#[test]
+fn test_parse_cheats() {
+    let cheats = parse_cheats("# Lives\nlives always 3A0=09\nskip load 20A=1300,3A0=FF off # not yet\n").unwrap();
+    assert_eq!(
+        cheats,
+        [
+            Cheat {
+                name: "lives".to_string(),
+                mode: CheatMode::Always,
+                pokes: vec![Poke {
+                    addr: 0x3A0,
+                    bytes: vec![0x09]
+                }],
+                enabled: true,
+            },
+            Cheat {
+                name: "skip".to_string(),
+                mode: CheatMode::Load,
+                pokes: vec![
+                    Poke {
+                        addr: 0x20A,
+                        bytes: vec![0x13, 0x00]
+                    },
+                    Poke {
+                        addr: 0x3A0,
+                        bytes: vec![0xFF]
+                    },
+                ],
+                enabled: false,
+            },
+        ]
+    );
+    assert_eq!(cheats[1].to_string(), "skip load 20A=1300,3A0=FF off");
+
+    assert!(parse_cheats("lives sometimes 3A0=09").is_err());
+    assert!(parse_cheats("lives always 3A0=9").is_err());
+    assert!(parse_cheats("lives always FFF=1300").is_err());
+    assert!(parse_cheats("lives always").is_err());
+}
+
+#[test]
+fn test_cheat_table() {
+    let mut table = CheatTable::new(parse_cheats("a load 300=01\nb always 301=0203\n").unwrap());
+    let mut memory = [0; MEMORY_BYTES];
+    assert_eq!(table.apply(&mut memory, &[CheatMode::Always]).len(), 1);
+    assert_eq!(memory[0x300..0x303], [0, 2, 3]);
+
+    assert_eq!(table.toggle(1).map(|cheat| cheat.enabled), Some(false));
+    assert!(table.toggle(2).is_none());
+    let mut memory = [0; MEMORY_BYTES];
+    table.apply(&mut memory, &[CheatMode::Load, CheatMode::Always]);
+    assert_eq!(memory[0x300..0x303], [1, 0, 0]);
+}
@@ -0,0 +1,196 @@
+//! `chip8 compare`: run a ROM on two VMs at once, e.g. with different
+//! quirks, pressing the same keys with the same random seed, and report
+//! the first instruction after which their states differ. That's
+//! usually the instruction a quirk changes, which is what to look at
+//! when a ROM only works on some platforms.
+//!
+//! The report lists what differs and shows the two displays side by
+//! side, with the pixels that differ drawn as `X` where they're lit and
+//! `-` where they aren't.
+
+use chip8::instruction::parse_instruction;
+use chip8::quirks::Quirks;
+use chip8::vm::{fetch, VM};
+
+use crate::interrupt;
+use crate::lockstep::Machine;
+use crate::replay::Replay;
+
+/// Run `rom` on two VMs, with the `left` and `right` quirks, for up to
+/// `cycles` instructions, and report where they diverge.
+pub(crate) fn compare(
+    rom: &[u8],
+    left: Quirks,
+    right: Quirks,
+    replay: Option<Replay>,
+    cycles: u64,
+) -> Result<String, String> {
+    let mut machines = [
+        Machine::new(rom, left, None, replay.clone())?,
+        Machine::new(rom, right, None, replay)?,
+    ];
+    let mut instructions = 0;
+    while instructions < cycles && !machines[0].vm.exited {
+        interrupt::check()?;
+        let vm = &machines[0].vm;
+        let pc = (vm.pc as usize % vm.memory.len()) as u16;
+        let opcode = fetch(&vm.memory, pc);
+        let results: Vec<Result<(), String>> = machines.iter_mut().map(Machine::step).collect();
+        instructions += 1;
+
+        let mut differences = differences(&machines[0].vm, &machines[1].vm);
+        match (&results[0], &results[1]) {
+            (Err(left), Err(right)) if left == right => return Err(left.clone()),
+            (Ok(()), Ok(())) => {}
+            (left, right) => differences.insert(
+                0,
+                format!(
+                    "error: {} | {}",
+                    left.as_ref().err().map_or("none", String::as_str),
+                    right.as_ref().err().map_or("none", String::as_str)
+                ),
+            ),
+        }
+        if !differences.is_empty() {
+            let decoded = parse_instruction(opcode).map_or_else(|err| err, |instruction| instruction.to_string());
+            let mut report = format!(
+                "Diverged after instruction {}, at {:03X}: {:04X} {}\n\n",
+                instructions, pc, opcode, decoded
+            );
+            for difference in differences {
+                report += &format!("  {}\n", difference);
+            }
+            report += "\nDisplays (left | right):\n";
+            report += &side_by_side(&machines[0].vm, &machines[1].vm);
+            return Ok(report);
+        }
+    }
+    Ok(format!("No divergence in {} instructions\n", instructions))
+}
+
+/// What differs between the two VMs' states, as `name: left | right`,
+/// or for memory and the display, how many bytes or pixels differ.
+fn differences(left: &VM, right: &VM) -> Vec<String> {
+    let mut differences = Vec::new();
+    let mut differ = |name: &str, left: String, right: String| {
+        if left != right {
+            differences.push(format!("{}: {} | {}", name, left, right));
+        }
+    };
+    differ("PC", format!("{:03X}", left.pc), format!("{:03X}", right.pc));
+    differ("I", format!("{:03X}", left.ir), format!("{:03X}", right.ir));
+    for x in 0..16 {
+        differ(
+            &format!("V{:X}", x),
+            format!("{:02X}", left.v[x]),
+            format!("{:02X}", right.v[x]),
+        );
+    }
+    let stack = |vm: &VM| {
+        let addresses: Vec<String> = vm
+            .stack
+            .frames()
+            .iter()
+            .map(|frame| format!("{:03X}", frame.return_address()))
+            .collect();
+        format!("[{}]", addresses.join(" "))
+    };
+    differ("stack", stack(left), stack(right));
+    differ("DT", left.delay_timer.to_string(), right.delay_timer.to_string());
+    differ("ST", left.sound_timer.to_string(), right.sound_timer.to_string());
+    differ("cycles", left.cycles.to_string(), right.cycles.to_string());
+
+    let changed: Vec<usize> = (0..left.memory.len())
+        .filter(|&addr| left.memory[addr] != right.memory[addr])
+        .collect();
+    if let Some(&first) = changed.first() {
+        differences.push(format!(
+            "memory: {} bytes, the first at {:03X}: {:02X} | {:02X}",
+            changed.len(),
+            first,
+            left.memory[first],
+            right.memory[first]
+        ));
+    }
+
+    let (display, other) = (&left.display, &right.display);
+    if (display.width(), display.height()) != (other.width(), other.height()) {
+        differences.push(format!(
+            "display size: {}x{} | {}x{}",
+            display.width(),
+            display.height(),
+            other.width(),
+            other.height()
+        ));
+    } else {
+        let pixels = (0..display.height())
+            .flat_map(|y| (0..display.width()).map(move |x| (x, y)))
+            .filter(|&(x, y)| display.pixel_planes(x, y) != other.pixel_planes(x, y))
+            .count();
+        if pixels > 0 {
+            differences.push(format!("display: {} pixels", pixels));
+        }
+    }
+    differences
+}
+
+/// Both VMs' displays, a row of each per line, with the pixels that
+/// differ marked.
+fn side_by_side(left: &VM, right: &VM) -> String {
+    let (left, right) = (&left.display, &right.display);
+    let row = |display: &chip8::display::Display, other: &chip8::display::Display, y: usize| -> String {
+        if y >= display.height() {
+            return " ".repeat(display.width());
+        }
+        (0..display.width())
+            .map(|x| {
+                let same =
+                    y < other.height() && x < other.width() && display.pixel_planes(x, y) == other.pixel_planes(x, y);
+                match (display.get_pixel(x, y), same) {
+                    (true, true) => '#',
+                    (false, true) => '.',
+                    (true, false) => 'X',
+                    (false, false) => '-',
+                }
+            })
+            .collect()
+    };
+    let mut text = String::new();
+    for y in 0..left.height().max(right.height()) {
+        text += &format!("{} | {}\n", row(left, right, y), row(right, left, y));
+    }
+    text
+}
+
+#[test]
+fn test_compare() {
+    // 6105 V1 := 5, 8116 V1 >>= V1, A300 I := 300, D011 draw, 1208 loop
+    let rom = [0x61, 0x05, 0x81, 0x16, 0xA3, 0x00, 0xD0, 0x11, 0x12, 0x08];
+    let shift = Quirks::parse("shift-uses-vy").unwrap();
+    let report = compare(&rom, Quirks::default(), Quirks::default(), None, 100).unwrap();
+    assert_eq!(report, "No divergence in 100 instructions\n");
+
+    // 8126 shifts V2 (0) into V1 with shift-uses-vy, so V1 differs
+    let rom = [0x61, 0x05, 0x81, 0x26, 0x12, 0x04];
+    let report = compare(&rom, Quirks::default(), shift, None, 100).unwrap();
+    assert!(
+        report.starts_with("Diverged after instruction 2, at 202: 8126 SHR V1, V2\n"),
+        "{}",
+        report
+    );
+    assert!(report.contains("\n\n  V1: 02 | 00\n  VF: 01 | 00\n\nDisplays (left | right):\n"));
+    assert!(report.ends_with(&format!("{} | {}\n", ".".repeat(64), ".".repeat(64))));
+}
+
+#[test]
+fn test_side_by_side() {
+    let mut left = VM::new(Box::new(chip8::random::SeededRandom::new(0)));
+    let mut right = VM::new(Box::new(chip8::random::SeededRandom::new(0)));
+    left.display.set_pixel(0, 0, true);
+    right.display.set_pixel(0, 0, true);
+    left.display.set_pixel(1, 0, true);
+    assert_eq!(differences(&left, &right), ["display: 1 pixels"]);
+    let text = side_by_side(&left, &right);
+    assert!(text.starts_with(&format!("#X{} | #-{}\n", ".".repeat(62), ".".repeat(62))));
+    assert_eq!(text.lines().count(), 32);
+}
@@ -0,0 +1,299 @@
+//! Conditions for the debugger's conditional breakpoints, like `v3 ==
+//! 0x1F && i >= 0x300`. They're evaluated before every instruction, so
+//! they're parsed once into a tree and then walked.
+//!
+//! Conditions can use:
+//!
+//! - `v0` to `vF`, `i`, `pc`, `dt` and `st` (the timers), and `sp`, the
+//!   number of return addresses on the stack
+//! - `mem[ADDR]`, the byte at an address
+//! - numbers, in decimal or hex with `0x`, and labels from the ROM's
+//!   symbol file
+//! - `+ - & | == != < <= > >= && || !` and parentheses, with the same
+//!   precedence as in C
+//!
+//! Comparisons and `&&`, `||` and `!` are 1 when true and 0 when false,
+//! and a condition holds when it isn't 0.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use chip8::vm::VM;
+
+use crate::symbols::Symbols;
+
+/// Operators, longest first so `<=` isn't read as `<`.
+const OPERATORS: [&str; 17] = [
+    "&&", "||", "==", "!=", "<=", ">=", "<", ">", "+", "-", "&", "|", "!", "(", ")", "[", "]",
+];
+
+/// Binary operators from lowest to highest precedence.
+const PRECEDENCE: [&[&str]; 6] = [&["||"], &["&&"], &["==", "!="], &["<", "<=", ">", ">="], &["|"], &["&"]];
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Register {
+    V(usize),
+    I,
+    Pc,
+    Delay,
+    Sound,
+    StackDepth,
+}
+
+#[derive(Debug, PartialEq)]
+enum Expr {
+    Number(i64),
+    Register(Register),
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Operator(&'static str),
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Condition {
+    /// The condition as typed, for listing breakpoints
+    source: String,
+    expr: Expr,
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl Condition {
+    pub(crate) fn parse(source: &str, symbols: &Symbols) -> Result<Condition, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            pos: 0,
+            symbols,
+        };
+        let expr = parser.binary(0)?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(format!("Unexpected {} in condition", describe(token)));
+        }
+        Ok(Condition {
+            source: source.trim().to_string(),
+            expr,
+        })
+    }
+
+    /// Whether the condition holds for the VM's current state. Fails
+    /// when it reads outside of memory.
+    pub(crate) fn holds(&self, vm: &VM) -> Result<bool, String> {
+        Ok(evaluate(&self.expr, vm)? != 0)
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if let Some(&operator) = OPERATORS.iter().find(|&&operator| rest.starts_with(operator)) {
+            tokens.push(Token::Operator(operator));
+            operator.len()
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            let word = &rest[..len];
+            let number = match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+                Some(hex) => Some(i64::from_str_radix(hex, 16)),
+                None if c.is_ascii_digit() => Some(word.parse()),
+                None => None,
+            };
+            tokens.push(match number {
+                Some(Ok(n)) => Token::Number(n),
+                Some(Err(_)) => return Err(format!("Invalid number {:?}", word)),
+                None => Token::Name(word.to_string()),
+            });
+            len
+        } else {
+            return Err(format!("Unexpected {:?} in condition", c));
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Number(n) => n.to_string(),
+        Token::Name(name) => format!("{:?}", name),
+        Token::Operator(operator) => format!("{:?}", operator),
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    symbols: &'a Symbols,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    /// Consume the next token if it's one of `operators`.
+    fn operator(&mut self, operators: &[&'static str]) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Operator(operator)) if operators.contains(operator) => {
+                self.pos += 1;
+                Some(operator)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, operator: &'static str) -> Result<(), String> {
+        match self.operator(&[operator]) {
+            Some(_) => Ok(()),
+            None => Err(format!("Expected {:?} in condition", operator)),
+        }
+    }
+
+    /// Parse binary operators of `PRECEDENCE[level]` or higher.
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        let Some(operators) = PRECEDENCE.get(level) else {
+            return self.sum();
+        };
+        let mut expr = self.binary(level + 1)?;
+        while let Some(operator) = self.operator(operators) {
+            expr = Expr::Binary(operator, Box::new(expr), Box::new(self.binary(level + 1)?));
+        }
+        Ok(expr)
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while let Some(operator) = self.operator(&["+", "-"]) {
+            expr = Expr::Binary(operator, Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.operator(&["!", "-"]) {
+            Some("!") => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(_) => Ok(Expr::Negate(Box::new(self.unary()?))),
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Operator("(")) => {
+                let expr = self.binary(0)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(Token::Name(name)) if name.eq_ignore_ascii_case("mem") => {
+                self.expect("[")?;
+                let addr = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Expr::Memory(Box::new(addr)))
+            }
+            Some(Token::Name(name)) => match register(&name) {
+                Some(register) => Ok(Expr::Register(register)),
+                None => match self.symbols.address(&name) {
+                    Some(addr) => Ok(Expr::Number(addr as i64)),
+                    None => Err(format!("{:?} is neither a register nor a label", name)),
+                },
+            },
+            Some(token) => Err(format!("Unexpected {} in condition", describe(&token))),
+            None => Err("Condition ends unexpectedly".to_string()),
+        }
+    }
+}
+
+fn register(name: &str) -> Option<Register> {
+    let name = name.to_ascii_lowercase();
+    match name.as_str() {
+        "i" => Some(Register::I),
+        "pc" => Some(Register::Pc),
+        "dt" => Some(Register::Delay),
+        "st" => Some(Register::Sound),
+        "sp" => Some(Register::StackDepth),
+        _ => {
+            let x = name.strip_prefix('v').filter(|x| x.len() == 1)?;
+            usize::from_str_radix(x, 16).ok().map(Register::V)
+        }
+    }
+}
+
+fn evaluate(expr: &Expr, vm: &VM) -> Result<i64, String> {
+    Ok(match expr {
+        Expr::Number(n) => *n,
+        Expr::Register(Register::V(x)) => vm.v[*x] as i64,
+        Expr::Register(Register::I) => vm.ir as i64,
+        Expr::Register(Register::Pc) => vm.pc as i64,
+        Expr::Register(Register::Delay) => vm.delay_timer as i64,
+        Expr::Register(Register::Sound) => vm.sound_timer as i64,
+        Expr::Register(Register::StackDepth) => vm.stack.len() as i64,
+        Expr::Memory(addr) => {
+            let addr = evaluate(addr, vm)?;
+            match usize::try_from(addr).ok().filter(|&addr| addr < vm.memory.len()) {
+                Some(addr) => vm.memory[addr] as i64,
+                None => return Err(format!("Condition reads mem[{:#X}], outside of memory", addr)),
+            }
+        }
+        Expr::Not(expr) => (evaluate(expr, vm)? == 0) as i64,
+        Expr::Negate(expr) => evaluate(expr, vm)?.wrapping_neg(),
+        // Only evaluate the right-hand side when it's needed
+        Expr::Binary("&&", left, right) => (evaluate(left, vm)? != 0 && evaluate(right, vm)? != 0) as i64,
+        Expr::Binary("||", left, right) => (evaluate(left, vm)? != 0 || evaluate(right, vm)? != 0) as i64,
+        Expr::Binary(operator, left, right) => {
+            let (left, right) = (evaluate(left, vm)?, evaluate(right, vm)?);
+            match *operator {
+                "==" => (left == right) as i64,
+                "!=" => (left != right) as i64,
+                "<" => (left < right) as i64,
+                "<=" => (left <= right) as i64,
+                ">" => (left > right) as i64,
+                ">=" => (left >= right) as i64,
+                "+" => left.wrapping_add(right),
+                "-" => left.wrapping_sub(right),
+                "&" => left & right,
+                "|" => left | right,
+                _ => unreachable!("the parser only makes binary expressions from binary operators"),
+            }
+        }
+    })
+}
+
+#[test]
+fn test_condition() {
+    let mut vm = VM::new(Box::new(chip8::random::SeededRandom::new(0)));
+    vm.v[3] = 0x1F;
+    vm.ir = 0x300;
+    vm.memory[0x301] = 7;
+    let symbols = Symbols::parse("301 lives").unwrap();
+    let holds = |source: &str| Condition::parse(source, &symbols).unwrap().holds(&vm);
+
+    assert_eq!(holds("v3 == 0x1F && i >= 0x300"), Ok(true));
+    assert_eq!(holds("v3 == 0x1F && i > 0x300"), Ok(false));
+    assert_eq!(holds("V3 == 31 || mem[-1] == 0"), Ok(true));
+    assert_eq!(holds("mem[i + 1] == 7 && mem[lives] - 1 == 6"), Ok(true));
+    assert_eq!(holds("!(v3 & 0x10) || v0 != 0"), Ok(false));
+    assert_eq!(holds("1 + 2 == 3 == 1"), Ok(true));
+    assert_eq!(holds("sp == 0 && pc == 0x200"), Ok(true));
+    assert!(holds("mem[0x1000]").is_err());
+
+    assert_eq!(Condition::parse(" v3 == 1 ", &symbols).unwrap().to_string(), "v3 == 1");
+    for invalid in ["", "v3 ==", "vG == 1", "v3 = 1", "(v3", "mem 3", "0xZZ", "v3 1"] {
+        assert!(Condition::parse(invalid, &symbols).is_err(), "{:?}", invalid);
+    }
+}
@@ -0,0 +1,509 @@
+//! The user's config file, which sets defaults for every ROM and can
+//! override them for particular ROMs:
+//!
+//! ```toml
+//! speed = 700
+//! scale = 10
+//! # Sets the quirks for a machine, which [quirks] can change
+//! platform = "vip"
+//! # Speed multiplier while Tab is held
+//! fast-forward = 8
+//!
+//! [colors]
+//! foreground = "#FFB000"
+//! background = "#202020"
+//! # XO-CHIP's second plane, and where both planes are lit
+//! plane2 = "#FF6000"
+//! blend = "#602000"
+//!
+//! [quirks]
+//! shift-uses-vy = true
+//!
+//! # The buzzer: waveform is square, sine, triangle or noise
+//! [sound]
+//! waveform = "triangle"
+//! pitch = 330
+//! volume = 0.2
+//! attack-ms = 5
+//! release-ms = 50
+//! # Delay the buzzer this many ms, or the picture if it's negative, so
+//! # they line up (chip8 calibrate finds and saves this)
+//! offset-ms = -40
+//!
+//! # Keyboard key = keypad key, on top of a built-in keymap: "default",
+//! # or "two-player" to split the keypad between two players
+//! keymap-profile = "default"
+//! # Show the keypad beside the display, which F9 also toggles
+//! show-keypad = true
+//! # Hold quick key taps down for a frame, like --latch-keys
+//! latch-keys = true
+//! # Keypad keys pressed over and over while held, 10 times a second
+//! autofire = "5"
+//! autofire-hz = 10
+//! [keymap]
+//! k = "5"
+//!
+//! # Gamepad button = keypad key, for the first gamepad connected (SDL
+//! # frontend only)
+//! [gamepads.1]
+//! dpad-up = "1"
+//! dpad-down = "4"
+//!
+//! # Matched by file name or by the SHA-256 hash of the ROM
+//! [roms."invaders.ch8"]
+//! speed = 1000
+//! # Memory kept between runs (see src/save_ram.rs)
+//! save-ram = "E00-EFF"
+//! ```
+//!
+//! Command line flags take precedence over the config file.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use chip8::audio::Tone;
+use chip8::platform::{self, Platform};
+use chip8::quirks::Quirks;
+
+use crate::av_sync::MAX_AUDIO_OFFSET_MS;
+use crate::frontend::{parse_color, Colors, Keymap};
+use crate::input::{parse_keys, MAX_AUTOFIRE_HZ};
+use crate::save_ram::SaveRange;
+
+/// Settings from the config file. Anything that isn't set falls back
+/// to the built-in default.
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct Settings {
+    pub(crate) speed: Option<u64>,
+    pub(crate) fast_forward: Option<u64>,
+    pub(crate) scale: Option<u32>,
+    pub(crate) timer_hz: Option<u32>,
+    pub(crate) platform: Option<String>,
+    colors: ColorSettings,
+    sound: SoundSettings,
+    pub(crate) quirks: BTreeMap<String, bool>,
+    pub(crate) keymap_profile: Option<String>,
+    pub(crate) keymap: BTreeMap<char, String>,
+    pub(crate) show_keypad: Option<bool>,
+    pub(crate) latch_keys: Option<bool>,
+    autofire: Option<String>,
+    pub(crate) autofire_hz: Option<u64>,
+    save_ram: Option<String>,
+    /// Button mappings by gamepad number, from 1
+    gamepads: BTreeMap<String, BTreeMap<String, String>>,
+
+    /// Per-ROM overrides, only allowed at the top level
+    roms: BTreeMap<String, Settings>,
+}
+
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ColorSettings {
+    foreground: Option<String>,
+    background: Option<String>,
+    plane2: Option<String>,
+    blend: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct SoundSettings {
+    waveform: Option<String>,
+    pitch: Option<f32>,
+    volume: Option<f32>,
+    attack_ms: Option<f32>,
+    release_ms: Option<f32>,
+    offset_ms: Option<i32>,
+}
+
+impl Settings {
+    /// Load the config file at `path`, or from the default location if
+    /// there is no path. It's fine for the default file not to exist.
+    pub(crate) fn load(path: Option<&Path>) -> Result<Settings, String> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Settings::default()),
+            },
+        };
+        let contents = fs::read_to_string(&path).map_err(|err| format!("Error reading {}: {}", path.display(), err))?;
+        Settings::parse(&contents).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    pub(crate) fn parse(contents: &str) -> Result<Settings, String> {
+        let settings: Settings = toml::from_str(contents).map_err(|err| err.to_string())?;
+        settings.validate()?;
+        for (name, rom) in &settings.roms {
+            if !rom.roms.is_empty() {
+                return Err(format!("[roms.{:?}] can't contain more ROM sections", name));
+            }
+            rom.validate().map_err(|err| format!("[roms.{:?}]: {}", name, err))?;
+        }
+        Ok(settings)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.speed == Some(0) || self.fast_forward == Some(0) || self.scale == Some(0) || self.timer_hz == Some(0) {
+            return Err("speed, fast-forward, scale and timer-hz must be greater than 0".to_string());
+        }
+        self.save_ram()?;
+        self.autofire()?;
+        if let Some(hz) = self.autofire_hz.filter(|hz| !(1..=MAX_AUTOFIRE_HZ).contains(hz)) {
+            return Err(format!(
+                "Invalid autofire-hz {}, it must be from 1 to {}",
+                hz, MAX_AUTOFIRE_HZ
+            ));
+        }
+        Ok(())
+    }
+
+    /// The settings for a ROM: the defaults, overridden by a section
+    /// matching the ROM's file name and then by one matching its hash.
+    pub(crate) fn for_rom(mut self, rom_path: &Path, rom: &[u8]) -> Settings {
+        let mut roms = std::mem::take(&mut self.roms);
+        let file_name = rom_path.file_name().map(|name| name.to_string_lossy().into_owned());
+        if let Some(overrides) = file_name.and_then(|name| roms.remove(&name)) {
+            self.merge(overrides);
+        }
+        if let Some(overrides) = roms.remove(&sha256_hex(rom)) {
+            self.merge(overrides);
+        }
+        self
+    }
+
+    /// Override these settings with any that are set in `other`.
+    pub(crate) fn merge(&mut self, other: Settings) {
+        self.speed = other.speed.or(self.speed);
+        self.fast_forward = other.fast_forward.or(self.fast_forward);
+        self.scale = other.scale.or(self.scale);
+        self.timer_hz = other.timer_hz.or(self.timer_hz);
+        self.platform = other.platform.or(self.platform.take());
+        self.colors.foreground = other.colors.foreground.or(self.colors.foreground.take());
+        self.colors.background = other.colors.background.or(self.colors.background.take());
+        self.colors.plane2 = other.colors.plane2.or(self.colors.plane2.take());
+        self.colors.blend = other.colors.blend.or(self.colors.blend.take());
+        self.sound.waveform = other.sound.waveform.or(self.sound.waveform.take());
+        self.sound.pitch = other.sound.pitch.or(self.sound.pitch);
+        self.sound.volume = other.sound.volume.or(self.sound.volume);
+        self.sound.attack_ms = other.sound.attack_ms.or(self.sound.attack_ms);
+        self.sound.release_ms = other.sound.release_ms.or(self.sound.release_ms);
+        self.sound.offset_ms = other.sound.offset_ms.or(self.sound.offset_ms);
+        self.quirks.extend(other.quirks);
+        self.keymap_profile = other.keymap_profile.or(self.keymap_profile.take());
+        self.keymap.extend(other.keymap);
+        self.show_keypad = other.show_keypad.or(self.show_keypad);
+        self.latch_keys = other.latch_keys.or(self.latch_keys);
+        self.autofire = other.autofire.or(self.autofire.take());
+        self.autofire_hz = other.autofire_hz.or(self.autofire_hz);
+        self.save_ram = other.save_ram.or(self.save_ram.take());
+        for (pad, buttons) in other.gamepads {
+            self.gamepads.entry(pad).or_default().extend(buttons);
+        }
+    }
+
+    pub(crate) fn platform(&self) -> Result<Option<&'static Platform>, String> {
+        self.platform.as_deref().map(platform::find).transpose()
+    }
+
+    /// The quirks in `base` (usually the platform's), changed by any
+    /// set in the config file.
+    pub(crate) fn quirks(&self, mut quirks: Quirks) -> Result<Quirks, String> {
+        for (name, &enabled) in &self.quirks {
+            quirks.set(name, enabled)?;
+        }
+        Ok(quirks)
+    }
+
+    pub(crate) fn save_ram(&self) -> Result<Option<SaveRange>, String> {
+        self.save_ram.as_deref().map(SaveRange::parse).transpose()
+    }
+
+    /// The keypad keys to press over and over while they're held.
+    pub(crate) fn autofire(&self) -> Result<Option<[bool; 16]>, String> {
+        self.autofire.as_deref().map(parse_keys).transpose()
+    }
+
+    pub(crate) fn colors(&self) -> Result<Colors, String> {
+        let mut colors = Colors::default();
+        if let Some(foreground) = &self.colors.foreground {
+            colors.foreground = parse_color(foreground)?;
+        }
+        if let Some(background) = &self.colors.background {
+            colors.background = parse_color(background)?;
+        }
+        if let Some(plane2) = &self.colors.plane2 {
+            colors.plane2 = parse_color(plane2)?;
+        }
+        if let Some(blend) = &self.colors.blend {
+            colors.blend = parse_color(blend)?;
+        }
+        Ok(colors)
+    }
+
+    pub(crate) fn tone(&self) -> Result<Tone, String> {
+        let mut tone = Tone::default();
+        if let Some(waveform) = &self.sound.waveform {
+            tone.waveform = waveform.parse()?;
+        }
+        if let Some(pitch) = self.sound.pitch {
+            if !pitch.is_finite() || pitch <= 0.0 {
+                return Err(format!(
+                    "Invalid pitch {}, expected a number of Hz greater than 0",
+                    pitch
+                ));
+            }
+            tone.pitch_hz = pitch;
+        }
+        if let Some(volume) = self.sound.volume {
+            if !(0.0..=1.0).contains(&volume) {
+                return Err(format!("Invalid volume {}, it must be from 0 to 1", volume));
+            }
+            tone.volume = volume;
+        }
+        for (ms, setting) in [
+            (self.sound.attack_ms, &mut tone.attack_ms),
+            (self.sound.release_ms, &mut tone.release_ms),
+        ] {
+            if let Some(ms) = ms {
+                if !ms.is_finite() || ms < 0.0 {
+                    return Err(format!(
+                        "Invalid envelope time {}, expected a number of milliseconds",
+                        ms
+                    ));
+                }
+                *setting = ms;
+            }
+        }
+        Ok(tone)
+    }
+
+    /// How many milliseconds to delay the buzzer by, or the picture if
+    /// it's negative (see src/av_sync.rs).
+    pub(crate) fn audio_offset(&self) -> Result<Option<i32>, String> {
+        match self.sound.offset_ms {
+            Some(ms) if ms.abs() > MAX_AUDIO_OFFSET_MS => Err(format!(
+                "Invalid sound offset-ms {}, it must be from -{max} to {max}",
+                ms,
+                max = MAX_AUDIO_OFFSET_MS
+            )),
+            ms => Ok(ms),
+        }
+    }
+
+    /// The keymap profile (the default one if none is set) with any
+    /// keys and gamepad buttons from the config file added.
+    pub(crate) fn keymap(&self) -> Result<Keymap, String> {
+        let mut keymap = Keymap::profile(self.keymap_profile.as_deref().unwrap_or("default"))?;
+        let parse_key = |key: &str| u8::from_str_radix(key, 16).map_err(|_| format!("Invalid keypad key {:?}", key));
+        for (&c, key) in &self.keymap {
+            keymap.set(c, parse_key(key)?)?;
+        }
+        for (pad, buttons) in &self.gamepads {
+            let pad = pad
+                .parse()
+                .ok()
+                .filter(|&pad| pad > 0)
+                .ok_or_else(|| format!("Invalid gamepad number {:?}, they're numbered from 1", pad))?;
+            for (button, key) in buttons {
+                keymap.set_button(pad, button, parse_key(key)?)?;
+            }
+        }
+        Ok(keymap)
+    }
+}
+
+/// `config.toml` in the config directory
+fn default_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("config.toml"))
+}
+
+/// Set `offset-ms` in the `[sound]` section of the config file at
+/// `path`, or the default one, creating it if need be. The rest of the
+/// file, comments and all, is left as it is. Returns the file's path.
+pub(crate) fn save_audio_offset(path: Option<&Path>, offset_ms: i32) -> Result<PathBuf, String> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => default_path().ok_or("Can't find the config directory to save the offset in")?,
+    };
+    let contents = if path.exists() {
+        fs::read_to_string(&path).map_err(|err| format!("Error reading {}: {}", path.display(), err))?
+    } else {
+        String::new()
+    };
+    let contents = set_sound_offset(&contents, offset_ms);
+    // In case the file sets it in a way this doesn't spot, like a dotted
+    // key
+    Settings::parse(&contents).map_err(|err| format!("Can't save the offset to {}: {}", path.display(), err))?;
+    let error = |err: std::io::Error| format!("Error writing {}: {}", path.display(), err);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(error)?;
+    }
+    fs::write(&path, contents).map_err(error)?;
+    Ok(path)
+}
+
+/// `contents` with `offset-ms` set in the top-level `[sound]` section,
+/// replacing the line that sets it if there is one.
+fn set_sound_offset(contents: &str, offset_ms: i32) -> String {
+    let setting = format!("offset-ms = {}", offset_ms);
+    let mut lines: Vec<String> = contents.lines().map(str::to_string).collect();
+    let section = lines.iter().position(|line| line.trim() == "[sound]");
+    match section {
+        Some(header) => {
+            let end = (header + 1..lines.len())
+                .find(|&n| lines[n].trim_start().starts_with('['))
+                .unwrap_or(lines.len());
+            let existing = (header + 1..end).find(|&n| {
+                let line = lines[n].trim_start();
+                line.strip_prefix("offset-ms")
+                    .is_some_and(|rest| rest.trim_start().starts_with('='))
+            });
+            match existing {
+                Some(n) => lines[n] = setting,
+                None => lines.insert(header + 1, setting),
+            }
+        }
+        None => {
+            if lines.last().is_some_and(|line| !line.trim().is_empty()) {
+                lines.push(String::new());
+            }
+            lines.push("[sound]".to_string());
+            lines.push(setting);
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Where each OS keeps settings: `~/.config/chip8` on Linux and the
+/// BSDs, `~/Library/Application Support/chip8` on macOS and
+/// `%APPDATA%\chip8` on Windows, or under `$XDG_CONFIG_HOME` on any of
+/// them if it's set.
+pub(crate) fn config_dir() -> Option<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None if cfg!(windows) => PathBuf::from(env::var_os("APPDATA")?),
+        None if cfg!(target_os = "macos") => PathBuf::from(env::var_os("HOME")?)
+            .join("Library")
+            .join("Application Support"),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("chip8"))
+}
+
+pub(crate) fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[test]
+fn test_settings_for_rom() {
+    let rom = [0x00, 0xE0];
+    let config = format!(
+        "speed = 500\n\
+         scale = 4\n\
+         [quirks]\n\
+         shift-uses-vy = true\n\
+         [sound]\n\
+         waveform = \"sine\"\n\
+         [roms.\"game.ch8\"]\n\
+         speed = 1000\n\
+         sound = {{ pitch = 880 }}\n\
+         colors = {{ foreground = \"#FF0000\", plane2 = \"#00FF00\" }}\n\
+         quirks = {{ shift-uses-vy = false }}\n\
+         save-ram = \"E00-EFF\"\n\
+         [roms.\"{}\"]\n\
+         scale = 6\n",
+        sha256_hex(&rom)
+    );
+
+    let other = Settings::parse(&config).unwrap().for_rom(Path::new("other.ch8"), &[]);
+    assert_eq!((other.speed, other.scale), (Some(500), Some(4)));
+    assert!(other.quirks(Quirks::default()).unwrap().shift_uses_vy);
+    assert_eq!(other.save_ram(), Ok(None));
+
+    let game = Settings::parse(&config)
+        .unwrap()
+        .for_rom(Path::new("roms/game.ch8"), &rom);
+    assert_eq!((game.speed, game.scale), (Some(1000), Some(6)));
+    assert!(!game.quirks(Quirks::default()).unwrap().shift_uses_vy);
+    let colors = game.colors().unwrap();
+    assert_eq!((colors.foreground, colors.plane2), ([0xFF, 0, 0], [0, 0xFF, 0]));
+    let tone = game.tone().unwrap();
+    assert_eq!((tone.waveform, tone.pitch_hz), (chip8::audio::Waveform::Sine, 880.0));
+    assert_eq!(game.save_ram(), SaveRange::parse("E00-EFF").map(Some));
+
+    let config = "[gamepads.1]\ndpad-up = \"1\"\n\
+                  [roms.\"pong.ch8\"]\n\
+                  keymap-profile = \"two-player\"\n\
+                  gamepads = { 2 = { dpad-up = \"C\" } }\n";
+    let keymap = Settings::parse(config)
+        .unwrap()
+        .for_rom(Path::new("pong.ch8"), &[])
+        .keymap()
+        .unwrap();
+    assert_eq!((keymap.get('4'), keymap.get('9')), (None, Some(0xC)));
+    assert_eq!(keymap.get_button(1, "dpad-up"), Some(0x1));
+    assert_eq!(keymap.get_button(2, "dpad-up"), Some(0xC));
+}
+
+#[test]
+fn test_parse_settings_errors() {
+    assert!(Settings::parse("nope = 1").is_err());
+    assert!(Settings::parse("speed = 0").is_err());
+    assert!(Settings::parse("fast-forward = 0").is_err());
+    assert!(Settings::parse("[roms.a]\nscale = 0").is_err());
+    assert!(Settings::parse("[roms.a.roms.b]\nspeed = 1").is_err());
+    assert!(Settings::parse("[roms.a]\nsave-ram = \"EFF-E00\"").is_err());
+    let settings = Settings::parse("[keymap]\nk = \"G\"").unwrap();
+    assert!(settings.keymap().is_err());
+    let settings = Settings::parse("keymap-profile = \"nope\"").unwrap();
+    assert!(settings.keymap().is_err());
+    let settings = Settings::parse("[gamepads.0]\na = \"1\"").unwrap();
+    assert!(settings.keymap().is_err());
+    let settings = Settings::parse("[gamepads.1]\nturbo = \"1\"").unwrap();
+    assert!(settings.keymap().is_err());
+    let settings = Settings::parse("platform = \"nope\"").unwrap();
+    assert!(settings.platform().is_err());
+    let settings = Settings::parse("[sound]\nwaveform = \"sawtooth\"").unwrap();
+    assert!(settings.tone().is_err());
+    let settings = Settings::parse("[sound]\nvolume = 2").unwrap();
+    assert!(settings.tone().is_err());
+    let settings = Settings::parse("[sound]\noffset-ms = -5000").unwrap();
+    assert!(settings.audio_offset().is_err());
+    assert!(Settings::parse("autofire = \"X\"").is_err());
+    assert!(Settings::parse("autofire-hz = 0").is_err());
+    assert!(Settings::parse("[roms.a]\nautofire-hz = 31").is_err());
+    let settings = Settings::parse("autofire = \"5A\"\nautofire-hz = 30").unwrap();
+    assert!(settings.autofire().unwrap().unwrap()[0xA]);
+}
+
+#[test]
+fn test_set_sound_offset() {
+    assert_eq!(set_sound_offset("", -40), "[sound]\noffset-ms = -40\n");
+    assert_eq!(
+        set_sound_offset("speed = 500\n", 20),
+        "speed = 500\n\n[sound]\noffset-ms = 20\n"
+    );
+    // The [roms.x.sound] section is left alone
+    let config = "[sound]\n# Comment\nvolume = 0.5\noffset-ms=10\n[roms.\"a.ch8\".sound]\noffset-ms = 5\n";
+    let updated = set_sound_offset(config, -15);
+    assert_eq!(
+        updated,
+        "[sound]\n# Comment\nvolume = 0.5\noffset-ms = -15\n[roms.\"a.ch8\".sound]\noffset-ms = 5\n"
+    );
+    let settings = Settings::parse(&updated).unwrap();
+    assert_eq!(settings.audio_offset(), Ok(Some(-15)));
+    assert_eq!(
+        set_sound_offset("[sound]\nvolume = 0.5\n", 0),
+        "[sound]\noffset-ms = 0\nvolume = 0.5\n"
+    );
+}
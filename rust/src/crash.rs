@@ -0,0 +1,188 @@
+//! Crash reports. When the processor hits an error the emulator can't
+//! go on from, it writes the VM's state to a file that can be attached
+//! to a bug report: the registers, the call stack, the last instructions
+//! that ran, memory and the display.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chip8::instruction::parse_instruction;
+use chip8::quirks::QUIRK_NAMES;
+use chip8::vm::{fetch, VM};
+
+/// How many instructions a crash report shows leading up to the crash.
+pub(crate) const RECENT_INSTRUCTIONS: usize = 32;
+
+const BYTES_PER_ROW: usize = 16;
+
+/// The last few instructions run, as (cycle, address, opcode). Unlike
+/// `--trace-tail` this only copies a few numbers per instruction, so it
+/// is always on.
+pub(crate) struct RecentInstructions {
+    instructions: VecDeque<(u64, u16, u16)>,
+}
+
+impl RecentInstructions {
+    pub(crate) fn new() -> RecentInstructions {
+        RecentInstructions {
+            instructions: VecDeque::with_capacity(RECENT_INSTRUCTIONS),
+        }
+    }
+
+    /// Remember the instruction the VM is about to run.
+    pub(crate) fn record(&mut self, vm: &VM) {
+        if self.instructions.len() == RECENT_INSTRUCTIONS {
+            self.instructions.pop_front();
+        }
+        let pc = (vm.pc as usize % vm.memory.len()) as u16;
+        self.instructions.push_back((vm.cycles, pc, fetch(&vm.memory, pc)));
+    }
+}
+
+/// The crash report for `error`, which the instruction at the end of
+/// `recent` ran into.
+pub(crate) fn crash_report(vm: &VM, error: &str, rom: &Path, recent: &RecentInstructions) -> String {
+    let mut report = format!(
+        "chip8 {} crash report\n\nError: {}\nROM: {}\nCycle: {}\n",
+        env!("CARGO_PKG_VERSION"),
+        error,
+        rom.display(),
+        vm.cycles
+    );
+
+    report += "\nRegisters:\n";
+    let v: Vec<String> =
+        vm.v.iter()
+            .enumerate()
+            .map(|(i, v)| format!("V{:X}={:02X}", i, v))
+            .collect();
+    report += &format!("  {}\n", v.join(" "));
+    report += &format!(
+        "  PC={:03X} I={:03X} DT={:02X} ST={:02X} SP={}\n",
+        vm.pc,
+        vm.ir,
+        vm.delay_timer,
+        vm.sound_timer,
+        vm.stack.len()
+    );
+    let quirks: Vec<&str> = QUIRK_NAMES
+        .iter()
+        .copied()
+        .filter(|&name| vm.quirks.get(name) == Some(true))
+        .collect();
+    report += &format!(
+        "  Quirks: {}\n",
+        if quirks.is_empty() {
+            "none".to_string()
+        } else {
+            quirks.join(", ")
+        }
+    );
+
+    report += "\nStack (innermost call first):\n";
+    if vm.stack.frames().is_empty() {
+        report += "  (empty)\n";
+    }
+    for frame in vm.stack.frames().iter().rev() {
+        report += &format!(
+            "  {:03X} called subroutine {:03X}, returns to {:03X}\n",
+            frame.call_site,
+            frame.subroutine,
+            frame.return_address()
+        );
+    }
+
+    report += "\nLast executed instructions:\n";
+    for &(cycle, pc, opcode) in &recent.instructions {
+        let decoded = match parse_instruction(opcode) {
+            Ok(instruction) => instruction.to_string(),
+            Err(err) => err,
+        };
+        report += &format!("  {:06} {:03X}: {:04X} {}\n", cycle, pc, opcode, decoded);
+    }
+
+    report += "\nMemory:\n";
+    report += &memory_dump(&vm.memory[..]);
+
+    report += &format!("\nDisplay ({}x{}):\n", vm.display.width(), vm.display.height());
+    report += &vm.display.to_ascii();
+    report
+}
+
+/// Hex dump all of memory. Like `hexdump`, runs of rows that repeat the
+/// row before them are shown as a single `*`.
+fn memory_dump(memory: &[u8]) -> String {
+    let mut dump = String::new();
+    let mut previous: Option<&[u8]> = None;
+    let mut skipping = false;
+    for (row, bytes) in memory.chunks(BYTES_PER_ROW).enumerate() {
+        if previous == Some(bytes) {
+            if !skipping {
+                dump += "*\n";
+                skipping = true;
+            }
+            continue;
+        }
+        skipping = false;
+        previous = Some(bytes);
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        dump += &format!("{:03X}: {}\n", row * BYTES_PER_ROW, hex.join(" "));
+    }
+    dump
+}
+
+/// Where to write a crash report when --crash-report doesn't say.
+pub(crate) fn default_path() -> PathBuf {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    PathBuf::from(format!("chip8-crash-{}.txt", secs))
+}
+
+pub(crate) fn write_crash_report(path: &Path, report: &str) -> Result<(), String> {
+    fs::write(path, report).map_err(|err| format!("Error writing crash report {}: {}", path.display(), err))
+}
+
+#[test]
+fn test_crash_report() {
+    let mut vm = VM::new(Box::new(chip8::random::SeededRandom::new(0)));
+    // Call a subroutine that returns, then return again
+    vm.memory[0x200..0x206].copy_from_slice(&[0x22, 0x04, 0x00, 0xEE, 0x00, 0xEE]);
+    vm.v[3] = 0x2A;
+    let mut recent = RecentInstructions::new();
+    let mut error = None;
+    for _ in 0..4 {
+        recent.record(&vm);
+        if let Err(err) = chip8::vm::processor_cycle(&mut vm) {
+            error = Some(err);
+            break;
+        }
+        vm.cycles += 1;
+    }
+    let error = error.expect("the second return should fail");
+    let report = crash_report(&vm, &error, Path::new("test.ch8"), &recent);
+
+    assert!(report.contains(&format!("Error: {}\n", error)));
+    assert!(report.contains("ROM: test.ch8\n"));
+    assert!(report.contains(" V3=2A "));
+    // The faulting instruction comes last
+    assert!(report.contains("  000002 202: 00EE RET\n\nMemory:"));
+    assert!(report.contains("\n200: 22 04 00 EE 00 EE 00"));
+    // Empty memory collapses to one line
+    assert!(report.contains("\n210: 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00\n*\n"));
+    assert!(report.ends_with(&format!("{}\n", ".".repeat(vm.display.width()))));
+}
+
+#[test]
+fn test_recent_instructions() {
+    let mut vm = VM::new(Box::new(chip8::random::SeededRandom::new(0)));
+    let mut recent = RecentInstructions::new();
+    for cycle in 0..RECENT_INSTRUCTIONS as u64 + 5 {
+        vm.cycles = cycle;
+        recent.record(&vm);
+    }
+    assert_eq!(recent.instructions.len(), RECENT_INSTRUCTIONS);
+    assert_eq!(recent.instructions.front().map(|&(cycle, _, _)| cycle), Some(5));
+}
@@ -0,0 +1,1054 @@
+//! An interactive debugger driven by commands typed into the terminal
+//! that launched the emulator. Commands are read on a separate thread
+//! so the SDL window stays responsive while the VM is paused.
+
+use std::collections::VecDeque;
+use std::io;
+use std::io::{BufRead, Write};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+
+use chip8::instruction::{parse_instruction, Instruction};
+use chip8::vm::{fetch, Memory, SavedState, FONT_MEMORY_START, LARGE_FONT_BYTES, LARGE_FONT_MEMORY_START, VM};
+use chip8::watch::{parse_watch, Hit, Watch};
+
+use crate::condition::Condition;
+use crate::symbols::{parse_range, Symbols};
+
+const HELP: &str = "Commands:
+  s, step [N]     Execute N instructions (default 1) and pause, showing
+                  what changed
+  n, next         Step over: like step, but run a subroutine call to
+                  completion
+  fin, finish     Step out: run until the current subroutine returns
+  sb, back [N]    Undo the last N instructions (default 1)
+  c, continue     Resume execution
+  p, pause        Pause execution
+  r, regs         Show registers
+  bt, backtrace   Show the subroutine call chain
+  m, mem [ADDR]   Show memory around PC and I, or starting at ADDR (hex)
+  m+, m-          Scroll the memory view down/up
+  b, break LOC    Pause before executing the instruction at LOC, a label
+                  or a hex address
+  b LOC if COND   Pause at LOC only when COND holds, e.g. v3 == 0x1F
+  u, until LOC    Run until PC reaches LOC, like a breakpoint that's
+                  deleted as soon as execution pauses
+  b if COND       Pause when COND becomes true, e.g. mem[0x3A0] < 2
+  breaks          List breakpoints
+  unbreak N       Delete breakpoint number N
+  w, watch WATCH  Pause when a register changes (V3, I) or on a write to
+                  memory (300, 300-30F, or a region's name)
+  watches         List watchpoints
+  unwatch N       Delete watchpoint number N
+  annotate RANGE NAME
+                  Name a region of memory (3A0-3A3 score), to show in the
+                  memory view and watchpoint output
+  regions         List named regions
+  cheats          List cheats loaded with --cheats
+  cheat N         Turn cheat number N on or off
+  q, quit         Exit the emulator
+  h, help         Show this message";
+
+/// Number of 16-byte rows shown when viewing memory at an address.
+const MEMORY_VIEW_ROWS: u16 = 8;
+
+const BYTES_PER_ROW: u16 = 16;
+
+/// How much memory the states `back` goes back to can take up. Each
+/// one keeps a copy of memory and the display, so this is over 1000
+/// instructions with 4 KB of memory, but only a little over 200 with
+/// XO-CHIP's 64 KB.
+const HISTORY_BYTES: usize = 16 << 20;
+
+// ANSI escapes used to highlight regions of the memory dump
+const RESET: &str = "\x1b[0m";
+const HIGHLIGHT_PC: &str = "\x1b[7m"; // Reverse video
+const HIGHLIGHT_I: &str = "\x1b[4m"; // Underline
+const HIGHLIGHT_FONT: &str = "\x1b[2m"; // Dim
+const HIGHLIGHT_STACK: &str = "\x1b[33m"; // Yellow
+const HIGHLIGHT_CHANGED: &str = "\x1b[1;32m"; // Bold green
+
+/// How many changed memory bytes to list after a step; the rest are
+/// only counted.
+const MAX_LISTED_WRITES: usize = 8;
+
+pub(crate) struct Debugger {
+    commands: Receiver<String>,
+    paused: bool,
+    /// Set by `quit`, or when stdin is closed while paused, so the run
+    /// can end the usual way
+    quit: bool,
+
+    /// Instructions left to execute before pausing again
+    steps_remaining: u32,
+    /// Set by `next` and `finish`: pause once a return brings the call
+    /// stack down to this depth
+    finish_depth: Option<usize>,
+    /// Set by `until`: a breakpoint that's only kept until the next
+    /// pause, wherever that is
+    run_to: Option<u16>,
+
+    /// Start of the memory view, if it has been scrolled away from PC
+    memory_view: Option<u16>,
+
+    breakpoints: Vec<Breakpoint>,
+    /// Set when execution continues from a breakpoint, so it runs the
+    /// instruction there rather than stopping again straight away
+    resumed_at: Option<u16>,
+
+    /// Labels for breakpoints and messages
+    symbols: Symbols,
+
+    /// The state before the current step command, to show what it did
+    before_step: Option<Snapshot>,
+    /// What the last step changed, highlighted until execution resumes
+    changes: Option<Changes>,
+
+    /// The state before each of the last few instructions, oldest
+    /// first, for stepping backwards
+    history: VecDeque<SavedState>,
+    /// The total size of the states in `history`
+    history_bytes: usize,
+    /// Why `back` isn't allowed, if it isn't, in which case no history
+    /// is kept
+    back_refused: Option<&'static str>,
+    /// Why `cheat` isn't allowed, if it isn't
+    cheats_refused: Option<&'static str>,
+}
+
+impl Debugger {
+    pub(crate) fn new(start_paused: bool, symbols: Symbols) -> Debugger {
+        let (sender, commands) = channel();
+        std::thread::spawn(move || {
+            for line in io::stdin().lock().lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        if start_paused {
+            println!("Paused. Type \"help\" for a list of commands.");
+            prompt();
+        }
+
+        Debugger {
+            commands,
+            paused: start_paused,
+            quit: false,
+            steps_remaining: 0,
+            finish_depth: None,
+            run_to: None,
+            memory_view: None,
+            breakpoints: Vec::new(),
+            resumed_at: None,
+            symbols,
+            before_step: None,
+            changes: None,
+            history: VecDeque::new(),
+            history_bytes: 0,
+            back_refused: None,
+            cheats_refused: None,
+        }
+    }
+
+    /// Refuse `back`, since going back would undo cycles that have
+    /// already been recorded or replayed; `reason` is what's going on,
+    /// like "recording".
+    pub(crate) fn refuse_back(&mut self, reason: &'static str) {
+        self.back_refused = Some(reason);
+        self.history.clear();
+        self.history_bytes = 0;
+    }
+
+    /// Refuse `cheat`, since toggling a cheat pokes memory, which a
+    /// recording wouldn't have; `reason` is like `refuse_back`'s.
+    pub(crate) fn refuse_cheats(&mut self, reason: &'static str) {
+        self.cheats_refused = Some(reason);
+    }
+
+    pub(crate) fn add_breakpoint(&mut self, addr: u16) {
+        let breakpoint = Breakpoint {
+            addr: Some(addr),
+            condition: None,
+            held: false,
+        };
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+    }
+
+    /// Whether the user asked to quit, and the run should end.
+    pub(crate) fn wants_quit(&self) -> bool {
+        self.quit
+    }
+
+    pub(crate) fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Remove the breakpoint without a condition at `addr`, or add one if
+    /// there isn't one.
+    pub(crate) fn toggle_breakpoint(&mut self, addr: u16) {
+        let count = self.breakpoints.len();
+        self.breakpoints
+            .retain(|breakpoint| breakpoint.addr != Some(addr) || breakpoint.condition.is_some());
+        if self.breakpoints.len() == count {
+            self.add_breakpoint(addr);
+        }
+    }
+
+    /// Delete breakpoint number `n`, if there is one.
+    pub(crate) fn remove_breakpoint(&mut self, n: usize) {
+        if n < self.breakpoints.len() {
+            self.breakpoints.remove(n);
+        }
+    }
+
+    /// The breakpoints in the order `breaks` numbers them, with their
+    /// addresses and descriptions.
+    pub(crate) fn breakpoints(&self) -> Vec<(Option<u16>, String)> {
+        self.breakpoints
+            .iter()
+            .map(|breakpoint| (breakpoint.addr, self.describe_breakpoint(breakpoint)))
+            .collect()
+    }
+
+    fn add_conditional_breakpoint(&mut self, addr: Option<u16>, condition: &[&str]) {
+        match Condition::parse(&condition.join(" "), &self.symbols) {
+            Ok(condition) => {
+                self.breakpoints.push(Breakpoint {
+                    addr,
+                    condition: Some(condition),
+                    held: false,
+                });
+                prompt();
+            }
+            Err(err) => error(&err),
+        }
+    }
+
+    /// The first breakpoint that the VM's current state hits, or an
+    /// error from evaluating a condition. Every condition is evaluated
+    /// so that ones without an address know whether they just became
+    /// true.
+    fn hit_breakpoint(&mut self, vm: &VM) -> Option<String> {
+        let mut hit = None;
+        for i in 0..self.breakpoints.len() {
+            let breakpoint = &mut self.breakpoints[i];
+            let holds = match &breakpoint.condition {
+                Some(condition) if breakpoint.addr.is_none_or(|addr| addr == vm.pc) => condition.holds(vm),
+                _ => Ok(breakpoint.addr == Some(vm.pc)),
+            };
+            let held = std::mem::replace(&mut breakpoint.held, holds == Ok(true));
+            let message = match holds {
+                Ok(true) if breakpoint.addr.is_some() || !held => Some(format!(
+                    "Breakpoint: {}",
+                    self.describe_breakpoint(&self.breakpoints[i])
+                )),
+                Ok(_) => None,
+                Err(err) => Some(format!("Breakpoint {}: {}", i, err)),
+            };
+            hit = hit.or(message);
+        }
+        hit.or_else(|| {
+            self.run_to
+                .filter(|&addr| addr == vm.pc)
+                .map(|addr| format!("Reached {}", self.location(addr)))
+        })
+    }
+
+    fn describe_breakpoint(&self, breakpoint: &Breakpoint) -> String {
+        match (breakpoint.addr, &breakpoint.condition) {
+            (Some(addr), Some(condition)) => format!("{} if {}", self.location(addr), condition),
+            (Some(addr), None) => self.location(addr),
+            (None, Some(condition)) => condition.to_string(),
+            (None, None) => "anywhere".to_string(),
+        }
+    }
+
+    pub(crate) fn pause(&mut self, vm: &VM) {
+        self.paused = true;
+        self.steps_remaining = 0;
+        self.finish_depth = None;
+        self.run_to = None;
+        self.memory_view = None;
+        self.changes = self.before_step.take().map(|before| before.diff(vm));
+        println!();
+        print_registers(vm, self.changes.as_ref());
+        if let Some(chain) = vm.stack.call_chain() {
+            println!("Called from {}", chain);
+        }
+        print!("{}", self.memory_dump(vm));
+        if let Some(changes) = &self.changes {
+            print!("{}", changes.describe());
+        }
+        prompt();
+    }
+
+    /// Handle any pending commands. Returns true if the VM should
+    /// execute an instruction this cycle.
+    pub(crate) fn should_run(&mut self, vm: &mut VM) -> bool {
+        loop {
+            match self.commands.try_recv() {
+                Ok(line) => self.handle_command(line.trim(), vm),
+                Err(TryRecvError::Empty) => break,
+                // stdin was closed, so nobody can unpause us
+                Err(TryRecvError::Disconnected) if self.paused && self.steps_remaining == 0 => self.quit = true,
+                Err(TryRecvError::Disconnected) => break,
+            }
+            if self.quit {
+                return false;
+            }
+        }
+        if !self.paused || self.steps_remaining > 0 {
+            let hit = self.hit_breakpoint(vm);
+            if let Some(message) = hit.filter(|_| !self.paused && self.resumed_at != Some(vm.pc)) {
+                println!();
+                println!("{}", message);
+                self.pause(vm);
+            }
+        }
+        let stepping = self.paused && self.steps_remaining > 0;
+        if stepping && self.before_step.is_none() {
+            self.before_step = Some(Snapshot::take(vm));
+        }
+        let run = !self.paused || stepping;
+        if run && self.back_refused.is_none() {
+            let state = vm.save_state();
+            self.history_bytes += state.size();
+            self.history.push_back(state);
+            while self.history_bytes > HISTORY_BYTES {
+                let oldest = self.history.pop_front().expect("the history takes up some bytes");
+                self.history_bytes -= oldest.size();
+            }
+        }
+        run
+    }
+
+    /// Called after each executed instruction so stepping and
+    /// watchpoints can pause again.
+    pub(crate) fn after_cycle(&mut self, vm: &mut VM) {
+        self.resumed_at = None;
+        let hits = vm.watches.take_hits();
+        if !hits.is_empty() {
+            println!();
+            for hit in hits {
+                let name = match hit {
+                    Hit::Write { addr, .. } => self.symbols.name(addr),
+                    _ => None,
+                };
+                match name {
+                    Some(name) => println!("Watchpoint: {} ({})", hit, name),
+                    None => println!("Watchpoint: {}", hit),
+                }
+            }
+            self.pause(vm);
+            return;
+        }
+
+        if self.finish_depth.is_some_and(|depth| vm.stack.len() <= depth) {
+            self.pause(vm);
+            return;
+        }
+        if self.paused && self.steps_remaining > 0 {
+            self.steps_remaining -= 1;
+            if self.steps_remaining == 0 {
+                self.pause(vm);
+            }
+        }
+    }
+
+    fn handle_command(&mut self, line: &str, vm: &mut VM) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] if !self.paused => self.pause(vm),
+            [] => prompt(),
+            ["s"] | ["step"] => self.step(1),
+            ["s", n] | ["step", n] => match n.parse() {
+                Ok(n) if n > 0 => self.step(n),
+                _ => error(&format!("Invalid step count {:?}", n)),
+            },
+            ["n"] | ["next"] => self.step_over(vm),
+            ["fin"] | ["finish"] => match vm.stack.len() {
+                0 => error("Not in a subroutine"),
+                depth => self.finish(vm, depth - 1),
+            },
+            ["sb"] | ["back"] => self.step_back(vm, 1),
+            ["sb", n] | ["back", n] => match n.parse() {
+                Ok(n) if n > 0 => self.step_back(vm, n),
+                _ => error(&format!("Invalid step count {:?}", n)),
+            },
+            ["c"] | ["continue"] => self.resume(vm),
+            ["p"] | ["pause"] => self.pause(vm),
+            ["r"] | ["regs"] => {
+                print_registers(vm, self.changes.as_ref());
+                prompt();
+            }
+            ["bt"] | ["backtrace"] => {
+                print_backtrace(vm);
+                prompt();
+            }
+            ["m"] | ["mem"] => {
+                self.memory_view = None;
+                print!("{}", self.memory_dump(vm));
+                prompt();
+            }
+            ["m", addr] | ["mem", addr] => match parse_address(addr, vm) {
+                Some(addr) => {
+                    self.memory_view = Some(addr - addr % BYTES_PER_ROW);
+                    print!("{}", self.memory_dump(vm));
+                    prompt();
+                }
+                None => error(&format!("Invalid address {:?}", addr)),
+            },
+            ["m+"] => self.scroll(vm, MEMORY_VIEW_ROWS as i32),
+            ["m-"] => self.scroll(vm, -(MEMORY_VIEW_ROWS as i32)),
+            ["b", "if", condition @ ..] | ["break", "if", condition @ ..] => {
+                self.add_conditional_breakpoint(None, condition)
+            }
+            ["b", location, "if", condition @ ..] | ["break", location, "if", condition @ ..] => {
+                match self.symbols.parse_location(location) {
+                    Ok(addr) => self.add_conditional_breakpoint(Some(addr), condition),
+                    Err(err) => error(&err),
+                }
+            }
+            ["b", location] | ["break", location] => match self.symbols.parse_location(location) {
+                Ok(addr) => {
+                    self.add_breakpoint(addr);
+                    prompt();
+                }
+                Err(err) => error(&err),
+            },
+            ["u", location] | ["until", location] => match self.symbols.parse_location(location) {
+                Ok(addr) => self.run_to(vm, addr),
+                Err(err) => error(&err),
+            },
+            ["breaks"] => {
+                for (i, breakpoint) in self.breakpoints.iter().enumerate() {
+                    println!("{}: {}", i, self.describe_breakpoint(breakpoint));
+                }
+                prompt();
+            }
+            ["unbreak", n] => match n.parse().ok().filter(|&n| n < self.breakpoints.len()) {
+                Some(n) => {
+                    self.breakpoints.remove(n);
+                    prompt();
+                }
+                None => error(&format!("No breakpoint {:?}", n)),
+            },
+            ["w", watch] | ["watch", watch] => {
+                let watch = match self.symbols.region_named(watch) {
+                    Some(region) => Ok(Watch::Memory(region.start..=region.end)),
+                    None => parse_watch(watch),
+                };
+                match watch {
+                    Ok(watch) => {
+                        vm.watches.add(watch);
+                        prompt();
+                    }
+                    Err(err) => error(&err),
+                }
+            }
+            ["watches"] => {
+                for (i, watch) in vm.watches.watches().iter().enumerate() {
+                    println!("{}: {}", i, watch);
+                }
+                prompt();
+            }
+            ["unwatch", n] => match n.parse().ok().and_then(|n| vm.watches.remove(n)) {
+                Some(_) => prompt(),
+                None => error(&format!("No watchpoint {:?}", n)),
+            },
+            ["annotate", range, name] => match parse_range(range) {
+                Some((start, end)) => {
+                    self.symbols.add_region(start, end, name.to_string());
+                    prompt();
+                }
+                None => error(&format!("Invalid range {:?}, expected e.g. 3A0-3A3", range)),
+            },
+            ["regions"] => {
+                for region in self.symbols.regions() {
+                    println!("{}", region);
+                }
+                prompt();
+            }
+            ["cheats"] => {
+                for (i, cheat) in vm.cheats.cheats().iter().enumerate() {
+                    println!("{}: {}", i, cheat);
+                }
+                prompt();
+            }
+            ["cheat", _] if self.cheats_refused.is_some() => {
+                error(&format!("Can't toggle cheats while {}", self.cheats_refused.unwrap()))
+            }
+            ["cheat", n] => match n.parse().ok().and_then(|n| vm.toggle_cheat(n)) {
+                Some(enabled) => {
+                    println!("Cheat {} {}", n, if enabled { "on" } else { "off" });
+                    prompt();
+                }
+                None => error(&format!("No cheat {:?}", n)),
+            },
+            ["q"] | ["quit"] => self.quit = true,
+            ["h"] | ["help"] => {
+                println!("{}", HELP);
+                prompt();
+            }
+            _ => error(&format!(
+                "Unknown command {:?}, type \"help\" for a list of commands",
+                line
+            )),
+        }
+    }
+
+    /// An address, with its label if it has one.
+    fn location(&self, addr: u16) -> String {
+        match self.symbols.name(addr) {
+            Some(name) => format!("{:03X} ({})", addr, name),
+            None => format!("{:03X}", addr),
+        }
+    }
+
+    fn step(&mut self, n: u32) {
+        self.paused = true;
+        self.steps_remaining = n;
+        self.finish_depth = None;
+        self.run_to = None;
+    }
+
+    /// Step, running a subroutine call through to its return. The call
+    /// stack's depth tells when the call has returned, so recursive
+    /// calls back to the same return address don't stop it early.
+    fn step_over(&mut self, vm: &VM) {
+        match parse_instruction(fetch(&vm.memory, vm.pc)) {
+            Ok(Instruction::SubroutineCall { .. }) => self.finish(vm, vm.stack.len()),
+            _ => self.step(1),
+        }
+    }
+
+    /// Run until returns bring the call stack down to `depth`, then
+    /// pause, showing what changed on the way. Breakpoints and
+    /// watchpoints still pause it before then.
+    fn finish(&mut self, vm: &VM, depth: usize) {
+        self.resume(vm);
+        self.before_step = Some(Snapshot::take(vm));
+        self.finish_depth = Some(depth);
+    }
+
+    /// Run until PC reaches `addr`, or anything else pauses first.
+    pub(crate) fn run_to(&mut self, vm: &VM, addr: u16) {
+        self.resume(vm);
+        self.run_to = Some(addr);
+    }
+
+    /// Continue execution from where it's paused.
+    fn resume(&mut self, vm: &VM) {
+        self.changes = None;
+        self.paused = false;
+        self.steps_remaining = 0;
+        self.finish_depth = None;
+        self.run_to = None;
+        self.resumed_at = Some(vm.pc);
+    }
+
+    /// Restore the state from `n` instructions ago and pause, showing
+    /// what that undid.
+    fn step_back(&mut self, vm: &mut VM, n: usize) {
+        #[cfg(feature = "megachip")]
+        if vm.megachip.as_ref().is_some_and(|mega| mega.enabled) {
+            error("Can't step back in Mega-Chip mode");
+            return;
+        }
+        if let Some(reason) = self.back_refused {
+            error(&format!("Can't step back while {}", reason));
+            return;
+        }
+        if n > self.history.len() {
+            error(&format!("Can only step back {} instructions", self.history.len()));
+            return;
+        }
+        let start = self.history.len() - n;
+        let undone = self.history.split_off(start);
+        self.history_bytes -= undone.iter().map(SavedState::size).sum::<usize>();
+        let state = undone.into_iter().next().expect("n is at least 1");
+        self.before_step = Some(Snapshot::take(vm));
+        vm.restore_state(&state);
+        self.pause(vm);
+    }
+
+    fn scroll(&mut self, vm: &VM, rows: i32) {
+        let start = self.memory_view.unwrap_or(vm.pc - vm.pc % BYTES_PER_ROW) as i32;
+        let last_row = (vm.memory.len() as i32 / BYTES_PER_ROW as i32 - MEMORY_VIEW_ROWS as i32) * BYTES_PER_ROW as i32;
+        let start = (start + rows * BYTES_PER_ROW as i32).clamp(0, last_row);
+        self.memory_view = Some(start as u16);
+        print!("{}", self.memory_dump(vm));
+        prompt();
+    }
+
+    /// The current memory view: either the scrolled-to region, or a few
+    /// rows around PC and I.
+    fn memory_dump(&self, vm: &VM) -> String {
+        match self.memory_view {
+            Some(start) => memory_dump(vm, start, MEMORY_VIEW_ROWS, &self.symbols, self.changes.as_ref()),
+            None => {
+                let around = |addr: u16| (addr - addr % BYTES_PER_ROW).saturating_sub(BYTES_PER_ROW);
+                let changes = self.changes.as_ref();
+                let symbols = &self.symbols;
+                let mut dump = format!("PC:\n{}", memory_dump(vm, around(vm.pc), 3, symbols, changes));
+                if (vm.ir as usize) < vm.memory.len() {
+                    dump += &format!("I:\n{}", memory_dump(vm, around(vm.ir), 3, symbols, changes));
+                }
+                dump
+            }
+        }
+    }
+}
+
+fn prompt() {
+    print!("(chip8) ");
+    let _ = io::stdout().flush();
+}
+
+fn error(message: &str) {
+    println!("{}", message);
+    prompt();
+}
+
+fn parse_address(s: &str, vm: &VM) -> Option<u16> {
+    let s = s.trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(s, 16)
+        .ok()
+        .filter(|&addr| (addr as usize) < vm.memory.len())
+}
+
+/// Print the registers, highlighting the ones in `changes`.
+fn print_registers(vm: &VM, changes: Option<&Changes>) {
+    let highlight = |changed: bool, text: String| {
+        if changed {
+            format!("{}{}{}", HIGHLIGHT_CHANGED, text, RESET)
+        } else {
+            text
+        }
+    };
+    let changed = |register: Register| changes.is_some_and(|changes| changes.registers.contains(&register));
+    let v: Vec<String> =
+        vm.v.iter()
+            .enumerate()
+            .map(|(i, v)| highlight(changed(Register::V(i)), format!("V{:X}={:02X}", i, v)))
+            .collect();
+    println!("{}", v.join(" "));
+    let others = [
+        format!("PC={:03X}", vm.pc),
+        highlight(changed(Register::I), format!("I={:03X}", vm.ir)),
+        highlight(changed(Register::DelayTimer), format!("DT={:02X}", vm.delay_timer)),
+        highlight(changed(Register::SoundTimer), format!("ST={:02X}", vm.sound_timer)),
+        highlight(
+            changes.is_some_and(|changes| changes.stack.is_some()),
+            format!("SP={}", vm.stack.len()),
+        ),
+    ];
+    println!("{}", others.join(" "));
+}
+
+/// A breakpoint at an address, when a condition holds, or both.
+/// Breakpoints with only a condition pause when it becomes true, rather
+/// than on every instruction while it stays true.
+#[derive(PartialEq)]
+struct Breakpoint {
+    addr: Option<u16>,
+    condition: Option<Condition>,
+    /// Whether the condition held before the last instruction
+    held: bool,
+}
+
+/// The state that stepping can change, other than PC.
+struct Snapshot {
+    v: [u8; 16],
+    ir: u16,
+    delay_timer: u8,
+    sound_timer: u8,
+    /// Return addresses, innermost last
+    stack: Vec<u16>,
+    memory: Memory,
+    /// (width, height, pixels row by row)
+    display: (usize, usize, Vec<bool>),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Register {
+    V(usize),
+    I,
+    DelayTimer,
+    SoundTimer,
+}
+
+/// The difference between a snapshot and a later state.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Changes {
+    registers: Vec<Register>,
+    /// (address, before, after)
+    memory: Vec<(u16, u8, u8)>,
+    /// Return addresses before and after
+    stack: Option<(Vec<u16>, Vec<u16>)>,
+    /// Pixels that were flipped, as (x, y)
+    pixels: Vec<(usize, usize)>,
+    /// Whether the display switched between lo-res and hi-res
+    resolution_changed: bool,
+}
+
+impl Snapshot {
+    fn take(vm: &VM) -> Snapshot {
+        Snapshot {
+            v: vm.v,
+            ir: vm.ir,
+            delay_timer: vm.delay_timer,
+            sound_timer: vm.sound_timer,
+            stack: return_addresses(vm),
+            memory: vm.memory.clone(),
+            display: pixels(vm),
+        }
+    }
+
+    fn diff(&self, vm: &VM) -> Changes {
+        let mut registers: Vec<Register> = (0..16).filter(|&x| self.v[x] != vm.v[x]).map(Register::V).collect();
+        for (changed, register) in [
+            (self.ir != vm.ir, Register::I),
+            (self.delay_timer != vm.delay_timer, Register::DelayTimer),
+            (self.sound_timer != vm.sound_timer, Register::SoundTimer),
+        ] {
+            if changed {
+                registers.push(register);
+            }
+        }
+
+        let stack = return_addresses(vm);
+        let (width, height, before) = &self.display;
+        let (new_width, new_height, after) = pixels(vm);
+        let resolution_changed = (*width, *height) != (new_width, new_height);
+        let pixels = if resolution_changed {
+            Vec::new()
+        } else {
+            (0..before.len())
+                .filter(|&i| before[i] != after[i])
+                .map(|i| (i % width, i / width))
+                .collect()
+        };
+        Changes {
+            registers,
+            memory: (0..vm.memory.len())
+                .filter(|&addr| self.memory[addr] != vm.memory[addr])
+                .map(|addr| (addr as u16, self.memory[addr], vm.memory[addr]))
+                .collect(),
+            stack: (stack != self.stack).then(|| (self.stack.clone(), stack)),
+            pixels,
+            resolution_changed,
+        }
+    }
+}
+
+impl Changes {
+    /// Lines describing the changes to memory, the stack and the
+    /// display. Changed registers are highlighted instead.
+    fn describe(&self) -> String {
+        let mut lines = String::new();
+        if !self.memory.is_empty() {
+            let writes: Vec<String> = self
+                .memory
+                .iter()
+                .take(MAX_LISTED_WRITES)
+                .map(|(addr, before, after)| format!("{:03X}: {:02X} -> {:02X}", addr, before, after))
+                .collect();
+            lines += &format!("Memory: {}", writes.join(", "));
+            if self.memory.len() > MAX_LISTED_WRITES {
+                lines += &format!(" and {} more", self.memory.len() - MAX_LISTED_WRITES);
+            }
+            lines += "\n";
+        }
+        if let Some((before, after)) = &self.stack {
+            let format_stack = |stack: &[u16]| {
+                let addrs: Vec<String> = stack.iter().map(|addr| format!("{:03X}", addr)).collect();
+                format!("[{}]", addrs.join(" "))
+            };
+            lines += &format!("Stack: {} -> {}\n", format_stack(before), format_stack(after));
+        }
+        if self.resolution_changed {
+            lines += "Display: switched resolution\n";
+        } else if !self.pixels.is_empty() {
+            let min_x = self.pixels.iter().map(|&(x, _)| x).min().unwrap_or_default();
+            let max_x = self.pixels.iter().map(|&(x, _)| x).max().unwrap_or_default();
+            let min_y = self.pixels.iter().map(|&(_, y)| y).min().unwrap_or_default();
+            let max_y = self.pixels.iter().map(|&(_, y)| y).max().unwrap_or_default();
+            lines += &format!(
+                "Display: {} pixels flipped in ({}, {})-({}, {})\n",
+                self.pixels.len(),
+                min_x,
+                min_y,
+                max_x,
+                max_y
+            );
+        }
+        lines
+    }
+}
+
+fn return_addresses(vm: &VM) -> Vec<u16> {
+    vm.stack.frames().iter().map(|frame| frame.return_address()).collect()
+}
+
+fn pixels(vm: &VM) -> (usize, usize, Vec<bool>) {
+    let (width, height) = (vm.display.width(), vm.display.height());
+    let pixels = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| vm.display.get_pixel(x, y))
+        .collect();
+    (width, height, pixels)
+}
+
+/// Print the subroutine call chain, innermost call first.
+fn print_backtrace(vm: &VM) {
+    let frames = vm.stack.frames();
+    let current = frames.last().map(|frame| frame.subroutine);
+    match current {
+        Some(subroutine) => println!("#0 {:03X} in subroutine {:03X}", vm.pc, subroutine),
+        None => println!("#0 {:03X} (not in a subroutine)", vm.pc),
+    }
+    for (i, frame) in frames.iter().rev().enumerate() {
+        let caller = frames.len().checked_sub(i + 2).map(|caller| frames[caller].subroutine);
+        match caller {
+            Some(subroutine) => println!("#{} {:03X} in subroutine {:03X}", i + 1, frame.call_site, subroutine),
+            None => println!("#{} {:03X}", i + 1, frame.call_site),
+        }
+    }
+}
+
+/// Hex dump `rows` rows of memory starting at `start`, highlighting
+/// bytes in `changes`, the next instruction, the byte at I, font data
+/// and return addresses on the stack. Rows end with the names of the
+/// regions they overlap.
+pub(crate) fn memory_dump(vm: &VM, start: u16, rows: u16, symbols: &Symbols, changes: Option<&Changes>) -> String {
+    let mut dump = String::new();
+    for row in 0..rows {
+        let row_start = start as usize + (row * BYTES_PER_ROW) as usize;
+        if row_start >= vm.memory.len() {
+            break;
+        }
+        dump += &format!("{:03X}:", row_start);
+        for addr in row_start..row_start + BYTES_PER_ROW as usize {
+            let changed = changes.is_some_and(|changes| {
+                changes
+                    .memory
+                    .binary_search_by_key(&(addr as u16), |&(changed, _, _)| changed)
+                    .is_ok()
+            });
+            let highlight = if changed {
+                HIGHLIGHT_CHANGED
+            } else if addr == vm.pc as usize || addr == vm.pc as usize + 1 {
+                HIGHLIGHT_PC
+            } else if addr == vm.ir as usize {
+                HIGHLIGHT_I
+            } else if vm.stack.frames().iter().any(|frame| {
+                let ret = frame.return_address() as usize;
+                addr == ret || addr == ret + 1
+            }) {
+                HIGHLIGHT_STACK
+            } else if (FONT_MEMORY_START..LARGE_FONT_MEMORY_START + LARGE_FONT_BYTES.len()).contains(&addr) {
+                HIGHLIGHT_FONT
+            } else {
+                ""
+            };
+            if highlight.is_empty() {
+                dump += &format!(" {:02X}", vm.memory[addr]);
+            } else {
+                dump += &format!(" {}{:02X}{}", highlight, vm.memory[addr], RESET);
+            }
+        }
+        let row_end = (row_start + BYTES_PER_ROW as usize - 1) as u16;
+        let regions: Vec<&str> = symbols
+            .regions()
+            .iter()
+            .filter(|region| region.start <= row_end && region.end >= row_start as u16)
+            .map(|region| region.name.as_str())
+            .collect();
+        if !regions.is_empty() {
+            dump += &format!("  {}", regions.join(", "));
+        }
+        dump += "\n";
+    }
+    dump
+}
+
+#[test]
+fn test_memory_dump() {
+    let mut vm = VM::new(Box::new(chip8::random::SeededRandom::new(0)));
+    vm.memory[0x200] = 0x12;
+    vm.memory[0x201] = 0x34;
+    vm.memory[0x210] = 0xAB;
+    let symbols = Symbols::parse("21E-221 score").unwrap();
+    let dump = memory_dump(&vm, 0x200, 2, &symbols, None);
+    let lines: Vec<&str> = dump.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with(&format!(
+        "200: {}12{} {}34{} 00",
+        HIGHLIGHT_PC, RESET, HIGHLIGHT_PC, RESET
+    )));
+    assert!(lines[1].starts_with("210: AB 00"));
+    assert!(lines[1].ends_with(" 00  score"));
+}
+
+#[test]
+fn test_step_changes() {
+    let mut vm = VM::new(Box::new(chip8::random::SeededRandom::new(0)));
+    // V3 := 5, I := 300, save V0-V3, call 20A, I := 303, draw it
+    vm.reload(&[
+        0x63, 0x05, 0xA3, 0x00, 0xF3, 0x55, 0x22, 0x0A, 0x00, 0x00, 0xA3, 0x03, 0xD0, 0x01,
+    ])
+    .unwrap();
+    let before = Snapshot::take(&vm);
+    for _ in 0..6 {
+        chip8::vm::processor_cycle(&mut vm).unwrap();
+    }
+    let changes = before.diff(&vm);
+    assert_eq!(changes.registers, [Register::V(3), Register::I]);
+    assert_eq!(changes.memory, [(0x303, 0x00, 0x05)]);
+    assert_eq!(changes.stack, Some((vec![], vec![0x208])));
+    assert_eq!(changes.pixels.len(), 2);
+    assert_eq!(
+        changes.describe(),
+        "Memory: 303: 00 -> 05\nStack: [] -> [208]\nDisplay: 2 pixels flipped in (5, 0)-(7, 0)\n"
+    );
+    assert_eq!(Snapshot::take(&vm).diff(&vm), Changes::default());
+}
+
+#[test]
+fn test_step_over_and_out() {
+    let mut vm = VM::new(Box::new(chip8::random::SeededRandom::new(0)));
+    // call 206, V1 := 1, loop; 206: V2 := 2, call 20C, return;
+    // 20C: V3 := 3, return
+    vm.reload(&[
+        0x22, 0x06, 0x61, 0x01, 0x12, 0x04, 0x62, 0x02, 0x22, 0x0C, 0x00, 0xEE, 0x63, 0x03, 0x00, 0xEE,
+    ])
+    .unwrap();
+    let mut debugger = Debugger::new(true, Symbols::default());
+    let run = |debugger: &mut Debugger, vm: &mut VM, command: &str| {
+        debugger.handle_command(command, vm);
+        // Stop as soon as it pauses: should_run quits when paused with
+        // stdin closed, as it can be under cargo test
+        for _ in 0..100 {
+            if debugger.paused && debugger.steps_remaining == 0 {
+                break;
+            }
+            if debugger.should_run(vm) {
+                chip8::vm::processor_cycle(vm).unwrap();
+                debugger.after_cycle(vm);
+            }
+        }
+        assert!(debugger.is_paused());
+    };
+
+    // Stepping over the outer call runs both subroutines
+    run(&mut debugger, &mut vm, "next");
+    assert_eq!((vm.pc, vm.v[2], vm.v[3]), (0x202, 2, 3));
+    assert!(vm.stack.is_empty());
+    // Anything else is an ordinary step
+    run(&mut debugger, &mut vm, "next");
+    assert_eq!((vm.pc, vm.v[1]), (0x204, 1));
+    run(&mut debugger, &mut vm, "finish");
+    assert_eq!(vm.pc, 0x204);
+
+    // Step into both calls, then out of the inner one and the outer one
+    vm.pc = 0x200;
+    run(&mut debugger, &mut vm, "step 3");
+    assert_eq!((vm.pc, vm.stack.len()), (0x20C, 2));
+    run(&mut debugger, &mut vm, "finish");
+    assert_eq!((vm.pc, vm.stack.len()), (0x20A, 1));
+    run(&mut debugger, &mut vm, "finish");
+    assert_eq!((vm.pc, vm.stack.len()), (0x202, 0));
+
+    // A breakpoint inside the call stops stepping over it
+    vm.pc = 0x200;
+    debugger.add_breakpoint(0x20C);
+    run(&mut debugger, &mut vm, "next");
+    assert_eq!((vm.pc, vm.stack.len()), (0x20C, 2));
+}
+
+#[test]
+fn test_run_to() {
+    let mut vm = VM::new(Box::new(chip8::random::SeededRandom::new(0)));
+    // V0 += 1, V1 += 1, loop
+    vm.reload(&[0x70, 0x01, 0x71, 0x01, 0x12, 0x00]).unwrap();
+    let mut debugger = Debugger::new(true, Symbols::parse("202 second").unwrap());
+    // As in test_step_over_and_out, stop without asking should_run once
+    // it pauses
+    let run = |debugger: &mut Debugger, vm: &mut VM| {
+        for _ in 0..100 {
+            if debugger.paused && debugger.steps_remaining == 0 {
+                break;
+            }
+            if debugger.should_run(vm) {
+                chip8::vm::processor_cycle(vm).unwrap();
+                debugger.after_cycle(vm);
+            }
+        }
+        assert!(debugger.is_paused());
+    };
+
+    debugger.handle_command("until second", &mut vm);
+    run(&mut debugger, &mut vm);
+    assert_eq!((vm.pc, vm.v[0], vm.v[1]), (0x202, 1, 0));
+    assert!(debugger.breakpoints().is_empty());
+
+    // Running to where it already is goes round the loop once
+    debugger.run_to(&vm, 0x202);
+    run(&mut debugger, &mut vm);
+    assert_eq!((vm.pc, vm.v[0], vm.v[1]), (0x202, 2, 1));
+
+    // Pausing somewhere else first forgets it
+    debugger.add_breakpoint(0x204);
+    debugger.run_to(&vm, 0x200);
+    run(&mut debugger, &mut vm);
+    assert_eq!(vm.pc, 0x204);
+    debugger.remove_breakpoint(0);
+    debugger.add_breakpoint(0x202);
+    debugger.handle_command("continue", &mut vm);
+    run(&mut debugger, &mut vm);
+    assert_eq!(vm.pc, 0x202);
+}
+
+#[test]
+fn test_step_back() {
+    let mut vm = VM::new(Box::new(chip8::random::SeededRandom::new(0)));
+    vm.set_memory_bytes(chip8::vm::XO_CHIP_MEMORY_BYTES);
+    // V0 += 1, loop
+    vm.reload(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+    let mut debugger = Debugger::new(false, Symbols::default());
+    for _ in 0..1000 {
+        if debugger.should_run(&mut vm) {
+            chip8::vm::processor_cycle(&mut vm).unwrap();
+            debugger.after_cycle(&mut vm);
+        }
+    }
+    assert_eq!(vm.v[0], 244);
+
+    // Only as many states as fit in HISTORY_BYTES are kept
+    let size = vm.save_state().size();
+    assert_eq!(debugger.history.len(), HISTORY_BYTES / size);
+    assert_eq!(debugger.history_bytes, debugger.history.len() * size);
+
+    debugger.handle_command("back 4", &mut vm);
+    assert_eq!((vm.pc, vm.v[0]), (0x200, 242));
+    assert_eq!(debugger.history_bytes, debugger.history.len() * size);
+
+    debugger.refuse_back("recording");
+    debugger.handle_command("back", &mut vm);
+    assert_eq!((vm.pc, vm.v[0]), (0x200, 242));
+}
+
+#[test]
+fn test_refuse_cheats() {
+    let mut vm = VM::new(Box::new(chip8::random::SeededRandom::new(0)));
+    vm.cheats = chip8::cheats::CheatTable::new(chip8::cheats::parse_cheats("lives always 3A0=09 off").unwrap());
+    let mut debugger = Debugger::new(false, Symbols::default());
+    debugger.refuse_cheats("replaying");
+    debugger.handle_command("cheat 0", &mut vm);
+    assert!(!vm.cheats.cheats()[0].enabled);
+    assert_eq!(vm.memory[0x3A0], 0);
+}
@@ -0,0 +1,68 @@
+//! Caches decoded instructions by address, so that running the same
+//! code over and over doesn't decode it every time. Programs can modify
+//! their own code, so entries are dropped when memory is written.
+
+use crate::instruction::{parse_instruction, Instruction};
+use crate::vm::{fetch, MEMORY_BYTES};
+
+pub struct DecodeCache {
+    entries: Box<[Option<Instruction>]>,
+}
+
+impl Default for DecodeCache {
+    fn default() -> DecodeCache {
+        DecodeCache::new(MEMORY_BYTES)
+    }
+}
+
+impl DecodeCache {
+    /// A cache for a VM with `memory_bytes` of memory.
+    pub fn new(memory_bytes: usize) -> DecodeCache {
+        DecodeCache {
+            entries: vec![None; memory_bytes].into_boxed_slice(),
+        }
+    }
+
+    /// The instruction at `addr`, decoding it if it isn't cached.
+    /// Invalid instructions aren't cached.
+    pub fn decode(&mut self, addr: u16, memory: &[u8]) -> Result<Instruction, String> {
+        let entry = &mut self.entries[addr as usize % memory.len()];
+        if let Some(instruction) = *entry {
+            return Ok(instruction);
+        }
+        let instruction = parse_instruction(fetch(memory, addr))?;
+        *entry = Some(instruction);
+        Ok(instruction)
+    }
+
+    /// Forget any instruction that includes the byte at `addr`, i.e.
+    /// the ones starting at `addr` and the byte before it.
+    pub fn invalidate(&mut self, addr: usize) {
+        let len = self.entries.len();
+        self.entries[addr % len] = None;
+        self.entries[(addr + len - 1) % len] = None;
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.iter_mut().for_each(|entry| *entry = None);
+    }
+}
+
+#[test]
+fn test_decode_cache() {
+    let mut memory = [0; MEMORY_BYTES];
+    memory[0x200..0x204].copy_from_slice(&[0x00, 0xE0, 0x12, 0x00]);
+    let mut cache = DecodeCache::new(MEMORY_BYTES);
+    assert_eq!(cache.decode(0x200, &memory), Ok(Instruction::ClearScreen));
+
+    // Stale until invalidated
+    memory[0x201] = 0xEE;
+    assert_eq!(cache.decode(0x200, &memory), Ok(Instruction::ClearScreen));
+    cache.invalidate(0x201);
+    assert_eq!(cache.decode(0x200, &memory), Ok(Instruction::SubroutineReturn));
+
+    memory[0x202] = 0x13;
+    cache.decode(0x202, &memory).unwrap();
+    cache.invalidate(0x202);
+    assert_eq!(cache.decode(0x202, &memory), Ok(Instruction::Jump { nnn: 0x300 }));
+}
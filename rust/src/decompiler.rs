@@ -0,0 +1,148 @@
+//! `chip8 decompile`: turn a ROM back into Octo source (see `octo.rs`).
+//! Unlike `chip8 disasm`, code is told apart from data by following
+//! every path through the program from the entry point, so:
+//!
+//! - jump and call targets get labels (`label_20A`, `sub_2A0`), and
+//!   `i :=` targets do too (`data_300`), or the names from the ROM's
+//!   symbol file if it has one
+//! - bytes that no path executes are data, written as binary with the
+//!   pixels drawn in a comment, since they're usually sprites
+//!
+//! Paths through `jump0` can't be followed, so code only reached that
+//! way comes out as data.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chip8::instruction::Instruction;
+
+use crate::analysis::{flow, Flow, Program, ROM_START};
+use crate::symbols::Symbols;
+
+/// Prefixes of the names given to addresses, in order of preference
+/// when an address is used in more than one way.
+const NAME_PREFIXES: [&str; 4] = ["sub", "label", "table", "data"];
+
+pub(crate) fn decompile(rom: &[u8], symbols: &Symbols) -> String {
+    // Find the code, and name the addresses it refers to
+    let program = Program::new(rom);
+    let code = program.reachable();
+    let mut names: BTreeMap<u16, String> = BTreeMap::new();
+    for &addr in &code {
+        let instruction = program.fetch(addr).expect("reachable code is valid");
+        match (flow(&instruction), instruction) {
+            (Flow::Jump(target), _) => name(&mut names, target, "label"),
+            (Flow::Call(target), _) => name(&mut names, target, "sub"),
+            (Flow::Computed(target), _) => name(&mut names, target, "table"),
+            (_, Instruction::SetIndexNnn { nnn }) => name(&mut names, nnn, "data"),
+            _ => {}
+        }
+    }
+    names.insert(ROM_START, "main".to_string());
+
+    // Lay out the ROM as instructions and data bytes. Only addresses
+    // at the start of one can be labelled.
+    let mut items = Vec::new();
+    let mut addr = ROM_START;
+    while addr < program.end() {
+        let is_code = code.contains(&addr);
+        items.push((addr, is_code));
+        addr += if is_code { 2 } else { 1 };
+    }
+    let starts: BTreeSet<u16> = items.iter().map(|&(addr, _)| addr).collect();
+    let labels: BTreeMap<u16, String> = names
+        .into_iter()
+        .filter(|(addr, _)| starts.contains(addr))
+        .map(|(addr, name)| (addr, symbols.label(addr).map_or(name, str::to_string)))
+        .collect();
+
+    let mut source = String::from("# Decompiled by chip8 decompile\n");
+    for (addr, is_code) in items {
+        if let Some(label) = labels.get(&addr) {
+            source += &format!("\n: {}\n", label);
+        }
+        if is_code {
+            if let Some(instruction) = program.fetch(addr) {
+                source += &format!("\t{}\n", statement(&instruction, &labels));
+            }
+        } else {
+            let byte = program.byte(addr);
+            let pixels: String = (0..8)
+                .map(|bit| if byte >> (7 - bit) & 1 == 1 { '#' } else { '.' })
+                .collect();
+            source += &format!("\t0b{:08b} # {}\n", byte, pixels);
+        }
+    }
+    source
+}
+
+/// Name `addr` with `prefix`, unless it already has a name with a
+/// preferred prefix.
+fn name(names: &mut BTreeMap<u16, String>, addr: u16, prefix: &str) {
+    let rank = |prefix: &str| NAME_PREFIXES.iter().position(|&p| p == prefix);
+    let better = match names.get(&addr) {
+        Some(old) => rank(prefix) < rank(old.split('_').next().unwrap_or_default()),
+        None => true,
+    };
+    if better {
+        names.insert(addr, format!("{}_{:03X}", prefix, addr));
+    }
+}
+
+/// The Octo statement for an instruction. Skips become `if ... then`
+/// with the opposite condition, which skips the next statement in the
+/// same cases.
+fn statement(instruction: &Instruction, labels: &BTreeMap<u16, String>) -> String {
+    let address = |nnn: u16| labels.get(&nnn).cloned().unwrap_or_else(|| format!("0x{:03X}", nnn));
+    match *instruction {
+        Instruction::Jump { nnn } => format!("jump {}", address(nnn)),
+        Instruction::SubroutineCall { nnn } => match labels.get(&nnn) {
+            Some(label) => label.clone(),
+            None => format!(":call 0x{:03X}", nnn),
+        },
+        Instruction::JumpV0Nnn { nnn } => format!("jump0 {}", address(nnn)),
+        Instruction::SetIndexNnn { nnn } => format!("i := {}", address(nnn)),
+        _ => format!("{:#}", instruction),
+    }
+}
+
+#[test]
+fn test_decompile() {
+    let rom = [
+        0x00, 0xE0, // 200: clear
+        0xA2, 0x0C, // 202: i := sprite
+        0x22, 0x0A, // 204: call 20A
+        0x12, 0x06, // 206: loop forever
+        0x00, 0x00, // 208: never executed
+        0xD0, 0x11, // 20A: draw
+        0x00, 0xEE, // 20C: return, also the sprite
+    ];
+    let source = decompile(&rom, &Symbols::default());
+    assert_eq!(
+        source,
+        "# Decompiled by chip8 decompile
+
+: main
+\tclear
+\ti := data_20C
+\tsub_20A
+
+: label_206
+\tjump label_206
+\t0b00000000 # ........
+\t0b00000000 # ........
+
+: sub_20A
+\tsprite v0 v1 1
+
+: data_20C
+\treturn
+"
+    );
+
+    // The output assembles back into the same ROM
+    let assembled = crate::octo::assemble_octo(&[(None, &source)]).unwrap();
+    assert_eq!(assembled.rom, rom);
+
+    let symbols = Symbols::parse("20A draw").unwrap();
+    assert!(decompile(&rom, &symbols).contains(": draw\n"));
+}
@@ -0,0 +1,82 @@
+//! `chip8 disasm`: print each two-byte word of a ROM alongside the
+//! instruction it decodes to. Code and data aren't told apart, so
+//! sprite data shows up as (often invalid) instructions. If the ROM has
+//! symbols, labels and named regions are shown where they start and in
+//! place of the addresses they name.
+
+use chip8::instruction::{parse_instruction, Instruction};
+
+use crate::symbols::Symbols;
+
+/// ROMs are loaded at this address, so listings start here.
+const ROM_START: usize = 0x200;
+
+pub(crate) fn disassemble(rom: &[u8], symbols: &Symbols) -> String {
+    let mut listing = String::new();
+    for (i, word) in rom.chunks(2).enumerate() {
+        let addr = ROM_START + i * 2;
+        if let Some(label) = symbols.label(addr as u16) {
+            listing += &format!("{}:\n", label);
+        }
+        // A region can start at either byte of the word
+        for region in symbols.regions() {
+            if (addr..addr + word.len()).contains(&(region.start as usize)) {
+                listing += &format!("{} ({:03X}-{:03X}):\n", region.name, region.start, region.end);
+            }
+        }
+        let line = match *word {
+            [hi, lo] => {
+                let raw = (hi as u16) << 8 | lo as u16;
+                match parse_instruction(raw) {
+                    Ok(instruction) => format!("{:03X}: {:04X}  {}", addr, raw, describe(&instruction, symbols)),
+                    Err(_) => format!("{:03X}: {:04X}  (data)", addr, raw),
+                }
+            }
+            // A trailing odd byte can only be data
+            [byte] => format!("{:03X}: {:02X}    (data)", addr, byte),
+            _ => unreachable!(),
+        };
+        listing += &line;
+        listing += "\n";
+    }
+    listing
+}
+
+/// The instruction, with the address's name in place of it if it has
+/// one.
+pub(crate) fn describe(instruction: &Instruction, symbols: &Symbols) -> String {
+    let description = instruction.to_string();
+    let nnn = match *instruction {
+        Instruction::Jump { nnn }
+        | Instruction::JumpV0Nnn { nnn }
+        | Instruction::SubroutineCall { nnn }
+        | Instruction::SetIndexNnn { nnn } => nnn,
+        _ => return description,
+    };
+    match symbols.name(nnn) {
+        Some(name) => description.replace(&format!("0x{:03X}", nnn), &name),
+        None => description,
+    }
+}
+
+#[test]
+fn test_disassemble() {
+    let listing = disassemble(&[0x00, 0xE0, 0x12, 0x00, 0xFF], &Symbols::default());
+    let lines: Vec<&str> = listing.lines().collect();
+    assert_eq!(lines, ["200: 00E0  CLS", "202: 1200  JP 0x200", "204: FF    (data)"]);
+}
+
+#[test]
+fn test_disassemble_with_symbols() {
+    let symbols = Symbols::parse("202 main_loop").unwrap();
+    let listing = disassemble(&[0x00, 0xE0, 0x12, 0x02], &symbols);
+    assert_eq!(listing, "200: 00E0  CLS\nmain_loop:\n202: 1202  JP main_loop\n");
+
+    let symbols = Symbols::parse("205-207 sprite").unwrap();
+    let listing = disassemble(&[0xA2, 0x06, 0x00, 0xE0, 0x00, 0xFA, 0x81], &symbols);
+    assert_eq!(
+        listing,
+        "200: A206  LD I, sprite+1\n202: 00E0  CLS\n\
+         sprite (205-207):\n204: 00FA  (data)\n206: 81    (data)\n"
+    );
+}
@@ -1,86 +1,614 @@
-pub(crate) const DISPLAY_WIDTH_PX: usize = 64;
-pub(crate) const DISPLAY_HEIGHT_PX: usize = 32;
-pub(crate) const PIXEL_SCALE_FACTOR: usize = 8;
+use std::ops::Range;
 
-pub(crate) struct Display {
-    pixels: [[bool; DISPLAY_HEIGHT_PX]; DISPLAY_WIDTH_PX],
-    canvas: sdl2::render::Canvas<sdl2::video::Window>,
+pub const DISPLAY_WIDTH_PX: usize = 64;
+pub const DISPLAY_HEIGHT_PX: usize = 32;
+
+/// The height of the display in the two-page hi-res mode some early
+/// ROMs use (see `vm::is_hires_rom`).
+pub const HIRES_DISPLAY_HEIGHT_PX: usize = 64;
+
+/// The size of the SUPER-CHIP and XO-CHIP hi-res display (see
+/// `set_schip_hires`).
+pub const SCHIP_HIRES_WIDTH_PX: usize = 128;
+pub const SCHIP_HIRES_HEIGHT_PX: usize = 64;
+
+/// Both of the display's planes, as a bit mask (see
+/// `Display::set_planes`).
+const PLANES_MASK: u8 = 0b11;
+
+/// The CHIP-8 frame buffer. This is independent of any frontend so the
+/// VM can run without a window (e.g. in tests or in a browser).
+///
+/// Its size depends on the mode: 64x32 normally, 64x64 in the two-page
+/// hi-res mode and 128x64 in SUPER-CHIP's hi-res mode. Switching mode
+/// clears the display rather than scaling the picture to the new size.
+/// SUPER-CHIP 1.1 left the picture on the screen, but modern SUPER-CHIP
+/// interpreters and XO-CHIP clear it, and programs clear the screen
+/// after switching anyway.
+///
+/// Like XO-CHIP's, the display has two bit planes, so each pixel is one
+/// of four colors. Drawing, clearing and scrolling only touch the
+/// selected planes, which are just the first unless a program selects
+/// others, so other programs only ever use two colors.
+#[derive(Clone)]
+pub struct Display {
+    /// Row by row, as bit masks of the planes each pixel is lit in
+    pixels: Vec<u8>,
+    width: usize,
+    height: usize,
+
+    /// The selected planes, as a bit mask
+    planes: u8,
+
+    /// Whether scrolling in lo-res moves half as far, as on SUPER-CHIP
+    /// 1.1, which scrolled by hi-res pixels whatever the mode. See
+    /// `set_half_scroll`.
+    half_scroll: bool,
 
     /// Set to `true` when the display is modified and we need to
     /// repaint the canvas.
     needs_repaint: bool,
+
+    /// What changed since the last `take_damage`
+    damage: DamageRegion,
+}
+
+/// The part of the display that changed, as the smallest rectangle of
+/// pixels holding every change (see `Display::take_damage`). Empty if
+/// nothing changed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DamageRegion {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl DamageRegion {
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// The rows the region covers.
+    pub fn rows(&self) -> Range<usize> {
+        if self.is_empty() {
+            0..0
+        } else {
+            self.y..self.y + self.height
+        }
+    }
+
+    /// Grow the region to take in the pixel at (x, y).
+    fn add(&mut self, x: usize, y: usize) {
+        if self.is_empty() {
+            *self = DamageRegion {
+                x,
+                y,
+                width: 1,
+                height: 1,
+            };
+            return;
+        }
+        let (right, bottom) = ((self.x + self.width).max(x + 1), (self.y + self.height).max(y + 1));
+        self.x = self.x.min(x);
+        self.y = self.y.min(y);
+        self.width = right - self.x;
+        self.height = bottom - self.y;
+    }
+}
+
+/// Displays are equal if they're showing the same picture in the same
+/// mode, with the same planes selected, however they got there.
+impl PartialEq for Display {
+    fn eq(&self, other: &Display) -> bool {
+        (self.width, self.height, self.planes, self.half_scroll)
+            == (other.width, other.height, other.planes, other.half_scroll)
+            && self.pixels == other.pixels
+    }
+}
+
+impl Default for Display {
+    fn default() -> Display {
+        Display::new()
+    }
 }
 
 impl Display {
-    pub(crate) fn new() -> Display {
+    pub fn new() -> Display {
         Display {
-            pixels: [[false; DISPLAY_HEIGHT_PX]; DISPLAY_WIDTH_PX],
+            pixels: vec![0; DISPLAY_WIDTH_PX * DISPLAY_HEIGHT_PX],
+            width: DISPLAY_WIDTH_PX,
+            height: DISPLAY_HEIGHT_PX,
+            planes: 1,
+            half_scroll: false,
             needs_repaint: false,
-            canvas: create_sdl_window(),
+            // Nothing has drawn it yet
+            damage: DamageRegion {
+                x: 0,
+                y: 0,
+                width: DISPLAY_WIDTH_PX,
+                height: DISPLAY_HEIGHT_PX,
+            },
         }
     }
 
-    pub(crate) fn clear(&mut self) {
-        for i in 0..DISPLAY_WIDTH_PX {
-            for j in 0..DISPLAY_HEIGHT_PX {
-                self.pixels[i][j] = false;
+    /// A blank display of the given size, which must be the size of
+    /// one of the modes.
+    pub fn with_size(width: usize, height: usize) -> Result<Display, String> {
+        let mut display = Display::new();
+        match (width, height) {
+            (DISPLAY_WIDTH_PX, DISPLAY_HEIGHT_PX) => {}
+            (DISPLAY_WIDTH_PX, HIRES_DISPLAY_HEIGHT_PX) => display.set_hires(true),
+            (SCHIP_HIRES_WIDTH_PX, SCHIP_HIRES_HEIGHT_PX) => display.set_schip_hires(true),
+            _ => {
+                return Err(format!(
+                    "The image is {}x{}, but the display is {}x{}, or {}x{} or {}x{} in hi-res mode",
+                    width,
+                    height,
+                    DISPLAY_WIDTH_PX,
+                    DISPLAY_HEIGHT_PX,
+                    DISPLAY_WIDTH_PX,
+                    HIRES_DISPLAY_HEIGHT_PX,
+                    SCHIP_HIRES_WIDTH_PX,
+                    SCHIP_HIRES_HEIGHT_PX
+                ))
             }
         }
+        Ok(display)
+    }
+
+    /// Row by row, a byte per pixel: a bit mask of the planes it's lit
+    /// in, bit 0 for the first.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Switch between the normal 64x32 display and the 64x64 hi-res
+    /// one. This clears the display.
+    pub fn set_hires(&mut self, hires: bool) {
+        let height = if hires {
+            HIRES_DISPLAY_HEIGHT_PX
+        } else {
+            DISPLAY_HEIGHT_PX
+        };
+        self.resize(DISPLAY_WIDTH_PX, height);
+    }
+
+    /// Switch between the normal 64x32 display and SUPER-CHIP's 128x64
+    /// hi-res one (00FE and 00FF). This clears the display.
+    pub fn set_schip_hires(&mut self, hires: bool) {
+        if hires {
+            self.resize(SCHIP_HIRES_WIDTH_PX, SCHIP_HIRES_HEIGHT_PX);
+        } else {
+            self.resize(DISPLAY_WIDTH_PX, DISPLAY_HEIGHT_PX);
+        }
+    }
+
+    /// Whether this is the normal 64x32 display.
+    pub fn is_lores(&self) -> bool {
+        (self.width, self.height) == (DISPLAY_WIDTH_PX, DISPLAY_HEIGHT_PX)
+    }
+
+    fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.pixels = vec![0; width * height];
+        self.damage_all();
+    }
+
+    /// Select the planes that drawing, clearing and scrolling affect,
+    /// as a bit mask (XO-CHIP's FN01).
+    pub fn set_planes(&mut self, planes: u8) {
+        self.planes = planes & PLANES_MASK;
+    }
+
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    /// Clear the selected planes.
+    pub fn clear(&mut self) {
+        let keep = !self.planes;
+        self.pixels.iter_mut().for_each(|pixel| *pixel &= keep);
+        self.damage_all();
+    }
+
+    /// Whether the pixel is lit in any plane.
+    pub fn get_pixel(&self, x: usize, y: usize) -> bool {
+        self.pixels[y * self.width + x] != 0
+    }
+
+    /// Light or clear the pixel in the selected planes.
+    pub fn set_pixel(&mut self, x: usize, y: usize, val: bool) {
+        let planes = if val {
+            self.pixels[y * self.width + x] | self.planes
+        } else {
+            self.pixels[y * self.width + x] & !self.planes
+        };
+        self.set_pixel_planes(x, y, planes);
+    }
+
+    /// The planes the pixel is lit in, as a bit mask, which is its
+    /// color: 0 for the background, 1 for the first plane, 2 for the
+    /// second and 3 for both.
+    pub fn pixel_planes(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set_pixel_planes(&mut self, x: usize, y: usize, planes: u8) {
+        let pixel = &mut self.pixels[y * self.width + x];
+        if *pixel != planes & PLANES_MASK {
+            *pixel = planes & PLANES_MASK;
+            self.damage.add(x, y);
+        }
         self.needs_repaint = true;
     }
 
-    pub(crate) fn get_pixel(&self, x: usize, y: usize) -> bool {
-        self.pixels[x][y]
+    /// Make lo-res scrolls move half as far (the `half-scroll` quirk).
+    /// Half a lo-res pixel can't be shown, so odd distances round down.
+    pub fn set_half_scroll(&mut self, half_scroll: bool) {
+        self.half_scroll = half_scroll;
+    }
+
+    /// Scroll the display down `n` rows (SUPER-CHIP's 00CN).
+    pub fn scroll_down(&mut self, n: usize) {
+        let n = self.scroll_distance(n);
+        self.shift(0, n as isize);
+    }
+
+    /// Scroll the display up `n` rows (XO-CHIP's 00DN).
+    pub fn scroll_up(&mut self, n: usize) {
+        let n = self.scroll_distance(n);
+        self.shift(0, -(n as isize));
+    }
+
+    /// Scroll the display 4 pixels left (SUPER-CHIP's 00FC).
+    pub fn scroll_left(&mut self) {
+        let n = self.scroll_distance(4);
+        self.shift(-(n as isize), 0);
     }
 
-    pub(crate) fn set_pixel(&mut self, x: usize, y: usize, val: bool) {
-        self.pixels[x][y] = val;
+    /// Scroll the display 4 pixels right (SUPER-CHIP's 00FB).
+    pub fn scroll_right(&mut self) {
+        let n = self.scroll_distance(4);
+        self.shift(n as isize, 0);
+    }
+
+    fn scroll_distance(&self, n: usize) -> usize {
+        if self.half_scroll && self.is_lores() {
+            n / 2
+        } else {
+            n
+        }
+    }
+
+    /// Move every pixel in the selected planes by (dx, dy). Pixels
+    /// moved off the screen are lost, and the ones uncovered are off.
+    fn shift(&mut self, dx: isize, dy: isize) {
+        let (width, height) = (self.width as isize, self.height as isize);
+        // Only the selected planes move
+        let mut pixels: Vec<u8> = self.pixels.iter().map(|pixel| pixel & !self.planes).collect();
+        for y in 0..height {
+            for x in 0..width {
+                let (from_x, from_y) = (x - dx, y - dy);
+                if (0..width).contains(&from_x) && (0..height).contains(&from_y) {
+                    pixels[(y * width + x) as usize] |= self.pixels[(from_y * width + from_x) as usize] & self.planes;
+                }
+            }
+        }
+        self.pixels = pixels;
+        self.damage_all();
+    }
+
+    /// Replace the contents with `other`'s, e.g. when restoring a saved
+    /// state.
+    pub fn copy_from(&mut self, other: &Display) {
+        self.pixels.clone_from(&other.pixels);
+        self.width = other.width;
+        self.height = other.height;
+        self.planes = other.planes;
+        self.damage_all();
+    }
+
+    /// Returns true if the display has changed since the last call to
+    /// `mark_painted`.
+    pub fn needs_repaint(&self) -> bool {
+        self.needs_repaint
+    }
+
+    /// Called by frontends once they have drawn the current contents.
+    pub fn mark_painted(&mut self) {
+        self.needs_repaint = false;
+    }
+
+    /// What changed since the last call, so a frontend can redraw just
+    /// that part. This is kept apart from `needs_repaint`, so frontends
+    /// that redraw everything don't affect it.
+    pub fn take_damage(&mut self) -> DamageRegion {
+        std::mem::take(&mut self.damage)
+    }
+
+    fn damage_all(&mut self) {
+        self.damage = DamageRegion {
+            x: 0,
+            y: 0,
+            width: self.width,
+            height: self.height,
+        };
         self.needs_repaint = true;
     }
 
-    pub(crate) fn paint(&mut self) {
-        if !self.needs_repaint {
-            return;
+    /// Each row's pixels' planes.
+    fn rows(&self) -> impl Iterator<Item = impl Iterator<Item = u8> + '_> + '_ {
+        (0..self.height()).map(move |y| (0..self.width()).map(move |x| self.pixel_planes(x, y)))
+    }
+
+    /// The display as text, one line per row, with `#` for pixels that
+    /// are on in any plane and `.` for those that are off.
+    pub fn to_ascii(&self) -> String {
+        let mut text = String::with_capacity((self.width() + 1) * self.height());
+        for row in self.rows() {
+            text.extend(row.map(|planes| if planes != 0 { '#' } else { '.' }));
+            text.push('\n');
         }
+        text
+    }
 
-        self.canvas.set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
-        self.canvas.clear();
+    /// The display as RGBA bytes, row by row, white on black, with the
+    /// second plane dark gray and both planes together light gray.
+    pub fn to_rgba_bytes(&self) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(self.width() * self.height() * 4);
+        for planes in self.rows().flatten() {
+            let value = [0, 255, 85, 170][planes as usize];
+            rgba.extend_from_slice(&[value, value, value, 255]);
+        }
+        rgba
+    }
 
-        self.canvas.set_draw_color(sdl2::pixels::Color::RGB(255, 255, 255)); // White
+    /// The display as a plain (P1) PBM image, which is text that diffs
+    /// well and that most image viewers open. Lit pixels are 1s, which
+    /// PBM shows as black.
+    pub fn to_pbm(&self) -> String {
+        let mut pbm = format!("P1\n{} {}\n", self.width(), self.height());
+        for row in self.rows() {
+            pbm.extend(row.map(|planes| if planes != 0 { '1' } else { '0' }));
+            pbm.push('\n');
+        }
+        pbm
+    }
 
-        for i in 0..DISPLAY_WIDTH_PX {
-            for j in 0..DISPLAY_HEIGHT_PX {
-                if self.pixels[i][j] {
-                    let rect = sdl2::rect::Rect::new(
-                        (i * PIXEL_SCALE_FACTOR) as i32, // x
-                        (j * PIXEL_SCALE_FACTOR) as i32, // y
-                        PIXEL_SCALE_FACTOR as u32,       // width
-                        PIXEL_SCALE_FACTOR as u32,       // height
-                    );
-                    if let Err(err) = self.canvas.fill_rect(rect) {
-                        eprintln!("Error drawing rectangle {:?}: {}", rect, err);
-                        std::process::exit(1);
+    /// Read a PBM image, in the plain (P1) format `to_pbm` writes or the
+    /// raw (P4) one other programs often do. It must be the size of the
+    /// normal or the hi-res display.
+    pub fn from_pbm(pbm: &[u8]) -> Result<Display, String> {
+        let mut pos = 0;
+        let raw = match pbm_token(pbm, &mut pos) {
+            Some(b"P1") => false,
+            Some(b"P4") => true,
+            _ => return Err("Not a PBM image".to_string()),
+        };
+        let mut size = [0; 2];
+        for n in &mut size {
+            *n = pbm_token(pbm, &mut pos)
+                .and_then(|token| std::str::from_utf8(token).ok()?.parse().ok())
+                .ok_or("Invalid PBM header")?;
+        }
+        let [width, height] = size;
+        let mut display = Display::with_size(width, height)?;
+
+        let pixels: Vec<bool> = if raw {
+            // One whitespace character ends the header, and each row is
+            // padded to a whole number of bytes
+            let bytes = pbm.get(pos + 1..).unwrap_or_default();
+            let row_bytes = width.div_ceil(8);
+            if bytes.len() < row_bytes * height {
+                return Err("The PBM image is truncated".to_string());
+            }
+            (0..height)
+                .flat_map(|y| (0..width).map(move |x| bytes[y * row_bytes + x / 8] >> (7 - x % 8) & 1 == 1))
+                .collect()
+        } else {
+            let mut pixels = Vec::with_capacity(width * height);
+            while let Some(token) = pbm_token(pbm, &mut pos) {
+                for &c in token {
+                    match c {
+                        b'0' => pixels.push(false),
+                        b'1' => pixels.push(true),
+                        _ => return Err(format!("Invalid pixel {:?} in PBM image", c as char)),
                     }
                 }
             }
+            pixels
+        };
+        if pixels.len() < width * height {
+            return Err("The PBM image is truncated".to_string());
         }
+        for (i, &on) in pixels.iter().take(width * height).enumerate() {
+            display.set_pixel(i % width, i / width, on);
+        }
+        Ok(display)
+    }
 
-        self.canvas.present();
-        self.needs_repaint = false;
+    /// A hash of the display's size and contents. Unlike `std::hash`
+    /// this is the same on every platform and Rust version, so it can
+    /// be stored in tests.
+    pub fn hash(&self) -> u64 {
+        // 64-bit FNV-1a
+        let mut hash: u64 = 0xCBF2_9CE4_8422_2325;
+        let size = [self.width() as u8, self.height() as u8];
+        for byte in size.iter().copied().chain(self.rows().flatten()) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01B3);
+        }
+        hash
     }
 }
 
-fn create_sdl_window() -> sdl2::render::Canvas<sdl2::video::Window> {
-    let sdl_context = sdl2::init().expect("failed to init SDL context");
-    let video_subsystem = sdl_context.video().expect("failed to init SDL video subsystem");
-
-    let window_width = (DISPLAY_WIDTH_PX * PIXEL_SCALE_FACTOR) as u32;
-    let window_height = (DISPLAY_HEIGHT_PX * PIXEL_SCALE_FACTOR) as u32;
-    let window = video_subsystem
-        .window("CHIP-8", window_width, window_height)
-        .position_centered()
-        .opengl()
-        .build()
-        .expect("failed to create SDL window");
-    window.into_canvas().build().expect("failed to create SDL canvas")
+/// The next whitespace-separated token of a PBM image from `pos`,
+/// skipping comments, which run from `#` to the end of the line.
+fn pbm_token<'a>(pbm: &'a [u8], pos: &mut usize) -> Option<&'a [u8]> {
+    loop {
+        match pbm.get(*pos)? {
+            b'#' => {
+                while pbm.get(*pos).is_some_and(|&c| c != b'\n') {
+                    *pos += 1;
+                }
+            }
+            c if c.is_ascii_whitespace() => *pos += 1,
+            _ => break,
+        }
+    }
+    let start = *pos;
+    while pbm.get(*pos).is_some_and(|c| !c.is_ascii_whitespace()) {
+        *pos += 1;
+    }
+    Some(&pbm[start..*pos])
+}
+
+#[test]
+fn test_snapshots() {
+    let mut display = Display::new();
+    display.set_pixel(1, 0, true);
+    let ascii = display.to_ascii();
+    assert_eq!(ascii.lines().count(), DISPLAY_HEIGHT_PX);
+    assert!(ascii.starts_with(".#..."));
+
+    let rgba = display.to_rgba_bytes();
+    assert_eq!(rgba.len(), DISPLAY_WIDTH_PX * DISPLAY_HEIGHT_PX * 4);
+    assert_eq!(&rgba[..8], &[0, 0, 0, 255, 255, 255, 255, 255]);
+
+    let hash = display.hash();
+    assert_ne!(hash, Display::new().hash());
+    display.set_hires(true);
+    assert_ne!(display.hash(), Display::new().hash());
+}
+
+#[test]
+fn test_modes() {
+    let mut display = Display::new();
+    display.set_pixel(63, 31, true);
+    display.set_schip_hires(true);
+    assert_eq!((display.width(), display.height()), (128, 64));
+    // Switching clears the display rather than scaling it
+    assert_eq!(display.to_ascii(), Display::with_size(128, 64).unwrap().to_ascii());
+    display.set_pixel(127, 0, true);
+    display.set_pixel(0, 63, true);
+    assert!(display.get_pixel(127, 0) && !display.get_pixel(0, 1));
+    assert!(display
+        .to_pbm()
+        .starts_with(&format!("P1\n128 64\n{}1\n", "0".repeat(127))));
+    assert!(!display.is_lores());
+    display.set_schip_hires(false);
+    assert!(display.is_lores() && !display.get_pixel(63, 31));
+    assert!(Display::with_size(128, 32).is_err());
+}
+
+#[test]
+fn test_planes() {
+    let mut display = Display::new();
+    display.set_pixel(0, 0, true);
+    display.set_planes(2);
+    display.set_pixel(0, 0, true);
+    display.set_pixel(1, 0, true);
+    assert_eq!((display.pixel_planes(0, 0), display.pixel_planes(1, 0)), (3, 2));
+    assert_eq!(&display.to_rgba_bytes()[..8], &[170, 170, 170, 255, 85, 85, 85, 255]);
+
+    // Scrolling and clearing leave the other plane alone
+    display.scroll_down(1);
+    assert_eq!((display.pixel_planes(0, 0), display.pixel_planes(0, 1)), (1, 2));
+    display.clear();
+    assert_eq!((display.pixel_planes(0, 0), display.pixel_planes(0, 1)), (1, 0));
+    display.set_planes(0);
+    display.set_pixel(5, 5, true);
+    assert!(!display.get_pixel(5, 5));
+    display.set_planes(0xFF);
+    assert_eq!(display.planes(), 3);
+}
+
+#[test]
+fn test_damage() {
+    let mut display = Display::new();
+    assert_eq!(display.take_damage().rows(), 0..DISPLAY_HEIGHT_PX);
+    assert!(display.take_damage().is_empty());
+
+    display.set_pixel(10, 3, true);
+    display.set_pixel(4, 7, true);
+    // Setting a pixel to what it already is isn't a change
+    display.set_pixel(0, 0, false);
+    assert_eq!(
+        display.take_damage(),
+        DamageRegion {
+            x: 4,
+            y: 3,
+            width: 7,
+            height: 5
+        }
+    );
+    assert!(display.take_damage().rows().is_empty());
+
+    display.scroll_left();
+    assert_eq!(display.take_damage().rows(), 0..DISPLAY_HEIGHT_PX);
+    display.set_schip_hires(true);
+    assert_eq!(display.take_damage().width, SCHIP_HIRES_WIDTH_PX);
+}
+
+#[test]
+fn test_scroll() {
+    let mut display = Display::new();
+    display.set_pixel(10, 10, true);
+    display.scroll_down(3);
+    display.scroll_right();
+    assert!(display.get_pixel(14, 13));
+    display.scroll_up(13);
+    display.scroll_left();
+    assert!(display.get_pixel(10, 0));
+    // Pixels scrolled off the screen are gone
+    display.scroll_up(1);
+    assert_eq!(display.to_ascii(), Display::new().to_ascii());
+
+    // Half as far in lo-res, but not in hi-res
+    display.set_pixel(10, 10, true);
+    display.set_half_scroll(true);
+    display.scroll_down(3);
+    display.scroll_right();
+    assert!(display.get_pixel(12, 11));
+    display.set_hires(true);
+    display.set_pixel(10, 10, true);
+    display.scroll_down(3);
+    display.scroll_left();
+    assert!(display.get_pixel(6, 13));
+    // The bottom of the hi-res display scrolls too
+    display.scroll_down(50);
+    assert!(display.get_pixel(6, 63) && display.to_ascii().matches('#').count() == 1);
+}
+
+#[test]
+fn test_pbm() {
+    let mut display = Display::new();
+    display.set_pixel(1, 0, true);
+    display.set_pixel(63, 31, true);
+    let pbm = display.to_pbm();
+    assert!(pbm.starts_with("P1\n64 32\n01000"));
+    let loaded = Display::from_pbm(pbm.as_bytes()).unwrap();
+    assert_eq!(loaded.to_ascii(), display.to_ascii());
+
+    // Raw images, with a comment in the header
+    let mut raw = b"P4\n# hi-res\n64 64\n".to_vec();
+    raw.extend([0x80, 0, 0, 0, 0, 0, 0, 0x01]);
+    raw.extend([0; 8 * 63]);
+    let loaded = Display::from_pbm(&raw).unwrap();
+    assert_eq!(loaded.height(), HIRES_DISPLAY_HEIGHT_PX);
+    assert!(loaded.get_pixel(0, 0) && loaded.get_pixel(63, 0) && !loaded.get_pixel(1, 0));
+
+    assert!(Display::from_pbm(b"P1\n64 32\n0101").is_err());
+    assert!(Display::from_pbm(b"P1\n32 32\n").is_err());
+    assert!(Display::from_pbm(b"P2\n64 32\n").is_err());
 }
@@ -0,0 +1,241 @@
+//! C functions for embedding the core in frontends written in other
+//! languages: C, Python with ctypes, Swift and so on. They're built with
+//! the `ffi` feature, and declared in `include/chip8.h`, which
+//! `test_header` keeps in step with what build.rs generates.
+//!
+//! A frontend creates a `Chip8` with `chip8_new`, loads a ROM, and then
+//! 60 times a second passes on the keys, calls `chip8_run_frame` and
+//! draws `chip8_framebuffer`. Functions that can fail return 0 on
+//! success and -1 on error, when `chip8_last_error` says what went
+//! wrong. Every `Chip8` pointer passed in must have come from
+//! `chip8_new` and not yet been given to `chip8_free`.
+
+use std::ffi::{c_char, c_int, CString};
+use std::ptr;
+use std::slice;
+
+use crate::random::SeededRandom;
+use crate::vm::{processor_cycle, run_frame, VM};
+
+/// An emulator. Its fields aren't part of the C API.
+pub struct Chip8 {
+    vm: VM,
+    frames: u64,
+    last_error: CString,
+}
+
+impl Chip8 {
+    /// 0 if `result` is Ok, and otherwise -1, keeping the error for
+    /// `chip8_last_error`.
+    fn status(&mut self, result: Result<(), String>) -> c_int {
+        match result {
+            Ok(()) => 0,
+            Err(err) => {
+                // Error messages don't contain NULs, but just in case
+                self.last_error = CString::new(err.replace('\0', " ")).unwrap_or_default();
+                -1
+            }
+        }
+    }
+}
+
+/// Create an emulator with nothing loaded, whose random numbers come
+/// from `seed`. Free it with `chip8_free`.
+#[no_mangle]
+pub extern "C" fn chip8_new(seed: u64) -> *mut Chip8 {
+    Box::into_raw(Box::new(Chip8 {
+        vm: VM::new(Box::new(SeededRandom::new(seed))),
+        frames: 0,
+        last_error: CString::default(),
+    }))
+}
+
+/// Free an emulator. Does nothing if `chip8` is NULL.
+///
+/// # Safety
+///
+/// `chip8` must be NULL or a live emulator, which can't be used again.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_free(chip8: *mut Chip8) {
+    if !chip8.is_null() {
+        drop(Box::from_raw(chip8));
+    }
+}
+
+/// Load the `len` bytes at `rom` and restart, as if the machine had just
+/// been turned on. Returns -1 if the ROM doesn't fit in memory.
+///
+/// # Safety
+///
+/// `chip8` must be a live emulator and `rom` must point to `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_rom(chip8: *mut Chip8, rom: *const u8, len: usize) -> c_int {
+    let chip8 = &mut *chip8;
+    let rom = if len == 0 { &[] } else { slice::from_raw_parts(rom, len) };
+    let result = chip8.vm.reload(rom);
+    if result.is_ok() {
+        chip8.vm.reset();
+        chip8.frames = 0;
+    }
+    chip8.status(result)
+}
+
+/// Run one instruction. Returns -1 if the program hit an error.
+///
+/// # Safety
+///
+/// `chip8` must be a live emulator.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_step(chip8: *mut Chip8) -> c_int {
+    let chip8 = &mut *chip8;
+    let result = processor_cycle(&mut chip8.vm);
+    if result.is_ok() {
+        chip8.vm.cycles += 1;
+    }
+    chip8.status(result)
+}
+
+/// Run a frame's worth of instructions and tick the timers, to be
+/// called 60 times a second. Returns -1 if the program hit an error.
+///
+/// # Safety
+///
+/// `chip8` must be a live emulator.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_run_frame(chip8: *mut Chip8) -> c_int {
+    let chip8 = &mut *chip8;
+    let result = run_frame(&mut chip8.vm, chip8.frames);
+    chip8.frames += 1;
+    chip8.status(result)
+}
+
+/// Whether the program has exited (with 00FD), after which running it
+/// does nothing.
+///
+/// # Safety
+///
+/// `chip8` must be a live emulator.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_exited(chip8: *const Chip8) -> bool {
+    (*chip8).vm.exited
+}
+
+/// Press (or release) keypad key `key`, 0 to 0xF. Other keys are
+/// ignored.
+///
+/// # Safety
+///
+/// `chip8` must be a live emulator.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_key(chip8: *mut Chip8, key: u8, pressed: bool) {
+    if let Some(held) = (*chip8).vm.keys_pressed.get_mut(key as usize) {
+        *held = pressed;
+    }
+}
+
+/// The display's width in pixels: 64, or 128 in hi-res.
+///
+/// # Safety
+///
+/// `chip8` must be a live emulator.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_display_width(chip8: *const Chip8) -> usize {
+    (*chip8).vm.display.width()
+}
+
+/// The display's height in pixels: 32, or 64 in hi-res.
+///
+/// # Safety
+///
+/// `chip8` must be a live emulator.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_display_height(chip8: *const Chip8) -> usize {
+    (*chip8).vm.display.height()
+}
+
+/// The display, width × height bytes row by row, one per pixel: 0 if
+/// it's off, and otherwise a bit mask of the planes it's lit in, 1 for
+/// the first (the only one before XO-CHIP). Only valid until the
+/// emulator next runs, as switching resolution moves it.
+///
+/// # Safety
+///
+/// `chip8` must be a live emulator.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_framebuffer(chip8: *const Chip8) -> *const u8 {
+    (*chip8).vm.display.pixels().as_ptr()
+}
+
+/// Whether the buzzer is sounding.
+///
+/// # Safety
+///
+/// `chip8` must be a live emulator.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_sound_on(chip8: *const Chip8) -> bool {
+    (*chip8).vm.sound_timer > 0
+}
+
+/// What went wrong the last time a function returned -1, as a
+/// NUL-terminated string that's valid until the next error, or NULL if
+/// nothing has.
+///
+/// # Safety
+///
+/// `chip8` must be a live emulator.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_last_error(chip8: *const Chip8) -> *const c_char {
+    let error = &(*chip8).last_error;
+    if error.as_bytes().is_empty() {
+        ptr::null()
+    } else {
+        error.as_ptr()
+    }
+}
+
+#[test]
+fn test_ffi() {
+    use std::ffi::CStr;
+
+    // Draw a 5 in the corner (6105 F129 D005), then loop until key 5 is
+    // pressed (E1A1 1206) and exit (00FD)
+    let rom = [0x61, 0x05, 0xF1, 0x29, 0xD0, 0x05, 0xE1, 0xA1, 0x00, 0xFD, 0x12, 0x06];
+    unsafe {
+        let chip8 = chip8_new(0);
+        assert!(chip8_last_error(chip8).is_null());
+        assert_eq!(chip8_load_rom(chip8, [0; 4000].as_ptr(), 4000), -1);
+        let error = CStr::from_ptr(chip8_last_error(chip8)).to_str().unwrap();
+        assert_eq!(
+            error,
+            "ROM is 4000 bytes, 416 more than the 3584 bytes that fit in memory"
+        );
+
+        assert_eq!(chip8_load_rom(chip8, rom.as_ptr(), rom.len()), 0);
+        assert_eq!(chip8_run_frame(chip8), 0);
+        assert!(!chip8_exited(chip8));
+        let (width, height) = (chip8_display_width(chip8), chip8_display_height(chip8));
+        assert_eq!((width, height), (64, 32));
+        let pixels = slice::from_raw_parts(chip8_framebuffer(chip8), width * height);
+        // The top row of the 5, 0xF0
+        assert_eq!(&pixels[..5], [1, 1, 1, 1, 0]);
+
+        chip8_set_key(chip8, 5, true);
+        chip8_set_key(chip8, 16, true);
+        for _ in 0..3 {
+            assert_eq!(chip8_step(chip8), 0);
+        }
+        assert!(chip8_exited(chip8));
+        assert!(!chip8_sound_on(chip8));
+        chip8_free(chip8);
+    }
+}
+
+#[test]
+fn test_header() {
+    let generated = include_str!(concat!(env!("OUT_DIR"), "/chip8.h"));
+    assert!(
+        generated == include_str!("../include/chip8.h"),
+        "include/chip8.h is out of date: copy {}/chip8.h over it",
+        env!("OUT_DIR")
+    );
+}
@@ -0,0 +1,81 @@
+//! `--watch`: notice when the ROM file, or a project's sources, change
+//! on disk, e.g. because the ROM was just reassembled, by polling their
+//! modification times.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often `--watch` checks the files.
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+pub(crate) struct FileWatcher {
+    paths: Vec<PathBuf>,
+    interval: Duration,
+    /// Modification time of each path when last checked, if it existed
+    modified: Vec<Option<SystemTime>>,
+    last_check: Instant,
+}
+
+impl FileWatcher {
+    pub(crate) fn new(paths: Vec<PathBuf>, interval: Duration) -> FileWatcher {
+        let modified = paths.iter().map(|path| modified(path)).collect();
+        FileWatcher {
+            paths,
+            interval,
+            modified,
+            last_check: Instant::now(),
+        }
+    }
+
+    /// Whether any of the files has been modified since the last time
+    /// this returned true. The disk is only checked once per interval,
+    /// so this can be called every frame. While a file is missing
+    /// (e.g. partway through being replaced) it isn't reported as
+    /// changed.
+    pub(crate) fn changed(&mut self) -> bool {
+        if self.last_check.elapsed() < self.interval {
+            return false;
+        }
+        self.last_check = Instant::now();
+        let mut changed = false;
+        for (path, last_modified) in self.paths.iter().zip(&mut self.modified) {
+            match modified(path) {
+                Some(modified) if Some(modified) != *last_modified => {
+                    *last_modified = Some(modified);
+                    changed = true;
+                }
+                _ => {}
+            }
+        }
+        changed
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+#[test]
+fn test_file_watcher() {
+    let path = std::env::temp_dir().join(format!("chip8-watch-test-{}.ch8", std::process::id()));
+    fs::write(&path, [0x12, 0x00]).unwrap();
+    let other = path.with_extension("8o");
+    fs::write(&other, "").unwrap();
+    let mut watcher = FileWatcher::new(vec![path.clone(), other.clone()], Duration::ZERO);
+    assert!(!watcher.changed());
+
+    let file = fs::File::options().write(true).open(&path).unwrap();
+    file.set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+    assert!(watcher.changed());
+    assert!(!watcher.changed());
+
+    // Any of the files changing counts
+    let file = fs::File::options().write(true).open(&other).unwrap();
+    file.set_modified(SystemTime::now() + Duration::from_secs(20)).unwrap();
+    assert!(watcher.changed());
+
+    fs::remove_file(&path).unwrap();
+    fs::remove_file(&other).unwrap();
+    assert!(!watcher.changed());
+}
@@ -0,0 +1,201 @@
+//! Post-processing that makes the display look like it's on an old CRT:
+//! darkened scanlines, a grid between pixels, and a screen that bulges
+//! out and darkens towards its corners. Chosen with `--effects` and
+//! turned on and off with F10.
+//!
+//! The effects work on the scaled-up image, so they're drawn into an RGB
+//! buffer the size of the window, pixel by pixel, rather than as
+//! rectangles.
+
+use std::fmt;
+
+use super::Colors;
+
+/// Brightness of the lower part of each row of pixels with scanlines.
+const SCANLINE_BRIGHTNESS: f32 = 0.55;
+
+/// Brightness of the lines between pixels with the grid.
+const GRID_BRIGHTNESS: f32 = 0.75;
+
+/// How far the corners of the screen bulge out with curvature, as a
+/// fraction of the distance from the center.
+const CURVATURE: f32 = 0.08;
+
+/// How much darker the corners get with curvature.
+const VIGNETTE: f32 = 0.35;
+
+pub(crate) const EFFECT_NAMES: [&str; 3] = ["scanlines", "grid", "curvature"];
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) struct Effects {
+    pub(crate) scanlines: bool,
+    pub(crate) grid: bool,
+    /// Barrel distortion with a vignette
+    pub(crate) curvature: bool,
+}
+
+impl Default for Effects {
+    /// The effects F10 turns on if `--effects` isn't given
+    fn default() -> Effects {
+        Effects {
+            scanlines: true,
+            grid: false,
+            curvature: true,
+        }
+    }
+}
+
+impl Effects {
+    /// Parse a comma-separated list of `EFFECT_NAMES`, like
+    /// `scanlines,curvature`.
+    pub(crate) fn parse(s: &str) -> Result<Effects, String> {
+        let mut effects = Effects {
+            scanlines: false,
+            grid: false,
+            curvature: false,
+        };
+        for name in s.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            let effect = match name {
+                "scanlines" => &mut effects.scanlines,
+                "grid" => &mut effects.grid,
+                "curvature" => &mut effects.curvature,
+                _ => {
+                    return Err(format!(
+                        "Unknown effect {:?}, expected some of: {}",
+                        name,
+                        EFFECT_NAMES.join(", ")
+                    ))
+                }
+            };
+            *effect = true;
+        }
+        Ok(effects)
+    }
+}
+
+impl fmt::Display for Effects {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let enabled = [self.scanlines, self.grid, self.curvature];
+        let names: Vec<&str> = EFFECT_NAMES
+            .iter()
+            .zip(enabled)
+            .filter(|&(_, on)| on)
+            .map(|(&name, _)| name)
+            .collect();
+        write!(f, "{}", names.join(","))
+    }
+}
+
+/// Draw a `width` by `height` image, whose pixels are lit in the planes
+/// `planes` says, into `buffer` as 24-bit RGB, with each pixel `scale`
+/// buffer pixels across and the effects applied.
+pub(crate) fn render(
+    buffer: &mut Vec<u8>,
+    (width, height): (usize, usize),
+    scale: usize,
+    colors: Colors,
+    effects: Effects,
+    planes: impl Fn(usize, usize) -> u8,
+) {
+    let (buffer_width, buffer_height) = (width * scale, height * scale);
+    buffer.clear();
+    buffer.reserve(buffer_width * buffer_height * 3);
+    for y in 0..buffer_height {
+        for x in 0..buffer_width {
+            let mut brightness = 1.0;
+            let (mut x, mut y) = (x, y);
+            if effects.curvature {
+                // Work from the center, from -1 to 1 on each axis
+                let u = (x as f32 + 0.5) / buffer_width as f32 * 2.0 - 1.0;
+                let v = (y as f32 + 0.5) / buffer_height as f32 * 2.0 - 1.0;
+                let r2 = u * u + v * v;
+                let (u, v) = (u * (1.0 + CURVATURE * r2), v * (1.0 + CURVATURE * r2));
+                if u.abs() >= 1.0 || v.abs() >= 1.0 {
+                    // Off the edge of the screen
+                    buffer.extend_from_slice(&[0, 0, 0]);
+                    continue;
+                }
+                x = ((u + 1.0) / 2.0 * buffer_width as f32) as usize;
+                y = ((v + 1.0) / 2.0 * buffer_height as f32) as usize;
+                brightness *= 1.0 - VIGNETTE * r2 / 2.0;
+            }
+
+            // Scanlines and the grid need a few buffer pixels per pixel
+            let (dx, dy) = (x % scale, y % scale);
+            if effects.scanlines && scale >= 2 && dy >= scale - scale.div_ceil(4) {
+                brightness *= SCANLINE_BRIGHTNESS;
+            }
+            if effects.grid && scale >= 3 && (dx == scale - 1 || dy == scale - 1) {
+                brightness *= GRID_BRIGHTNESS;
+            }
+
+            let color = colors.color(planes(x / scale, y / scale));
+            buffer.extend(color.iter().map(|&c| (c as f32 * brightness) as u8));
+        }
+    }
+}
+
+#[test]
+fn test_parse_effects() {
+    assert_eq!(
+        Effects::parse("scanlines, grid"),
+        Ok(Effects {
+            scanlines: true,
+            grid: true,
+            curvature: false,
+        })
+    );
+    assert_eq!(Effects::parse("curvature").unwrap().to_string(), "curvature");
+    assert_eq!(Effects::default().to_string(), "scanlines,curvature");
+    assert!(Effects::parse("scanlines,bloom").is_err());
+}
+
+#[test]
+fn test_render() {
+    let colors = Colors {
+        foreground: [200, 200, 200],
+        background: [0, 0, 100],
+        ..Colors::default()
+    };
+    let lit = |x, _| u8::from(x == 0);
+    let pixel = |buffer: &[u8], x: usize, y: usize| buffer[(y * 8 + x) * 3..][..3].to_vec();
+    let mut buffer = Vec::new();
+
+    let effects = Effects::parse("").unwrap();
+    render(&mut buffer, (2, 1), 4, colors, effects, lit);
+    assert_eq!(buffer.len(), 8 * 4 * 3);
+    assert_eq!(pixel(&buffer, 0, 0), [200, 200, 200]);
+    assert_eq!(pixel(&buffer, 4, 3), [0, 0, 100]);
+
+    // The last row of each pixel is darker, and the grid darkens the
+    // last column too
+    render(
+        &mut buffer,
+        (2, 1),
+        4,
+        colors,
+        Effects::parse("scanlines").unwrap(),
+        lit,
+    );
+    assert_eq!(pixel(&buffer, 0, 0), [200, 200, 200]);
+    assert_eq!(pixel(&buffer, 0, 3), [110, 110, 110]);
+    render(&mut buffer, (2, 1), 4, colors, Effects::parse("grid").unwrap(), lit);
+    assert_eq!(pixel(&buffer, 3, 0), [150, 150, 150]);
+    assert_eq!(pixel(&buffer, 2, 0), [200, 200, 200]);
+
+    // The corners are off the curved screen, and the rest gets darker
+    // away from the center
+    render(
+        &mut buffer,
+        (2, 1),
+        32,
+        colors,
+        Effects::parse("curvature").unwrap(),
+        lit,
+    );
+    let pixel = |x: usize, y: usize| buffer[(y * 64 + x) * 3..][..3].to_vec();
+    assert_eq!(pixel(0, 0), [0, 0, 0]);
+    assert_eq!(pixel(63, 31), [0, 0, 0]);
+    assert!((150..200).contains(&pixel(16, 16)[0]));
+    assert!(pixel(32, 16)[2] > 95);
+}
@@ -0,0 +1,21 @@
+//! Doesn't draw anything or take any input, for running ROMs with no
+//! window or terminal, e.g. flat out with `--turbo` or in scripts that
+//! only look at `--dump-display`.
+
+use chip8::display::Display;
+
+use super::{Frontend, InputEvent};
+
+pub(crate) struct HeadlessFrontend;
+
+impl Frontend for HeadlessFrontend {
+    fn poll_events(&mut self) -> Vec<InputEvent> {
+        Vec::new()
+    }
+
+    fn paint(&mut self, display: &mut Display) {
+        display.mark_painted();
+    }
+
+    fn set_title(&mut self, _title: &str) {}
+}
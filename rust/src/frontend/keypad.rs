@@ -0,0 +1,193 @@
+//! The keypad panel, shown beside the display with `--show-keypad` or
+//! by pressing F9: the 4x4 keypad with the keys being held down lit up,
+//! each labelled with the keyboard key that presses it, so a game's
+//! controls can be learned without trial and error. The panel is drawn
+//! on a CHIP-8 display of its own, so each frontend draws it the same
+//! way as the main one. Window frontends can also press keys by
+//! clicking or touching them, which works on touch screens and without
+//! knowing the keymap.
+
+use chip8::display::Display;
+
+use super::Keymap;
+use crate::text::draw_text;
+
+/// The keypad's keys as they're laid out on the COSMAC VIP.
+const LAYOUT: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+/// Each key gets a cell this many pixels across and down, so the
+/// panel is the size of a low-res display. The first column of pixels
+/// and the last row of each cell are left as a gap.
+const CELL_WIDTH: usize = 16;
+const CELL_HEIGHT: usize = 8;
+
+pub(crate) struct KeypadPanel {
+    display: Display,
+    visible: bool,
+    pressed: [bool; 16],
+}
+
+impl KeypadPanel {
+    pub(crate) fn new(keymap: &Keymap, visible: bool) -> KeypadPanel {
+        let mut panel = KeypadPanel {
+            display: Display::new(),
+            visible,
+            pressed: [false; 16],
+        };
+        panel.draw(keymap);
+        panel
+    }
+
+    /// The panel, if it's being shown.
+    pub(crate) fn shown(&self) -> Option<&Display> {
+        Some(&self.display).filter(|_| self.visible)
+    }
+
+    pub(crate) fn toggle(&mut self, keymap: &Keymap) {
+        self.visible = !self.visible;
+        // Redraw so the frontend knows to repaint
+        self.draw(keymap);
+    }
+
+    /// Light up the keys in `pressed`.
+    pub(crate) fn set_pressed(&mut self, keymap: &Keymap, pressed: &[bool; 16]) {
+        if self.pressed != *pressed {
+            self.pressed = *pressed;
+            self.draw(keymap);
+        }
+    }
+
+    /// Whether the panel has changed since the last call to
+    /// `mark_painted`, including being shown or hidden.
+    pub(crate) fn needs_repaint(&self) -> bool {
+        self.display.needs_repaint()
+    }
+
+    pub(crate) fn mark_painted(&mut self) {
+        self.display.mark_painted();
+    }
+
+    fn draw(&mut self, keymap: &Keymap) {
+        let display = &mut self.display;
+        display.clear();
+        for (row, keys) in LAYOUT.iter().enumerate() {
+            for (column, &key) in keys.iter().enumerate() {
+                let (x, y) = (column * CELL_WIDTH, row * CELL_HEIGHT);
+                let pressed = self.pressed[key as usize];
+                if pressed {
+                    for dx in 1..CELL_WIDTH {
+                        for dy in 0..CELL_HEIGHT - 1 {
+                            display.set_pixel(x + dx, y + dy, true);
+                        }
+                    }
+                }
+                let label = match keymap.host_key(key) {
+                    Some(c) => format!("{:X} {}", key, c),
+                    None => format!("{:X}", key),
+                };
+                draw_text(display, x + 3, y + 1, &label, pressed);
+            }
+        }
+        // Separate the panel from the display beside it
+        for y in 0..display.height() {
+            display.set_pixel(0, y, true);
+        }
+    }
+}
+
+/// How many display pixels across each of the panel's pixels takes, so
+/// it's as tall as the display beside it.
+fn panel_scale(display_height: usize, panel: &Display) -> usize {
+    (display_height / panel.height()).max(1)
+}
+
+/// The size of the display with the keypad panel beside it, if it's
+/// shown, in display pixels.
+pub(crate) fn canvas_size(display: &Display, keypad: &KeypadPanel) -> (usize, usize) {
+    match keypad.shown() {
+        Some(panel) => (
+            display.width() + panel.width() * panel_scale(display.height(), panel),
+            display.height(),
+        ),
+        None => (display.width(), display.height()),
+    }
+}
+
+/// The planes the pixel at (`x`, `y`) of the display with the keypad
+/// panel beside it is lit in (see `Display::pixel_planes`). The panel
+/// only uses the first plane.
+pub(crate) fn canvas_pixel(display: &Display, keypad: &KeypadPanel, x: usize, y: usize) -> u8 {
+    if x < display.width() {
+        return if y < display.height() {
+            display.pixel_planes(x, y)
+        } else {
+            0
+        };
+    }
+    keypad.shown().map_or(0, |panel| {
+        let scale = panel_scale(display.height(), panel);
+        let (x, y) = ((x - display.width()) / scale, y / scale);
+        if x < panel.width() && y < panel.height() {
+            panel.pixel_planes(x, y)
+        } else {
+            0
+        }
+    })
+}
+
+/// The key at the point (`x`, `y`) of a display of `display_size` with
+/// the keypad panel beside it, if the panel is shown and the point is
+/// on it. Only the SDL frontend reads the mouse.
+#[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+pub(crate) fn key_at(display_size: (usize, usize), keypad: &KeypadPanel, x: usize, y: usize) -> Option<u8> {
+    let panel = keypad.shown()?;
+    let scale = panel_scale(display_size.1, panel);
+    let (x, y) = (x.checked_sub(display_size.0)? / scale, y / scale);
+    LAYOUT.get(y / CELL_HEIGHT)?.get(x / CELL_WIDTH).copied()
+}
+
+#[test]
+fn test_keypad_panel() {
+    let keymap = Keymap::default();
+    let mut keypad = KeypadPanel::new(&keymap, false);
+    let mut display = Display::new();
+    display.set_hires(true);
+    assert_eq!(canvas_size(&display, &keypad), (64, 64));
+
+    keypad.toggle(&keymap);
+    assert!(keypad.needs_repaint());
+    // The panel is scaled up to the height of the hi-res display
+    assert_eq!(canvas_size(&display, &keypad), (64 + 128, 64));
+    let ascii = keypad.shown().unwrap().to_ascii();
+    let rows: Vec<&str> = ascii.lines().collect();
+    // "1 1" in the first cell, and "C 4" in the last column
+    assert_eq!(&rows[1][..15], "#...#.......#..");
+    assert_eq!(&rows[1][48..], "....##.....#.#..");
+
+    let mut pressed = [false; 16];
+    pressed[0x1] = true;
+    keypad.mark_painted();
+    keypad.set_pressed(&keymap, &pressed);
+    assert!(keypad.needs_repaint());
+    let ascii = keypad.shown().unwrap().to_ascii();
+    let rows: Vec<&str> = ascii.lines().collect();
+    // The first cell is lit, with the label cut out of it
+    assert_eq!(&rows[1][..15], "####.#######.##");
+    assert_eq!(canvas_pixel(&display, &keypad, 64 + 2, 0), 1);
+    assert_eq!(canvas_pixel(&display, &keypad, 64 + 3, 13), 1);
+    assert_eq!(canvas_pixel(&display, &keypad, 64 + 2, 40), 0);
+
+    let size = (display.width(), display.height());
+    assert_eq!(key_at(size, &keypad, 64 + 2, 0), Some(0x1));
+    assert_eq!(key_at(size, &keypad, 64 + 127, 63), Some(0xF));
+    assert_eq!(key_at(size, &keypad, 64 + 40, 20), Some(0x5));
+    assert_eq!(key_at(size, &keypad, 10, 10), None);
+    assert_eq!(key_at(size, &keypad, 64 + 128, 10), None);
+    keypad.toggle(&keymap);
+    assert_eq!(key_at(size, &keypad, 64 + 2, 0), None);
+}
@@ -0,0 +1,577 @@
+//! Frontends draw the display and turn host input into keypad events.
+//! The main loop only talks to the `Frontend` trait, so the emulator
+//! can run in an SDL window, a winit window, in a terminal or with no
+//! display at all. Each frontend but the headless one is behind a Cargo
+//! feature of the same name.
+
+use chip8::audio::Tone;
+use chip8::display::{Display, DISPLAY_WIDTH_PX};
+
+use crate::audio_sink::AudioSink;
+use crate::views::View;
+use effects::Effects;
+use viewport::ScaleMode;
+
+// Only the SDL frontend draws effects
+#[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+pub(crate) mod effects;
+pub(crate) mod headless;
+pub(crate) mod keypad;
+#[cfg(feature = "pixels")]
+pub(crate) mod pixels;
+#[cfg(feature = "sdl")]
+pub(crate) mod sdl;
+#[cfg(feature = "tui")]
+pub(crate) mod tui;
+// Only the SDL frontend's window can be resized
+#[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+pub(crate) mod viewport;
+
+/// Window frontends draw each lo-res CHIP-8 pixel as a square this
+/// many screen pixels wide, unless overridden with --scale.
+pub(crate) const DEFAULT_SCALE: u32 = 8;
+
+/// How many screen pixels wide window frontends draw each of
+/// `display`'s pixels at `scale`. Wider displays get smaller pixels, so
+/// switching to SUPER-CHIP's 128x64 mode doesn't resize the window.
+#[cfg_attr(not(any(feature = "sdl", feature = "pixels")), allow(dead_code))]
+pub(crate) fn pixel_scale(scale: u32, display: &Display) -> u32 {
+    width_scale(scale, display.width())
+}
+
+/// How many screen pixels wide window frontends draw each pixel of a
+/// picture `width` pixels wide at `scale`, as for `pixel_scale`.
+#[cfg_attr(not(any(feature = "sdl", feature = "pixels")), allow(dead_code))]
+pub(crate) fn width_scale(scale: u32, width: usize) -> u32 {
+    (scale * DISPLAY_WIDTH_PX as u32 / width as u32).max(1)
+}
+
+/// How a frontend should look and which keys it should listen to.
+pub(crate) struct FrontendSettings {
+    /// Ignored by the terminal frontend
+    #[cfg_attr(not(any(feature = "sdl", feature = "pixels")), allow(dead_code))]
+    pub(crate) scale: u32,
+    pub(crate) colors: Colors,
+    pub(crate) keymap: Keymap,
+    /// Only the SDL frontend has sound
+    pub(crate) tone: Tone,
+    /// Whether to start with the keypad panel shown
+    pub(crate) show_keypad: bool,
+    /// Whether to wait for the display to refresh when painting. Only
+    /// the SDL frontend does this
+    #[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+    pub(crate) vsync: bool,
+    /// Post-processing to start with, if any, which is also what F10
+    /// turns on. Only the SDL frontend has effects
+    #[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+    pub(crate) effects: Option<Effects>,
+    /// How the SDL frontend fits the display to its window
+    #[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+    pub(crate) scale_mode: ScaleMode,
+    /// Whether the SDL frontend starts fullscreen
+    #[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+    pub(crate) fullscreen: bool,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) struct Colors {
+    /// Color of lit pixels, as RGB
+    pub(crate) foreground: [u8; 3],
+    /// Color of unlit pixels, as RGB
+    pub(crate) background: [u8; 3],
+    /// Color of pixels lit in only XO-CHIP's second plane, as RGB
+    pub(crate) plane2: [u8; 3],
+    /// Color of pixels lit in both planes, as RGB
+    pub(crate) blend: [u8; 3],
+}
+
+impl Default for Colors {
+    fn default() -> Colors {
+        Colors {
+            foreground: [0xFF, 0xFF, 0xFF],
+            background: [0x00, 0x00, 0x00],
+            plane2: [0x55, 0x55, 0x55],
+            blend: [0xAA, 0xAA, 0xAA],
+        }
+    }
+}
+
+impl Colors {
+    /// The color of a pixel lit in `planes` (see `Display::pixel_planes`).
+    pub(crate) fn color(&self, planes: u8) -> [u8; 3] {
+        match planes {
+            0 => self.background,
+            1 => self.foreground,
+            2 => self.plane2,
+            _ => self.blend,
+        }
+    }
+}
+
+/// Parse a color written like `#FFA500`.
+pub(crate) fn parse_color(s: &str) -> Result<[u8; 3], String> {
+    let err = || format!("Invalid color {:?}, expected e.g. #FFA500", s);
+    let hex = s.strip_prefix('#').filter(|hex| hex.len() == 6).ok_or_else(err)?;
+    let mut rgb = [0; 3];
+    for (i, channel) in rgb.iter_mut().enumerate() {
+        *channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| err())?;
+    }
+    Ok(rgb)
+}
+
+/// Gamepad buttons that can be mapped to keypad keys, by the names
+/// used in the config file.
+pub(crate) const GAMEPAD_BUTTONS: [&str; 12] = [
+    "a",
+    "b",
+    "x",
+    "y",
+    "back",
+    "start",
+    "left-shoulder",
+    "right-shoulder",
+    "dpad-up",
+    "dpad-down",
+    "dpad-left",
+    "dpad-right",
+];
+
+/// Names of the built-in keymaps, which the config file can choose
+/// between with `keymap-profile`.
+pub(crate) const KEYMAP_PROFILES: [&str; 2] = ["default", "two-player"];
+
+/// Maps keyboard keys, identified by the (lowercase) character on them,
+/// and gamepad buttons to CHIP-8 keypad keys. By default the keypad is
+/// laid out on the left side of a QWERTY keyboard:
+///
+/// ```text
+/// 1 2 3 C      1 2 3 4
+/// 4 5 6 D  <=  Q W E R
+/// 7 8 9 E      A S D F
+/// A 0 B F      Z X C V
+/// ```
+///
+/// Two-player games like Pong give each player half of the keypad, so
+/// the `two-player` profile splits it between the two hands' sides of
+/// the keyboard instead, leaving room between the players:
+///
+/// ```text
+/// 1 2      1 2          3 C      8 9
+/// 4 5  <=  Q W          6 D  <=  I O
+/// 7 8      A S          9 E      K L
+/// A 0      Z X          B F      , .
+/// ```
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct Keymap {
+    keys: [Option<u8>; 128],
+    /// (gamepad, button, keypad key), with gamepads numbered from 1 in
+    /// the order they were connected
+    buttons: Vec<(usize, &'static str, u8)>,
+}
+
+impl Keymap {
+    /// An empty keymap
+    pub(crate) fn new() -> Keymap {
+        Keymap {
+            keys: [None; 128],
+            buttons: Vec::new(),
+        }
+    }
+
+    /// One of the built-in `KEYMAP_PROFILES`.
+    pub(crate) fn profile(name: &str) -> Result<Keymap, String> {
+        let layout: &[(char, u8)] = match name {
+            "default" => return Ok(Keymap::default()),
+            "two-player" => &[
+                ('1', 0x1),
+                ('2', 0x2),
+                ('q', 0x4),
+                ('w', 0x5),
+                ('a', 0x7),
+                ('s', 0x8),
+                ('z', 0xA),
+                ('x', 0x0),
+                ('8', 0x3),
+                ('9', 0xC),
+                ('i', 0x6),
+                ('o', 0xD),
+                ('k', 0x9),
+                ('l', 0xE),
+                (',', 0xB),
+                ('.', 0xF),
+            ],
+            _ => {
+                return Err(format!(
+                    "Unknown keymap profile {:?}, expected one of: {}",
+                    name,
+                    KEYMAP_PROFILES.join(", ")
+                ))
+            }
+        };
+        let mut keymap = Keymap::new();
+        for &(c, key) in layout {
+            keymap.keys[c as usize] = Some(key);
+        }
+        Ok(keymap)
+    }
+
+    /// Map the keyboard key labelled `c` to the keypad key `key`.
+    pub(crate) fn set(&mut self, c: char, key: u8) -> Result<(), String> {
+        let c = c.to_ascii_lowercase();
+        if !c.is_ascii() {
+            return Err(format!("Only ASCII keys can be mapped, not {:?}", c));
+        }
+        if key > 0xF {
+            return Err(format!("Invalid keypad key {:X}", key));
+        }
+        self.keys[c as usize] = Some(key);
+        Ok(())
+    }
+
+    /// The keypad key for the keyboard key labelled `c`, if any.
+    pub(crate) fn get(&self, c: char) -> Option<u8> {
+        let c = c.to_ascii_lowercase();
+        if c.is_ascii() {
+            self.keys[c as usize]
+        } else {
+            None
+        }
+    }
+
+    /// The first keyboard key that presses the keypad key `key`, if
+    /// any, for labelling it.
+    pub(crate) fn host_key(&self, key: u8) -> Option<char> {
+        (0..128u8).map(char::from).find(|&c| self.keys[c as usize] == Some(key))
+    }
+
+    /// Map `button` (one of `GAMEPAD_BUTTONS`) on gamepad number `pad`
+    /// to the keypad key `key`.
+    pub(crate) fn set_button(&mut self, pad: usize, button: &str, key: u8) -> Result<(), String> {
+        let button = GAMEPAD_BUTTONS
+            .iter()
+            .find(|&&name| name == button)
+            .ok_or_else(|| format!("Unknown gamepad button {:?}", button))?;
+        if key > 0xF {
+            return Err(format!("Invalid keypad key {:X}", key));
+        }
+        self.buttons.retain(|&(p, b, _)| (p, b) != (pad, *button));
+        self.buttons.push((pad, button, key));
+        Ok(())
+    }
+
+    /// The keypad key for `button` on gamepad number `pad`, if any.
+    pub(crate) fn get_button(&self, pad: usize, button: &str) -> Option<u8> {
+        self.buttons
+            .iter()
+            .find(|&&(p, b, _)| p == pad && b == button)
+            .map(|&(_, _, key)| key)
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        let mut keymap = Keymap::new();
+        let layout = [
+            ('1', 0x1),
+            ('2', 0x2),
+            ('3', 0x3),
+            ('4', 0xC),
+            ('q', 0x4),
+            ('w', 0x5),
+            ('e', 0x6),
+            ('r', 0xD),
+            ('a', 0x7),
+            ('s', 0x8),
+            ('d', 0x9),
+            ('f', 0xE),
+            ('z', 0xA),
+            ('x', 0x0),
+            ('c', 0xB),
+            ('v', 0xF),
+        ];
+        for (c, key) in layout {
+            keymap.keys[c as usize] = Some(key);
+        }
+        keymap
+    }
+}
+
+pub(crate) enum InputEvent {
+    /// A keypad key was pressed or released
+    Key { key: u8, pressed: bool },
+    /// A key that controls the emulator was pressed or released
+    Hotkey { hotkey: Hotkey, pressed: bool },
+    /// The user asked to exit, e.g. by closing the window
+    Quit,
+    /// A line of a debug view was clicked
+    #[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+    ViewClicked {
+        view: &'static str,
+        line: usize,
+        column: usize,
+        /// With the right mouse button rather than the left
+        right: bool,
+    },
+    /// A key for editing a value was typed in a debug view's window
+    #[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+    ViewKey(EditKey),
+}
+
+/// Keys that edit the value selected in a debug view.
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+pub(crate) enum EditKey {
+    /// A hex digit
+    Digit(u8),
+    Backspace,
+    /// Set the value to the digits typed so far
+    Enter,
+    /// Stop editing without changing anything
+    Escape,
+}
+
+/// Keys that control the emulator rather than the program.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum Hotkey {
+    /// F5: restart the program, keeping memory as it is
+    Reset,
+    /// F6: reload the ROM from disk and restart it
+    HardReset,
+    /// P or Space: stop or resume running the program
+    Pause,
+    /// N: while paused, run one frame's worth of instructions
+    FrameAdvance,
+    /// Tab, held: run faster than the configured speed
+    FastForward,
+    /// M: toggle running at a quarter of the configured speed
+    SlowMotion,
+    /// F7: switch the buzzer to the next waveform
+    CycleWaveform,
+    /// F8: switch to the most recently played other ROM
+    SwitchRom,
+    /// F9: show or hide the keypad panel
+    ToggleKeypad,
+    /// F10: turn post-processing effects on or off
+    ToggleEffects,
+    /// Up arrow: move up the ROM menu
+    MenuUp,
+    /// Down arrow: move down the ROM menu
+    MenuDown,
+    /// Enter: run the ROM chosen in the menu
+    MenuSelect,
+}
+
+/// A host keyboard key, in a form shared by all frontends.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum HostKey {
+    /// A key that types a character, including space
+    Char(char),
+    /// F1, F2, ...
+    Function(u8),
+    Tab,
+    Up,
+    Down,
+    Enter,
+    /// A button (one of `GAMEPAD_BUTTONS`) on a gamepad, numbered from
+    /// 1 in the order they were connected. Only the SDL frontend reads
+    /// gamepads.
+    #[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+    Button {
+        pad: usize,
+        button: &'static str,
+    },
+}
+
+/// Turn a host key press or release into an input event. Keys in the
+/// keymap go to the keypad, so they take precedence over hotkeys.
+pub(crate) fn input_event(keymap: &Keymap, key: HostKey, pressed: bool) -> Option<InputEvent> {
+    match key {
+        HostKey::Char(c) => {
+            if let Some(key) = keymap.get(c) {
+                return Some(InputEvent::Key { key, pressed });
+            }
+        }
+        // Gamepads only control the keypad
+        HostKey::Button { pad, button } => {
+            let key = keymap.get_button(pad, button)?;
+            return Some(InputEvent::Key { key, pressed });
+        }
+        _ => {}
+    }
+    let hotkey = match key {
+        HostKey::Function(5) => Hotkey::Reset,
+        HostKey::Function(6) => Hotkey::HardReset,
+        HostKey::Function(7) => Hotkey::CycleWaveform,
+        HostKey::Function(8) => Hotkey::SwitchRom,
+        HostKey::Function(9) => Hotkey::ToggleKeypad,
+        HostKey::Function(10) => Hotkey::ToggleEffects,
+        HostKey::Tab => Hotkey::FastForward,
+        HostKey::Up => Hotkey::MenuUp,
+        HostKey::Down => Hotkey::MenuDown,
+        HostKey::Enter => Hotkey::MenuSelect,
+        HostKey::Char(c) => match c.to_ascii_lowercase() {
+            'p' | ' ' => Hotkey::Pause,
+            'n' => Hotkey::FrameAdvance,
+            'm' => Hotkey::SlowMotion,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(InputEvent::Hotkey { hotkey, pressed })
+}
+
+pub(crate) trait Frontend {
+    /// Return the input events that happened since the last call.
+    fn poll_events(&mut self) -> Vec<InputEvent>;
+
+    /// Draw the display if it has changed since it was last painted.
+    fn paint(&mut self, display: &mut Display);
+
+    /// Draw a full-color picture instead of the display, `width` by
+    /// `height` pixels of RGBA bytes row by row, like Mega-Chip's.
+    /// Frontends that can only draw the display ignore this.
+    #[cfg_attr(not(feature = "megachip"), allow(dead_code))]
+    fn paint_rgba(&mut self, _width: usize, _height: usize, _rgba: &[u8]) {}
+
+    /// Set the window (or terminal) title.
+    fn set_title(&mut self, title: &str);
+
+    /// Where to play the buzzer, for frontends with sound.
+    fn audio(&mut self) -> Option<&mut dyn AudioSink> {
+        None
+    }
+
+    /// Whether `paint` waits for the display to refresh, at close enough
+    /// to 60 Hz that it can pace frames instead of the main loop.
+    fn paced_by_vsync(&self) -> bool {
+        false
+    }
+
+    /// Turn post-processing effects on or off. Frontends without
+    /// effects ignore this.
+    fn toggle_effects(&mut self) {}
+
+    /// Show debug views in windows of their own, updating any that
+    /// have changed. Frontends without windows ignore this.
+    fn show_views(&mut self, _views: &[View]) {}
+
+    /// Light up the keys being held down on the keypad panel.
+    fn set_keys_pressed(&mut self, _pressed: &[bool; 16]) {}
+
+    /// Show or hide the keypad panel.
+    fn toggle_keypad(&mut self) {}
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, clap::ValueEnum)]
+pub(crate) enum FrontendKind {
+    Sdl,
+    Pixels,
+    Tui,
+    /// No display or input
+    Headless,
+}
+
+impl Default for FrontendKind {
+    /// The first window frontend included in this build, falling back
+    /// to the terminal.
+    fn default() -> FrontendKind {
+        if cfg!(feature = "sdl") {
+            FrontendKind::Sdl
+        } else if cfg!(feature = "pixels") {
+            FrontendKind::Pixels
+        } else {
+            FrontendKind::Tui
+        }
+    }
+}
+
+pub(crate) fn create(kind: FrontendKind, settings: FrontendSettings) -> Result<Box<dyn Frontend>, String> {
+    match kind {
+        #[cfg(feature = "sdl")]
+        FrontendKind::Sdl => Ok(Box::new(sdl::SdlFrontend::new(settings)?)),
+        #[cfg(feature = "pixels")]
+        FrontendKind::Pixels => Ok(Box::new(pixels::PixelsFrontend::new(settings)?)),
+        #[cfg(feature = "tui")]
+        FrontendKind::Tui => Ok(Box::new(tui::TuiFrontend::new(settings)?)),
+        FrontendKind::Headless => Ok(Box::new(headless::HeadlessFrontend)),
+        #[allow(unreachable_patterns)]
+        _ => Err(format!("This build doesn't include the {:?} frontend", kind)),
+    }
+}
+
+#[test]
+fn test_input_event() {
+    let mut keymap = Keymap::default();
+    assert!(matches!(
+        input_event(&keymap, HostKey::Char('Q'), true),
+        Some(InputEvent::Key {
+            key: 0x4,
+            pressed: true
+        })
+    ));
+    assert!(matches!(
+        input_event(&keymap, HostKey::Function(5), false),
+        Some(InputEvent::Hotkey {
+            hotkey: Hotkey::Reset,
+            pressed: false
+        })
+    ));
+    assert!(matches!(
+        input_event(&keymap, HostKey::Char(' '), true),
+        Some(InputEvent::Hotkey {
+            hotkey: Hotkey::Pause,
+            pressed: true
+        })
+    ));
+    assert!(input_event(&keymap, HostKey::Char('k'), true).is_none());
+    keymap.set('k', 0x5).unwrap();
+    assert!(input_event(&keymap, HostKey::Char('k'), true).is_some());
+}
+
+#[test]
+fn test_pixel_scale() {
+    let mut display = Display::new();
+    assert_eq!(pixel_scale(8, &display), 8);
+    display.set_hires(true);
+    assert_eq!(pixel_scale(8, &display), 8);
+    display.set_schip_hires(true);
+    assert_eq!(pixel_scale(8, &display), 4);
+    assert_eq!(pixel_scale(1, &display), 1);
+}
+
+#[test]
+fn test_parse_color() {
+    assert_eq!(parse_color("#FFA500"), Ok([0xFF, 0xA5, 0x00]));
+    assert_eq!(parse_color("#ffa500"), Ok([0xFF, 0xA5, 0x00]));
+    assert!(parse_color("FFA500").is_err());
+    assert!(parse_color("#FFA50").is_err());
+    assert!(parse_color("#GGA500").is_err());
+}
+
+#[test]
+fn test_keymap() {
+    let mut keymap = Keymap::default();
+    assert_eq!(keymap.get('Q'), Some(0x4));
+    assert_eq!(keymap.get('k'), None);
+    keymap.set('K', 0x5).unwrap();
+    assert_eq!(keymap.get('k'), Some(0x5));
+    assert!(keymap.set('é', 0x5).is_err());
+    assert!(keymap.set('k', 0x10).is_err());
+    assert_eq!(keymap.host_key(0x4), Some('q'));
+
+    assert_eq!(keymap.get_button(1, "dpad-up"), None);
+    keymap.set_button(1, "dpad-up", 0x1).unwrap();
+    keymap.set_button(1, "dpad-up", 0x2).unwrap();
+    keymap.set_button(2, "dpad-up", 0xC).unwrap();
+    assert_eq!(keymap.get_button(1, "dpad-up"), Some(0x2));
+    assert_eq!(keymap.get_button(2, "dpad-up"), Some(0xC));
+    assert!(keymap.set_button(1, "turbo", 0x1).is_err());
+    assert!(keymap.set_button(1, "a", 0x10).is_err());
+
+    // The two players' halves of the keypad don't overlap
+    let two_player = Keymap::profile("two-player").unwrap();
+    assert_eq!((two_player.get('1'), two_player.get('4')), (Some(0x1), None));
+    assert_eq!((two_player.get('9'), two_player.get('o')), (Some(0xC), Some(0xD)));
+    assert_eq!(Keymap::profile("default"), Ok(Keymap::default()));
+    assert!(Keymap::profile("three-player").is_err());
+}
@@ -0,0 +1,194 @@
+//! Draws the display to a winit window through a `pixels` frame buffer.
+//! Unlike the SDL frontend this needs no C libraries, so it builds
+//! anywhere Rust does.
+
+use pixels::{Pixels, SurfaceTexture};
+use winit::dpi::LogicalSize;
+use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoop};
+use winit::platform::run_return::EventLoopExtRunReturn;
+use winit::window::{Window, WindowBuilder};
+
+use chip8::display::{Display, DISPLAY_HEIGHT_PX, DISPLAY_WIDTH_PX};
+
+use super::keypad::{canvas_pixel, canvas_size, KeypadPanel};
+use super::{input_event, pixel_scale, width_scale, Frontend, FrontendSettings, HostKey, InputEvent};
+
+pub(crate) struct PixelsFrontend {
+    event_loop: EventLoop<()>,
+    pixels: Pixels,
+    keypad: KeypadPanel,
+    settings: FrontendSettings,
+    /// The size of the display, with the keypad panel if it's shown, in
+    /// `pixels`' buffer
+    size: (usize, usize),
+
+    // The window must outlive the surface `pixels` draws to
+    window: Window,
+}
+
+impl PixelsFrontend {
+    pub(crate) fn new(settings: FrontendSettings) -> Result<PixelsFrontend, String> {
+        let event_loop = EventLoop::new();
+        let size = LogicalSize::new(
+            (DISPLAY_WIDTH_PX as u32 * settings.scale) as f64,
+            (DISPLAY_HEIGHT_PX as u32 * settings.scale) as f64,
+        );
+        let window = WindowBuilder::new()
+            .with_title("CHIP-8")
+            .with_inner_size(size)
+            .build(&event_loop)
+            .map_err(|err| format!("Error creating window: {}", err))?;
+
+        let window_size = window.inner_size();
+        let surface = SurfaceTexture::new(window_size.width, window_size.height, &window);
+        let pixels = Pixels::new(DISPLAY_WIDTH_PX as u32, DISPLAY_HEIGHT_PX as u32, surface)
+            .map_err(|err| format!("Error creating pixel buffer: {}", err))?;
+
+        Ok(PixelsFrontend {
+            event_loop,
+            pixels,
+            keypad: KeypadPanel::new(&settings.keymap, settings.show_keypad),
+            settings,
+            size: (DISPLAY_WIDTH_PX, DISPLAY_HEIGHT_PX),
+            window,
+        })
+    }
+
+    /// Resize the frame buffer to `size`, and the window to fit it with
+    /// each pixel `scale` screen pixels wide, if it's changed.
+    fn resize(&mut self, size: (usize, usize), scale: u32) {
+        if size == self.size {
+            return;
+        }
+        if let Err(err) = self.pixels.resize_buffer(size.0 as u32, size.1 as u32) {
+            log::error!("Error resizing frame buffer: {}", err);
+            std::process::exit(1);
+        }
+        self.window.set_inner_size(LogicalSize::new(
+            (size.0 as u32 * scale) as f64,
+            (size.1 as u32 * scale) as f64,
+        ));
+        self.size = size;
+    }
+}
+
+impl Frontend for PixelsFrontend {
+    fn poll_events(&mut self) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+        let pixels = &mut self.pixels;
+        let keymap = &self.settings.keymap;
+
+        // Run the event loop until it has handled everything that's
+        // queued, then hand control back to the emulator.
+        self.event_loop.run_return(|event, _, control_flow| {
+            *control_flow = ControlFlow::Poll;
+            match event {
+                Event::WindowEvent { event, .. } => match event {
+                    WindowEvent::CloseRequested => events.push(InputEvent::Quit),
+                    WindowEvent::Resized(size) => {
+                        if let Err(err) = pixels.resize_surface(size.width, size.height) {
+                            log::error!("Error resizing window: {}", err);
+                        }
+                    }
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                virtual_keycode: Some(keycode),
+                                state,
+                                ..
+                            },
+                        ..
+                    } => {
+                        let pressed = state == ElementState::Pressed;
+                        if let Some(event) = host_key(keycode).and_then(|key| input_event(keymap, key, pressed)) {
+                            events.push(event);
+                        }
+                    }
+                    _ => {}
+                },
+                Event::MainEventsCleared => *control_flow = ControlFlow::Exit,
+                _ => {}
+            }
+        });
+        events
+    }
+
+    fn paint(&mut self, display: &mut Display) {
+        // Two-page hi-res ROMs change the height of the display, and the
+        // keypad panel widens the window. Mega-Chip's picture is bigger
+        // still, and leaving it needs the display drawn again.
+        let size = canvas_size(display, &self.keypad);
+        if !display.needs_repaint() && !self.keypad.needs_repaint() && size == self.size {
+            return;
+        }
+        self.resize(size, pixel_scale(self.settings.scale, display));
+
+        let colors = self.settings.colors;
+        let frame = self.pixels.frame_mut();
+        for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
+            let [r, g, b] = colors.color(canvas_pixel(display, &self.keypad, i % size.0, i / size.0));
+            pixel.copy_from_slice(&[r, g, b, 255]);
+        }
+
+        if let Err(err) = self.pixels.render() {
+            log::error!("Error drawing frame: {}", err);
+            std::process::exit(1);
+        }
+        display.mark_painted();
+        self.keypad.mark_painted();
+    }
+
+    fn paint_rgba(&mut self, width: usize, height: usize, rgba: &[u8]) {
+        self.resize((width, height), width_scale(self.settings.scale, width));
+        for (pixel, color) in self.pixels.frame_mut().chunks_exact_mut(4).zip(rgba.chunks_exact(4)) {
+            pixel.copy_from_slice(&[color[0], color[1], color[2], 255]);
+        }
+        if let Err(err) = self.pixels.render() {
+            log::error!("Error drawing frame: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.window.set_title(title);
+    }
+
+    fn set_keys_pressed(&mut self, pressed: &[bool; 16]) {
+        self.keypad.set_pressed(&self.settings.keymap, pressed);
+    }
+
+    fn toggle_keypad(&mut self) {
+        self.keypad.toggle(&self.settings.keymap);
+    }
+}
+
+fn host_key(keycode: VirtualKeyCode) -> Option<HostKey> {
+    // The number keys 1-9, 0 come first in VirtualKeyCode, followed by
+    // the letters A-Z. The function keys are numbered consecutively.
+    let f1 = VirtualKeyCode::F1 as u32;
+    match keycode as u32 {
+        n @ 0..=8 => char::from_digit(n + 1, 10).map(HostKey::Char),
+        9 => Some(HostKey::Char('0')),
+        n @ 10..=35 => char::from_u32('a' as u32 + n - 10).map(HostKey::Char),
+        n if (f1..=VirtualKeyCode::F12 as u32).contains(&n) => Some(HostKey::Function((n - f1 + 1) as u8)),
+        _ if keycode == VirtualKeyCode::Space => Some(HostKey::Char(' ')),
+        _ if keycode == VirtualKeyCode::Tab => Some(HostKey::Tab),
+        _ if keycode == VirtualKeyCode::Up => Some(HostKey::Up),
+        _ if keycode == VirtualKeyCode::Down => Some(HostKey::Down),
+        _ if keycode == VirtualKeyCode::Return => Some(HostKey::Enter),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_host_key() {
+    assert_eq!(host_key(VirtualKeyCode::Key1), Some(HostKey::Char('1')));
+    assert_eq!(host_key(VirtualKeyCode::Key0), Some(HostKey::Char('0')));
+    assert_eq!(host_key(VirtualKeyCode::A), Some(HostKey::Char('a')));
+    assert_eq!(host_key(VirtualKeyCode::Z), Some(HostKey::Char('z')));
+    assert_eq!(host_key(VirtualKeyCode::F5), Some(HostKey::Function(5)));
+    assert_eq!(host_key(VirtualKeyCode::Tab), Some(HostKey::Tab));
+    assert_eq!(host_key(VirtualKeyCode::Return), Some(HostKey::Enter));
+    assert_eq!(host_key(VirtualKeyCode::Escape), None);
+}
@@ -0,0 +1,791 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::controller::{Button, GameController};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::PixelFormatEnum;
+
+use chip8::audio::{AudioPattern, SampleGenerator, Tone};
+use chip8::display::{Display, DISPLAY_HEIGHT_PX, DISPLAY_WIDTH_PX};
+
+use super::effects::{self, Effects};
+use super::keypad::{canvas_pixel, canvas_size, key_at, KeypadPanel};
+use super::viewport::Viewport;
+use super::{input_event, pixel_scale, width_scale, Colors, EditKey, Frontend, FrontendSettings, HostKey, InputEvent};
+use crate::audio_sink::AudioSink;
+use crate::text::{text_pixels, CHAR_WIDTH, LINE_HEIGHT};
+use crate::views::View;
+
+/// SDL also sends mouse events for touches, from this mouse ID, which
+/// are ignored in favor of the touch events themselves.
+const TOUCH_MOUSE_ID: u32 = u32::MAX;
+
+/// With --vsync, frames are paced by the display's refresh if it's
+/// within this many Hz of 60. Otherwise they're timed with sleeps.
+const VSYNC_TOLERANCE_HZ: i32 = 1;
+
+/// Each pixel of the font is this many window pixels across in the
+/// debug views' windows.
+const VIEW_SCALE: u32 = 3;
+
+/// A window showing one of the debug views.
+struct ViewWindow {
+    title: &'static str,
+    /// None once the user has closed it
+    canvas: Option<sdl2::render::Canvas<sdl2::video::Window>>,
+    /// What it's showing, or nothing if it needs to be redrawn
+    lines: Vec<String>,
+}
+
+/// Something pressing keys on the keypad panel.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Pointer {
+    Mouse,
+    /// A finger on a touch screen, by SDL's finger ID
+    Finger(i64),
+}
+
+/// Draws the display to an SDL window, and any debug views to windows
+/// of their own, and reads the keyboard, any gamepads, and clicks and
+/// touches on the keypad panel.
+pub(crate) struct SdlFrontend {
+    canvas: sdl2::render::Canvas<sdl2::video::Window>,
+    event_pump: sdl2::EventPump,
+    /// None if gamepads aren't supported
+    controller_subsystem: Option<sdl2::GameControllerSubsystem>,
+    /// Open gamepads, numbered by their position plus one. Slots of
+    /// disconnected gamepads are kept so the others keep their numbers.
+    gamepads: Vec<Option<GameController>>,
+    /// None if the audio device couldn't be opened
+    audio: Option<SdlAudio>,
+    keypad: KeypadPanel,
+    /// The keys being pressed on the keypad panel
+    pointers: Vec<(Pointer, u8)>,
+    /// The size of the display when it was last painted, for finding
+    /// the keypad panel beside it
+    display_size: (usize, usize),
+    /// The size of the display and the keypad panel when they were last
+    /// painted. The window is resized to fit when this changes
+    canvas_size: (usize, usize),
+    /// Where they were painted in the window
+    viewport: Viewport,
+    /// Whether presenting waits for a display refreshing at 60 Hz
+    paced_by_vsync: bool,
+    /// The post-processing F10 turns on and off
+    effects: Effects,
+    effects_on: bool,
+    /// The window's pixels, when drawing with effects
+    effects_buffer: Vec<u8>,
+    /// Whether to paint even if nothing has changed
+    repaint: bool,
+    video: sdl2::VideoSubsystem,
+    views: Vec<ViewWindow>,
+    settings: FrontendSettings,
+}
+
+impl SdlFrontend {
+    pub(crate) fn new(settings: FrontendSettings) -> Result<SdlFrontend, String> {
+        let sdl_context = sdl2::init()?;
+        let event_pump = sdl_context.event_pump()?;
+        // SDL sends an event for each gamepad that's already connected,
+        // which opens it
+        let controller_subsystem = sdl_context
+            .game_controller()
+            .map_err(|err| log::warn!("Gamepads aren't supported: {}", err))
+            .ok();
+        let audio = open_audio(&sdl_context, settings.tone)
+            .map_err(|err| log::warn!("No sound, couldn't open the audio device: {}", err))
+            .ok();
+        let canvas = create_sdl_window(&sdl_context, &settings);
+        let paced_by_vsync = settings.vsync && {
+            let refresh_rate = refresh_rate(&sdl_context, canvas.window());
+            let at_60_hz = refresh_rate
+                .as_ref()
+                .is_ok_and(|hz| (hz - 60).abs() <= VSYNC_TOLERANCE_HZ);
+            if !at_60_hz {
+                let refresh_rate = refresh_rate.map_or_else(|err| err, |hz| format!("{} Hz", hz));
+                log::info!(
+                    "The display's refresh rate is {}, not 60 Hz, so frames are timed with sleeps",
+                    refresh_rate
+                );
+            }
+            at_60_hz
+        };
+        let viewport = Viewport::new(
+            settings.scale_mode,
+            (DISPLAY_WIDTH_PX, DISPLAY_HEIGHT_PX),
+            canvas.window().size(),
+        );
+        Ok(SdlFrontend {
+            canvas,
+            event_pump,
+            controller_subsystem,
+            gamepads: Vec::new(),
+            audio,
+            keypad: KeypadPanel::new(&settings.keymap, settings.show_keypad),
+            pointers: Vec::new(),
+            display_size: (DISPLAY_WIDTH_PX, DISPLAY_HEIGHT_PX),
+            canvas_size: (DISPLAY_WIDTH_PX, DISPLAY_HEIGHT_PX),
+            viewport,
+            paced_by_vsync,
+            effects: settings.effects.unwrap_or_default(),
+            effects_on: settings.effects.is_some(),
+            effects_buffer: Vec::new(),
+            repaint: false,
+            video: sdl_context.video()?,
+            views: Vec::new(),
+            settings,
+        })
+    }
+}
+
+impl SdlFrontend {
+    fn open_gamepad(&mut self, joystick_index: u32) {
+        let subsystem = match &self.controller_subsystem {
+            Some(subsystem) => subsystem,
+            None => return,
+        };
+        match subsystem.open(joystick_index) {
+            Ok(gamepad) => match self.gamepads.iter_mut().find(|slot| slot.is_none()) {
+                Some(slot) => *slot = Some(gamepad),
+                None => self.gamepads.push(Some(gamepad)),
+            },
+            Err(err) => log::error!("Error opening gamepad: {}", err),
+        }
+    }
+
+    /// A button on the gamepad with the SDL instance ID `which`.
+    fn gamepad_button(&self, which: u32, button: Button) -> Option<HostKey> {
+        let pad = self
+            .gamepads
+            .iter()
+            .position(|slot| slot.as_ref().is_some_and(|gamepad| gamepad.instance_id() == which))?;
+        let button = match button {
+            Button::A => "a",
+            Button::B => "b",
+            Button::X => "x",
+            Button::Y => "y",
+            Button::Back => "back",
+            Button::Start => "start",
+            Button::LeftShoulder => "left-shoulder",
+            Button::RightShoulder => "right-shoulder",
+            Button::DPadUp => "dpad-up",
+            Button::DPadDown => "dpad-down",
+            Button::DPadLeft => "dpad-left",
+            Button::DPadRight => "dpad-right",
+            _ => return None,
+        };
+        Some(HostKey::Button { pad: pad + 1, button })
+    }
+
+    /// Press or release keys on the keypad panel for a pointer that's
+    /// down at a point in the window, or has been lifted if it's None.
+    /// Sliding a pointer from one key to another presses the new one.
+    fn move_pointer(&mut self, pointer: Pointer, down_at: Option<(f32, f32)>, events: &mut Vec<InputEvent>) {
+        let key = down_at
+            .and_then(|(x, y)| self.viewport.canvas_position(x, y))
+            .and_then(|(x, y)| key_at(self.display_size, &self.keypad, x, y));
+        let old_key = self
+            .pointers
+            .iter()
+            .position(|&(p, _)| p == pointer)
+            .map(|i| self.pointers.remove(i).1);
+        if let Some(key) = key {
+            self.pointers.push((pointer, key));
+        }
+        if key == old_key {
+            return;
+        }
+        if let Some(key) = old_key {
+            events.push(InputEvent::Key { key, pressed: false });
+        }
+        if let Some(key) = key {
+            events.push(InputEvent::Key { key, pressed: true });
+        }
+    }
+
+    /// Draw the display, and the keypad panel beside it, with effects.
+    /// They're drawn at the starting scale and then stretched to fit
+    /// the viewport.
+    fn draw_with_effects(&mut self, display: &Display, size: (usize, usize)) -> Result<(), String> {
+        let scale = pixel_scale(self.settings.scale, display);
+        let keypad = &self.keypad;
+        effects::render(
+            &mut self.effects_buffer,
+            size,
+            scale as usize,
+            self.settings.colors,
+            self.effects,
+            |x, y| canvas_pixel(display, keypad, x, y),
+        );
+        let (width, height) = (size.0 as u32 * scale, size.1 as u32 * scale);
+        let texture_creator = self.canvas.texture_creator();
+        let mut texture = texture_creator
+            .create_texture_static(PixelFormatEnum::RGB24, width, height)
+            .map_err(|err| err.to_string())?;
+        texture
+            .update(None, &self.effects_buffer, width as usize * 3)
+            .map_err(|err| err.to_string())?;
+        let viewport = self.viewport;
+        let target = sdl2::rect::Rect::new(viewport.x as i32, viewport.y as i32, viewport.width, viewport.height);
+        self.canvas.copy(&texture, None, target)
+    }
+
+    /// Get ready to paint a picture `size` pixels across and down, each
+    /// drawn `scale` screen pixels wide, clearing the window to the
+    /// black borders around it. The window is resized to fit when the
+    /// size changes, and otherwise keeps whatever size the user gave it.
+    fn fit_canvas(&mut self, size: (usize, usize), scale: u32) {
+        if size != self.canvas_size && !self.settings.fullscreen {
+            if let Err(err) = self
+                .canvas
+                .window_mut()
+                .set_size(size.0 as u32 * scale, size.1 as u32 * scale)
+            {
+                log::error!("Error resizing window: {}", err);
+            }
+        }
+        self.canvas_size = size;
+        self.viewport = Viewport::new(self.settings.scale_mode, size, self.canvas.window().size());
+
+        self.canvas.set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
+        self.canvas.clear();
+    }
+
+    /// Draw a picture of RGBA bytes, `size` pixels across and down,
+    /// stretched to fit the viewport.
+    fn draw_rgba(&mut self, size: (usize, usize), rgba: &[u8]) -> Result<(), String> {
+        let texture_creator = self.canvas.texture_creator();
+        let mut texture = texture_creator
+            .create_texture_static(PixelFormatEnum::RGBA32, size.0 as u32, size.1 as u32)
+            .map_err(|err| err.to_string())?;
+        texture.update(None, rgba, size.0 * 4).map_err(|err| err.to_string())?;
+        let viewport = self.viewport;
+        let target = sdl2::rect::Rect::new(viewport.x as i32, viewport.y as i32, viewport.width, viewport.height);
+        self.canvas.copy(&texture, None, target)
+    }
+
+    /// Whether `window_id` is the display's window rather than a view's.
+    fn is_main_window(&self, window_id: u32) -> bool {
+        window_id == self.canvas.window().id()
+    }
+
+    /// The view shown in the window `window_id`, if any.
+    fn view_window(&mut self, window_id: u32) -> Option<&mut ViewWindow> {
+        self.views.iter_mut().find(|view| {
+            view.canvas
+                .as_ref()
+                .is_some_and(|canvas| canvas.window().id() == window_id)
+        })
+    }
+
+    /// Open a window for the view called `title`.
+    fn open_view(&self, title: &'static str) -> Result<ViewWindow, String> {
+        let window = self
+            .video
+            .window(title, 1, 1)
+            .resizable()
+            .build()
+            .map_err(|err| err.to_string())?;
+        let canvas = window.into_canvas().build().map_err(|err| err.to_string())?;
+        Ok(ViewWindow {
+            title,
+            canvas: Some(canvas),
+            lines: Vec::new(),
+        })
+    }
+
+    fn present(&mut self, display: &mut Display) {
+        self.canvas.present();
+        self.display_size = (display.width(), display.height());
+        display.mark_painted();
+        self.keypad.mark_painted();
+        self.repaint = false;
+    }
+
+    /// Where a finger is in the window, from SDL's coordinates, which
+    /// go from 0 to 1 across it.
+    fn finger_position(&self, x: f32, y: f32) -> (f32, f32) {
+        let (width, height) = self.canvas.window().size();
+        (x * width as f32, y * height as f32)
+    }
+}
+
+/// Plays the buzzer from SDL's audio thread.
+struct Buzzer {
+    generator: SampleGenerator,
+    on: bool,
+    /// Samples played so far
+    position: u64,
+    /// How many samples later than it's set the buzzer starts and stops
+    delay: u64,
+    /// Starts and stops waiting for their delay, by the sample they're
+    /// due at
+    changes: VecDeque<(u64, bool)>,
+}
+
+impl Buzzer {
+    fn new(tone: Tone, sample_rate: u32) -> Buzzer {
+        Buzzer {
+            generator: SampleGenerator::new(tone, sample_rate),
+            on: false,
+            position: 0,
+            delay: 0,
+            changes: VecDeque::new(),
+        }
+    }
+
+    fn set(&mut self, on: bool) {
+        let last = self.changes.back().map_or(self.on, |&(_, on)| on);
+        if on != last {
+            self.changes.push_back((self.position + self.delay, on));
+        }
+    }
+}
+
+impl AudioCallback for Buzzer {
+    type Channel = f32;
+
+    fn callback(&mut self, mut out: &mut [f32]) {
+        while !out.is_empty() {
+            // Play up to the next change, if it's due in this buffer
+            let samples = match self.changes.front() {
+                Some(&(at, on)) if at <= self.position => {
+                    self.on = on;
+                    self.changes.pop_front();
+                    continue;
+                }
+                Some(&(at, _)) => ((at - self.position) as usize).min(out.len()),
+                None => out.len(),
+            };
+            let (now, rest) = std::mem::take(&mut out).split_at_mut(samples);
+            self.generator.fill(now, self.on);
+            self.position += samples as u64;
+            out = rest;
+        }
+    }
+}
+
+/// The speakers, through SDL's audio device.
+struct SdlAudio(AudioDevice<Buzzer>);
+
+impl AudioSink for SdlAudio {
+    fn set_buzzer(&mut self, on: bool) {
+        self.0.lock().set(on);
+    }
+
+    fn set_delay(&mut self, delay: Duration) {
+        let samples = delay.as_secs_f64() * self.0.spec().freq as f64;
+        self.0.lock().delay = samples.round() as u64;
+    }
+
+    fn latency(&self) -> Option<Duration> {
+        let spec = self.0.spec();
+        Some(Duration::from_secs_f64(spec.samples as f64 / spec.freq as f64))
+    }
+
+    fn set_tone(&mut self, tone: Tone) {
+        self.0.lock().generator.set_tone(tone);
+    }
+
+    fn set_audio_pattern(&mut self, pattern: Option<AudioPattern>) {
+        self.0.lock().generator.set_pattern(pattern);
+    }
+}
+
+/// Open the audio device and start it playing. It plays silence until
+/// the buzzer is turned on, so the buzzer can fade out when it stops.
+fn open_audio(sdl_context: &sdl2::Sdl, tone: Tone) -> Result<SdlAudio, String> {
+    let desired = AudioSpecDesired {
+        freq: Some(44_100),
+        channels: Some(1),
+        samples: None,
+    };
+    let device = sdl_context
+        .audio()?
+        .open_playback(None, &desired, |spec| Buzzer::new(tone, spec.freq as u32))?;
+    device.resume();
+    Ok(SdlAudio(device))
+}
+
+impl Frontend for SdlFrontend {
+    fn poll_events(&mut self) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+        let sdl_events: Vec<Event> = self.event_pump.poll_iter().collect();
+        for event in sdl_events {
+            let (key, pressed) = match event {
+                Event::Quit { .. } => {
+                    events.push(InputEvent::Quit);
+                    continue;
+                }
+                // Keys typed in a view's window edit its values rather
+                // than pressing keypad keys
+                Event::KeyDown {
+                    window_id,
+                    keycode: Some(keycode),
+                    ..
+                } if !self.is_main_window(window_id) && edit_key(keycode).is_some() => {
+                    events.extend(edit_key(keycode).map(InputEvent::ViewKey));
+                    continue;
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode), ..
+                } => (host_key(keycode), true),
+                Event::KeyUp {
+                    keycode: Some(keycode), ..
+                } => (host_key(keycode), false),
+                Event::ControllerButtonDown { which, button, .. } => (self.gamepad_button(which, button), true),
+                Event::ControllerButtonUp { which, button, .. } => (self.gamepad_button(which, button), false),
+                Event::Window {
+                    window_id,
+                    win_event: WindowEvent::Close,
+                    ..
+                } => {
+                    // SDL only sends Quit once every window is closed
+                    if self.is_main_window(window_id) {
+                        events.push(InputEvent::Quit);
+                    }
+                    if let Some(view) = self.view_window(window_id) {
+                        view.canvas = None;
+                    }
+                    continue;
+                }
+                Event::Window {
+                    window_id,
+                    win_event: WindowEvent::SizeChanged(..) | WindowEvent::Exposed,
+                    ..
+                } => {
+                    if self.is_main_window(window_id) {
+                        self.repaint = true;
+                    }
+                    // Redraw views too, which is cheap
+                    for view in &mut self.views {
+                        view.lines.clear();
+                    }
+                    continue;
+                }
+                Event::MouseButtonDown {
+                    window_id,
+                    which,
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                } if which != TOUCH_MOUSE_ID && self.is_main_window(window_id) => {
+                    self.move_pointer(Pointer::Mouse, Some((x as f32, y as f32)), &mut events);
+                    continue;
+                }
+                Event::MouseButtonDown {
+                    window_id,
+                    which,
+                    mouse_btn: mouse_btn @ (MouseButton::Left | MouseButton::Right),
+                    x,
+                    y,
+                    ..
+                } if which != TOUCH_MOUSE_ID => {
+                    if let Some(view) = self.view_window(window_id) {
+                        // Views are drawn with a pixel's margin
+                        let line = (y.max(0) as u32 / VIEW_SCALE).saturating_sub(1) as usize / LINE_HEIGHT;
+                        let column = (x.max(0) as u32 / VIEW_SCALE).saturating_sub(1) as usize / CHAR_WIDTH;
+                        if line < view.lines.len() {
+                            events.push(InputEvent::ViewClicked {
+                                view: view.title,
+                                line,
+                                column,
+                                right: mouse_btn == MouseButton::Right,
+                            });
+                        }
+                    }
+                    continue;
+                }
+                Event::MouseMotion {
+                    window_id,
+                    which,
+                    mousestate,
+                    x,
+                    y,
+                    ..
+                } if which != TOUCH_MOUSE_ID && mousestate.left() && self.is_main_window(window_id) => {
+                    self.move_pointer(Pointer::Mouse, Some((x as f32, y as f32)), &mut events);
+                    continue;
+                }
+                Event::MouseButtonUp {
+                    which,
+                    mouse_btn: MouseButton::Left,
+                    ..
+                } if which != TOUCH_MOUSE_ID => {
+                    self.move_pointer(Pointer::Mouse, None, &mut events);
+                    continue;
+                }
+                Event::FingerDown { finger_id, x, y, .. } | Event::FingerMotion { finger_id, x, y, .. } => {
+                    let position = self.finger_position(x, y);
+                    self.move_pointer(Pointer::Finger(finger_id), Some(position), &mut events);
+                    continue;
+                }
+                Event::FingerUp { finger_id, .. } => {
+                    self.move_pointer(Pointer::Finger(finger_id), None, &mut events);
+                    continue;
+                }
+                Event::ControllerDeviceAdded { which, .. } => {
+                    self.open_gamepad(which);
+                    continue;
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    for slot in &mut self.gamepads {
+                        if slot.as_ref().is_some_and(|gamepad| gamepad.instance_id() == which) {
+                            *slot = None;
+                        }
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            if let Some(event) = key.and_then(|key| input_event(&self.settings.keymap, key, pressed)) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    fn paint(&mut self, display: &mut Display) {
+        // When frames are paced by vsync every frame is presented, as
+        // presenting is what waits for the refresh
+        if !display.needs_repaint() && !self.keypad.needs_repaint() && !self.repaint && !self.paced_by_vsync {
+            return;
+        }
+
+        // Two-page hi-res ROMs change the height of the display, and the
+        // keypad panel widens the window
+        let (width, height) = canvas_size(display, &self.keypad);
+        self.fit_canvas((width, height), pixel_scale(self.settings.scale, display));
+
+        if self.effects_on {
+            if let Err(err) = self.draw_with_effects(display, (width, height)) {
+                log::error!("Error drawing with effects: {}", err);
+                std::process::exit(1);
+            }
+            self.present(display);
+            return;
+        }
+
+        let viewport = self.viewport;
+        let [r, g, b] = self.settings.colors.background;
+        self.canvas.set_draw_color(sdl2::pixels::Color::RGB(r, g, b));
+        let background = sdl2::rect::Rect::new(viewport.x as i32, viewport.y as i32, viewport.width, viewport.height);
+        if let Err(err) = self.canvas.fill_rect(background) {
+            log::error!("Error drawing rectangle {:?}: {}", background, err);
+            std::process::exit(1);
+        }
+
+        for i in 0..width {
+            for j in 0..height {
+                let planes = canvas_pixel(display, &self.keypad, i, j);
+                if planes != 0 {
+                    let [r, g, b] = self.settings.colors.color(planes);
+                    self.canvas.set_draw_color(sdl2::pixels::Color::RGB(r, g, b));
+                    let (x, y, width, height) = viewport.pixel_rect(i, j);
+                    let rect = sdl2::rect::Rect::new(x, y, width, height);
+                    if let Err(err) = self.canvas.fill_rect(rect) {
+                        log::error!("Error drawing rectangle {:?}: {}", rect, err);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+
+        self.present(display);
+    }
+
+    fn paint_rgba(&mut self, width: usize, height: usize, rgba: &[u8]) {
+        self.fit_canvas((width, height), width_scale(self.settings.scale, width));
+        if let Err(err) = self.draw_rgba((width, height), rgba) {
+            log::error!("Error drawing frame: {}", err);
+            std::process::exit(1);
+        }
+        self.canvas.present();
+        // The display has to be drawn again when it's shown instead
+        self.repaint = true;
+    }
+
+    fn set_title(&mut self, title: &str) {
+        // Titles never contain NUL bytes, so this can't fail
+        let _ = self.canvas.window_mut().set_title(title);
+    }
+
+    fn audio(&mut self) -> Option<&mut dyn AudioSink> {
+        self.audio.as_mut().map(|audio| audio as &mut dyn AudioSink)
+    }
+
+    fn paced_by_vsync(&self) -> bool {
+        self.paced_by_vsync
+    }
+
+    fn toggle_effects(&mut self) {
+        self.effects_on = !self.effects_on;
+        self.repaint = true;
+    }
+
+    fn show_views(&mut self, views: &[View]) {
+        for view in views {
+            let index = match self.views.iter().position(|window| window.title == view.title) {
+                Some(index) => index,
+                None => {
+                    let window = self.open_view(view.title).unwrap_or_else(|err| {
+                        log::error!("Error opening the {} window: {}", view.title, err);
+                        ViewWindow {
+                            title: view.title,
+                            canvas: None,
+                            lines: Vec::new(),
+                        }
+                    });
+                    self.views.push(window);
+                    self.views.len() - 1
+                }
+            };
+            let window = &mut self.views[index];
+            if let Some(canvas) = &mut window.canvas {
+                if window.lines != view.lines {
+                    window.lines = view.lines.clone();
+                    if let Err(err) = draw_view(canvas, &window.lines, self.settings.colors) {
+                        log::error!("Error drawing the {} window: {}", view.title, err);
+                    }
+                }
+            }
+        }
+    }
+
+    fn set_keys_pressed(&mut self, pressed: &[bool; 16]) {
+        self.keypad.set_pressed(&self.settings.keymap, pressed);
+    }
+
+    fn toggle_keypad(&mut self) {
+        self.keypad.toggle(&self.settings.keymap);
+    }
+}
+
+fn create_sdl_window(
+    sdl_context: &sdl2::Sdl,
+    settings: &FrontendSettings,
+) -> sdl2::render::Canvas<sdl2::video::Window> {
+    let video_subsystem = sdl_context.video().expect("failed to init SDL video subsystem");
+
+    let window_width = DISPLAY_WIDTH_PX as u32 * settings.scale;
+    let window_height = DISPLAY_HEIGHT_PX as u32 * settings.scale;
+    let mut window = video_subsystem.window("CHIP-8", window_width, window_height);
+    window.position_centered().resizable().opengl();
+    if settings.fullscreen {
+        window.fullscreen_desktop();
+    }
+    let window = window.build().expect("failed to create SDL window");
+    let mut canvas = window.into_canvas();
+    if settings.vsync {
+        canvas = canvas.present_vsync();
+    }
+    canvas.build().expect("failed to create SDL canvas")
+}
+
+/// Draw a debug view's lines of text, growing the window to fit them.
+fn draw_view(
+    canvas: &mut sdl2::render::Canvas<sdl2::video::Window>,
+    lines: &[String],
+    colors: Colors,
+) -> Result<(), String> {
+    let columns = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+    // A pixel's margin around the text
+    let width = ((columns * CHAR_WIDTH + 1) as u32 * VIEW_SCALE).max(canvas.window().size().0);
+    let height = ((lines.len() * LINE_HEIGHT + 1) as u32 * VIEW_SCALE).max(canvas.window().size().1);
+    if canvas.window().size() != (width, height) {
+        canvas
+            .window_mut()
+            .set_size(width, height)
+            .map_err(|err| err.to_string())?;
+    }
+
+    let [r, g, b] = colors.background;
+    canvas.set_draw_color(sdl2::pixels::Color::RGB(r, g, b));
+    canvas.clear();
+    let mut rects = Vec::new();
+    for (row, line) in lines.iter().enumerate() {
+        text_pixels(line, |x, y| {
+            let x = (x + 1) as u32 * VIEW_SCALE;
+            let y = (row * LINE_HEIGHT + y + 1) as u32 * VIEW_SCALE;
+            rects.push(sdl2::rect::Rect::new(x as i32, y as i32, VIEW_SCALE, VIEW_SCALE));
+        });
+    }
+    let [r, g, b] = colors.foreground;
+    canvas.set_draw_color(sdl2::pixels::Color::RGB(r, g, b));
+    canvas.fill_rects(&rects)?;
+    canvas.present();
+    Ok(())
+}
+
+/// The refresh rate of the display the window is on, in Hz.
+fn refresh_rate(sdl_context: &sdl2::Sdl, window: &sdl2::video::Window) -> Result<i32, String> {
+    let mode = sdl_context.video()?.current_display_mode(window.display_index()?)?;
+    match mode.refresh_rate {
+        // SDL reports 0 when it doesn't know
+        0 => Err("unknown".to_string()),
+        hz => Ok(hz),
+    }
+}
+
+/// The key for editing a value in a debug view that `keycode` is, if
+/// any.
+fn edit_key(keycode: Keycode) -> Option<EditKey> {
+    match keycode {
+        Keycode::Return | Keycode::KpEnter => Some(EditKey::Enter),
+        Keycode::Backspace => Some(EditKey::Backspace),
+        Keycode::Escape => Some(EditKey::Escape),
+        _ => char::from_u32(keycode as u32)
+            .and_then(|c| c.to_digit(16))
+            .map(|digit| EditKey::Digit(digit as u8)),
+    }
+}
+
+fn host_key(keycode: Keycode) -> Option<HostKey> {
+    // SDL key codes for character keys are the (lowercase) character
+    // itself, and the function keys are numbered consecutively
+    match keycode {
+        Keycode::Tab => return Some(HostKey::Tab),
+        Keycode::Up => return Some(HostKey::Up),
+        Keycode::Down => return Some(HostKey::Down),
+        Keycode::Return => return Some(HostKey::Enter),
+        _ => {}
+    }
+    let code = keycode as i32;
+    let f1 = Keycode::F1 as i32;
+    if (f1..=Keycode::F12 as i32).contains(&code) {
+        return Some(HostKey::Function((code - f1 + 1) as u8));
+    }
+    char::from_u32(code as u32)
+        .filter(|c| c.is_ascii_graphic() || *c == ' ')
+        .map(HostKey::Char)
+}
+
+#[test]
+fn test_buzzer_delay() {
+    let tone = Tone {
+        attack_ms: 0.0,
+        release_ms: 0.0,
+        ..Tone::default()
+    };
+    let mut buzzer = Buzzer::new(tone, 1000);
+    buzzer.delay = 10;
+    buzzer.set(true);
+    let mut out = [0.0; 16];
+    buzzer.callback(&mut out);
+    assert!(out[..10].iter().all(|&sample| sample == 0.0));
+    assert!(out[10..].iter().all(|&sample| sample != 0.0));
+
+    // Stopped 10 samples from now, during the next buffer
+    buzzer.set(false);
+    buzzer.set(false);
+    assert_eq!(buzzer.changes.len(), 1);
+    let mut out = [0.0; 16];
+    buzzer.callback(&mut out);
+    assert!(out[..10].iter().all(|&sample| sample != 0.0));
+    assert!(out[10..].iter().all(|&sample| sample == 0.0));
+}
@@ -0,0 +1,242 @@
+//! Draws the display in the terminal, so the emulator can run over SSH
+//! with no graphical environment. Each character cell shows two pixels
+//! stacked vertically using Unicode half blocks.
+
+use std::io;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags, PopKeyboardEnhancementFlags,
+    PushKeyboardEnhancementFlags,
+};
+use crossterm::style::{Color, Print, ResetColor, SetColors};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen, SetTitle};
+use crossterm::{execute, queue};
+
+use chip8::display::Display;
+
+use super::keypad::{canvas_pixel, canvas_size, KeypadPanel};
+use super::{input_event, Frontend, FrontendSettings, HostKey, Hotkey, InputEvent};
+
+/// Most terminals only report key presses, not releases. In that case a
+/// key counts as held until this long after its last press (or
+/// auto-repeat), which is a bit longer than a typical repeat delay.
+const KEY_HOLD_TIME: Duration = Duration::from_millis(500);
+
+pub(crate) struct TuiFrontend {
+    keypad: KeypadPanel,
+    settings: FrontendSettings,
+
+    /// Whether the terminal reports key releases
+    reports_releases: bool,
+
+    /// When each held key was last pressed, if the terminal doesn't
+    /// report releases
+    held_since: [Option<Instant>; 16],
+
+    /// Likewise for the fast-forward key
+    fast_forward_since: Option<Instant>,
+}
+
+impl TuiFrontend {
+    pub(crate) fn new(settings: FrontendSettings) -> Result<TuiFrontend, String> {
+        let err = |err: io::Error| format!("Error setting up terminal: {}", err);
+        terminal::enable_raw_mode().map_err(err)?;
+        execute!(io::stdout(), EnterAlternateScreen, Hide).map_err(err)?;
+
+        let reports_releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if reports_releases {
+            execute!(
+                io::stdout(),
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )
+            .map_err(err)?;
+        }
+
+        Ok(TuiFrontend {
+            keypad: KeypadPanel::new(&settings.keymap, settings.show_keypad),
+            settings,
+            reports_releases,
+            held_since: [None; 16],
+            fast_forward_since: None,
+        })
+    }
+
+    fn handle_key(&mut self, key_event: KeyEvent, events: &mut Vec<InputEvent>) {
+        let is_ctrl_c = key_event.code == KeyCode::Char('c') && key_event.modifiers.contains(KeyModifiers::CONTROL);
+        if key_event.code == KeyCode::Esc || is_ctrl_c {
+            events.push(InputEvent::Quit);
+            return;
+        }
+
+        let host_key = match key_event.code {
+            KeyCode::Char(c) => HostKey::Char(c),
+            KeyCode::F(n) => HostKey::Function(n),
+            KeyCode::Tab => HostKey::Tab,
+            KeyCode::Up => HostKey::Up,
+            KeyCode::Down => HostKey::Down,
+            KeyCode::Enter => HostKey::Enter,
+            _ => return,
+        };
+        let pressed = key_event.kind != KeyEventKind::Release;
+        let event = match input_event(&self.settings.keymap, host_key, pressed) {
+            Some(event) => event,
+            None => return,
+        };
+        if !self.reports_releases {
+            match event {
+                InputEvent::Key { key, .. } => self.held_since[key as usize] = Some(Instant::now()),
+                InputEvent::Hotkey {
+                    hotkey: Hotkey::FastForward,
+                    ..
+                } => self.fast_forward_since = Some(Instant::now()),
+                _ => {}
+            }
+        }
+        events.push(event);
+    }
+}
+
+impl Frontend for TuiFrontend {
+    fn poll_events(&mut self) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+        while let Ok(true) = event::poll(Duration::ZERO) {
+            match event::read() {
+                Ok(Event::Key(key_event)) => self.handle_key(key_event, &mut events),
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+
+        let now = Instant::now();
+        for (key, held_since) in self.held_since.iter_mut().enumerate() {
+            if held_since.is_some_and(|since| now - since > KEY_HOLD_TIME) {
+                *held_since = None;
+                events.push(InputEvent::Key {
+                    key: key as u8,
+                    pressed: false,
+                });
+            }
+        }
+        if self.fast_forward_since.is_some_and(|since| now - since > KEY_HOLD_TIME) {
+            self.fast_forward_since = None;
+            events.push(InputEvent::Hotkey {
+                hotkey: Hotkey::FastForward,
+                pressed: false,
+            });
+        }
+        events
+    }
+
+    fn paint(&mut self, display: &mut Display) {
+        if !display.needs_repaint() && !self.keypad.needs_repaint() {
+            return;
+        }
+
+        let rgb = |[r, g, b]: [u8; 3]| Color::Rgb { r, g, b };
+        let colors = self.settings.colors;
+        let mut stdout = io::stdout().lock();
+        let _ = queue!(
+            stdout,
+            SetColors(crossterm::style::Colors::new(
+                rgb(colors.foreground),
+                rgb(colors.background)
+            ))
+        );
+        // Two pixel rows per line. Only the lines the display changed
+        // on need redrawing, unless the keypad panel changed too
+        let damage = display.take_damage().rows();
+        let redraw_all = self.keypad.needs_repaint() || damage.len() == display.height();
+        let lines = damage.start / 2..damage.end.div_ceil(2);
+        for (row, line) in render_rows(display, &self.keypad).iter().enumerate() {
+            if !redraw_all && !lines.contains(&row) {
+                continue;
+            }
+            // Clear what's left of the line in case the keypad panel
+            // was just hidden
+            let _ = queue!(
+                stdout,
+                MoveTo(0, row as u16),
+                Print(line),
+                Clear(ClearType::UntilNewLine)
+            );
+        }
+        if redraw_all {
+            // In case the display got shorter after leaving hi-res mode
+            let _ = queue!(stdout, Clear(ClearType::FromCursorDown));
+        }
+        let _ = stdout.flush();
+        display.mark_painted();
+        self.keypad.mark_painted();
+    }
+
+    fn set_title(&mut self, title: &str) {
+        let _ = execute!(io::stdout(), SetTitle(title));
+    }
+
+    fn set_keys_pressed(&mut self, pressed: &[bool; 16]) {
+        self.keypad.set_pressed(&self.settings.keymap, pressed);
+    }
+
+    fn toggle_keypad(&mut self) {
+        self.keypad.toggle(&self.settings.keymap);
+    }
+}
+
+impl Drop for TuiFrontend {
+    fn drop(&mut self) {
+        if self.reports_releases {
+            let _ = execute!(io::stdout(), PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(io::stdout(), ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Render the display, with the keypad panel beside it if it's shown,
+/// as lines of half-block characters, two pixel rows per line.
+fn render_rows(display: &Display, keypad: &KeypadPanel) -> Vec<String> {
+    let (width, height) = canvas_size(display, keypad);
+    // The whole screen is drawn in one pair of colors, so every plane
+    // is in the foreground color
+    let pixel = |x, y| canvas_pixel(display, keypad, x, y) != 0;
+    (0..height)
+        .step_by(2)
+        .map(|y| {
+            (0..width)
+                .map(|x| match (pixel(x, y), pixel(x, y + 1)) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn test_render_rows() {
+    use chip8::display::{DISPLAY_HEIGHT_PX, DISPLAY_WIDTH_PX, HIRES_DISPLAY_HEIGHT_PX};
+
+    let mut keypad = KeypadPanel::new(&super::Keymap::default(), false);
+    let mut display = Display::new();
+    display.set_pixel(0, 0, true);
+    display.set_pixel(1, 1, true);
+    display.set_pixel(2, 0, true);
+    display.set_pixel(2, 1, true);
+    let rows = render_rows(&display, &keypad);
+    assert_eq!(rows.len(), DISPLAY_HEIGHT_PX / 2);
+    assert!(rows[0].starts_with("▀▄█ "));
+    assert_eq!(rows[0].chars().count(), DISPLAY_WIDTH_PX);
+
+    display.set_hires(true);
+    assert_eq!(render_rows(&display, &keypad).len(), HIRES_DISPLAY_HEIGHT_PX / 2);
+
+    // The keypad panel goes to the right of the display
+    keypad.toggle(&super::Keymap::default());
+    let rows = render_rows(&display, &keypad);
+    assert_eq!(rows[0].chars().count(), 2 * DISPLAY_WIDTH_PX + DISPLAY_WIDTH_PX);
+}
@@ -0,0 +1,119 @@
+//! Where the display goes in a window of any size, which `--scale-mode`
+//! chooses. The window starts at `--scale` times the size of the
+//! display, where every mode gives the same result, but it can be
+//! resized or made fullscreen.
+
+/// How the display is scaled to fill the window.
+#[derive(Debug, Default, PartialEq, Clone, Copy, clap::ValueEnum)]
+pub(crate) enum ScaleMode {
+    /// The largest whole number of window pixels per CHIP-8 pixel that
+    /// fits, centered with black borders, so every pixel is the same
+    /// size
+    #[default]
+    Integer,
+    /// Fill the window, even if the pixels end up stretched
+    Stretch,
+    /// As large as fits while keeping the display's shape, centered
+    /// with black borders
+    Fit,
+}
+
+/// The part of the window the display is drawn to.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) struct Viewport {
+    pub(crate) x: u32,
+    pub(crate) y: u32,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    /// The size of the display, in CHIP-8 pixels
+    canvas: (usize, usize),
+}
+
+impl Viewport {
+    /// Lay out a display of `canvas` pixels in a window of `window`
+    /// pixels.
+    pub(crate) fn new(mode: ScaleMode, canvas: (usize, usize), window: (u32, u32)) -> Viewport {
+        let (canvas_width, canvas_height) = (canvas.0.max(1) as u32, canvas.1.max(1) as u32);
+        let (width, height) = match mode {
+            ScaleMode::Stretch => window,
+            ScaleMode::Integer => {
+                let scale = (window.0 / canvas_width).min(window.1 / canvas_height).max(1);
+                (canvas_width * scale, canvas_height * scale)
+            }
+            ScaleMode::Fit => {
+                // Whichever of the window's sides runs out first
+                if window.0 as u64 * canvas_height as u64 <= window.1 as u64 * canvas_width as u64 {
+                    (window.0, window.0 * canvas_height / canvas_width)
+                } else {
+                    (window.1 * canvas_width / canvas_height, window.1)
+                }
+            }
+        };
+        Viewport {
+            x: window.0.saturating_sub(width) / 2,
+            y: window.1.saturating_sub(height) / 2,
+            width,
+            height,
+            canvas,
+        }
+    }
+
+    /// The window pixels covered by the CHIP-8 pixel at (`x`, `y`), as
+    /// (x, y, width, height). Pixels are spread over the viewport so
+    /// there are no gaps between them.
+    pub(crate) fn pixel_rect(&self, x: usize, y: usize) -> (i32, i32, u32, u32) {
+        let left = self.x + (x as u64 * self.width as u64 / self.canvas.0 as u64) as u32;
+        let right = self.x + ((x as u64 + 1) * self.width as u64 / self.canvas.0 as u64) as u32;
+        let top = self.y + (y as u64 * self.height as u64 / self.canvas.1 as u64) as u32;
+        let bottom = self.y + ((y as u64 + 1) * self.height as u64 / self.canvas.1 as u64) as u32;
+        (left as i32, top as i32, right - left, bottom - top)
+    }
+
+    /// The CHIP-8 pixel at the window position (`x`, `y`), if it's on
+    /// the display.
+    pub(crate) fn canvas_position(&self, x: f32, y: f32) -> Option<(usize, usize)> {
+        let x = (x - self.x as f32) / self.width as f32;
+        let y = (y - self.y as f32) / self.height as f32;
+        if !(0.0..1.0).contains(&x) || !(0.0..1.0).contains(&y) {
+            return None;
+        }
+        Some(((x * self.canvas.0 as f32) as usize, (y * self.canvas.1 as f32) as usize))
+    }
+}
+
+#[test]
+fn test_viewport() {
+    // At the starting size every mode fills the window
+    for mode in [ScaleMode::Integer, ScaleMode::Stretch, ScaleMode::Fit] {
+        let viewport = Viewport::new(mode, (64, 32), (512, 256));
+        assert_eq!(
+            (viewport.x, viewport.y, viewport.width, viewport.height),
+            (0, 0, 512, 256)
+        );
+    }
+
+    let integer = Viewport::new(ScaleMode::Integer, (64, 32), (1920, 1080));
+    assert_eq!(
+        (integer.x, integer.y, integer.width, integer.height),
+        (0, 60, 1920, 960)
+    );
+    assert_eq!(integer.pixel_rect(1, 1), (30, 90, 30, 30));
+
+    let fit = Viewport::new(ScaleMode::Fit, (64, 32), (1000, 1000));
+    assert_eq!((fit.x, fit.y, fit.width, fit.height), (0, 250, 1000, 500));
+    // Pixels are 15 or 16 window pixels across, with no gaps
+    assert_eq!(fit.pixel_rect(0, 0), (0, 250, 15, 15));
+    assert_eq!(fit.pixel_rect(1, 0), (15, 250, 16, 15));
+    assert_eq!(fit.pixel_rect(63, 31), (984, 734, 16, 16));
+
+    let stretch = Viewport::new(ScaleMode::Stretch, (64, 32), (640, 640));
+    assert_eq!(stretch.pixel_rect(1, 1), (10, 20, 10, 20));
+
+    assert_eq!(fit.canvas_position(999.0, 749.0), Some((63, 31)));
+    assert_eq!(fit.canvas_position(500.0, 100.0), None);
+
+    // Windows too small for the display still get one window pixel per
+    // pixel
+    let tiny = Viewport::new(ScaleMode::Integer, (64, 32), (32, 32));
+    assert_eq!((tiny.width, tiny.height), (64, 32));
+}
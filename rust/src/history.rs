@@ -0,0 +1,122 @@
+//! The recently played ROMs, most recent first, kept in
+//! `history.toml` next to the config file. Each entry remembers the
+//! settings it was run with on the command line, so F8 can switch back
+//! to it as it was:
+//!
+//! ```toml
+//! [[rom]]
+//! path = "/home/me/roms/test.ch8"
+//! speed = 2000
+//! platform = "vip"
+//! ```
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+
+/// How many ROMs to remember.
+const MAX_ENTRIES: usize = 10;
+
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct History {
+    #[serde(rename = "rom")]
+    entries: Vec<HistoryEntry>,
+}
+
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct HistoryEntry {
+    pub(crate) path: PathBuf,
+    pub(crate) speed: Option<u64>,
+    pub(crate) platform: Option<String>,
+    pub(crate) timer_hz: Option<u32>,
+}
+
+impl History {
+    /// Load the history, starting a new one if there isn't one yet or
+    /// it can't be read. It's only a convenience, so problems with it
+    /// are warnings rather than errors.
+    pub(crate) fn load() -> History {
+        let path = match default_path() {
+            Some(path) if path.exists() => path,
+            _ => return History::default(),
+        };
+        match fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|contents| History::parse(&contents))
+        {
+            Ok(history) => history,
+            Err(err) => {
+                log::warn!("Ignoring {}: {}", path.display(), err);
+                History::default()
+            }
+        }
+    }
+
+    fn parse(contents: &str) -> Result<History, String> {
+        toml::from_str(contents).map_err(|err| err.to_string())
+    }
+
+    pub(crate) fn save(&self) {
+        let result = default_path()
+            .ok_or_else(|| "no config directory".to_string())
+            .and_then(|path| {
+                let contents = toml::to_string(self).map_err(|err| err.to_string())?;
+                fs::create_dir_all(path.parent().unwrap_or(Path::new("."))).map_err(|err| err.to_string())?;
+                fs::write(&path, contents).map_err(|err| format!("{}: {}", path.display(), err))
+            });
+        if let Err(err) = result {
+            log::warn!("Couldn't save the ROM history: {}", err);
+        }
+    }
+
+    /// Move `entry` to the front, replacing any older entry for the
+    /// same ROM.
+    pub(crate) fn record(&mut self, entry: HistoryEntry) {
+        self.entries.retain(|old| old.path != entry.path);
+        self.entries.insert(0, entry);
+        self.entries.truncate(MAX_ENTRIES);
+    }
+
+    /// The most recent ROM other than `current`, to switch to.
+    pub(crate) fn previous(&self, current: &Path) -> Option<&HistoryEntry> {
+        self.entries.iter().find(|entry| entry.path != current)
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    Some(config::config_dir()?.join("history.toml"))
+}
+
+#[test]
+fn test_history() {
+    let entry = |path: &str| HistoryEntry {
+        path: PathBuf::from(path),
+        speed: None,
+        platform: None,
+        timer_hz: None,
+    };
+    let mut history = History::default();
+    history.record(entry("test.ch8"));
+    history.record(HistoryEntry {
+        speed: Some(2000),
+        ..entry("game.ch8")
+    });
+    history.record(entry("test.ch8"));
+    assert_eq!(history.entries.len(), 2);
+    assert_eq!(history.previous(Path::new("test.ch8")).unwrap().speed, Some(2000));
+
+    let saved = toml::to_string(&history).unwrap();
+    assert!(saved.contains("[[rom]]\npath = \"test.ch8\""), "{}", saved);
+    assert_eq!(History::parse(&saved), Ok(history));
+
+    let mut history = History::default();
+    for i in 0..20 {
+        history.record(entry(&format!("{}.ch8", i)));
+    }
+    assert_eq!(history.entries.len(), MAX_ENTRIES);
+}
@@ -0,0 +1,146 @@
+//! Saving the display as an image and loading one back, for
+//! `--dump-display` and `chip8 render`. The format comes from the file's
+//! extension: PBM (see `Display::to_pbm`) or PNG. PNGs are white on
+//! black like the screen, and when loading one, pixels brighter than
+//! middle grey are lit.
+
+use std::fs;
+use std::path::Path;
+
+use chip8::display::Display;
+use chip8::vm::VM;
+
+use crate::audio_sink::AudioSink;
+use crate::bench;
+
+#[derive(Debug, PartialEq)]
+enum Format {
+    Pbm,
+    Png,
+}
+
+fn format(path: &Path) -> Result<Format, String> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("pbm") => Ok(Format::Pbm),
+        Some(ext) if ext.eq_ignore_ascii_case("png") => Ok(Format::Png),
+        _ => Err(format!(
+            "Can't tell the format of {}, expected a .pbm or .png file",
+            path.display()
+        )),
+    }
+}
+
+pub(crate) fn save_display(display: &Display, path: &Path) -> Result<(), String> {
+    let bytes = match format(path)? {
+        Format::Pbm => display.to_pbm().into_bytes(),
+        Format::Png => encode_png(display).map_err(|err| format!("Error encoding {}: {}", path.display(), err))?,
+    };
+    fs::write(path, bytes).map_err(|err| format!("Error writing {}: {}", path.display(), err))
+}
+
+pub(crate) fn load_display(path: &Path) -> Result<Display, String> {
+    let format = format(path)?;
+    let bytes = fs::read(path).map_err(|err| format!("Error reading {}: {}", path.display(), err))?;
+    match format {
+        Format::Pbm => Display::from_pbm(&bytes),
+        Format::Png => decode_png(&bytes),
+    }
+    .map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+/// Run `rom` headless for `cycles` instructions, or until it exits,
+/// playing the buzzer into `audio`, and return the VM to take its
+/// display from, for `chip8 render`.
+pub(crate) fn render(
+    rom: &[u8],
+    cycles: u64,
+    exit_opcode: Option<u16>,
+    audio: &mut dyn AudioSink,
+) -> Result<VM, String> {
+    let mut vm = VM::new(Box::new(chip8::random::SeededRandom::new(0)));
+    vm.exit_opcode = exit_opcode;
+    vm.reload(rom)?;
+    bench::run(&mut vm, cycles, audio)?;
+    Ok(vm)
+}
+
+fn encode_png(display: &Display) -> Result<Vec<u8>, png::EncodingError> {
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, display.width() as u32, display.height() as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let pixels: Vec<u8> = (0..display.height())
+        .flat_map(|y| (0..display.width()).map(move |x| if display.get_pixel(x, y) { 255 } else { 0 }))
+        .collect();
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pixels)?;
+    writer.finish()?;
+    Ok(png)
+}
+
+fn decode_png(bytes: &[u8]) -> Result<Display, String> {
+    let mut decoder = png::Decoder::new(bytes);
+    // Whatever the image's format, get 8-bit samples
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|err| err.to_string())?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(|err| err.to_string())?;
+    let (width, height) = (info.width as usize, info.height as usize);
+    let mut display = Display::with_size(width, height)?;
+
+    let samples = info.color_type.samples();
+    // Leave out the alpha channel
+    let colors = match info.color_type {
+        png::ColorType::GrayscaleAlpha | png::ColorType::Rgba => samples - 1,
+        _ => samples,
+    };
+    for y in 0..height {
+        let row = &buffer[y * info.line_size..];
+        for x in 0..width {
+            let pixel = &row[x * samples..][..colors];
+            let brightness = pixel.iter().map(|&c| c as usize).sum::<usize>() / colors;
+            display.set_pixel(x, y, brightness >= 128);
+        }
+    }
+    Ok(display)
+}
+
+#[test]
+fn test_png() {
+    let mut display = Display::new();
+    display.set_pixel(2, 1, true);
+    let png = encode_png(&display).unwrap();
+    let loaded = decode_png(&png).unwrap();
+    assert_eq!(loaded.to_ascii(), display.to_ascii());
+
+    assert_eq!(format(Path::new("out.PNG")), Ok(Format::Png));
+    assert!(format(Path::new("out.bmp")).is_err());
+}
+
+#[test]
+fn test_render() {
+    // 00E0 clear, F029 I = the font's 0, D005 draw it, 1206 loop
+    let vm = render(
+        &[0x00, 0xE0, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x06],
+        100,
+        None,
+        &mut crate::audio_sink::NullSink,
+    )
+    .unwrap();
+    assert!(!vm.exited);
+    let ascii = vm.display.to_ascii();
+    let rows: Vec<&str> = ascii.lines().collect();
+    assert!(rows[0].starts_with("####."), "{}", ascii);
+    assert!(rows[1].starts_with("#..#."), "{}", ascii);
+
+    // Ending at 0000 instead of looping
+    let vm = render(
+        &[0xF0, 0x29, 0xD0, 0x05, 0x00, 0x00],
+        100,
+        Some(0x0000),
+        &mut crate::audio_sink::NullSink,
+    )
+    .unwrap();
+    assert!(vm.exited);
+    assert_eq!(vm.cycles, 3);
+}
@@ -0,0 +1,425 @@
+//! Input sources decide which keypad keys are held down. The main loop
+//! and `chip8 lockstep` only talk to the `InputSource` trait, so keys
+//! can come from a frontend's keyboard, a replay file or nowhere at all.
+
+use chip8::instruction::{parse_instruction, Instruction};
+use chip8::vm::{fetch, VM};
+
+use crate::frontend::InputEvent;
+use crate::replay::{KeyEvent, Replay};
+
+pub(crate) trait InputSource {
+    /// Take in an event from the frontend. Sources that don't follow
+    /// the user's keys ignore the keypad, so it can't perturb their run.
+    fn handle_event(&mut self, _event: &InputEvent) {}
+
+    /// The keys held down as instruction number `cycle` is about to run.
+    fn poll_keys(&mut self, cycle: u64) -> [bool; 16];
+
+    /// Whether the emulator should stop, e.g. because the window was
+    /// closed.
+    fn wants_quit(&self) -> bool {
+        false
+    }
+
+    /// Called when frame number `frame` is about to start, so sources
+    /// that keep in step with another machine can wait for it there.
+    /// Keys then only change at the start of frames.
+    fn end_frame(&mut self, _frame: u64) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Keys from the frontend, which turns the keyboard, gamepads and the
+/// keypad panel into keypad events whichever frontend it is.
+#[derive(Default)]
+pub(crate) struct FrontendInput {
+    keys: [bool; 16],
+    quit: bool,
+}
+
+impl InputSource for FrontendInput {
+    fn handle_event(&mut self, event: &InputEvent) {
+        match *event {
+            InputEvent::Key { key, pressed } => self.keys[key as usize] = pressed,
+            InputEvent::Quit => self.quit = true,
+            _ => {}
+        }
+    }
+
+    fn poll_keys(&mut self, _cycle: u64) -> [bool; 16] {
+        self.keys
+    }
+
+    fn wants_quit(&self) -> bool {
+        self.quit
+    }
+}
+
+/// How many times a second `--autofire` presses a key by default.
+pub(crate) const DEFAULT_AUTOFIRE_HZ: u64 = 10;
+
+/// The most times a second it can: any faster and presses would be
+/// shorter than a frame, and games could miss them.
+pub(crate) const MAX_AUTOFIRE_HZ: u64 = 30;
+
+/// Presses some keys over and over while they're held down
+/// (`--autofire`), for games that want a button mashed. Presses are
+/// timed in instructions, like replays, so recording one plays back the
+/// same.
+pub(crate) struct Autofire {
+    input: Box<dyn InputSource>,
+    keys: [bool; 16],
+    /// How long each press, and each gap between presses, lasts in
+    /// instructions
+    half_period: u64,
+    /// When each autofire key held down was pressed
+    held_since: [Option<u64>; 16],
+}
+
+impl Autofire {
+    /// Pulse `keys` from `input` `hz` times a second, at `speed`
+    /// instructions a second.
+    pub(crate) fn new(input: Box<dyn InputSource>, keys: [bool; 16], speed: u64, hz: u64) -> Autofire {
+        Autofire {
+            input,
+            keys,
+            half_period: (speed / hz / 2).max(1),
+            held_since: [None; 16],
+        }
+    }
+}
+
+impl InputSource for Autofire {
+    fn handle_event(&mut self, event: &InputEvent) {
+        self.input.handle_event(event);
+    }
+
+    fn poll_keys(&mut self, cycle: u64) -> [bool; 16] {
+        let mut keys = self.input.poll_keys(cycle);
+        let autofire = self.keys;
+        for key in (0..16).filter(|&key| autofire[key]) {
+            if keys[key] {
+                let since = match self.held_since[key] {
+                    Some(since) if since <= cycle => since,
+                    // The cycle count went backwards (the debugger's
+                    // `back` or a state restore): start a new press
+                    _ => *self.held_since[key].insert(cycle),
+                };
+                keys[key] = ((cycle - since) / self.half_period).is_multiple_of(2);
+            } else {
+                self.held_since[key] = None;
+            }
+        }
+        keys
+    }
+
+    fn wants_quit(&self) -> bool {
+        self.input.wants_quit()
+    }
+
+    fn end_frame(&mut self, frame: u64) -> Result<(), String> {
+        self.input.end_frame(frame)
+    }
+}
+
+/// Parse keypad keys written as hex digits, like "5A".
+pub(crate) fn parse_keys(s: &str) -> Result<[bool; 16], String> {
+    let mut keys = [false; 16];
+    for c in s.chars() {
+        match c.to_digit(16) {
+            Some(key) => keys[key as usize] = true,
+            None => return Err(format!("Invalid keypad keys {:?}, expected hex digits like 5A", s)),
+        }
+    }
+    if s.is_empty() {
+        return Err("No keypad keys given".to_string());
+    }
+    Ok(keys)
+}
+
+/// Keys played back from a replay file. The frontend can still quit.
+pub(crate) struct ReplayInput {
+    replay: Replay,
+    keys: [bool; 16],
+    quit: bool,
+}
+
+impl ReplayInput {
+    pub(crate) fn new(replay: Replay) -> ReplayInput {
+        ReplayInput {
+            replay,
+            keys: [false; 16],
+            quit: false,
+        }
+    }
+}
+
+impl InputSource for ReplayInput {
+    fn handle_event(&mut self, event: &InputEvent) {
+        if let InputEvent::Quit = event {
+            self.quit = true;
+        }
+    }
+
+    fn poll_keys(&mut self, cycle: u64) -> [bool; 16] {
+        while let Some(event) = self.replay.next_due(cycle) {
+            self.keys[event.key as usize] = event.pressed;
+        }
+        self.keys
+    }
+
+    fn wants_quit(&self) -> bool {
+        self.quit
+    }
+}
+
+/// No keys are ever pressed, for running headless.
+pub(crate) struct NullInput;
+
+impl InputSource for NullInput {
+    fn poll_keys(&mut self, _cycle: u64) -> [bool; 16] {
+        [false; 16]
+    }
+}
+
+/// Holds keypad presses down for a while after they're released
+/// (`--latch-keys`). Programs that poll the keypad with EX9E and EXA1
+/// rather than waiting for a key with FX0A can miss a quick tap that
+/// starts and ends between two polls, which gets likelier the faster
+/// they run. A key the program has already read is let go as soon as
+/// it's released, so one tap isn't read as two.
+pub(crate) struct KeyLatch {
+    /// How long to hold a press, in instructions
+    hold_cycles: u64,
+    /// When each key that's down, or held down by the latch, was pressed
+    pressed_at: [Option<u64>; 16],
+    /// Which of those the program has read since
+    read: [bool; 16],
+}
+
+impl KeyLatch {
+    pub(crate) fn new(hold_cycles: u64) -> KeyLatch {
+        KeyLatch {
+            hold_cycles,
+            pressed_at: [None; 16],
+            read: [false; 16],
+        }
+    }
+
+    /// The keys to show the program as `vm` is about to run its next
+    /// instruction, given the keys really held down.
+    fn latch(&mut self, keys: [bool; 16], vm: &VM) -> [bool; 16] {
+        let mut latched = keys;
+        for key in 0..16 {
+            match self.pressed_at[key] {
+                None if keys[key] => {
+                    self.pressed_at[key] = Some(vm.cycles);
+                    self.read[key] = false;
+                }
+                Some(at) if !keys[key] => {
+                    if self.read[key] || vm.cycles >= at + self.hold_cycles {
+                        self.pressed_at[key] = None;
+                    } else {
+                        latched[key] = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Note the keys the next instruction reads
+        match parse_instruction(fetch(&vm.memory, vm.pc)) {
+            Ok(Instruction::SkipIfVxPressed { x }) | Ok(Instruction::SkipIfVxNotPressed { x }) => {
+                let key = vm.v[x] as usize;
+                if latched.get(key) == Some(&true) {
+                    self.read[key] = true;
+                }
+            }
+            Ok(Instruction::BlockUntilAnyKey { .. }) => {
+                if let Some(key) = latched.iter().position(|&pressed| pressed) {
+                    self.read[key] = true;
+                }
+            }
+            _ => {}
+        }
+        latched
+    }
+}
+
+/// Poll `input` and copy the keys that changed since the last poll,
+/// which `held` remembers, to the VM, holding presses with `latch` if
+/// there is one. Keys pressed some other way, by a script or the remote
+/// API, stay as they are. Returns the changes, for recording.
+pub(crate) fn apply_input(
+    input: &mut dyn InputSource,
+    held: &mut [bool; 16],
+    latch: Option<&mut KeyLatch>,
+    vm: &mut VM,
+) -> Vec<KeyEvent> {
+    let mut keys = input.poll_keys(vm.cycles);
+    if let Some(latch) = latch {
+        keys = latch.latch(keys, vm);
+    }
+    let mut changes = Vec::new();
+    for key in 0..16 {
+        if keys[key] != held[key] {
+            vm.keys_pressed[key] = keys[key];
+            changes.push(KeyEvent {
+                cycle: vm.cycles,
+                key: key as u8,
+                pressed: keys[key],
+            });
+        }
+    }
+    *held = keys;
+    changes
+}
+
+#[test]
+fn test_input_sources() {
+    let mut vm = VM::new(Box::new(chip8::random::SeededRandom::new(0)));
+    let mut held = [false; 16];
+
+    let mut input = FrontendInput::default();
+    input.handle_event(&InputEvent::Key {
+        key: 0xA,
+        pressed: true,
+    });
+    // Repeats don't count as changes
+    input.handle_event(&InputEvent::Key {
+        key: 0xA,
+        pressed: true,
+    });
+    let changes = apply_input(&mut input, &mut held, None, &mut vm);
+    assert_eq!(changes.len(), 1);
+    assert!(vm.keys_pressed[0xA]);
+    // A key pressed by a script isn't released by the next poll
+    vm.keys_pressed[0x3] = true;
+    assert!(apply_input(&mut input, &mut held, None, &mut vm).is_empty());
+    assert!(vm.keys_pressed[0x3]);
+    assert!(!input.wants_quit());
+    input.handle_event(&InputEvent::Quit);
+    assert!(input.wants_quit());
+
+    let mut input = ReplayInput::new(Replay::parse("seed 1\n10 5 down\n").unwrap());
+    input.handle_event(&InputEvent::Key {
+        key: 0x6,
+        pressed: true,
+    });
+    assert_eq!(input.poll_keys(9), [false; 16]);
+    assert!(input.poll_keys(10)[0x5]);
+    assert!(!input.poll_keys(11)[0x6]);
+
+    assert_eq!(NullInput.poll_keys(0), [false; 16]);
+}
+
+#[test]
+fn test_key_latch() {
+    let mut vm = VM::new(Box::new(chip8::random::SeededRandom::new(0)));
+    // V0 := 0, skip if key V3 is down
+    vm.reload(&[0x60, 0x00, 0xE3, 0x9E]).unwrap();
+    vm.v[3] = 3;
+    let mut latch = KeyLatch::new(10);
+    let mut keys = [false; 16];
+
+    // A tap is held for 10 instructions
+    keys[3] = true;
+    assert!(latch.latch(keys, &vm)[3]);
+    keys[3] = false;
+    vm.cycles = 9;
+    assert!(latch.latch(keys, &vm)[3]);
+    vm.cycles = 10;
+    assert!(!latch.latch(keys, &vm)[3]);
+
+    // But once EX9E has seen it, it's let go when released
+    keys[3] = true;
+    latch.latch(keys, &vm);
+    vm.pc = 0x202;
+    assert!(latch.latch(keys, &vm)[3]);
+    keys[3] = false;
+    vm.cycles = 11;
+    assert!(!latch.latch(keys, &vm)[3]);
+
+    // Keys held longer are released straight away
+    keys[5] = true;
+    latch.latch(keys, &vm);
+    keys[5] = false;
+    vm.cycles = 30;
+    assert_eq!(latch.latch(keys, &vm), [false; 16]);
+
+    // Latched keys reach the VM through apply_input
+    let mut input = FrontendInput::default();
+    let mut held = [false; 16];
+    input.handle_event(&InputEvent::Key {
+        key: 0x7,
+        pressed: true,
+    });
+    apply_input(&mut input, &mut held, Some(&mut latch), &mut vm);
+    input.handle_event(&InputEvent::Key {
+        key: 0x7,
+        pressed: false,
+    });
+    assert!(apply_input(&mut input, &mut held, Some(&mut latch), &mut vm).is_empty());
+    assert!(vm.keys_pressed[0x7]);
+
+    // FX0A only reads the lowest key held
+    let mut latch = KeyLatch::new(10);
+    vm.reload(&[0xF0, 0x0A]).unwrap();
+    keys = [false; 16];
+    keys[0x4] = true;
+    keys[0xF] = true;
+    latch.latch(keys, &vm);
+    keys = [false; 16];
+    vm.cycles += 1;
+    let latched = latch.latch(keys, &vm);
+    assert!(!latched[0x4] && latched[0xF]);
+}
+
+#[test]
+fn test_autofire() {
+    let mut keys = [false; 16];
+    keys[0x5] = true;
+    assert_eq!(parse_keys("5"), Ok(keys));
+    keys[0xA] = true;
+    assert_eq!(parse_keys("a5"), Ok(keys));
+    assert!(parse_keys("5G").is_err());
+    assert!(parse_keys("").is_err());
+
+    // 10 presses a second at 100 instructions a second: 5 down, 5 up
+    let mut input = Autofire::new(Box::<FrontendInput>::default(), parse_keys("5").unwrap(), 100, 10);
+    input.handle_event(&InputEvent::Key {
+        key: 0x5,
+        pressed: true,
+    });
+    input.handle_event(&InputEvent::Key {
+        key: 0x6,
+        pressed: true,
+    });
+    let pulses: Vec<bool> = (20..32).map(|cycle| input.poll_keys(cycle)[0x5]).collect();
+    assert_eq!(
+        pulses,
+        [true, true, true, true, true, false, false, false, false, false, true, true]
+    );
+    // Other keys are left alone
+    assert!((20..32).all(|cycle| input.poll_keys(cycle)[0x6]));
+
+    // Going back in time starts a new press instead of underflowing
+    assert!(input.poll_keys(10)[0x5]);
+    assert!(!input.poll_keys(15)[0x5]);
+
+    // Pressing again starts with a press
+    input.handle_event(&InputEvent::Key {
+        key: 0x5,
+        pressed: false,
+    });
+    assert!(!input.poll_keys(33)[0x5]);
+    input.handle_event(&InputEvent::Key {
+        key: 0x5,
+        pressed: true,
+    });
+    assert!(input.poll_keys(36)[0x5]);
+
+    input.handle_event(&InputEvent::Quit);
+    assert!(input.wants_quit());
+}
@@ -1,9 +1,27 @@
-#[derive(Debug, PartialEq)]
-pub(crate) enum Instruction {
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Instruction {
     /// 0x00E0: Clear screen
     ClearScreen,
     /// 0x00EE: Return from subroutine
     SubroutineReturn,
+    /// 0x00FD: Stop the program (SUPER-CHIP and Octo's `exit`)
+    Exit,
+    /// 0x00CN: Scroll the display down N rows (half as far in lo-res
+    /// with the `half_scroll` quirk, as are the other scrolls)
+    ScrollDown { n: u8 },
+    /// 0x00DN: Scroll the display up N rows
+    ScrollUp { n: u8 },
+    /// 0x00FB: Scroll the display right 4 pixels
+    ScrollRight,
+    /// 0x00FC: Scroll the display left 4 pixels
+    ScrollLeft,
+    /// 0x00FE: Switch to the 64x32 lo-res display
+    LowRes,
+    /// 0x00FF: Switch to the 128x64 hi-res display
+    HighRes,
     /// 0x1NNN: Jump to NNN
     Jump { nnn: u16 },
     /// 0x2NNN: Subroutine call at NNN
@@ -20,7 +38,8 @@ pub(crate) enum Instruction {
     AddNnVx { x: usize, nn: u8 },
     /// 0x8XY0: Set VX to VY
     SetVxVy { x: usize, y: usize },
-    /// 0x8XY1: Set VX to VX | VY
+    /// 0x8XY1: Set VX to VX | VY (and VF to 0, with the `vf_reset` quirk,
+    /// as do 8XY2 and 8XY3)
     SetVxOrVy { x: usize, y: usize },
     /// 0x8XY2: Set VX to VX & VY
     SetVxAndVy { x: usize, y: usize },
@@ -31,20 +50,23 @@ pub(crate) enum Instruction {
     /// 0x8XY5: Set VX to VX - VY, accounting for carry
     SetVxMinusVy { x: usize, y: usize },
     /// 0x8XY6: Store least significant bit of VX in VF and shift VX right by 1
-    ShiftVxRight { x: usize },
+    /// (or VY, with the `shift_uses_vy` quirk)
+    ShiftVxRight { x: usize, y: usize },
     /// 0x8XY7: Set VX to VY - VX, accounting for carry
     SetVyMinusVx { x: usize, y: usize },
     /// 0x8XYE: Store most significant bit of VX in VF and shift VX left by 1
-    ShiftVxLeft { x: usize },
+    /// (or VY, with the `shift_uses_vy` quirk)
+    ShiftVxLeft { x: usize, y: usize },
     /// 0x9XY0: Skip if VX != VY
     SkipVxNeqVy { x: usize, y: usize },
     /// 0xANNN: Set index register to NNN
     SetIndexNnn { nnn: u16 },
-    /// 0xBNNN: Jump to V0 + NNN
+    /// 0xBNNN: Jump to V0 + NNN (or VX + NNN, where X is the top nibble
+    /// of NNN, with the `jump_uses_vx` quirk)
     JumpV0Nnn { nnn: u16 },
     /// 0xCXNN: Set VX to a random number AND'ed with NN
     SetVxRandNn { x: usize, nn: u8 },
-    /// 0xDXYN: Display
+    /// 0xDXYN: Display an 8xN sprite, or a 16x16 one for DXY0
     Display { x: usize, y: usize, n: u8 },
     /// 0xEX9E: Skip instruction if key VX is being pressed
     SkipIfVxPressed { x: usize },
@@ -62,15 +84,53 @@ pub(crate) enum Instruction {
     BlockUntilAnyKey { x: usize },
     /// 0xFX29: Set I to font character in VX
     SetIFontVx { x: usize },
+    /// 0xFX30: Set I to the large (8x10) font character in VX
+    SetILargeFontVx { x: usize },
     /// 0xFX33: Store 3 decimal digits of VX in I, I+1, I+2
     StoreVxDigitsI { x: usize },
     /// 0xFX55: Store all registers from V0 to VX in I, I+1, I+2, ... I+X
     StoreVxI { x: usize },
     /// 0xFX65: Store all memory from I, I+1, I+2, ... I+X in registers V0 to VX
     StoreIVx { x: usize },
+    /// 0xFN01: Select the XO-CHIP bit planes in the mask N for drawing,
+    /// clearing and scrolling
+    SelectPlanes { n: u8 },
+    /// 0xF002: Load the 16-byte XO-CHIP audio pattern from I
+    LoadAudioPattern,
+    /// 0xFX3A: Set the XO-CHIP audio pitch to VX
+    SetPitchVx { x: usize },
+}
+
+/// The instruction sets of CHIP-8 and its descendants, each adding to
+/// the one before.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum InstructionSet {
+    Chip8,
+    SuperChip,
+    XoChip,
 }
 
-pub(crate) fn parse_instruction(instruction: u16) -> Result<Instruction, String> {
+impl Instruction {
+    /// The first instruction set to include this instruction.
+    pub fn instruction_set(&self) -> InstructionSet {
+        match self {
+            Instruction::Exit
+            | Instruction::ScrollDown { .. }
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::LowRes
+            | Instruction::HighRes
+            | Instruction::SetILargeFontVx { .. } => InstructionSet::SuperChip,
+            Instruction::ScrollUp { .. }
+            | Instruction::SelectPlanes { .. }
+            | Instruction::LoadAudioPattern
+            | Instruction::SetPitchVx { .. } => InstructionSet::XoChip,
+            _ => InstructionSet::Chip8,
+        }
+    }
+}
+
+pub fn parse_instruction(instruction: u16) -> Result<Instruction, String> {
     let op: u8 = (instruction >> 12) as u8;
     let x: usize = ((instruction & 0x0F00) >> 8) as usize;
     let y: usize = ((instruction & 0x00F0) >> 4) as usize;
@@ -81,11 +141,18 @@ pub(crate) fn parse_instruction(instruction: u16) -> Result<Instruction, String>
     match (op, x, y, n) {
         (0, 0, 0xE, 0) => Ok(Instruction::ClearScreen),
         (0, 0, 0xE, 0xE) => Ok(Instruction::SubroutineReturn),
+        (0, 0, 0xF, 0xD) => Ok(Instruction::Exit),
+        (0, 0, 0xC, _) => Ok(Instruction::ScrollDown { n }),
+        (0, 0, 0xD, _) => Ok(Instruction::ScrollUp { n }),
+        (0, 0, 0xF, 0xB) => Ok(Instruction::ScrollRight),
+        (0, 0, 0xF, 0xC) => Ok(Instruction::ScrollLeft),
+        (0, 0, 0xF, 0xE) => Ok(Instruction::LowRes),
+        (0, 0, 0xF, 0xF) => Ok(Instruction::HighRes),
         (1, _, _, _) => Ok(Instruction::Jump { nnn }),
         (2, _, _, _) => Ok(Instruction::SubroutineCall { nnn }),
         (3, _, _, _) => Ok(Instruction::SkipVxEqNn { x, nn }),
         (4, _, _, _) => Ok(Instruction::SkipVxNeqNn { x, nn }),
-        (5, _, _, _) => Ok(Instruction::SkipVxEqVy { x, y }),
+        (5, _, _, 0) => Ok(Instruction::SkipVxEqVy { x, y }),
         (6, _, _, _) => Ok(Instruction::SetVxNn { x, nn }),
         (7, _, _, _) => Ok(Instruction::AddNnVx { x, nn }),
         (8, _, _, 0) => Ok(Instruction::SetVxVy { x, y }),
@@ -94,10 +161,10 @@ pub(crate) fn parse_instruction(instruction: u16) -> Result<Instruction, String>
         (8, _, _, 3) => Ok(Instruction::SetVxXorVy { x, y }),
         (8, _, _, 4) => Ok(Instruction::SetVxPlusVy { x, y }),
         (8, _, _, 5) => Ok(Instruction::SetVxMinusVy { x, y }),
-        (8, _, _, 6) => Ok(Instruction::ShiftVxRight { x }),
+        (8, _, _, 6) => Ok(Instruction::ShiftVxRight { x, y }),
         (8, _, _, 7) => Ok(Instruction::SetVyMinusVx { x, y }),
-        (8, _, _, 0xE) => Ok(Instruction::ShiftVxLeft { x }),
-        (9, _, _, _) => Ok(Instruction::SkipVxNeqVy { x, y }),
+        (8, _, _, 0xE) => Ok(Instruction::ShiftVxLeft { x, y }),
+        (9, _, _, 0) => Ok(Instruction::SkipVxNeqVy { x, y }),
         (0xA, _, _, _) => Ok(Instruction::SetIndexNnn { nnn }),
         (0xB, _, _, _) => Ok(Instruction::JumpV0Nnn { nnn }),
         (0xC, _, _, _) => Ok(Instruction::SetVxRandNn { x, nn }),
@@ -110,50 +177,460 @@ pub(crate) fn parse_instruction(instruction: u16) -> Result<Instruction, String>
         (0xF, _, 1, 0xE) => Ok(Instruction::AddVxI { x }),
         (0xF, _, 0, 0xA) => Ok(Instruction::BlockUntilAnyKey { x }),
         (0xF, _, 2, 9) => Ok(Instruction::SetIFontVx { x }),
+        (0xF, _, 3, 0) => Ok(Instruction::SetILargeFontVx { x }),
         (0xF, _, 3, 3) => Ok(Instruction::StoreVxDigitsI { x }),
         (0xF, _, 5, 5) => Ok(Instruction::StoreVxI { x }),
         (0xF, _, 6, 5) => Ok(Instruction::StoreIVx { x }),
-        _ => Err(format!("Unknown instruction {:#04X?}", instruction)),
+        (0xF, _, 0, 1) => Ok(Instruction::SelectPlanes { n: x as u8 }),
+        (0xF, 0, 0, 2) => Ok(Instruction::LoadAudioPattern),
+        (0xF, _, 3, 0xA) => Ok(Instruction::SetPitchVx { x }),
+        _ => Err(format!("Unknown instruction {:#06X}", instruction)),
     }
 }
 
-#[test]
-fn test_parse_instruction() {
-    let assert_parse = |raw: u16, instruction: Instruction| {
-        assert_eq!(parse_instruction(raw), Ok(instruction));
+/// Encode an instruction back into its raw two-byte form. This is the
+/// inverse of `parse_instruction`.
+pub fn encode_instruction(instruction: &Instruction) -> u16 {
+    let nnn = |op: u16, nnn: u16| op << 12 | nnn;
+    let xnn = |op: u16, x: usize, nn: u8| op << 12 | (x as u16) << 8 | nn as u16;
+    let xyn = |op: u16, x: usize, y: usize, n: u8| op << 12 | (x as u16) << 8 | (y as u16) << 4 | n as u16;
+
+    match *instruction {
+        Instruction::ClearScreen => 0x00E0,
+        Instruction::SubroutineReturn => 0x00EE,
+        Instruction::Exit => 0x00FD,
+        Instruction::ScrollDown { n } => 0x00C0 | n as u16,
+        Instruction::ScrollUp { n } => 0x00D0 | n as u16,
+        Instruction::ScrollRight => 0x00FB,
+        Instruction::ScrollLeft => 0x00FC,
+        Instruction::LowRes => 0x00FE,
+        Instruction::HighRes => 0x00FF,
+        Instruction::Jump { nnn: addr } => nnn(1, addr),
+        Instruction::SubroutineCall { nnn: addr } => nnn(2, addr),
+        Instruction::SkipVxEqNn { x, nn } => xnn(3, x, nn),
+        Instruction::SkipVxNeqNn { x, nn } => xnn(4, x, nn),
+        Instruction::SkipVxEqVy { x, y } => xyn(5, x, y, 0),
+        Instruction::SetVxNn { x, nn } => xnn(6, x, nn),
+        Instruction::AddNnVx { x, nn } => xnn(7, x, nn),
+        Instruction::SetVxVy { x, y } => xyn(8, x, y, 0),
+        Instruction::SetVxOrVy { x, y } => xyn(8, x, y, 1),
+        Instruction::SetVxAndVy { x, y } => xyn(8, x, y, 2),
+        Instruction::SetVxXorVy { x, y } => xyn(8, x, y, 3),
+        Instruction::SetVxPlusVy { x, y } => xyn(8, x, y, 4),
+        Instruction::SetVxMinusVy { x, y } => xyn(8, x, y, 5),
+        Instruction::ShiftVxRight { x, y } => xyn(8, x, y, 6),
+        Instruction::SetVyMinusVx { x, y } => xyn(8, x, y, 7),
+        Instruction::ShiftVxLeft { x, y } => xyn(8, x, y, 0xE),
+        Instruction::SkipVxNeqVy { x, y } => xyn(9, x, y, 0),
+        Instruction::SetIndexNnn { nnn: addr } => nnn(0xA, addr),
+        Instruction::JumpV0Nnn { nnn: addr } => nnn(0xB, addr),
+        Instruction::SetVxRandNn { x, nn } => xnn(0xC, x, nn),
+        Instruction::Display { x, y, n } => xyn(0xD, x, y, n),
+        Instruction::SkipIfVxPressed { x } => xnn(0xE, x, 0x9E),
+        Instruction::SkipIfVxNotPressed { x } => xnn(0xE, x, 0xA1),
+        Instruction::SetVxDelay { x } => xnn(0xF, x, 0x07),
+        Instruction::SetDelayVx { x } => xnn(0xF, x, 0x15),
+        Instruction::SetSoundVx { x } => xnn(0xF, x, 0x18),
+        Instruction::AddVxI { x } => xnn(0xF, x, 0x1E),
+        Instruction::BlockUntilAnyKey { x } => xnn(0xF, x, 0x0A),
+        Instruction::SetIFontVx { x } => xnn(0xF, x, 0x29),
+        Instruction::SetILargeFontVx { x } => xnn(0xF, x, 0x30),
+        Instruction::StoreVxDigitsI { x } => xnn(0xF, x, 0x33),
+        Instruction::StoreVxI { x } => xnn(0xF, x, 0x55),
+        Instruction::StoreIVx { x } => xnn(0xF, x, 0x65),
+        Instruction::SelectPlanes { n } => 0xF001 | (n as u16) << 8,
+        Instruction::LoadAudioPattern => 0xF002,
+        Instruction::SetPitchVx { x } => xnn(0xF, x, 0x3A),
+    }
+}
+
+/// Instructions are written with the classic mnemonics from Cowgod's
+/// technical reference, like `LD V3, 0x20`, which `FromStr` parses
+/// back. The alternate flag (`{:#}`) writes Octo syntax instead, like
+/// `v3 := 0x20`, with skips as the `if ... then` that assembles to them.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if f.alternate() {
+            return self.fmt_octo(f);
+        }
+        match *self {
+            Instruction::ClearScreen => write!(f, "CLS"),
+            Instruction::SubroutineReturn => write!(f, "RET"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::ScrollDown { n } => write!(f, "SCD {}", n),
+            Instruction::ScrollUp { n } => write!(f, "SCU {}", n),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::LowRes => write!(f, "LOW"),
+            Instruction::HighRes => write!(f, "HIGH"),
+            Instruction::Jump { nnn } => write!(f, "JP 0x{:03X}", nnn),
+            Instruction::SubroutineCall { nnn } => write!(f, "CALL 0x{:03X}", nnn),
+            Instruction::SkipVxEqNn { x, nn } => write!(f, "SE V{:X}, 0x{:02X}", x, nn),
+            Instruction::SkipVxNeqNn { x, nn } => write!(f, "SNE V{:X}, 0x{:02X}", x, nn),
+            Instruction::SkipVxEqVy { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::SetVxNn { x, nn } => write!(f, "LD V{:X}, 0x{:02X}", x, nn),
+            Instruction::AddNnVx { x, nn } => write!(f, "ADD V{:X}, 0x{:02X}", x, nn),
+            Instruction::SetVxVy { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::SetVxOrVy { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::SetVxAndVy { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::SetVxXorVy { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::SetVxPlusVy { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::SetVxMinusVy { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            // VY only matters with the shift_uses_vy quirk, so it's left
+            // out when it's VX
+            Instruction::ShiftVxRight { x, y } if x == y => write!(f, "SHR V{:X}", x),
+            Instruction::ShiftVxRight { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SetVyMinusVx { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShiftVxLeft { x, y } if x == y => write!(f, "SHL V{:X}", x),
+            Instruction::ShiftVxLeft { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipVxNeqVy { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::SetIndexNnn { nnn } => write!(f, "LD I, 0x{:03X}", nnn),
+            Instruction::JumpV0Nnn { nnn } => write!(f, "JP V0, 0x{:03X}", nnn),
+            Instruction::SetVxRandNn { x, nn } => write!(f, "RND V{:X}, 0x{:02X}", x, nn),
+            Instruction::Display { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::SkipIfVxPressed { x } => write!(f, "SKP V{:X}", x),
+            Instruction::SkipIfVxNotPressed { x } => write!(f, "SKNP V{:X}", x),
+            Instruction::SetVxDelay { x } => write!(f, "LD V{:X}, DT", x),
+            Instruction::SetDelayVx { x } => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSoundVx { x } => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddVxI { x } => write!(f, "ADD I, V{:X}", x),
+            Instruction::BlockUntilAnyKey { x } => write!(f, "LD V{:X}, K", x),
+            Instruction::SetIFontVx { x } => write!(f, "LD F, V{:X}", x),
+            Instruction::SetILargeFontVx { x } => write!(f, "LD HF, V{:X}", x),
+            Instruction::StoreVxDigitsI { x } => write!(f, "LD B, V{:X}", x),
+            Instruction::StoreVxI { x } => write!(f, "LD [I], V{:X}", x),
+            Instruction::StoreIVx { x } => write!(f, "LD V{:X}, [I]", x),
+            Instruction::SelectPlanes { n } => write!(f, "PLANE {}", n),
+            Instruction::LoadAudioPattern => write!(f, "AUDIO"),
+            Instruction::SetPitchVx { x } => write!(f, "PITCH V{:X}", x),
+        }
+    }
+}
+
+impl Instruction {
+    fn fmt_octo(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::ClearScreen => write!(f, "clear"),
+            Instruction::SubroutineReturn => write!(f, "return"),
+            Instruction::Exit => write!(f, "exit"),
+            Instruction::ScrollDown { n } => write!(f, "scroll-down {}", n),
+            Instruction::ScrollUp { n } => write!(f, "scroll-up {}", n),
+            Instruction::ScrollRight => write!(f, "scroll-right"),
+            Instruction::ScrollLeft => write!(f, "scroll-left"),
+            Instruction::LowRes => write!(f, "lores"),
+            Instruction::HighRes => write!(f, "hires"),
+            Instruction::Jump { nnn } => write!(f, "jump 0x{:03X}", nnn),
+            Instruction::SubroutineCall { nnn } => write!(f, ":call 0x{:03X}", nnn),
+            Instruction::JumpV0Nnn { nnn } => write!(f, "jump0 0x{:03X}", nnn),
+            Instruction::SkipVxEqNn { x, nn } => write!(f, "if v{:X} != 0x{:02X} then", x, nn),
+            Instruction::SkipVxNeqNn { x, nn } => write!(f, "if v{:X} == 0x{:02X} then", x, nn),
+            Instruction::SkipVxEqVy { x, y } => write!(f, "if v{:X} != v{:X} then", x, y),
+            Instruction::SkipVxNeqVy { x, y } => write!(f, "if v{:X} == v{:X} then", x, y),
+            Instruction::SkipIfVxPressed { x } => write!(f, "if v{:X} -key then", x),
+            Instruction::SkipIfVxNotPressed { x } => write!(f, "if v{:X} key then", x),
+            Instruction::SetVxNn { x, nn } => write!(f, "v{:X} := 0x{:02X}", x, nn),
+            Instruction::AddNnVx { x, nn } => write!(f, "v{:X} += 0x{:02X}", x, nn),
+            Instruction::SetVxVy { x, y } => write!(f, "v{:X} := v{:X}", x, y),
+            Instruction::SetVxOrVy { x, y } => write!(f, "v{:X} |= v{:X}", x, y),
+            Instruction::SetVxAndVy { x, y } => write!(f, "v{:X} &= v{:X}", x, y),
+            Instruction::SetVxXorVy { x, y } => write!(f, "v{:X} ^= v{:X}", x, y),
+            Instruction::SetVxPlusVy { x, y } => write!(f, "v{:X} += v{:X}", x, y),
+            Instruction::SetVxMinusVy { x, y } => write!(f, "v{:X} -= v{:X}", x, y),
+            Instruction::ShiftVxRight { x, y } => write!(f, "v{:X} >>= v{:X}", x, y),
+            Instruction::SetVyMinusVx { x, y } => write!(f, "v{:X} =- v{:X}", x, y),
+            Instruction::ShiftVxLeft { x, y } => write!(f, "v{:X} <<= v{:X}", x, y),
+            Instruction::SetIndexNnn { nnn } => write!(f, "i := 0x{:03X}", nnn),
+            Instruction::SetVxRandNn { x, nn } => write!(f, "v{:X} := random 0x{:02X}", x, nn),
+            Instruction::Display { x, y, n } => write!(f, "sprite v{:X} v{:X} {}", x, y, n),
+            Instruction::SetVxDelay { x } => write!(f, "v{:X} := delay", x),
+            Instruction::BlockUntilAnyKey { x } => write!(f, "v{:X} := key", x),
+            Instruction::SetDelayVx { x } => write!(f, "delay := v{:X}", x),
+            Instruction::SetSoundVx { x } => write!(f, "buzzer := v{:X}", x),
+            Instruction::AddVxI { x } => write!(f, "i += v{:X}", x),
+            Instruction::SetIFontVx { x } => write!(f, "i := hex v{:X}", x),
+            Instruction::SetILargeFontVx { x } => write!(f, "i := bighex v{:X}", x),
+            Instruction::StoreVxDigitsI { x } => write!(f, "bcd v{:X}", x),
+            Instruction::StoreVxI { x } => write!(f, "save v{:X}", x),
+            Instruction::StoreIVx { x } => write!(f, "load v{:X}", x),
+            Instruction::SelectPlanes { n } => write!(f, "plane {}", n),
+            Instruction::LoadAudioPattern => write!(f, "audio"),
+            Instruction::SetPitchVx { x } => write!(f, "pitch := v{:X}", x),
+        }
+    }
+}
+
+impl FromStr for Instruction {
+    type Err = String;
+
+    /// Parse a mnemonic like `LD V3, 0x20`. Unlike the assembler, only
+    /// numbers can stand for addresses.
+    fn from_str(s: &str) -> Result<Instruction, String> {
+        let s = s.trim();
+        let (mnemonic, rest) = match s.find(char::is_whitespace) {
+            Some(space) => (&s[..space], s[space..].trim()),
+            None => (s, ""),
+        };
+        let operands: Vec<&str> = if rest.is_empty() {
+            Vec::new()
+        } else {
+            rest.split(',').map(str::trim).collect()
+        };
+        parse_mnemonic(mnemonic, &operands, &|_| None)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operand {
+    V(usize),
+    I,
+    IndirectI,
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    LargeFont,
+    Bcd,
+    Value(u16),
+}
+
+fn parse_operand(operand: &str, resolve: &dyn Fn(&str) -> Option<u16>) -> Result<Operand, String> {
+    let upper = operand.to_ascii_uppercase();
+    let operand = match upper.as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::DelayTimer,
+        "ST" => Operand::SoundTimer,
+        "K" => Operand::Key,
+        "F" => Operand::Font,
+        "HF" => Operand::LargeFont,
+        "B" => Operand::Bcd,
+        _ if upper.len() == 2 && upper.starts_with('V') => match usize::from_str_radix(&upper[1..], 16) {
+            Ok(x) => Operand::V(x),
+            Err(_) => return Err(format!("invalid register {:?}", operand)),
+        },
+        _ => match parse_number(operand).or_else(|| resolve(operand)) {
+            Some(value) => Operand::Value(value),
+            None => return Err(format!("unknown label or invalid number {:?}", operand)),
+        },
+    };
+    Ok(operand)
+}
+
+/// Parse a number written in decimal, hex (`0x` or `#`) or binary
+/// (`0b`).
+pub fn parse_number(s: &str) -> Option<u16> {
+    let s = s.to_ascii_uppercase();
+    if let Some(hex) = s.strip_prefix("0X").or_else(|| s.strip_prefix('#')) {
+        u16::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = s.strip_prefix("0B") {
+        u16::from_str_radix(binary, 2).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+/// Parse an instruction from its mnemonic and operands, in any case.
+/// Operands that aren't registers or numbers are looked up with
+/// `resolve`, e.g. in the assembler's labels.
+pub fn parse_mnemonic(
+    mnemonic: &str,
+    operands: &[&str],
+    resolve: &dyn Fn(&str) -> Option<u16>,
+) -> Result<Instruction, String> {
+    let parsed = operands
+        .iter()
+        .map(|operand| parse_operand(operand, resolve))
+        .collect::<Result<Vec<Operand>, String>>()?;
+
+    let addr = |value: u16| {
+        if value <= 0xFFF {
+            Ok(value)
+        } else {
+            Err(format!("address {:#X} is out of range", value))
+        }
+    };
+    let byte = |value: u16| {
+        if value <= 0xFF {
+            Ok(value as u8)
+        } else {
+            Err(format!("value {:#X} does not fit in a byte", value))
+        }
     };
-    assert_parse(0x00E0, Instruction::ClearScreen);
-    assert_parse(0x00EE, Instruction::SubroutineReturn);
-    assert_parse(0x1ABC, Instruction::Jump { nnn: 0xABC });
-    assert_parse(0x2ABC, Instruction::SubroutineCall { nnn: 0xABC });
-    assert_parse(0x3ABC, Instruction::SkipVxEqNn { x: 0xA, nn: 0xBC });
-    assert_parse(0x4ABC, Instruction::SkipVxNeqNn { x: 0xA, nn: 0xBC });
-    assert_parse(0x5ABC, Instruction::SkipVxEqVy { x: 0xA, y: 0xB });
-    assert_parse(0x6ABC, Instruction::SetVxNn { x: 0xA, nn: 0xBC });
-    assert_parse(0x7ABC, Instruction::AddNnVx { x: 0xA, nn: 0xBC });
-    assert_parse(0x8AB0, Instruction::SetVxVy { x: 0xA, y: 0xB });
-    assert_parse(0x8AB1, Instruction::SetVxOrVy { x: 0xA, y: 0xB });
-    assert_parse(0x8AB2, Instruction::SetVxAndVy { x: 0xA, y: 0xB });
-    assert_parse(0x8AB3, Instruction::SetVxXorVy { x: 0xA, y: 0xB });
-    assert_parse(0x8AB4, Instruction::SetVxPlusVy { x: 0xA, y: 0xB });
-    assert_parse(0x8AB5, Instruction::SetVxMinusVy { x: 0xA, y: 0xB });
-    assert_parse(0x8AB6, Instruction::ShiftVxRight { x: 0xA });
-    assert_parse(0x8AB7, Instruction::SetVyMinusVx { x: 0xA, y: 0xB });
-    assert_parse(0x8ABE, Instruction::ShiftVxLeft { x: 0xA });
-    assert_parse(0x9ABC, Instruction::SkipVxNeqVy { x: 0xA, y: 0xB });
-    assert_parse(0xAABC, Instruction::SetIndexNnn { nnn: 0xABC });
-    assert_parse(0xBABC, Instruction::JumpV0Nnn { nnn: 0xABC });
-    assert_parse(0xCABC, Instruction::SetVxRandNn { x: 0xA, nn: 0xBC });
-    assert_parse(0xDABC, Instruction::Display { x: 0xA, y: 0xB, n: 0xC });
-    assert_parse(0xE19E, Instruction::SkipIfVxPressed { x: 1 });
-    assert_parse(0xE2A1, Instruction::SkipIfVxNotPressed { x: 2 });
-    assert_parse(0xF307, Instruction::SetVxDelay { x: 3 });
-    assert_parse(0xF415, Instruction::SetDelayVx { x: 4 });
-    assert_parse(0xF518, Instruction::SetSoundVx { x: 5 });
-    assert_parse(0xF61E, Instruction::AddVxI { x: 6 });
-    assert_parse(0xF70A, Instruction::BlockUntilAnyKey { x: 7 });
-    assert_parse(0xF829, Instruction::SetIFontVx { x: 8 });
-    assert_parse(0xF933, Instruction::StoreVxDigitsI { x: 9 });
-    assert_parse(0xFA55, Instruction::StoreVxI { x: 0xA });
-    assert_parse(0xFB65, Instruction::StoreIVx { x: 0xB });
+
+    use Operand::*;
+    let mnemonic = mnemonic.to_ascii_uppercase();
+    let instruction = match (mnemonic.as_str(), parsed.as_slice()) {
+        ("CLS", []) => Instruction::ClearScreen,
+        ("RET", []) => Instruction::SubroutineReturn,
+        ("EXIT", []) => Instruction::Exit,
+        ("SCD", [Value(n)]) if *n <= 0xF => Instruction::ScrollDown { n: *n as u8 },
+        ("SCU", [Value(n)]) if *n <= 0xF => Instruction::ScrollUp { n: *n as u8 },
+        ("SCR", []) => Instruction::ScrollRight,
+        ("SCL", []) => Instruction::ScrollLeft,
+        ("LOW", []) => Instruction::LowRes,
+        ("HIGH", []) => Instruction::HighRes,
+        ("JP", [Value(nnn)]) => Instruction::Jump { nnn: addr(*nnn)? },
+        ("JP", [V(0), Value(nnn)]) => Instruction::JumpV0Nnn { nnn: addr(*nnn)? },
+        ("CALL", [Value(nnn)]) => Instruction::SubroutineCall { nnn: addr(*nnn)? },
+        ("SE", [V(x), Value(nn)]) => Instruction::SkipVxEqNn { x: *x, nn: byte(*nn)? },
+        ("SE", [V(x), V(y)]) => Instruction::SkipVxEqVy { x: *x, y: *y },
+        ("SNE", [V(x), Value(nn)]) => Instruction::SkipVxNeqNn { x: *x, nn: byte(*nn)? },
+        ("SNE", [V(x), V(y)]) => Instruction::SkipVxNeqVy { x: *x, y: *y },
+        ("LD", [V(x), Value(nn)]) => Instruction::SetVxNn { x: *x, nn: byte(*nn)? },
+        ("LD", [V(x), V(y)]) => Instruction::SetVxVy { x: *x, y: *y },
+        ("LD", [I, Value(nnn)]) => Instruction::SetIndexNnn { nnn: addr(*nnn)? },
+        ("LD", [V(x), DelayTimer]) => Instruction::SetVxDelay { x: *x },
+        ("LD", [V(x), Key]) => Instruction::BlockUntilAnyKey { x: *x },
+        ("LD", [DelayTimer, V(x)]) => Instruction::SetDelayVx { x: *x },
+        ("LD", [SoundTimer, V(x)]) => Instruction::SetSoundVx { x: *x },
+        ("LD", [Font, V(x)]) => Instruction::SetIFontVx { x: *x },
+        ("LD", [LargeFont, V(x)]) => Instruction::SetILargeFontVx { x: *x },
+        ("LD", [Bcd, V(x)]) => Instruction::StoreVxDigitsI { x: *x },
+        ("LD", [IndirectI, V(x)]) => Instruction::StoreVxI { x: *x },
+        ("LD", [V(x), IndirectI]) => Instruction::StoreIVx { x: *x },
+        ("PLANE", [Value(n)]) if *n <= 0xF => Instruction::SelectPlanes { n: *n as u8 },
+        ("AUDIO", []) => Instruction::LoadAudioPattern,
+        ("PITCH", [V(x)]) => Instruction::SetPitchVx { x: *x },
+        ("ADD", [V(x), Value(nn)]) => Instruction::AddNnVx { x: *x, nn: byte(*nn)? },
+        ("ADD", [V(x), V(y)]) => Instruction::SetVxPlusVy { x: *x, y: *y },
+        ("ADD", [I, V(x)]) => Instruction::AddVxI { x: *x },
+        ("OR", [V(x), V(y)]) => Instruction::SetVxOrVy { x: *x, y: *y },
+        ("AND", [V(x), V(y)]) => Instruction::SetVxAndVy { x: *x, y: *y },
+        ("XOR", [V(x), V(y)]) => Instruction::SetVxXorVy { x: *x, y: *y },
+        ("SUB", [V(x), V(y)]) => Instruction::SetVxMinusVy { x: *x, y: *y },
+        ("SUBN", [V(x), V(y)]) => Instruction::SetVyMinusVx { x: *x, y: *y },
+        // With one operand, VY is VX so the shift is the same with or
+        // without the shift_uses_vy quirk
+        ("SHR", [V(x)]) => Instruction::ShiftVxRight { x: *x, y: *x },
+        ("SHR", [V(x), V(y)]) => Instruction::ShiftVxRight { x: *x, y: *y },
+        ("SHL", [V(x)]) => Instruction::ShiftVxLeft { x: *x, y: *x },
+        ("SHL", [V(x), V(y)]) => Instruction::ShiftVxLeft { x: *x, y: *y },
+        ("RND", [V(x), Value(nn)]) => Instruction::SetVxRandNn { x: *x, nn: byte(*nn)? },
+        ("DRW", [V(x), V(y), Value(n)]) if *n <= 0xF => Instruction::Display {
+            x: *x,
+            y: *y,
+            n: *n as u8,
+        },
+        ("SKP", [V(x)]) => Instruction::SkipIfVxPressed { x: *x },
+        ("SKNP", [V(x)]) => Instruction::SkipIfVxNotPressed { x: *x },
+        _ => return Err(format!("invalid instruction {} {}", mnemonic, operands.join(", "))),
+    };
+    Ok(instruction)
+}
+
+/// Parse a raw opcode written in hex, like 00FD or 0x0000.
+pub fn parse_opcode(s: &str) -> Result<u16, String> {
+    let digits = s.trim_start_matches("0x").trim_start_matches("0X");
+    match u16::from_str_radix(digits, 16) {
+        Ok(opcode) if digits.len() == 4 && digits.chars().all(|c| c.is_ascii_hexdigit()) => Ok(opcode),
+        _ => Err(format!("Invalid opcode {:?}, expected four hex digits, e.g. 00FD", s)),
+    }
+}
+
+#[test]
+fn test_parse_opcode() {
+    assert_eq!(parse_opcode("00FD"), Ok(0x00FD));
+    assert_eq!(parse_opcode("0x0000"), Ok(0));
+    assert!(parse_opcode("FD").is_err());
+    assert!(parse_opcode("00FG").is_err());
+}
+
+#[test]
+fn test_display_and_parse() {
+    let instruction = Instruction::SetVxNn { x: 3, nn: 0x20 };
+    assert_eq!(instruction.to_string(), "LD V3, 0x20");
+    assert_eq!(format!("{:#}", instruction), "v3 := 0x20");
+    assert_eq!(
+        format!("{:#}", Instruction::SkipVxEqNn { x: 0xA, nn: 1 }),
+        "if vA != 0x01 then"
+    );
+    assert_eq!(Instruction::ShiftVxLeft { x: 1, y: 1 }.to_string(), "SHL V1");
+
+    assert_eq!("ld v3, 32".parse(), Ok(instruction));
+    assert_eq!("DRW V0, V1, #F".parse(), Ok(Instruction::Display { x: 0, y: 1, n: 15 }));
+    assert!("JP start".parse::<Instruction>().is_err());
+    assert!("LD V0, 256".parse::<Instruction>().is_err());
+}
+
+#[cfg(test)]
+use proptest::prelude::*;
+
+/// Any valid instruction
+#[cfg(test)]
+fn any_instruction() -> impl Strategy<Value = Instruction> {
+    use Instruction::*;
+
+    let addr = || 0..0x1000u16;
+    let x = || 0..16usize;
+    let xy = || (0..16usize, 0..16usize);
+    let xnn = || (0..16usize, any::<u8>());
+    prop_oneof![
+        Just(ClearScreen),
+        Just(SubroutineReturn),
+        Just(Exit),
+        (0..16u8).prop_map(|n| ScrollDown { n }),
+        (0..16u8).prop_map(|n| ScrollUp { n }),
+        Just(ScrollRight),
+        Just(ScrollLeft),
+        Just(LowRes),
+        Just(HighRes),
+        addr().prop_map(|nnn| Jump { nnn }),
+        addr().prop_map(|nnn| SubroutineCall { nnn }),
+        xnn().prop_map(|(x, nn)| SkipVxEqNn { x, nn }),
+        xnn().prop_map(|(x, nn)| SkipVxNeqNn { x, nn }),
+        xy().prop_map(|(x, y)| SkipVxEqVy { x, y }),
+        xnn().prop_map(|(x, nn)| SetVxNn { x, nn }),
+        xnn().prop_map(|(x, nn)| AddNnVx { x, nn }),
+        xy().prop_map(|(x, y)| SetVxVy { x, y }),
+        xy().prop_map(|(x, y)| SetVxOrVy { x, y }),
+        xy().prop_map(|(x, y)| SetVxAndVy { x, y }),
+        xy().prop_map(|(x, y)| SetVxXorVy { x, y }),
+        xy().prop_map(|(x, y)| SetVxPlusVy { x, y }),
+        xy().prop_map(|(x, y)| SetVxMinusVy { x, y }),
+        xy().prop_map(|(x, y)| ShiftVxRight { x, y }),
+        xy().prop_map(|(x, y)| SetVyMinusVx { x, y }),
+        xy().prop_map(|(x, y)| ShiftVxLeft { x, y }),
+        xy().prop_map(|(x, y)| SkipVxNeqVy { x, y }),
+        addr().prop_map(|nnn| SetIndexNnn { nnn }),
+        addr().prop_map(|nnn| JumpV0Nnn { nnn }),
+        xnn().prop_map(|(x, nn)| SetVxRandNn { x, nn }),
+        (xy(), 0..16u8).prop_map(|((x, y), n)| Display { x, y, n }),
+        x().prop_map(|x| SkipIfVxPressed { x }),
+        x().prop_map(|x| SkipIfVxNotPressed { x }),
+        x().prop_map(|x| SetVxDelay { x }),
+        x().prop_map(|x| SetDelayVx { x }),
+        x().prop_map(|x| SetSoundVx { x }),
+        x().prop_map(|x| AddVxI { x }),
+        x().prop_map(|x| BlockUntilAnyKey { x }),
+        x().prop_map(|x| SetIFontVx { x }),
+        x().prop_map(|x| SetILargeFontVx { x }),
+        x().prop_map(|x| StoreVxDigitsI { x }),
+        x().prop_map(|x| StoreVxI { x }),
+        x().prop_map(|x| StoreIVx { x }),
+        (0..16u8).prop_map(|n| SelectPlanes { n }),
+        Just(LoadAudioPattern),
+        x().prop_map(|x| SetPitchVx { x }),
+    ]
+}
+
+#[cfg(test)]
+proptest! {
+    #[test]
+    fn test_encode_round_trip(instruction in any_instruction()) {
+        prop_assert_eq!(parse_instruction(encode_instruction(&instruction)), Ok(instruction));
+    }
+
+    #[test]
+    fn test_display_round_trip(instruction in any_instruction()) {
+        prop_assert_eq!(instruction.to_string().parse(), Ok(instruction));
+    }
+
+    #[test]
+    fn test_parse_any_word(raw in any::<u16>()) {
+        match parse_instruction(raw) {
+            // Each instruction has exactly one encoding
+            Ok(instruction) => prop_assert_eq!(encode_instruction(&instruction), raw),
+            Err(err) => prop_assert!(err.contains(&format!("{:04X}", raw)), "{}", err),
+        }
+    }
 }
@@ -0,0 +1,39 @@
+//! Ctrl-C handling. Rather than the process being killed, Ctrl-C
+//! (SIGINT, or the console's Ctrl-C event on Windows) asks the emulator
+//! to stop, which it does the same way as when the window is closed:
+//! finishing recordings and reports, and restoring the terminal. A
+//! second Ctrl-C exits straight away, in case it's stuck.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// The exit status for a second Ctrl-C, as shells report SIGINT.
+const FORCED_EXIT_STATUS: i32 = 130;
+
+/// Catch Ctrl-C from now on. If that's not possible, Ctrl-C kills the
+/// process as usual.
+pub(crate) fn install() {
+    let result = ctrlc::set_handler(|| {
+        if REQUESTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(FORCED_EXIT_STATUS);
+        }
+    });
+    if let Err(err) = result {
+        log::warn!("Can't catch Ctrl-C: {}", err);
+    }
+}
+
+/// Whether Ctrl-C has been pressed, so the emulator should stop.
+pub(crate) fn requested() -> bool {
+    REQUESTED.load(Ordering::Relaxed)
+}
+
+/// For commands that can't stop part way with a useful result: an error
+/// if Ctrl-C has been pressed.
+pub(crate) fn check() -> Result<(), String> {
+    if requested() {
+        return Err("Interrupted".to_string());
+    }
+    Ok(())
+}
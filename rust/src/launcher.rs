@@ -0,0 +1,173 @@
+//! The ROM menu shown when `chip8 run` is given a directory (or run
+//! outside a project): a list of the ROMs in the directory, drawn on a
+//! hi-res CHIP-8 display so it works in every frontend. Up and Down
+//! choose a ROM and Enter runs it.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chip8::display::Display;
+use chip8::vm::FRAMES_PER_SECOND;
+
+use crate::frontend::{self, FrontendKind, FrontendSettings, Hotkey, InputEvent};
+use crate::interrupt;
+use crate::text::{draw_text, LINE_HEIGHT};
+
+/// File extensions of CHIP-8 ROMs and their descendants.
+const ROM_EXTENSIONS: [&str; 4] = ["ch8", "c8", "sc8", "xo8"];
+
+/// Show the menu for the ROMs in `dir` and return the one chosen, or
+/// None if the user quit instead.
+pub(crate) fn choose_rom(
+    dir: &Path,
+    kind: FrontendKind,
+    settings: FrontendSettings,
+) -> Result<Option<PathBuf>, String> {
+    let roms = find_roms(dir)?;
+    if roms.is_empty() {
+        return Err(format!("No ROMs found in {}", dir.display()));
+    }
+    let names: Vec<String> = roms
+        .iter()
+        .map(|rom| rom.file_stem().unwrap_or_default().to_string_lossy().into_owned())
+        .collect();
+
+    let mut frontend = frontend::create(kind, settings)?;
+    frontend.set_title(&format!("CHIP-8 - {}", dir.display()));
+    let mut display = Display::new();
+    display.set_hires(true);
+    let mut menu = Menu::new(names);
+    menu.draw(&mut display);
+
+    let frame_duration = Duration::from_secs_f64(1.0 / FRAMES_PER_SECOND as f64);
+    while !interrupt::requested() {
+        for event in frontend.poll_events() {
+            match event {
+                InputEvent::Quit => return Ok(None),
+                InputEvent::Hotkey { hotkey, pressed: true } => match hotkey {
+                    Hotkey::MenuUp => menu.move_by(-1),
+                    Hotkey::MenuDown => menu.move_by(1),
+                    Hotkey::MenuSelect => return Ok(Some(roms[menu.selected].clone())),
+                    _ => continue,
+                },
+                _ => continue,
+            }
+            menu.draw(&mut display);
+        }
+        frontend.paint(&mut display);
+        std::thread::sleep(frame_duration);
+    }
+    Ok(None)
+}
+
+/// The ROM files directly inside `dir`, sorted by name.
+fn find_roms(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries = fs::read_dir(dir).map_err(|err| format!("Error reading {}: {}", dir.display(), err))?;
+    let mut roms = Vec::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|err| format!("Error reading {}: {}", dir.display(), err))?
+            .path();
+        let is_rom = path.extension().is_some_and(|extension| {
+            let extension = extension.to_string_lossy().to_ascii_lowercase();
+            ROM_EXTENSIONS.contains(&extension.as_str())
+        });
+        if is_rom && path.is_file() {
+            roms.push(path);
+        }
+    }
+    roms.sort();
+    Ok(roms)
+}
+
+struct Menu {
+    names: Vec<String>,
+    selected: usize,
+    /// Index of the first name on screen
+    scroll: usize,
+}
+
+impl Menu {
+    const TITLE: &'static str = "CHIP-8 ROMS";
+
+    fn new(names: Vec<String>) -> Menu {
+        Menu {
+            names,
+            selected: 0,
+            scroll: 0,
+        }
+    }
+
+    /// How many names fit below the title.
+    fn visible_rows(display: &Display) -> usize {
+        display.height() / LINE_HEIGHT - 1
+    }
+
+    /// Move the selection, stopping at either end of the list.
+    fn move_by(&mut self, delta: isize) {
+        let last = self.names.len() - 1;
+        self.selected = (self.selected as isize + delta).clamp(0, last as isize) as usize;
+    }
+
+    /// Draw the title, then a page of names with the selected one
+    /// highlighted, scrolling to keep it on screen.
+    fn draw(&mut self, display: &mut Display) {
+        let rows = Menu::visible_rows(display);
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + rows {
+            self.scroll = self.selected + 1 - rows;
+        }
+
+        display.clear();
+        // Leave a pixel of margin on the left
+        draw_text(display, 1, 0, Menu::TITLE, false);
+        for (row, i) in (self.scroll..self.names.len()).take(rows).enumerate() {
+            let y = (row + 1) * LINE_HEIGHT;
+            let selected = i == self.selected;
+            if selected {
+                for dy in 0..LINE_HEIGHT {
+                    for x in 0..display.width() {
+                        display.set_pixel(x, y + dy, true);
+                    }
+                }
+            }
+            draw_text(display, 1, y, &self.names[i], selected);
+        }
+    }
+}
+
+#[test]
+fn test_menu() {
+    let mut display = Display::new();
+    display.set_hires(true);
+    let names: Vec<String> = (0..12).map(|i| format!("ROM {}", i)).collect();
+    let mut menu = Menu::new(names);
+
+    menu.move_by(-1);
+    assert_eq!(menu.selected, 0);
+    menu.draw(&mut display);
+    let ascii = display.to_ascii();
+    let rows: Vec<&str> = ascii.lines().collect();
+    // The title's "CH", then the highlighted first row with an unlit 'R'
+    assert!(rows[0].starts_with("..##.#.#"), "{}", rows[0]);
+    assert!(rows[LINE_HEIGHT].starts_with("#..##"), "{}", rows[LINE_HEIGHT]);
+
+    // Scrolls to keep the selection on screen, and stops at the end
+    menu.move_by(20);
+    menu.draw(&mut display);
+    assert_eq!((menu.selected, menu.scroll), (11, 3));
+}
+
+#[test]
+fn test_find_roms() {
+    let dir = std::env::temp_dir().join(format!("chip8-launcher-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for name in ["b.ch8", "a.CH8", "notes.txt"] {
+        fs::write(dir.join(name), [0x12, 0x00]).unwrap();
+    }
+    let roms = find_roms(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(roms, Ok(vec![dir.join("a.CH8"), dir.join("b.ch8")]));
+}
@@ -0,0 +1,25 @@
+//! The CHIP-8 interpreter core. This has no dependency on SDL or any
+//! other frontend, so it can be embedded elsewhere (e.g. compiled to
+//! WebAssembly with the `web` feature, or called from C with the `ffi`
+//! feature).
+
+pub mod audio;
+pub mod cheats;
+pub mod decode_cache;
+pub mod display;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod instruction;
+#[cfg(feature = "megachip")]
+pub mod megachip;
+pub mod platform;
+pub mod quirks;
+pub mod random;
+pub mod stack;
+pub mod step;
+pub mod threaded;
+pub mod vm;
+pub mod watch;
+
+#[cfg(feature = "web")]
+pub mod web;
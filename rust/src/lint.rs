@@ -0,0 +1,416 @@
+//! `chip8 lint`: look for bugs in a ROM without running it, by
+//! following its control flow from the entry point (see `analysis.rs`).
+//! It reports:
+//!
+//! - jumps and calls to odd addresses or outside the ROM, and paths
+//!   that run off the end of the ROM or into invalid instructions
+//! - unreachable bytes, other than data that `i :=` or `jump0` point at
+//! - subroutines that never return, and returns outside any subroutine
+//! - registers that are read before anything sets them
+//!
+//! The analysis is conservative about paths (every skip can go either
+//! way) but can't see computed jumps, so code only reached through
+//! `jump0` isn't checked.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chip8::instruction::Instruction;
+
+use crate::analysis::{flow, Flow, Program, ROM_START};
+
+/// A set of registers, one bit per register.
+type Registers = u16;
+
+const ALL_REGISTERS: Registers = 0xFFFF;
+const VF: Registers = 1 << 0xF;
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Finding {
+    pub(crate) addr: u16,
+    pub(crate) message: String,
+}
+
+/// Every problem found, in address order.
+pub(crate) fn lint(rom: &[u8]) -> Vec<Finding> {
+    let program = Program::new(rom);
+    let code = program.reachable();
+    let mut findings = Vec::new();
+    check_entry(&program, &mut findings);
+    check_flow(&program, &code, &mut findings);
+    check_unreachable(&program, &code, &mut findings);
+    check_subroutines(&program, &code, &mut findings);
+    findings.sort_by_key(|finding| finding.addr);
+    findings
+}
+
+/// Nothing else can be checked if the program doesn't start with an
+/// instruction.
+fn check_entry(program: &Program, findings: &mut Vec<Finding>) {
+    if program.fetch(ROM_START).is_some() {
+        return;
+    }
+    let message = if !program.contains(ROM_START + 1) {
+        "the ROM is too short to start with an instruction".to_string()
+    } else {
+        let raw = (program.byte(ROM_START) as u16) << 8 | program.byte(ROM_START + 1) as u16;
+        format!("execution starts at the invalid instruction {:04X}", raw)
+    };
+    findings.push(Finding {
+        addr: ROM_START,
+        message,
+    });
+}
+
+fn check_flow(program: &Program, code: &BTreeSet<u16>, findings: &mut Vec<Finding>) {
+    for &addr in code {
+        let flow = flow(&program.fetch(addr).expect("reachable code is valid"));
+        let target = match flow {
+            Flow::Jump(target) => Some(("jumps", target)),
+            Flow::Call(target) => Some(("calls", target)),
+            Flow::Computed(target) => Some(("jumps", target)),
+            _ => None,
+        };
+        if let Some((verb, target)) = target {
+            if !program.contains(target) {
+                findings.push(Finding {
+                    addr,
+                    message: format!("{} to {:03X}, outside the ROM", verb, target),
+                });
+            } else if target % 2 == 1 {
+                findings.push(Finding {
+                    addr,
+                    message: format!("{} to odd address {:03X}", verb, target),
+                });
+            }
+        }
+
+        for next in program.local_successors(addr, flow) {
+            // Bad jump targets were reported above
+            if matches!(flow, Flow::Jump(_)) || program.fetch(next).is_some() {
+                continue;
+            }
+            let message = if !program.contains(next + 1) {
+                "execution can run past the end of the ROM".to_string()
+            } else {
+                let raw = (program.byte(next) as u16) << 8 | program.byte(next + 1) as u16;
+                format!(
+                    "execution can reach the invalid instruction {:04X} at {:03X}",
+                    raw, next
+                )
+            };
+            findings.push(Finding { addr, message });
+        }
+    }
+}
+
+fn check_unreachable(program: &Program, code: &BTreeSet<u16>, findings: &mut Vec<Finding>) {
+    let executed = |addr: u16| code.contains(&addr) || (addr > ROM_START && code.contains(&(addr - 1)));
+
+    // Data runs from wherever something points at it to the next code
+    let mut data_starts = BTreeSet::new();
+    for &addr in code {
+        match program.fetch(addr) {
+            Some(Instruction::SetIndexNnn { nnn }) | Some(Instruction::JumpV0Nnn { nnn }) => {
+                data_starts.insert(nnn);
+            }
+            _ => {}
+        }
+    }
+    let mut is_data = false;
+    let mut unreachable: Vec<(u16, u16)> = Vec::new();
+    for addr in ROM_START..program.end() {
+        if executed(addr) {
+            is_data = false;
+            continue;
+        }
+        is_data |= data_starts.contains(&addr);
+        if is_data {
+            continue;
+        }
+        match unreachable.last_mut() {
+            Some((_, last)) if *last + 1 == addr => *last = addr,
+            _ => unreachable.push((addr, addr)),
+        }
+    }
+    for (start, end) in unreachable {
+        findings.push(Finding {
+            addr: start,
+            message: format!("{} unreachable bytes ({:03X}-{:03X})", end - start + 1, start, end),
+        });
+    }
+}
+
+/// What dataflow analysis found out about one subroutine (or the main
+/// program).
+struct Function {
+    /// Registers definitely set on the way to each instruction,
+    /// counting only the ones the function and its callees set
+    set_before: BTreeMap<u16, Registers>,
+    /// Registers definitely set whenever the function returns, or None
+    /// if it never returns
+    set_on_return: Option<Registers>,
+}
+
+/// Find the registers definitely set at each instruction of the
+/// function starting at `entry`, given which registers each subroutine
+/// sets.
+fn analyze_function(program: &Program, entry: u16, summaries: &BTreeMap<u16, Registers>) -> Function {
+    let mut set_before = BTreeMap::new();
+    let mut set_on_return = None;
+    set_before.insert(entry, 0);
+    let mut to_visit = vec![entry];
+    while let Some(addr) = to_visit.pop() {
+        let instruction = match program.fetch(addr) {
+            Some(instruction) => instruction,
+            None => continue,
+        };
+        let before = set_before[&addr];
+        let flow = flow(&instruction);
+        let mut after = before | writes(&instruction);
+        match flow {
+            Flow::Call(target) => after |= summaries.get(&target).copied().unwrap_or(ALL_REGISTERS),
+            Flow::Return => set_on_return = Some(set_on_return.unwrap_or(ALL_REGISTERS) & before),
+            _ => {}
+        }
+        for next in program.local_successors(addr, flow) {
+            if program.fetch(next).is_none() {
+                continue;
+            }
+            let merged = set_before.get(&next).map_or(after, |&old| old & after);
+            if set_before.get(&next) != Some(&merged) {
+                set_before.insert(next, merged);
+                to_visit.push(next);
+            }
+        }
+    }
+    Function {
+        set_before,
+        set_on_return,
+    }
+}
+
+fn check_subroutines(program: &Program, code: &BTreeSet<u16>, findings: &mut Vec<Finding>) {
+    let mut entries: BTreeSet<u16> = code
+        .iter()
+        .filter_map(
+            |&addr| match program.fetch(addr).map(|instruction| flow(&instruction)) {
+                Some(Flow::Call(target)) if program.fetch(target).is_some() => Some(target),
+                _ => None,
+            },
+        )
+        .collect();
+    entries.insert(ROM_START);
+
+    // Work out which registers each subroutine sets, starting from
+    // "all of them" and narrowing down until nothing changes (which
+    // handles recursion)
+    let mut summaries: BTreeMap<u16, Registers> = entries.iter().map(|&entry| (entry, ALL_REGISTERS)).collect();
+    let functions = loop {
+        let functions: BTreeMap<u16, Function> = entries
+            .iter()
+            .map(|&entry| (entry, analyze_function(program, entry, &summaries)))
+            .collect();
+        let mut changed = false;
+        for (entry, function) in &functions {
+            let summary = function.set_on_return.unwrap_or(ALL_REGISTERS);
+            changed |= summaries.insert(*entry, summary) != Some(summary);
+        }
+        if !changed {
+            break functions;
+        }
+    };
+
+    // Then which registers are set whenever each subroutine is called
+    let mut set_on_entry: BTreeMap<u16, Registers> = entries.iter().map(|&entry| (entry, ALL_REGISTERS)).collect();
+    set_on_entry.insert(ROM_START, 0);
+    loop {
+        let mut new_entry = set_on_entry.clone();
+        for &entry in entries.iter().filter(|&&entry| entry != ROM_START) {
+            new_entry.insert(entry, ALL_REGISTERS);
+        }
+        for (caller, function) in &functions {
+            for (&addr, &set) in &function.set_before {
+                if let Some(Flow::Call(target)) = program.fetch(addr).map(|instruction| flow(&instruction)) {
+                    if let Some(registers) = new_entry.get_mut(&target) {
+                        *registers &= set_on_entry[caller] | set;
+                    }
+                }
+            }
+        }
+        if new_entry == set_on_entry {
+            break;
+        }
+        set_on_entry = new_entry;
+    }
+
+    let mut unset_reads: BTreeMap<u16, Registers> = BTreeMap::new();
+    for (&entry, function) in &functions {
+        for (&addr, &set) in &function.set_before {
+            // The entry point is analyzed even if it isn't valid,
+            // which check_entry reports
+            let instruction = match program.fetch(addr) {
+                Some(instruction) => instruction,
+                None => continue,
+            };
+            let unset = reads(&instruction) & !(set_on_entry[&entry] | set);
+            if unset != 0 {
+                *unset_reads.entry(addr).or_insert(0) |= unset;
+            }
+            if entry == ROM_START && instruction == Instruction::SubroutineReturn {
+                findings.push(Finding {
+                    addr,
+                    message: "returns from the main program, with no subroutine to return from".to_string(),
+                });
+            }
+        }
+        if entry != ROM_START && function.set_on_return.is_none() {
+            findings.push(Finding {
+                addr: entry,
+                message: format!(
+                    "subroutine {:03X} never returns, so each call to it leaves an entry on the stack",
+                    entry
+                ),
+            });
+        }
+    }
+    for (addr, unset) in unset_reads {
+        let names: Vec<String> = (0..16)
+            .filter(|&x| unset >> x & 1 == 1)
+            .map(|x| format!("V{:X}", x))
+            .collect();
+        findings.push(Finding {
+            addr,
+            message: format!("reads {} before anything sets it", names.join(", ")),
+        });
+    }
+}
+
+fn register(x: usize) -> Registers {
+    1 << x
+}
+
+/// Registers 0 to x, as used by FX55 and FX65.
+fn registers_to(x: usize) -> Registers {
+    ((1u32 << (x + 1)) - 1) as Registers
+}
+
+fn reads(instruction: &Instruction) -> Registers {
+    match *instruction {
+        Instruction::SkipVxEqNn { x, .. }
+        | Instruction::SkipVxNeqNn { x, .. }
+        | Instruction::AddNnVx { x, .. }
+        // Assumes the default quirks, where shifts ignore VY
+        | Instruction::ShiftVxRight { x, .. }
+        | Instruction::ShiftVxLeft { x, .. }
+        | Instruction::SkipIfVxPressed { x }
+        | Instruction::SkipIfVxNotPressed { x }
+        | Instruction::SetDelayVx { x }
+        | Instruction::SetSoundVx { x }
+        | Instruction::AddVxI { x }
+        | Instruction::SetIFontVx { x }
+        | Instruction::SetILargeFontVx { x }
+        | Instruction::StoreVxDigitsI { x }
+        | Instruction::SetPitchVx { x } => register(x),
+        Instruction::SetVxVy { y, .. } => register(y),
+        Instruction::SkipVxEqVy { x, y }
+        | Instruction::SkipVxNeqVy { x, y }
+        | Instruction::SetVxOrVy { x, y }
+        | Instruction::SetVxAndVy { x, y }
+        | Instruction::SetVxXorVy { x, y }
+        | Instruction::SetVxPlusVy { x, y }
+        | Instruction::SetVxMinusVy { x, y }
+        | Instruction::SetVyMinusVx { x, y }
+        | Instruction::Display { x, y, .. } => register(x) | register(y),
+        // Assumes the default quirks, where BNNN adds V0
+        Instruction::JumpV0Nnn { .. } => register(0),
+        Instruction::StoreVxI { x } => registers_to(x),
+        _ => 0,
+    }
+}
+
+fn writes(instruction: &Instruction) -> Registers {
+    match *instruction {
+        Instruction::SetVxNn { x, .. }
+        | Instruction::AddNnVx { x, .. }
+        | Instruction::SetVxVy { x, .. }
+        | Instruction::SetVxRandNn { x, .. }
+        | Instruction::SetVxDelay { x }
+        | Instruction::BlockUntilAnyKey { x } => register(x),
+        Instruction::SetVxOrVy { x, .. }
+        | Instruction::SetVxAndVy { x, .. }
+        | Instruction::SetVxXorVy { x, .. }
+        | Instruction::SetVxPlusVy { x, .. }
+        | Instruction::SetVxMinusVy { x, .. }
+        | Instruction::SetVyMinusVx { x, .. }
+        | Instruction::ShiftVxRight { x, .. }
+        | Instruction::ShiftVxLeft { x, .. } => register(x) | VF,
+        Instruction::Display { .. } => VF,
+        Instruction::StoreIVx { x } => registers_to(x),
+        _ => 0,
+    }
+}
+
+#[test]
+fn test_lint() {
+    let rom = [
+        0x60, 0x05, // 200: V0 = 5
+        0x22, 0x0A, // 202: call 20A
+        0x80, 0x14, // 204: V0 += V1, V1 set by the subroutine
+        0x82, 0x04, // 206: V2 += V0, but V2 was never set
+        0x12, 0x11, // 208: jump to an odd address
+        0x61, 0x01, // 20A: V1 = 1
+        0x00, 0xEE, // 20C: return
+        0x22, 0x12, // 20E: unreachable call
+        0xFF, 0x00, // 210: unreachable
+        0x12, 0x12, // 212: subroutine that never returns
+    ];
+    let findings = lint(&rom);
+    let messages: Vec<String> = findings
+        .iter()
+        .map(|finding| format!("{:03X}: {}", finding.addr, finding.message))
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            "206: reads V2 before anything sets it",
+            "208: jumps to odd address 211",
+            "20E: 6 unreachable bytes (20E-213)",
+        ]
+    );
+
+    let rom = [
+        0x22, 0x06, // 200: call a subroutine that never returns
+        0x00, 0xEE, // 202: return from the main program
+        0x00, 0x00, // 204: padding
+        0x12, 0x06, // 206: loop forever
+    ];
+    let messages: Vec<String> = lint(&rom).into_iter().map(|finding| finding.message).collect();
+    assert_eq!(
+        messages,
+        [
+            "returns from the main program, with no subroutine to return from",
+            "2 unreachable bytes (204-205)",
+            "subroutine 206 never returns, so each call to it leaves an entry on the stack",
+        ]
+    );
+}
+
+#[test]
+fn test_lint_bad_entry() {
+    let messages = |rom: &[u8]| -> Vec<String> { lint(rom).into_iter().map(|finding| finding.message).collect() };
+    assert_eq!(messages(&[]), ["the ROM is too short to start with an instruction"]);
+    assert_eq!(
+        messages(&[0x00]),
+        [
+            "the ROM is too short to start with an instruction",
+            "1 unreachable bytes (200-200)"
+        ]
+    );
+    assert_eq!(
+        messages(&[0xFF, 0xFF]),
+        [
+            "execution starts at the invalid instruction FFFF",
+            "2 unreachable bytes (200-201)"
+        ]
+    );
+}
@@ -0,0 +1,191 @@
+//! `chip8 lockstep`: run a ROM headless for a fixed number of
+//! instructions, pressing keys from a replay file, and print the
+//! machine's state as canonical JSON. Running the same ROM and keys on
+//! another emulator and diffing the output cross-checks the two.
+//!
+//! Each state is one line of JSON with no spaces and its keys in
+//! alphabetical order:
+//!
+//! - `audio_pattern`: the XO-CHIP audio pattern as lowercase hex, or null
+//! - `cycles`: instructions run, counting those skipped by `display-wait`
+//! - `delay_timer`: a number
+//! - `display`: one string of 0s and 1s per row, lit pixels being 1
+//! - `hires`: whether the 64x64 mode is on
+//! - `i`: a number
+//! - `keys`: the keys held down, as numbers
+//! - `memory`: all of memory as lowercase hex
+//! - `pc`, `pitch`, `sound_timer`: numbers
+//! - `stack`: the return addresses, oldest first
+//! - `v`: V0 to VF
+//!
+//! Instructions run in 60 Hz frames, as in `chip8 run`, with the timers
+//! ticked at the end of each.
+
+use std::fmt::Write;
+
+use chip8::quirks::Quirks;
+use chip8::random::SeededRandom;
+use chip8::vm::{cycles_in_frame, end_frame, processor_cycle, DEFAULT_TIMER_HZ, PROCESSOR_SPEED_HZ, VM};
+
+use crate::input::{apply_input, InputSource, NullInput, ReplayInput};
+use crate::interrupt;
+use crate::replay::Replay;
+
+/// Run `rom` until at least `cycles` instructions have run or it exits,
+/// returning the final state, and if `every` is given, the state after
+/// every that many instructions before it.
+pub(crate) fn lockstep(
+    rom: &[u8],
+    quirks: Quirks,
+    exit_opcode: Option<u16>,
+    replay: Option<Replay>,
+    cycles: u64,
+    every: Option<u64>,
+) -> Result<Vec<String>, String> {
+    let mut machine = Machine::new(rom, quirks, exit_opcode, replay)?;
+    let mut states = Vec::new();
+    while machine.vm.cycles < cycles && !machine.vm.exited {
+        interrupt::check()?;
+        let before = machine.vm.cycles;
+        machine.step()?;
+        let vm = &machine.vm;
+        if every.is_some_and(|every| vm.cycles / every > before / every) && vm.cycles < cycles {
+            states.push(state_json(vm));
+        }
+    }
+    states.push(state_json(&machine.vm));
+    Ok(states)
+}
+
+/// A VM that runs one instruction at a time, in frames, with keys from
+/// a replay file and the random seed it was recorded with. `chip8
+/// compare` runs two of these side by side.
+pub(crate) struct Machine {
+    pub(crate) vm: VM,
+    input: Box<dyn InputSource>,
+    held_keys: [bool; 16],
+    frames: u64,
+    frame_cycles_left: u64,
+}
+
+impl Machine {
+    pub(crate) fn new(
+        rom: &[u8],
+        quirks: Quirks,
+        exit_opcode: Option<u16>,
+        replay: Option<Replay>,
+    ) -> Result<Machine, String> {
+        let seed = replay.as_ref().map_or(0, |replay| replay.seed);
+        let mut vm = VM::new(Box::new(SeededRandom::new(seed)));
+        vm.quirks = quirks;
+        vm.exit_opcode = exit_opcode;
+        vm.reload(rom)?;
+        let input: Box<dyn InputSource> = match replay {
+            Some(replay) => Box::new(ReplayInput::new(replay)),
+            None => Box::new(NullInput),
+        };
+        Ok(Machine {
+            vm,
+            input,
+            held_keys: [false; 16],
+            frames: 0,
+            frame_cycles_left: cycles_in_frame(0, PROCESSOR_SPEED_HZ),
+        })
+    }
+
+    /// Run the next instruction, and end the frame if it's over.
+    pub(crate) fn step(&mut self) -> Result<(), String> {
+        let vm = &mut self.vm;
+        apply_input(self.input.as_mut(), &mut self.held_keys, None, vm);
+        processor_cycle(vm).map_err(|err| format!("Error in processor cycle {}: {}", vm.cycles, err))?;
+        vm.cycles += 1;
+        self.frame_cycles_left -= 1;
+        if vm.waiting_for_frame {
+            // The draw takes up the rest of the frame
+            vm.cycles += self.frame_cycles_left;
+            self.frame_cycles_left = 0;
+        }
+        if self.frame_cycles_left == 0 {
+            self.frames += 1;
+            end_frame(vm, self.frames, DEFAULT_TIMER_HZ);
+            self.frame_cycles_left = cycles_in_frame(self.frames, PROCESSOR_SPEED_HZ);
+        }
+        Ok(())
+    }
+}
+
+/// The VM's state as canonical JSON, described in the module's
+/// documentation.
+fn state_json(vm: &VM) -> String {
+    let numbers = |numbers: &mut dyn Iterator<Item = u64>| {
+        let numbers: Vec<String> = numbers.map(|n| n.to_string()).collect();
+        format!("[{}]", numbers.join(","))
+    };
+    let hex = |bytes: &[u8]| {
+        bytes.iter().fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        })
+    };
+    let display = &vm.display;
+    let rows: Vec<String> = (0..display.height())
+        .map(|y| {
+            let row: String = (0..display.width())
+                .map(|x| if display.get_pixel(x, y) { '1' } else { '0' })
+                .collect();
+            format!("\"{}\"", row)
+        })
+        .collect();
+    let audio_pattern = match &vm.audio_pattern {
+        Some(pattern) => format!("\"{}\"", hex(pattern)),
+        None => "null".to_string(),
+    };
+
+    format!(
+        concat!(
+            "{{\"audio_pattern\":{},\"cycles\":{},\"delay_timer\":{},\"display\":[{}],\"hires\":{},\"i\":{},",
+            "\"keys\":{},\"memory\":\"{}\",\"pc\":{},\"pitch\":{},\"sound_timer\":{},\"stack\":{},\"v\":{}}}"
+        ),
+        audio_pattern,
+        vm.cycles,
+        vm.delay_timer,
+        rows.join(","),
+        vm.hires,
+        vm.ir,
+        numbers(&mut (0..16).filter(|&key| vm.keys_pressed[key as usize])),
+        hex(&vm.memory),
+        vm.pc,
+        vm.pitch,
+        vm.sound_timer,
+        numbers(&mut vm.stack.frames().iter().map(|frame| frame.return_address() as u64)),
+        numbers(&mut vm.v.iter().map(|&v| v as u64)),
+    )
+}
+
+#[test]
+fn test_lockstep() {
+    // F00A wait for a key into V0, 2208 call 208, 1204 loop; 208: 00EE
+    let rom = [0xF0, 0x0A, 0x22, 0x08, 0x12, 0x04, 0x00, 0x00, 0x00, 0xEE];
+    let replay = Replay::parse("seed 1\n5 7 down\n6 7 up\n").unwrap();
+    let states = lockstep(&rom, Quirks::default(), None, Some(replay), 20, Some(10)).unwrap();
+    assert_eq!(states.len(), 2);
+    assert!(
+        states[0].starts_with("{\"audio_pattern\":null,\"cycles\":10,"),
+        "{}",
+        states[0]
+    );
+    assert!(states[1].contains("\"cycles\":20,"));
+    assert!(states[1].contains("\"v\":[7,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0]}"));
+    assert!(states[1].contains("\"keys\":[],"));
+    assert!(states[1].contains(&format!("\"display\":[\"{}\",", "0".repeat(64))));
+
+    // Without a replay, no key is pressed, so it keeps waiting
+    let states = lockstep(&rom, Quirks::default(), None, None, 20, None).unwrap();
+    assert_eq!(states.len(), 1);
+    assert!(states[0].contains("\"pc\":512,"));
+
+    // Stopping early at 0000, after F00A has been given key 7
+    let replay = Replay::parse("seed 1\n0 7 down\n").unwrap();
+    let states = lockstep(&rom[..4], Quirks::default(), Some(0x0000), Some(replay), 100, None).unwrap();
+    assert!(states[0].contains("\"pc\":520,"), "{}", states[0]);
+}
@@ -1,298 +1,1833 @@
-mod display;
-mod instruction;
+mod analysis;
+mod assembler;
+mod audio_sink;
+mod av_sync;
+mod bench;
+mod cfg;
+mod compare;
+mod condition;
+mod config;
+mod crash;
+mod debugger;
+mod decompiler;
+mod disassembler;
+mod file_watch;
+mod frontend;
+mod history;
+mod image;
+mod input;
+mod interrupt;
+mod launcher;
+mod lint;
+mod lockstep;
+mod netplay;
+mod octo;
+mod opcodes;
+mod paranoid;
+mod profile;
+mod project;
+#[cfg(feature = "remote")]
+mod remote;
+mod replay;
+mod rom_info;
+mod romdb;
+mod save_ram;
+#[cfg(feature = "scripting")]
+mod script;
+mod status;
+mod symbols;
+mod text;
+mod trace;
+mod views;
 
-use std::cmp::min;
-use std::env;
-use std::fs::File;
-use std::io;
-use std::io::Read;
-use std::os::unix::prelude::MetadataExt;
-use std::path::Path;
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
-use instruction::{parse_instruction, Instruction};
+use clap::{Args, Parser, Subcommand};
 
-const MEMORY_BYTES: usize = 4096;
+use audio_sink::{AudioSink, NullSink, WavSink};
+use chip8::audio::Tone;
+use chip8::cheats::{parse_cheats, CheatTable};
+use chip8::decode_cache::DecodeCache;
+use chip8::display::Display;
+use chip8::instruction::parse_opcode;
+#[cfg(feature = "megachip")]
+use chip8::megachip::{MEGA_HEIGHT_PX, MEGA_WIDTH_PX};
+use chip8::platform::{self, Platform};
+use chip8::quirks::Quirks;
+use chip8::random::SeededRandom;
+use chip8::stack;
+use chip8::threaded::BlockCache;
+use chip8::vm::{
+    cycles_in_frame, end_frame, max_rom_bytes, processor_cycle, read_rom_file, DEFAULT_TIMER_HZ, FONT_BYTES,
+    FRAMES_PER_SECOND, MEMORY_BYTES, PROCESSOR_SPEED_HZ, VM, XO_CHIP_MEMORY_BYTES,
+};
+use frontend::effects::Effects;
+use frontend::viewport::ScaleMode;
+use frontend::{FrontendKind, FrontendSettings, Hotkey, InputEvent};
+use history::{History, HistoryEntry};
+use input::{Autofire, FrontendInput, InputSource, KeyLatch, ReplayInput, DEFAULT_AUTOFIRE_HZ};
+use replay::{RecordedSettings, Recorder, Replay};
+use save_ram::SaveRange;
+use views::{Edit, View, ViewAction};
 
-const PROCESSOR_SPEED_HZ: u64 = 700;
+/// How many times faster to run while fast-forwarding, by default
+const DEFAULT_FAST_FORWARD: u64 = 4;
+
+/// Clock multiplier in slow motion
+const SLOW_MOTION_FACTOR: f64 = 0.25;
+
+#[derive(Parser)]
+#[command(name = "chip8", version, about = "A CHIP-8 emulator")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
+    /// How much to log: off, error, warn, info, debug or trace. Debug
+    /// adds an event for every frame (target chip8::frame) and
+    /// instruction (chip8::cpu). Overrides RUST_LOG's default level
+    #[arg(long, global = true, value_name = "LEVEL")]
+    log_level: Option<log::LevelFilter>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a ROM, or build and run the project in the current directory
+    Run(RunArgs),
+    /// Run a ROM, starting paused in the interactive debugger
+    Debug(RunArgs),
+    /// Print the instructions in a ROM, with labels from its symbol
+    /// file if it has one
+    Disasm { rom: PathBuf },
+    /// Print Octo source for a ROM, with labels for jump and call
+    /// targets and data shown as sprites
+    Decompile { rom: PathBuf },
+    /// Check a ROM for likely bugs without running it, such as bad jumps,
+    /// unreachable code and reads of registers that were never set
+    Lint { rom: PathBuf },
+    /// Print a ROM's size, SHA-256 and how much of memory it fills,
+    /// without running it
+    Info {
+        rom: PathBuf,
+
+        /// The platform whose memory to fill, e.g. xochip for 64 KB
+        #[arg(long, value_name = "PLATFORM", value_parser = platform::find)]
+        platform: Option<&'static Platform>,
+    },
+    /// Print the control flow graph of a ROM's code in Graphviz DOT
+    /// format
+    Cfg {
+        rom: PathBuf,
+
+        /// Write the graph to this file instead of standard output
+        #[arg(long, value_name = "FILE")]
+        dot: Option<PathBuf>,
+    },
+    /// Flash the screen and beep together to find the audio offset that
+    /// lines them up, and save it to the config file
+    Calibrate {
+        /// Config file to save to instead of config.toml in the config
+        /// directory
+        #[arg(long, value_name = "FILE")]
+        config: Option<PathBuf>,
+    },
+    /// Run a ROM headless as fast as possible and report its speed
+    Bench {
+        rom: PathBuf,
+
+        /// Number of instructions to run
+        #[arg(long, default_value_t = 1_000_000)]
+        cycles: u64,
+
+        /// Cache decoded instructions
+        #[arg(long)]
+        decode_cache: bool,
+
+        /// Compile instructions to threaded code
+        #[arg(long)]
+        threaded: bool,
+    },
+    /// Run a ROM headless and save its display as an image, or check it
+    /// against one
+    Render {
+        rom: PathBuf,
+
+        /// Where to save the display: a .png or a .pbm file
+        output: Option<PathBuf>,
+
+        /// Number of instructions to run first
+        #[arg(long, default_value_t = 10_000)]
+        cycles: u64,
+
+        /// Fail unless the display matches this .png or .pbm image
+        #[arg(long, value_name = "IMAGE")]
+        expect: Option<PathBuf>,
+
+        /// Write the buzzer to a WAV file
+        #[arg(long, value_name = "FILE")]
+        record_audio: Option<PathBuf>,
+
+        /// Also stop the program at this opcode, like 00FD, e.g. 0000 for
+        /// test ROMs that end by running into empty memory
+        #[arg(long, value_name = "OPCODE", value_parser = parse_opcode)]
+        exit_opcode: Option<u16>,
+    },
+    /// Run a ROM headless for a number of instructions, pressing keys
+    /// from a replay file, and print its state as canonical JSON to diff
+    /// against another emulator's
+    Lockstep {
+        rom: PathBuf,
+
+        /// Number of instructions to run
+        #[arg(long, default_value_t = 10_000)]
+        cycles: u64,
+
+        /// Replay file with the keys to press and the random seed, as
+        /// written by --record
+        #[arg(long, value_name = "FILE")]
+        input: Option<PathBuf>,
+
+        /// Also print the state every N instructions, one line each
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+        every: Option<u64>,
+
+        /// Interpreter quirks to enable, as with `chip8 run --compat`
+        #[arg(long, value_name = "QUIRKS", value_parser = Quirks::parse)]
+        compat: Option<Quirks>,
+
+        /// Also stop the program at this opcode, like 00FD, e.g. 0000 for
+        /// test ROMs that end by running into empty memory
+        #[arg(long, value_name = "OPCODE", value_parser = parse_opcode)]
+        exit_opcode: Option<u16>,
+    },
+    /// Run a ROM on two VMs with different quirks, pressing the same
+    /// keys, and show where their states first differ
+    Compare {
+        rom: PathBuf,
+
+        /// Quirks for the left VM, as with `chip8 run --compat`
+        #[arg(long, value_name = "QUIRKS", value_parser = Quirks::parse)]
+        left: Option<Quirks>,
+
+        /// Quirks for the right VM
+        #[arg(long, value_name = "QUIRKS", value_parser = Quirks::parse)]
+        right: Option<Quirks>,
+
+        /// Number of instructions to run
+        #[arg(long, default_value_t = 100_000)]
+        cycles: u64,
+
+        /// Replay file with the keys to press and the random seed, as
+        /// written by --record
+        #[arg(long, value_name = "FILE")]
+        input: Option<PathBuf>,
+    },
+    /// List the instructions the emulator supports, with the quirks
+    /// that change them and the platforms that have them
+    Opcodes {
+        /// Print JSON for other tools instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Create a new project directory
+    New { name: String },
+    /// Assemble the project in the current directory
+    Build,
+    /// Assemble source files into a ROM, and write its symbols next to
+    /// it. Files ending in .8o are Octo, others use the classic mnemonics
+    Assemble {
+        #[arg(required = true)]
+        sources: Vec<PathBuf>,
+
+        /// Where to write the ROM
+        #[arg(short, long, value_name = "ROM")]
+        output: PathBuf,
+    },
+}
+
+#[derive(Args, Clone)]
+struct RunArgs {
+    /// ROM file to run, or a directory to pick one from in a menu.
+    /// Without one, the project in the current directory is built and
+    /// run using the settings in its manifest, or if there isn't a
+    /// project, the menu lists the ROMs in the current directory.
+    rom: Option<PathBuf>,
+
+    /// Config file to use instead of config.toml in the config directory
+    /// (~/.config/chip8 on Linux)
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Instructions executed per second [default: 700]
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    speed: Option<u64>,
+
+    /// Run as fast as possible, still painting the display 60 times a
+    /// second, and report the speed reached in millions of instructions
+    /// per second (MIPS) on exit. --speed still sets how many
+    /// instructions there are to each tick of the timers. Use with
+    /// --frontend headless to not paint at all
+    #[arg(long)]
+    turbo: bool,
+
+    /// How many times faster to run while Tab is held [default: 4]
+    #[arg(long, value_name = "FACTOR", value_parser = clap::value_parser!(u64).range(1..))]
+    fast_forward: Option<u64>,
+
+    /// Size of each CHIP-8 pixel in window pixels [default: 8]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    scale: Option<u32>,
+
+    /// The machine the ROM was written for, which sets its quirks and
+    /// memory size: vip, chip48, schip-legacy, schip-modern or xochip
+    #[arg(long, value_name = "PLATFORM", value_parser = platform::find)]
+    platform: Option<&'static Platform>,
+
+    /// Interpreter quirks to enable, separated by commas (shift-uses-vy,
+    /// display-wait, wrap-sprites, increment-i, index-overflow,
+    /// jump-uses-vx, vf-reset, half-scroll), or a platform to start
+    /// from. Replaces any quirks set by --platform or the config file.
+    #[arg(long, value_name = "QUIRKS", value_parser = Quirks::parse)]
+    compat: Option<Quirks>,
+
+    /// Where to draw the display
+    #[arg(long, value_enum, default_value_t = FrontendKind::default())]
+    frontend: FrontendKind,
+
+    /// How to fit the display to a resized or fullscreen window (SDL
+    /// frontend only)
+    #[arg(long, value_enum, default_value_t = ScaleMode::default())]
+    scale_mode: ScaleMode,
+
+    /// Start fullscreen (SDL frontend only)
+    #[arg(long)]
+    fullscreen: bool,
+
+    /// Show the registers, the code around PC, the memory around I,
+    /// the breakpoints and the quirks in windows of their own, updated
+    /// as the ROM runs. Click an instruction to set a breakpoint, a
+    /// quirk to toggle it, or, while paused, a register or byte of
+    /// memory to type a new value (SDL frontend only)
+    #[arg(long)]
+    views: bool,
+
+    /// Show the keypad beside the display, with the keys being held
+    /// down lit up. F9 shows or hides it while running
+    #[arg(long)]
+    show_keypad: bool,
+
+    /// Hold each key press down for at least a frame, or until the
+    /// program reads it, so programs that poll the keypad can't miss
+    /// quick taps at high speeds
+    #[arg(long, conflicts_with_all = ["replay", "netplay"])]
+    latch_keys: bool,
+
+    /// Press these keypad keys, hex digits like 5A, over and over while
+    /// they're held down, for games that want a button mashed
+    #[arg(long, value_name = "KEYS", value_parser = input::parse_keys, conflicts_with_all = ["replay", "netplay"])]
+    autofire: Option<[bool; 16]>,
+
+    /// How many times a second --autofire presses its keys [default: 10]
+    #[arg(long, value_name = "HZ", value_parser = clap::value_parser!(u64).range(1..=input::MAX_AUTOFIRE_HZ))]
+    autofire_hz: Option<u64>,
+
+    /// Wait for the display to refresh before drawing each frame, which
+    /// avoids tearing, and time frames by the refresh on 60 Hz displays
+    /// (SDL frontend only)
+    #[arg(long)]
+    vsync: bool,
+
+    /// Post-processing to make the display look like an old CRT,
+    /// separated by commas: scanlines, grid, curvature. F10 turns it
+    /// on and off, starting with scanlines,curvature if this isn't
+    /// given (SDL frontend only)
+    #[arg(long, value_name = "EFFECTS", value_parser = Effects::parse)]
+    effects: Option<Effects>,
+
+    /// Timer frequency, which is also how often the display-wait quirk
+    /// lets a draw finish [default: 60]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    timer_hz: Option<u32>,
+
+    /// Record keypad input to FILE, along with the seed and the settings
+    /// the run depends on, like the speed, platform and quirks
+    #[arg(long, value_name = "FILE", conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Play back keypad input recorded in FILE, with the settings it was
+    /// recorded with
+    #[arg(long, value_name = "FILE")]
+    replay: Option<PathBuf>,
+
+    /// Write the buzzer to a WAV file, in time with the emulated frames
+    #[arg(long, value_name = "FILE")]
+    record_audio: Option<PathBuf>,
+
+    /// Delay the buzzer by MS milliseconds, or the picture if it's
+    /// negative, so they line up (see chip8 calibrate) [default: 0]
+    #[arg(long, value_name = "MS", allow_negative_numbers = true,
+          value_parser = clap::value_parser!(i32).range(-av_sync::MAX_AUDIO_OFFSET_MS as i64..=av_sync::MAX_AUDIO_OFFSET_MS as i64))]
+    audio_offset: Option<i32>,
+
+    /// Pause in the debugger before executing the instruction at
+    /// LOCATION, a label from the ROM's symbol file or a hex address.
+    /// Can be given more than once
+    #[arg(long = "break", value_name = "LOCATION")]
+    breakpoints: Vec<String>,
+
+    /// Reload and restart the ROM whenever its file changes, e.g. after
+    /// reassembling it. In a project, rebuild it whenever its sources
+    /// change
+    #[arg(long, conflicts_with_all = ["replay", "record"])]
+    watch: bool,
+
+    /// Keep memory from START to END (hex addresses, like E00-EFF)
+    /// between runs, for games to save to (see src/save_ram.rs)
+    #[arg(long, value_name = "START-END", value_parser = SaveRange::parse,
+          conflicts_with_all = ["record", "replay", "netplay"])]
+    save_ram: Option<SaveRange>,
+
+    /// Apply the cheats in FILE, memory pokes made when the ROM is loaded
+    /// or every frame (see src/cheats.rs for the format). They aren't
+    /// recorded, so this can't be used with --record or --replay
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["record", "replay"])]
+    cheats: Option<PathBuf>,
+
+    /// Seed for the random number generator
+    #[arg(long, conflicts_with = "replay")]
+    seed: Option<u64>,
+
+    /// Play with someone on another machine, running the same ROM with
+    /// --netplay pointing back here, sharing the keypad (see
+    /// src/netplay.rs). Anything that changes the machine other than
+    /// the keypad would make the two diverge, so this can't be used
+    /// with the debugger, cheats, scripts or remote control
+    #[arg(long, value_name = "HOST:PORT",
+          conflicts_with_all = ["replay", "turbo", "watch", "breakpoints", "cheats", "script", "remote"])]
+    netplay: Option<String>,
+
+    /// The UDP port to listen on for --netplay
+    #[arg(long, value_name = "PORT", default_value_t = netplay::DEFAULT_PORT)]
+    netplay_port: u16,
+
+    /// Maximum subroutine nesting depth
+    #[arg(long, default_value_t = stack::DEFAULT_STACK_DEPTH,
+          value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    stack_depth: usize,
+
+    /// Log suspicious VM states while running. POLICY relaxes or
+    /// tightens the checks: allow-odd-pc lets PC be odd, and
+    /// i=START-END limits I to those (hex) addresses
+    #[arg(long, value_name = "POLICY", num_args = 0..=1, require_equals = true, default_missing_value = "",
+          value_parser = paranoid::Policy::parse)]
+    paranoid: Option<paranoid::Policy>,
+
+    /// Stop with an error if the program counter leaves the program
+    /// area, instead of running interpreter memory or wrapping around
+    #[arg(long)]
+    strict: bool,
+
+    /// Cache decoded instructions, which helps at high speeds
+    #[arg(long)]
+    decode_cache: bool,
+
+    /// Compile instructions to threaded code the first time they run,
+    /// which is faster still at high speeds
+    #[arg(long)]
+    threaded: bool,
+
+    /// Run each instruction without --decode-cache or --threaded too
+    /// and stop with an error if the two disagree. Slow, for testing
+    /// them
+    #[arg(long)]
+    verify_caches: bool,
+
+    /// Log every executed instruction to FILE, or to stderr without one
+    #[arg(long, value_name = "FILE", num_args = 0..=1, require_equals = true)]
+    trace: Option<Option<PathBuf>>,
+
+    /// Only trace instructions in this (hex) address range, e.g. 200-2FF
+    #[arg(long, value_name = "START-END", value_parser = trace::parse_pc_range)]
+    trace_range: Option<RangeInclusive<u16>>,
+
+    /// On error, print the last N executed instructions
+    #[arg(long, value_name = "N")]
+    trace_tail: Option<usize>,
+
+    /// Where to write the crash report when the program hits an error,
+    /// instead of chip8-crash-<time>.txt in the current directory
+    #[arg(long, value_name = "FILE")]
+    crash_report: Option<PathBuf>,
+
+    /// Count how often each instruction runs and print a report on
+    /// exit. With FILE, also write folded stacks for flamegraph tools
+    #[arg(long, value_name = "FILE", num_args = 0..=1, require_equals = true)]
+    profile: Option<Option<PathBuf>>,
+
+    /// Save the display as an image when the emulator exits: a .png or
+    /// a .pbm file
+    #[arg(long, value_name = "FILE")]
+    dump_display: Option<PathBuf>,
+
+    /// Stop after running N instructions, exiting with status 2, so
+    /// unattended runs end
+    #[arg(long, value_name = "N")]
+    max_cycles: Option<u64>,
+
+    /// What to do once the ROM is idle, jumping to itself with the
+    /// timers run out and no keys held: exit (with status 0) or pause
+    #[arg(long, value_name = "ACTION")]
+    on_idle: Option<IdleAction>,
+
+    /// Also stop the program at this opcode, like 00FD, e.g. 0000 for
+    /// test ROMs that end by running into empty memory. Either way, the
+    /// emulator exits when the program does
+    #[arg(long, value_name = "OPCODE", value_parser = parse_opcode)]
+    exit_opcode: Option<u16>,
+
+    /// Run a Mega-Chip ROM, which can be bigger than memory, drawing its
+    /// 256x192 color display while it's in Mega-Chip mode (needs the
+    /// megachip feature)
+    #[arg(long, conflicts_with = "platform")]
+    megachip: bool,
+
+    /// Run a Rhai script that can read and change the VM each frame, to
+    /// automate play or apply cheats (needs the scripting feature). The
+    /// replay file wouldn't have its keys and pokes, so it can't be
+    /// used with --record
+    #[arg(long, value_name = "FILE", conflicts_with = "record")]
+    script: Option<PathBuf>,
+
+    /// Serve a WebSocket API for controlling the emulator on ADDR, e.g.
+    /// :8080 (needs the remote feature)
+    #[arg(long, value_name = "ADDR")]
+    remote: Option<String>,
+}
 
 fn main() {
-    let args: Vec<String> = env::args().skip(1).collect();
-    if args.len() != 1 {
-        eprintln!("Usage: chip8 ROM-FILE");
+    let cli = Cli::parse();
+    init_logging(cli.log_level);
+    interrupt::install();
+    let result = match cli.command {
+        Command::Run(args) => run(args, false),
+        Command::Debug(args) => run(args, true),
+        Command::Disasm { rom } => read_rom_file(&rom).and_then(|bytes| {
+            let symbols = load_symbols(&rom, &bytes)?;
+            print!("{}", disassembler::disassemble(&bytes, &symbols));
+            Ok(())
+        }),
+        Command::Decompile { rom } => read_rom_file(&rom).and_then(|bytes| {
+            let symbols = load_symbols(&rom, &bytes)?;
+            print!("{}", decompiler::decompile(&bytes, &symbols));
+            Ok(())
+        }),
+        Command::Cfg { rom, dot } => read_rom_file(&rom).and_then(|bytes| {
+            let symbols = load_symbols(&rom, &bytes)?;
+            let graph = cfg::cfg_dot(&bytes, &symbols);
+            match dot {
+                Some(path) => {
+                    fs::write(&path, graph).map_err(|err| format!("Error writing {}: {}", path.display(), err))
+                }
+                None => {
+                    print!("{}", graph);
+                    Ok(())
+                }
+            }
+        }),
+        Command::Lint { rom } => read_rom_file(&rom).and_then(|bytes| {
+            let findings = lint::lint(&bytes);
+            for finding in &findings {
+                println!("{:03X}: {}", finding.addr, finding.message);
+            }
+            match findings.len() {
+                0 => {
+                    println!("No problems found");
+                    Ok(())
+                }
+                1 => Err("1 problem found".to_string()),
+                n => Err(format!("{} problems found", n)),
+            }
+        }),
+        Command::Info { rom, platform } => read_rom_file(&rom).and_then(|bytes| {
+            let memory_bytes = platform.map_or(MEMORY_BYTES, |platform| platform.memory_bytes);
+            let info = rom_info::RomInfo::new(&bytes, memory_bytes)?;
+            print!("{}", info.report());
+            info.error().map_or(Ok(()), |err| Err(err.to_string()))
+        }),
+        Command::Calibrate { config } => av_sync::calibrate(config.as_deref()),
+        Command::Bench {
+            rom,
+            cycles,
+            decode_cache,
+            threaded,
+        } => read_rom_file(&rom)
+            .and_then(|bytes| {
+                bench::bench(&bytes, cycles, decode_cache, threaded)
+                    .map_err(|err| format!("{}: {}", rom.display(), err))
+            })
+            .map(|report| println!("{}", report)),
+        Command::Render {
+            rom,
+            output,
+            cycles,
+            expect,
+            record_audio,
+            exit_opcode,
+        } => read_rom_file(&rom)
+            .and_then(|bytes| {
+                let mut wav = record_audio
+                    .as_ref()
+                    .map(|path| WavSink::create(path, Tone::default()))
+                    .transpose()?;
+                let audio: &mut dyn AudioSink = match &mut wav {
+                    Some(wav) => wav,
+                    None => &mut NullSink,
+                };
+                let vm = image::render(&bytes, cycles, exit_opcode, audio)
+                    .map_err(|err| format!("{}: {}", rom.display(), err))?;
+                if let Some(wav) = wav {
+                    wav.finish().map_err(|err| format!("Error writing audio: {}", err))?;
+                }
+                Ok(vm)
+            })
+            .and_then(|vm| {
+                if vm.exited {
+                    log::info!("Exited at {:03X} after {} instructions", vm.pc, vm.cycles);
+                }
+                if let Some(path) = &output {
+                    image::save_display(&vm.display, path)?;
+                }
+                match &expect {
+                    Some(path) if image::load_display(path)?.to_ascii() != vm.display.to_ascii() => Err(format!(
+                        "FAIL: the display doesn't match {}:\n{}",
+                        path.display(),
+                        vm.display.to_ascii()
+                    )),
+                    Some(path) => {
+                        println!("PASS: the display matches {}", path.display());
+                        Ok(())
+                    }
+                    None => Ok(()),
+                }
+            }),
+        Command::Lockstep {
+            rom,
+            cycles,
+            input,
+            every,
+            compat,
+            exit_opcode,
+        } => input
+            .as_ref()
+            .map(|path| {
+                Replay::load(path).map_err(|err| format!("Error loading replay file {}: {}", path.display(), err))
+            })
+            .transpose()
+            .and_then(|replay| {
+                let bytes = read_rom_file(&rom)?;
+                lockstep::lockstep(&bytes, compat.unwrap_or_default(), exit_opcode, replay, cycles, every)
+                    .map_err(|err| format!("{}: {}", rom.display(), err))
+            })
+            .map(|states| states.iter().for_each(|state| println!("{}", state))),
+        Command::Compare {
+            rom,
+            left,
+            right,
+            cycles,
+            input,
+        } => input
+            .as_ref()
+            .map(|path| {
+                Replay::load(path).map_err(|err| format!("Error loading replay file {}: {}", path.display(), err))
+            })
+            .transpose()
+            .and_then(|replay| {
+                let bytes = read_rom_file(&rom)?;
+                compare::compare(
+                    &bytes,
+                    left.unwrap_or_default(),
+                    right.unwrap_or_default(),
+                    replay,
+                    cycles,
+                )
+                .map_err(|err| format!("{}: {}", rom.display(), err))
+            })
+            .map(|report| print!("{}", report)),
+        Command::Opcodes { json } => {
+            let opcodes = opcodes::opcodes();
+            if json {
+                print!("{}", opcodes::json(&opcodes));
+            } else {
+                print!("{}", opcodes::table(&opcodes));
+            }
+            Ok(())
+        }
+        Command::New { name } => project::new_project(&name),
+        Command::Build => build_project().map(|(_, rom_path)| println!("Wrote {}", rom_path.display())),
+        Command::Assemble { sources, output } => project::assemble_paths(&sources)
+            .and_then(|assembled| project::write_rom(&output, assembled))
+            .map(|()| println!("Wrote {}", output.display())),
+    };
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
         std::process::exit(1);
     }
+}
 
-    let rom_path = Path::new(&args[0]);
-    let mut vm = VM::new();
+/// Build the project in the current directory, returning its manifest
+/// and the ROM path.
+fn build_project() -> Result<(project::Manifest, PathBuf), String> {
+    let project_dir = Path::new(".");
+    let manifest = project::Manifest::load(project_dir)?;
+    let rom_path = project::build(project_dir, &manifest)?;
+    Ok((manifest, rom_path))
+}
 
-    if let Err(err) = load_rom_file(&mut vm.memory, rom_path) {
-        eprintln!("Error loading ROM file {}: {}", &rom_path.display(), err);
-        std::process::exit(1);
-    }
+/// Show the ROM menu for `dir`, using the config file's global settings
+/// for the frontend.
+fn choose_rom(dir: &Path, args: &RunArgs) -> Result<Option<PathBuf>, String> {
+    let settings = config::Settings::load(args.config.as_deref())?;
+    let frontend_settings = FrontendSettings {
+        scale: args.scale.or(settings.scale).unwrap_or(frontend::DEFAULT_SCALE),
+        colors: settings.colors()?,
+        keymap: settings.keymap()?,
+        tone: settings.tone()?,
+        show_keypad: false,
+        vsync: false,
+        effects: None,
+        scale_mode: args.scale_mode,
+        fullscreen: args.fullscreen,
+    };
+    launcher::choose_rom(dir, args.frontend, frontend_settings)
+}
 
-    loop {
-        // TODO: Process SDL events for keypresses
+fn run(mut args: RunArgs, debug: bool) -> Result<(), String> {
+    if (debug || !args.breakpoints.is_empty()) && args.frontend == FrontendKind::Tui {
+        return Err(
+            "The debugger reads commands from the terminal, so it can't be used with --frontend tui".to_string(),
+        );
+    }
+    if args.netplay.is_some() && (debug || args.on_idle == Some(IdleAction::Pause)) {
+        return Err(
+            "Netplay can't be used with the debugger or --on-idle pause, which would pause only one machine"
+                .to_string(),
+        );
+    }
 
-        if let Err(err) = processor_cycle(&mut vm) {
-            eprintln!("Error in processor cycle: {}", err);
-            std::process::exit(1);
+    // The project the ROM was built from, if any, for --watch to rebuild
+    let mut project_dir = None;
+    let mut rom_path = match args.rom.take() {
+        Some(dir) if dir.is_dir() => match choose_rom(&dir, &args)? {
+            Some(rom_path) => rom_path,
+            None => return Ok(()),
+        },
+        Some(rom_path) => rom_path,
+        None if !Path::new(project::MANIFEST_FILE).exists() => match choose_rom(Path::new("."), &args)? {
+            Some(rom_path) => rom_path,
+            None => return Ok(()),
+        },
+        None => {
+            // Settings on the command line override the manifest
+            let (manifest, rom_path) = build_project()?;
+            args.timer_hz = args.timer_hz.or(manifest.timer_hz);
+            args.seed = args.seed.or(manifest.seed);
+            project_dir = Some(PathBuf::from("."));
+            rom_path
         }
+    };
 
-        vm.display.paint();
-
-        // TODO: Perform more accurate clock speed emulation
-        // by using clock_gettime(CLOCK_MONOTONIC, ...),
-        // recording the nanosecond time of the last
-        // instruction, and trying to sleep until the next
-        // instruction execution time.
-        std::thread::sleep(std::time::Duration::from_micros(1000000 / PROCESSOR_SPEED_HZ));
+    // Each ROM run is added to the history with its command line
+    // settings, and F8 switches to the previous one with its settings
+    let mut history = History::load();
+    loop {
+        let path = fs::canonicalize(&rom_path).unwrap_or_else(|_| rom_path.clone());
+        history.record(HistoryEntry {
+            path: path.clone(),
+            speed: args.speed,
+            platform: args.platform.map(|platform| platform.name.to_string()),
+            timer_hz: args.timer_hz,
+        });
+        history.save();
+        let previous = history.previous(&path).cloned();
+        match (
+            run_rom(args.clone(), rom_path, project_dir.take(), debug, previous.is_some())?,
+            previous,
+        ) {
+            (Exit::SwitchRom, Some(entry)) => {
+                args.speed = entry.speed;
+                args.platform = entry.platform.as_deref().map(platform::find).transpose()?;
+                args.timer_hz = entry.timer_hz;
+                rom_path = entry.path;
+            }
+            (Exit::MaxCycles, _) => std::process::exit(MAX_CYCLES_EXIT_STATUS),
+            _ => return Ok(()),
+        }
     }
 }
 
-struct VM {
-    memory: Memory,
-    display: display::Display,
+/// The exit status when --max-cycles stops the emulator.
+const MAX_CYCLES_EXIT_STATUS: i32 = 2;
 
-    // Program counter
-    pc: u16,
+/// Why the emulator stopped.
+enum Exit {
+    Quit,
+    /// F8 was pressed to switch to the previous ROM in the history
+    SwitchRom,
+    /// It ran the number of instructions given by --max-cycles
+    MaxCycles,
+}
 
-    // Index register
-    ir: u16,
+/// What --on-idle does when the ROM has nothing more to do.
+#[derive(Debug, PartialEq, Clone, Copy, clap::ValueEnum)]
+enum IdleAction {
+    Exit,
+    Pause,
+}
 
-    // Stack is for subroutines
-    stack: Vec<u16>,
+/// Run the ROM at `rom_path`, built from the project in `project_dir` if
+/// given, until the user quits or switches ROMs.
+fn run_rom(
+    mut args: RunArgs,
+    rom_path: PathBuf,
+    project_dir: Option<PathBuf>,
+    debug: bool,
+    can_switch: bool,
+) -> Result<Exit, String> {
+    let rom = read_rom_file(&rom_path)?;
 
-    // General purpose registers
-    v: [u8; 16],
+    // Settings on the command line or in the manifest override the
+    // config file, which overrides the ROM database
+    let rom_info = romdb::lookup(&rom)?;
+    let mut settings = rom_info.as_ref().map(romdb::RomInfo::settings).unwrap_or_default();
+    settings.merge(config::Settings::load(args.config.as_deref())?.for_rom(&rom_path, &rom));
+    args.speed = args.speed.or(settings.speed);
+    args.fast_forward = args.fast_forward.or(settings.fast_forward);
+    args.timer_hz = args.timer_hz.or(settings.timer_hz);
+    args.audio_offset = args.audio_offset.or(settings.audio_offset()?);
+    // Recordings and replays have to start from the same memory every
+    // time, and both machines in netplay from the same memory as each
+    // other
+    if args.save_ram.is_none() && args.record.is_none() && args.replay.is_none() && args.netplay.is_none() {
+        args.save_ram = settings.save_ram()?;
+    }
+    // Replays already have the latched keys, and netplay keys only
+    // change between frames
+    if args.replay.is_none() && args.netplay.is_none() {
+        args.latch_keys |= settings.latch_keys.unwrap_or(false);
+        args.autofire = args.autofire.or(settings.autofire()?);
+    }
+    args.autofire_hz = args.autofire_hz.or(settings.autofire_hz);
+    let replay = match &args.replay {
+        Some(path) => {
+            Some(Replay::load(path).map_err(|err| format!("Error loading replay file {}: {}", path.display(), err))?)
+        }
+        None => None,
+    };
+    // Replays run with the settings they were recorded with, so they
+    // play back the same, including the platform, for the same memory
+    // size and font
+    if let Some(replay) = &replay {
+        args.stack_depth = replay.stack_depth.unwrap_or(args.stack_depth);
+        args.strict = replay.strict.unwrap_or(args.strict);
+        args.exit_opcode = replay.exit_opcode.unwrap_or(args.exit_opcode);
+        args.megachip = replay.megachip.unwrap_or(args.megachip);
+    }
+    let platform = match (replay.as_ref().and_then(|replay| replay.platform), args.platform) {
+        (Some(recorded), _) => recorded,
+        (None, Some(platform)) => Some(platform),
+        (None, None) => settings.platform()?,
+    };
+    let quirks = match args.compat {
+        Some(quirks) => quirks,
+        None => settings.quirks(platform.map_or_else(Quirks::default, |platform| platform.quirks))?,
+    };
+    // Refuse a ROM that doesn't fit before loading any of it
+    let memory_bytes = platform.map_or(MEMORY_BYTES, |platform| platform.memory_bytes);
+    let info = rom_info::RomInfo::new(&rom, memory_bytes)?;
+    // Mega-Chip ROMs only run code from the part that fits
+    if let Some(err) = info.error().filter(|_| !args.megachip) {
+        return Err(match platform {
+            None if rom.len() <= max_rom_bytes(XO_CHIP_MEMORY_BYTES) => format!(
+                "{}: {} (it would fit in XO-CHIP's 64 KB, with --platform xochip)",
+                rom_path.display(),
+                err
+            ),
+            _ => format!("{}: {}", rom_path.display(), err),
+        });
+    }
+    let rom_name = match &rom_info {
+        Some(info) => info.title.clone(),
+        None => rom_path.file_stem().map_or_else(
+            || rom_path.display().to_string(),
+            |stem| stem.to_string_lossy().into_owned(),
+        ),
+    };
+    log::info!("Loaded {}: {}", rom_name, info.summary());
+    if let Some(platform) = platform {
+        for feature in platform.unsupported_features() {
+            log::warn!(
+                "{} has {}, which isn't supported yet, so {} may not run correctly",
+                platform.name,
+                feature,
+                rom_name
+            );
+        }
+    }
+    let frontend_settings = FrontendSettings {
+        scale: args.scale.or(settings.scale).unwrap_or(frontend::DEFAULT_SCALE),
+        colors: settings.colors()?,
+        keymap: settings.keymap()?,
+        tone: settings.tone()?,
+        show_keypad: args.show_keypad || settings.show_keypad.unwrap_or(false),
+        vsync: args.vsync,
+        effects: args.effects,
+        scale_mode: args.scale_mode,
+        fullscreen: args.fullscreen,
+    };
 
-    keys_pressed: [bool; 16],
+    let rom = LoadedRom {
+        path: rom_path,
+        bytes: rom,
+        name: rom_name,
+        project_dir,
+        platform,
+        memory_bytes,
+        font: platform.map_or(&FONT_BYTES, |platform| platform.font),
+        megachip: args.megachip,
+    };
+    run_emulator(&args, &rom, replay, quirks, frontend_settings, debug, can_switch)
+        .map_err(|err| format!("{}: {}", rom.path.display(), err))
+}
 
-    // Timers decremented at 60 Hz
-    delay_timer: u8,
-    sound_timer: u8,
+/// The ROM being run, kept so it can be reloaded from disk.
+struct LoadedRom {
+    path: PathBuf,
+    bytes: Vec<u8>,
+    name: String,
+    /// The project it was built from, which --watch watches instead
+    project_dir: Option<PathBuf>,
+    /// The platform it runs as, if one was picked
+    platform: Option<&'static Platform>,
+    /// How much memory it runs with, which is more for XO-CHIP
+    memory_bytes: usize,
+    /// The platform's small font
+    font: &'static [u8; 80],
+    /// Whether it's a Mega-Chip ROM
+    #[cfg_attr(not(feature = "megachip"), allow(dead_code))]
+    megachip: bool,
 }
 
-type Memory = [u8; MEMORY_BYTES];
-
-impl VM {
-    fn new() -> VM {
-        VM {
-            memory: [0; MEMORY_BYTES],
-            display: display::Display::new(),
-            pc: 0x200,
-            ir: 0,
-            stack: Vec::new(),
-            v: [0; 16],
-            keys_pressed: [false; 16],
-            delay_timer: 0,
-            sound_timer: 0,
+impl LoadedRom {
+    /// Load `bytes`, this ROM as it is on disk now, into `vm`, as with
+    /// `VM::reload`.
+    fn load(&self, vm: &mut VM, bytes: &[u8]) -> Result<(), String> {
+        #[cfg(feature = "megachip")]
+        if self.megachip {
+            return chip8::megachip::load_megachip_rom(vm, bytes);
         }
+        vm.reload(bytes)
     }
 }
 
-const FONT_MEMORY_START: usize = 0x050;
-
-static FONT_BYTES: [u8; 80] = [
-    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-    0x20, 0x60, 0x20, 0x20, 0x70, // 1
-    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-];
-
-fn load_rom_file(memory: &mut Memory, path: &Path) -> io::Result<()> {
-    let mut f = File::open(path)?;
-    let metadata = f.metadata()?;
-
-    // Rom memory starts at 0x200
-    let amount_read = f.read(&mut memory[0x200..])?;
-    if amount_read as u64 != metadata.size() {
-        eprintln!(
-            "Read {} bytes of ROM file, but file size is {}",
-            amount_read,
-            metadata.size()
-        );
-        std::process::exit(1);
+/// The symbols for a ROM: its symbol file, plus any regions the ROM
+/// database names.
+fn load_symbols(rom_path: &Path, rom: &[u8]) -> Result<symbols::Symbols, String> {
+    let mut symbols = symbols::Symbols::load_for_rom(rom_path)?;
+    if let Some(info) = romdb::lookup(rom)? {
+        info.add_regions(&mut symbols);
+    }
+    Ok(symbols)
+}
+
+/// Log to stderr at `level`, or as RUST_LOG says, or otherwise at info
+/// and up. Info messages are printed as they are, like the rest of the
+/// command's output.
+fn init_logging(level: Option<log::LevelFilter>) {
+    let mut builder = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"));
+    if let Some(level) = level {
+        builder.filter_level(level);
     }
+    builder.format(|out, record| match record.level() {
+        log::Level::Info => writeln!(out, "{}", record.args()),
+        log::Level::Warn => writeln!(out, "warning: {}", record.args()),
+        log::Level::Error => writeln!(out, "error: {}", record.args()),
+        level => writeln!(out, "{} {}: {}", level, record.target(), record.args()),
+    });
+    builder.init();
+}
 
-    // Load font into 0x050–0x09F
-    memory[0x050..=0x09F].copy_from_slice(&FONT_BYTES);
+/// The files --watch watches: the ROM, or the sources it's built from.
+fn watch_paths(rom: &LoadedRom) -> Result<Vec<PathBuf>, String> {
+    match &rom.project_dir {
+        Some(project_dir) => project::watch_paths(project_dir),
+        None => Ok(vec![rom.path.clone()]),
+    }
+}
 
-    Ok(())
+/// Reload the ROM from disk to pick up any changes, e.g. after
+/// rebuilding it, keeping any save RAM. On error keep running what's
+/// already loaded.
+fn hard_reset(vm: &mut VM, rom: &LoadedRom, save_ram: Option<SaveRange>) {
+    let saved = save_ram.map(|range| range.read(vm));
+    let result = read_rom_file(&rom.path).and_then(|bytes| rom.load(vm, &bytes));
+    if let Err(err) = result {
+        log::error!("{}", err);
+    }
+    if let (Some(range), Some(saved)) = (save_ram, saved) {
+        range.write(vm, &saved);
+    }
 }
 
-fn processor_cycle(vm: &mut VM) -> Result<(), String> {
-    // Instructions are two bytes
-    let raw_instruction: u16 = (vm.memory[vm.pc as usize] as u16) << 8 | vm.memory[vm.pc as usize + 1] as u16;
-
-    // println!("instruction {:#04X?} (PC: {:#04X?})", instruction, &current_pc);
-
-    // Increment program counter here instead of in each instruction
-    // so we don't forget.
-    vm.pc += 2;
-
-    match parse_instruction(raw_instruction)? {
-        Instruction::ClearScreen => vm.display.clear(),
-        Instruction::SubroutineReturn => match vm.stack.pop() {
-            None => {
-                eprintln!(
-                    "internal error: pop from empty stack! instruction {:#04X?} (PC: {:#04X?})",
-                    raw_instruction, vm.pc
-                );
-                std::process::exit(1);
-            }
-            Some(pc) => vm.pc = pc,
+/// Run the emulator until the user quits, or switches ROMs if
+/// `can_switch`. Errors are returned rather than printed so the
+/// frontend can restore the terminal first.
+fn run_emulator(
+    options: &RunArgs,
+    rom: &LoadedRom,
+    replay: Option<Replay>,
+    mut quirks: Quirks,
+    frontend_settings: FrontendSettings,
+    debug: bool,
+    can_switch: bool,
+) -> Result<Exit, String> {
+    let mut speed = options.speed.unwrap_or(PROCESSOR_SPEED_HZ);
+    let mut timer_hz = options.timer_hz.unwrap_or(DEFAULT_TIMER_HZ);
+    // Replays run with the settings they were recorded with, so they
+    // play back the same
+    if let Some(replay) = &replay {
+        speed = replay.speed.unwrap_or(speed);
+        timer_hz = replay.timer_hz.unwrap_or(timer_hz);
+        quirks = replay.quirks.unwrap_or(quirks);
+    }
+
+    let netplay = match &options.netplay {
+        Some(peer) => {
+            let seed = options.seed.unwrap_or_else(rand::random);
+            let session = netplay::Session::new(&rom.bytes, seed, speed, timer_hz, quirks);
+            Some(netplay::Netplay::connect(options.netplay_port, peer, session)?)
+        }
+        None => None,
+    };
+
+    let seed = match (&replay, &netplay, options.seed) {
+        (Some(replay), _, _) => replay.seed,
+        (None, Some(netplay), _) => netplay.seed(),
+        (None, None, Some(seed)) => seed,
+        (None, None, None) => rand::random(),
+    };
+
+    let mut recorder = match &options.record {
+        Some(path) => Some(
+            Recorder::create(
+                path,
+                seed,
+                &RecordedSettings {
+                    speed,
+                    timer_hz,
+                    platform: rom.platform,
+                    quirks,
+                    stack_depth: options.stack_depth,
+                    strict: options.strict,
+                    exit_opcode: options.exit_opcode,
+                    megachip: rom.megachip,
+                },
+            )
+            .map_err(|err| format!("Error creating replay file {}: {}", path.display(), err))?,
+        ),
+        None => None,
+    };
+
+    // Keys come from the replay file if there is one, from both
+    // machines in netplay, and otherwise from the frontend, with any
+    // autofire keys pulsed
+    let replaying = replay.is_some();
+    let netplaying = netplay.is_some();
+    let mut input: Box<dyn InputSource> = match (replay, netplay) {
+        (Some(replay), _) => Box::new(ReplayInput::new(replay)),
+        (None, Some(netplay)) => Box::new(netplay),
+        (None, None) => match options.autofire {
+            Some(keys) => Box::new(Autofire::new(
+                Box::<FrontendInput>::default(),
+                keys,
+                speed,
+                options.autofire_hz.unwrap_or(DEFAULT_AUTOFIRE_HZ),
+            )),
+            None => Box::<FrontendInput>::default(),
         },
-        Instruction::Jump { nnn } => vm.pc = nnn,
-        Instruction::SubroutineCall { nnn } => {
-            vm.stack.push(vm.pc);
-            vm.pc = nnn; // Jump to NNN
+    };
+    let mut held_keys = [false; 16];
+    let mut latch = options
+        .latch_keys
+        .then(|| KeyLatch::new((speed / FRAMES_PER_SECOND).max(1)));
+
+    let mut vm = VM::new(Box::new(SeededRandom::new(seed)));
+    vm.set_memory_bytes(rom.memory_bytes);
+    vm.font = rom.font;
+    vm.stack = stack::CallStack::new(options.stack_depth);
+    vm.quirks = quirks;
+    vm.strict = options.strict;
+    vm.exit_opcode = options.exit_opcode;
+    if let Some(path) = &options.cheats {
+        let contents =
+            fs::read_to_string(path).map_err(|err| format!("Error reading cheats file {}: {}", path.display(), err))?;
+        let cheats = parse_cheats(&contents).map_err(|err| format!("{}: {}", path.display(), err))?;
+        vm.cheats = CheatTable::new(cheats);
+    }
+    #[cfg(not(feature = "megachip"))]
+    if rom.megachip {
+        return Err("--megachip needs chip8 to be built with the megachip feature".to_string());
+    }
+    rom.load(&mut vm, &rom.bytes)?;
+    if let Some(range) = options.save_ram {
+        save_ram::restore(range, &rom.bytes, &mut vm)?;
+    }
+    if options.decode_cache {
+        vm.decode_cache = Some(DecodeCache::new(vm.memory.len()));
+    }
+    if options.threaded {
+        vm.block_cache = Some(BlockCache::new(vm.memory.len()));
+    }
+    vm.verify_caches = options.verify_caches;
+
+    #[cfg(feature = "scripting")]
+    let mut script = match &options.script {
+        Some(path) => Some(script::Script::load(path, &mut vm)?),
+        None => None,
+    };
+    #[cfg(not(feature = "scripting"))]
+    if options.script.is_some() {
+        return Err("--script needs chip8 to be built with the scripting feature".to_string());
+    }
+
+    #[cfg(feature = "remote")]
+    let mut remote = match &options.remote {
+        Some(addr) => {
+            let remote = remote::Remote::start(addr, speed)?;
+            log::info!("Listening for remote control on ws://{}", remote.addr());
+            Some(remote)
         }
-        Instruction::SkipVxEqNn { x, nn } => {
-            if vm.v[x] == nn {
-                vm.pc += 2
+        None => None,
+    };
+    #[cfg(not(feature = "remote"))]
+    if options.remote.is_some() {
+        return Err("--remote needs chip8 to be built with the remote feature".to_string());
+    }
+
+    let mut paranoid = options.paranoid.clone().map(paranoid::Paranoid::new);
+
+    // The remote API serves the profiler's stats
+    let mut profiler = if options.profile.is_some() || options.remote.is_some() {
+        Some(profile::Profiler::new(
+            vm.memory.len(),
+            matches!(options.profile, Some(Some(_))),
+        ))
+    } else {
+        None
+    };
+
+    let mut recent = crash::RecentInstructions::new();
+    let tail = options
+        .trace_tail
+        .map(|n| Rc::new(RefCell::new(trace::RingBufferSink::new(n))));
+    let mut tracer = {
+        let mut sinks: Vec<Box<dyn trace::TraceSink>> = Vec::new();
+        match &options.trace {
+            Some(Some(path)) => {
+                let sink = trace::FileSink::create(path)
+                    .map_err(|err| format!("Error creating trace file {}: {}", path.display(), err))?;
+                sinks.push(Box::new(sink));
             }
+            Some(None) => sinks.push(Box::new(trace::StderrSink)),
+            None => {}
         }
-        Instruction::SkipVxNeqNn { x, nn } => {
-            if vm.v[x] != nn {
-                vm.pc += 2
-            }
+        if let Some(tail) = &tail {
+            sinks.push(Box::new(tail.clone()));
         }
-        Instruction::SkipVxEqVy { x, y } => {
-            if vm.v[x] == vm.v[y] {
-                vm.pc += 2
-            }
+        if sinks.is_empty() {
+            None
+        } else {
+            Some(trace::Tracer::new(sinks, options.trace_range.clone()))
         }
-        Instruction::SkipVxNeqVy { x, y } => {
-            if vm.v[x] != vm.v[y] {
-                vm.pc += 2
-            }
+    };
+
+    let mut watcher = if options.watch {
+        Some(file_watch::FileWatcher::new(
+            watch_paths(rom)?,
+            file_watch::POLL_INTERVAL,
+        ))
+    } else {
+        None
+    };
+
+    let view_symbols = if options.views {
+        Some(load_symbols(&rom.path, &rom.bytes)?)
+    } else {
+        None
+    };
+    let mut shown_views = Vec::new();
+    let mut edit: Option<Edit> = None;
+
+    // The views can add breakpoints, so they need a debugger
+    let mut debugger = if debug || !options.breakpoints.is_empty() || options.views {
+        let symbols = load_symbols(&rom.path, &rom.bytes)?;
+        let mut breakpoints = Vec::new();
+        for location in &options.breakpoints {
+            breakpoints.push(symbols.parse_location(location)?);
         }
-        Instruction::SetVxNn { x, nn } => vm.v[x] = nn,
-        Instruction::AddNnVx { x, nn } => vm.v[x] = vm.v[x].wrapping_add(nn),
-        Instruction::SetVxVy { x, y } => vm.v[x] = vm.v[y],
-        Instruction::SetVxOrVy { x, y } => vm.v[x] |= vm.v[y],
-        Instruction::SetVxAndVy { x, y } => vm.v[x] &= vm.v[y],
-        Instruction::SetVxXorVy { x, y } => vm.v[x] ^= vm.v[y],
-        Instruction::SetVxPlusVy { x, y } => match vm.v[x].checked_add(vm.v[y]) {
-            Some(sum) => vm.v[x] = sum,
-            None => {
-                // Set overflow register
-                vm.v[0xF] = 1;
-                vm.v[x] = vm.v[x].wrapping_add(vm.v[y]);
-            }
-        },
-        Instruction::SetVxMinusVy { x, y } => {
-            vm.v[0xF] = (vm.v[x] > vm.v[y]) as u8;
-            vm.v[x] = vm.v[x].wrapping_sub(vm.v[y]);
+        let mut debugger = debugger::Debugger::new(debug, symbols);
+        for addr in breakpoints {
+            debugger.add_breakpoint(addr);
         }
-        Instruction::ShiftVxRight { x } => {
-            vm.v[0xF] = vm.v[x] & 0x1;
-            vm.v[x] >>= 1;
+        if recorder.is_some() {
+            debugger.refuse_back("recording");
+            debugger.refuse_cheats("recording");
+        } else if replaying {
+            debugger.refuse_back("replaying");
+            debugger.refuse_cheats("replaying");
+        }
+        Some(debugger)
+    } else {
+        None
+    };
+
+    let mut tone = frontend_settings.tone;
+    let mut audio_pattern = None;
+    let mut wav = options
+        .record_audio
+        .as_ref()
+        .map(|path| WavSink::create(path, tone))
+        .transpose()?;
+    let mut frontend = frontend::create(options.frontend, frontend_settings)?;
+    let mut picture_delay = av_sync::apply_offset(options.audio_offset.unwrap_or(0), frontend.as_mut());
+    let mut throughput = if options.turbo {
+        Some(status::Throughput::new())
+    } else {
+        None
+    };
+    let mut status = status::TitleStatus::new(rom.name.clone());
+    frontend.set_title(&status.initial_title());
+
+    // Work is scheduled in 60 Hz frames: each frame runs its share of
+    // the instructions, then ticks the timers, and the display is
+    // painted once per frame. Frames are counted in instructions
+    // rather than wall-clock time so that replays are deterministic.
+    let mut frames = 0;
+    let mut frame_cycles_left = cycles_in_frame(frames, speed);
+    let frame_duration = Duration::from_secs_f64(1.0 / FRAMES_PER_SECOND as f64);
+    let mut next_frame = Instant::now();
+
+    // How many frames to run this time around, which fast-forward and
+    // slow motion change (along with the timers, as they're ticked per
+    // frame). Pausing stops both the processor and the timers.
+    let fast_forward_factor = options.fast_forward.unwrap_or(DEFAULT_FAST_FORWARD) as f64;
+    let mut frame_budget = 0.0;
+    let mut fast_forward = false;
+    let mut slow_motion = false;
+    let mut paused_by_user = false;
+    let mut advance_frame = false;
+    // Where --on-idle last paused, so it doesn't pause again there when
+    // resumed
+    let mut idle_paused_at = None;
+
+    loop {
+        for event in frontend.poll_events() {
+            input.handle_event(&event);
+            match event {
+                // The input source takes care of quitting, checked below
+                InputEvent::Quit => {}
+                // Showing the keypad and effects don't affect the
+                // program, so they work during a replay too
+                InputEvent::Hotkey {
+                    hotkey: Hotkey::ToggleKeypad,
+                    pressed: true,
+                } => frontend.toggle_keypad(),
+                InputEvent::Hotkey {
+                    hotkey: Hotkey::ToggleEffects,
+                    pressed: true,
+                } => frontend.toggle_effects(),
+                // Live input is ignored during a replay so it can't
+                // perturb the recorded run.
+                InputEvent::Key { .. }
+                | InputEvent::Hotkey { .. }
+                | InputEvent::ViewClicked { .. }
+                | InputEvent::ViewKey(_)
+                    if replaying => {}
+                // Both machines have to run the same frames, so only the
+                // keypad works during netplay
+                InputEvent::Hotkey { .. } | InputEvent::ViewClicked { .. } | InputEvent::ViewKey(_) if netplaying => {}
+                InputEvent::ViewClicked {
+                    view,
+                    line,
+                    column,
+                    right,
+                } => {
+                    let action = shown_views
+                        .iter()
+                        .find(|shown: &&View| shown.title == view)
+                        .and_then(|shown| shown.action(line, column));
+                    let paused = paused_by_user || debugger.as_ref().is_some_and(debugger::Debugger::is_paused);
+                    edit = None;
+                    match (action, &mut debugger) {
+                        (Some(ViewAction::ToggleBreakpoint(addr)), Some(debugger)) if right => {
+                            debugger.run_to(&vm, addr)
+                        }
+                        (Some(ViewAction::ToggleBreakpoint(addr)), Some(debugger)) => debugger.toggle_breakpoint(addr),
+                        (Some(ViewAction::DeleteBreakpoint(n)), Some(debugger)) => debugger.remove_breakpoint(n),
+                        // The replay file only has the quirks the run
+                        // started with
+                        (Some(ViewAction::ToggleQuirk(_)), _) if recorder.is_some() => {
+                            log::warn!("Can't change quirks while recording")
+                        }
+                        (Some(ViewAction::ToggleQuirk(name)), _) => {
+                            let on = vm.quirks.get(name) == Some(true);
+                            vm.quirks.set(name, !on)?;
+                        }
+                        (Some(ViewAction::Edit(_)), _) if recorder.is_some() => {
+                            log::warn!("Can't edit the machine's state while recording")
+                        }
+                        (Some(ViewAction::Edit(_)), _) if !paused => log::warn!("Pause before editing"),
+                        (Some(ViewAction::Edit(field)), _) => edit = Some(Edit::new(field)),
+                        _ => {}
+                    }
+                }
+                InputEvent::ViewKey(key) => {
+                    if edit.as_mut().is_some_and(|edit| !edit.type_key(key, &mut vm)) {
+                        edit = None;
+                    }
+                }
+                // The input source follows the keypad
+                InputEvent::Key { .. } => {}
+                InputEvent::Hotkey { hotkey, pressed: true } => {
+                    match hotkey {
+                        // The replay file only covers a run from power-on
+                        Hotkey::Reset | Hotkey::HardReset if recorder.is_some() => {
+                            log::warn!("Can't reset while recording")
+                        }
+                        Hotkey::Reset => vm.reset(),
+                        Hotkey::HardReset => hard_reset(&mut vm, rom, options.save_ram),
+                        Hotkey::Pause => paused_by_user = !paused_by_user,
+                        Hotkey::FrameAdvance if paused_by_user => advance_frame = true,
+                        Hotkey::FrameAdvance => {}
+                        Hotkey::FastForward => fast_forward = true,
+                        Hotkey::SlowMotion => slow_motion = !slow_motion,
+                        Hotkey::CycleWaveform => {
+                            tone.waveform = tone.waveform.next();
+                            for sink in audio_sinks(frontend.as_mut(), &mut wav) {
+                                sink.set_tone(tone);
+                            }
+                        }
+                        Hotkey::SwitchRom if !can_switch => log::warn!("No other ROM in the history to switch to"),
+                        // The replay file only covers one ROM
+                        Hotkey::SwitchRom if recorder.is_some() => log::warn!("Can't switch ROMs while recording"),
+                        Hotkey::SwitchRom => {
+                            drop(frontend);
+                            finish_run(profiler.as_ref(), throughput.as_ref(), wav.take(), options, &vm, rom)?;
+                            return Ok(Exit::SwitchRom);
+                        }
+                        Hotkey::ToggleKeypad
+                        | Hotkey::ToggleEffects
+                        | Hotkey::MenuUp
+                        | Hotkey::MenuDown
+                        | Hotkey::MenuSelect => {}
+                    }
+                }
+                InputEvent::Hotkey {
+                    hotkey: Hotkey::FastForward,
+                    pressed: false,
+                } => fast_forward = false,
+                InputEvent::Hotkey { .. } => {}
+            }
         }
-        Instruction::SetVyMinusVx { x, y } => {
-            vm.v[0xF] = (vm.v[y] > vm.v[x]) as u8;
-            vm.v[x] = vm.v[y].wrapping_sub(vm.v[x]);
+        if input.wants_quit() || interrupt::requested() || debugger.as_ref().is_some_and(debugger::Debugger::wants_quit)
+        {
+            drop(frontend);
+            finish_run(profiler.as_ref(), throughput.as_ref(), wav.take(), options, &vm, rom)?;
+            return Ok(Exit::Quit);
         }
-        Instruction::ShiftVxLeft { x } => {
-            vm.v[0xF] = (vm.v[x] >> 7) & 0x1;
-            vm.v[x] <<= 1;
+        // Apply the keys now too, so they show on the keypad panel while
+        // paused
+        poll_input(input.as_mut(), &mut held_keys, latch.as_mut(), &mut vm, &mut recorder)?;
+
+        #[cfg(feature = "remote")]
+        if let (Some(remote), Some(profiler)) = (&mut remote, &profiler) {
+            // Remote keys and steps would bypass the replay file
+            let locked = if recorder.is_some() {
+                Some("recording")
+            } else if replaying {
+                Some("replaying")
+            } else {
+                None
+            };
+            remote.poll(&mut vm, &mut paused_by_user, profiler, locked);
         }
-        Instruction::SetIndexNnn { nnn } => vm.ir = nnn,
-        Instruction::JumpV0Nnn { nnn } => vm.pc = vm.v[0] as u16 + nnn,
-        Instruction::SetVxRandNn { x, nn } => vm.v[x] = rand::random::<u8>() & nn,
-        Instruction::Display { x, y, n } => {
-            // Display n-byte sprite starting at memory location I at
-            // (Vx, Vy), set VF = collision.
-            let dx: u16 = vm.v[x] as u16 % display::DISPLAY_WIDTH_PX as u16;
-            let dy: u16 = vm.v[y] as u16 % display::DISPLAY_HEIGHT_PX as u16;
-
-            // Reset collision flag
-            vm.v[0xF] = 0;
-
-            // Read n bytes from memory. j is the y value
-            for j in 0..min(n as u16, display::DISPLAY_HEIGHT_PX as u16 - dy) {
-                let sprite_row: u8 = vm.memory[(vm.ir + j) as usize];
-
-                // i is the x value we use to iterate over bits
-                for i in 0..min(8, display::DISPLAY_WIDTH_PX as u16 - dx) {
-                    // Bit shift to get the current row bit
-                    let sprite_bit: bool = ((sprite_row >> (7 - i)) & 0b1) == 1;
-
-                    let x = (dx + i) as usize;
-                    let y = (dy + j) as usize;
-                    let pixel = vm.display.get_pixel(x, y);
-                    if pixel && sprite_bit {
-                        // Set collision register
-                        vm.v[0xF] = 1;
-                    }
 
-                    // XOR with current bit
-                    vm.display.set_pixel(x, y, pixel ^ sprite_bit);
+        if watcher.as_mut().is_some_and(|watcher| watcher.changed()) {
+            match &rom.project_dir {
+                Some(project_dir) => {
+                    log::info!("Sources changed, rebuilding");
+                    let built = project::Manifest::load(project_dir)
+                        .and_then(|manifest| project::build(project_dir, &manifest));
+                    match built {
+                        Ok(_) => hard_reset(&mut vm, rom, options.save_ram),
+                        // Keep running the last build until the next change
+                        Err(err) => log::error!("{}", err),
+                    }
+                    // Pick up any sprite files added or removed
+                    watcher = Some(file_watch::FileWatcher::new(
+                        watch_paths(rom)?,
+                        file_watch::POLL_INTERVAL,
+                    ));
+                }
+                None => {
+                    log::info!("{} changed, reloading", rom.path.display());
+                    hard_reset(&mut vm, rom, options.save_ram);
                 }
             }
         }
-        Instruction::SkipIfVxPressed { x } => {
-            if vm.keys_pressed[vm.v[x] as usize] {
-                vm.pc += 2;
+
+        if !paused_by_user && options.turbo {
+            // Run frames until the next paint is due, checked below
+            frame_budget = f64::INFINITY;
+        } else if !paused_by_user {
+            let mut clock = 1.0;
+            if fast_forward {
+                clock *= fast_forward_factor;
             }
-        }
-        Instruction::SkipIfVxNotPressed { x } => {
-            if !vm.keys_pressed[vm.v[x] as usize] {
-                vm.pc += 2;
+            if slow_motion {
+                clock *= SLOW_MOTION_FACTOR;
             }
+            frame_budget += clock;
+        } else if advance_frame {
+            frame_budget = 1.0;
+            advance_frame = false;
         }
-        Instruction::SetVxDelay { x } => vm.v[x] = vm.delay_timer,
-        Instruction::SetDelayVx { x } => vm.delay_timer = vm.v[x],
-        Instruction::SetSoundVx { x } => vm.sound_timer = vm.v[x],
-        Instruction::AddVxI { x } => match (vm.v[x] as u16).checked_add(vm.ir) {
-            // Overflow behavior is non-standard, but assumed safe
-            Some(sum) => vm.ir = sum,
-            None => {
-                // Set overflow register
-                vm.v[0xF] = 1;
-                vm.ir = (vm.v[x] as u16).wrapping_add(vm.ir);
+
+        let mut paused = paused_by_user;
+        while frame_budget >= 1.0 {
+            while frame_cycles_left > 0 {
+                poll_input(input.as_mut(), &mut held_keys, latch.as_mut(), &mut vm, &mut recorder)?;
+
+                if debugger.as_mut().is_some_and(|debugger| !debugger.should_run(&mut vm)) {
+                    paused = true;
+                    break;
+                }
+
+                if vm.exited {
+                    drop(frontend);
+                    log::info!("Exited at {:03X} after {} instructions", vm.pc, vm.cycles);
+                    finish_run(profiler.as_ref(), throughput.as_ref(), wav.take(), options, &vm, rom)?;
+                    return Ok(Exit::Quit);
+                }
+                if options.max_cycles.is_some_and(|max_cycles| vm.cycles >= max_cycles) {
+                    drop(frontend);
+                    log::info!("Stopped after {} instructions", vm.cycles);
+                    finish_run(profiler.as_ref(), throughput.as_ref(), wav.take(), options, &vm, rom)?;
+                    return Ok(Exit::MaxCycles);
+                }
+                if options.on_idle.is_some() && idle_paused_at != Some(vm.pc) && vm.is_idle() {
+                    if options.on_idle == Some(IdleAction::Exit) {
+                        drop(frontend);
+                        log::info!("Idle at {:03X} after {} instructions", vm.pc, vm.cycles);
+                        finish_run(profiler.as_ref(), throughput.as_ref(), wav.take(), options, &vm, rom)?;
+                        return Ok(Exit::Quit);
+                    }
+                    log::info!("Idle at {:03X}, pausing", vm.pc);
+                    idle_paused_at = Some(vm.pc);
+                    paused_by_user = true;
+                    paused = true;
+                    break;
+                }
+
+                if let Some(paranoid) = &mut paranoid {
+                    paranoid.check(&vm);
+                }
+
+                if let Some(profiler) = &mut profiler {
+                    profiler.record(&vm);
+                }
+                let registers = tracer.as_ref().map(|_| trace::Registers::capture(&vm));
+                recent.record(&vm);
+                let pc = vm.pc;
+                let result = processor_cycle(&mut vm);
+                if let (Some(tracer), Some(registers), Ok(())) = (&mut tracer, &registers, &result) {
+                    tracer.trace(registers, &vm)?;
+                }
+
+                if let Err(err) = result {
+                    // Restore the terminal before printing anything
+                    drop(frontend);
+                    if let Some(tail) = &tail {
+                        eprintln!("Last executed instructions:");
+                        for line in tail.borrow().lines() {
+                            eprintln!("  {}", line);
+                        }
+                    }
+                    finish_run(profiler.as_ref(), throughput.as_ref(), wav.take(), options, &vm, rom)?;
+                    let path = options.crash_report.clone().unwrap_or_else(crash::default_path);
+                    let report = crash::crash_report(&vm, &err, &rom.path, &recent);
+                    crash::write_crash_report(&path, &report)?;
+                    log::info!("Wrote a crash report to {}", path.display());
+                    let called_from = vm
+                        .stack
+                        .call_chain()
+                        .map_or(String::new(), |chain| format!(" (called from {})", chain));
+                    return Err(format!(
+                        "Error in processor cycle at {:03X}{}: {}",
+                        pc, called_from, err
+                    ));
+                }
+
+                if let Some(debugger) = &mut debugger {
+                    debugger.after_cycle(&mut vm);
+                }
+                if let Some(throughput) = &mut throughput {
+                    throughput.count();
+                }
+                vm.cycles += 1;
+                frame_cycles_left -= 1;
+                if vm.waiting_for_frame {
+                    // The draw takes up the rest of the frame
+                    vm.cycles += frame_cycles_left;
+                    frame_cycles_left = 0;
+                }
             }
-        },
-        Instruction::BlockUntilAnyKey { x } => {
-            // Decrement program counter to repeat this
-            // instruction in case a key isn't pressed
-            vm.pc -= 2;
-            for i in 0..0xF {
-                if vm.keys_pressed[i] {
-                    vm.v[x] = i as u8;
-                    vm.pc += 2;
+            if paused {
+                // The debugger stopped partway through the frame
+                frame_budget = 0.0;
+                break;
+            }
+
+            // The buzzer as it was during the frame, which files need
+            // frame by frame
+            if vm.audio_pattern() != audio_pattern {
+                audio_pattern = vm.audio_pattern();
+                for sink in audio_sinks(frontend.as_mut(), &mut wav) {
+                    sink.set_audio_pattern(audio_pattern);
+                }
+            }
+            for sink in audio_sinks(frontend.as_mut(), &mut wav) {
+                sink.set_buzzer(vm.sound_timer > 0);
+                sink.end_frame()?;
+            }
+
+            frames += 1;
+            if let Some(profiler) = &mut profiler {
+                profiler.end_frame();
+            }
+            log::debug!(target: "chip8::frame", "Frame {} ended at cycle {}", frames, vm.cycles);
+            end_frame(&mut vm, frames, timer_hz);
+            vm.apply_frame_cheats();
+            input.end_frame(frames)?;
+            #[cfg(feature = "scripting")]
+            if let Some(script) = &mut script {
+                let result = script.on_frame(&mut vm, frames);
+                if result != Ok(true) {
+                    drop(frontend);
+                    finish_run(profiler.as_ref(), throughput.as_ref(), wav.take(), options, &vm, rom)?;
+                    return result.map(|_| Exit::Quit);
                 }
             }
+            frame_cycles_left = cycles_in_frame(frames, speed);
+            if vm.waiting_for_frame {
+                // Below 60 Hz, some frames end without the vertical
+                // blank the draw is waiting for
+                vm.cycles += frame_cycles_left;
+                frame_cycles_left = 0;
+            }
+            frame_budget -= 1.0;
+            if options.turbo && Instant::now() >= next_frame {
+                frame_budget = 0.0;
+            }
         }
-        Instruction::SetIFontVx { x } => vm.ir = FONT_MEMORY_START as u16 + vm.v[x] as u16 * 5, // Fonts are 5 bytes wide
-        Instruction::StoreVxDigitsI { x } => {
-            vm.memory[vm.ir as usize] = vm.v[x] / 100;
-            vm.memory[vm.ir as usize + 1] = (vm.v[x] % 100) / 10;
-            vm.memory[vm.ir as usize + 2] = vm.v[x] % 10;
+
+        for sink in audio_sinks(frontend.as_mut(), &mut wav) {
+            sink.set_buzzer(vm.sound_timer > 0 && !paused);
+        }
+        frontend.set_keys_pressed(&vm.keys_pressed);
+        if let Some(title) = status.update(vm.cycles, paused) {
+            frontend.set_title(&title);
         }
-        Instruction::StoreVxI { x } => {
-            for i in 0..=x {
-                vm.memory[vm.ir as usize + i as usize] = vm.v[i];
+        #[cfg(feature = "remote")]
+        if let Some(remote) = &mut remote {
+            remote.send_frame(&vm.display);
+        }
+        // Mega-Chip mode draws its own picture instead of the display
+        #[cfg(feature = "megachip")]
+        let painted_megachip = match vm.megachip.as_ref().filter(|mega| mega.enabled) {
+            Some(mega) => {
+                frontend.paint_rgba(MEGA_WIDTH_PX, MEGA_HEIGHT_PX, &mega.rgba());
+                true
             }
+            None => false,
+        };
+        #[cfg(not(feature = "megachip"))]
+        let painted_megachip = false;
+        if !painted_megachip {
+            paint(frontend.as_mut(), &mut status, picture_delay.delay(&mut vm.display));
+        }
+        if let Some(symbols) = &view_symbols {
+            let breakpoints = debugger
+                .as_ref()
+                .map_or_else(Vec::new, |debugger| debugger.breakpoints());
+            shown_views = views::views(&vm, symbols, &breakpoints, edit.as_ref());
+            frontend.show_views(&shown_views);
         }
-        Instruction::StoreIVx { x } => {
-            for i in 0..=x {
-                vm.v[i] = vm.memory[vm.ir as usize + i as usize];
+
+        if options.turbo {
+            // Never sleep, just paint again a frame from now
+            next_frame = Instant::now() + frame_duration;
+            continue;
+        }
+
+        if frontend.paced_by_vsync() {
+            // Painting waited for the display to refresh, so the next
+            // frame is due now. Some drivers don't wait while the window
+            // is hidden, so don't run more than twice as fast as that.
+            let earliest = next_frame + frame_duration / 2;
+            let now = Instant::now();
+            if earliest > now {
+                std::thread::sleep(earliest - now);
             }
+            next_frame = Instant::now();
+            continue;
+        }
+
+        // Sleep until the next frame is due. If we've fallen behind,
+        // don't try to catch up.
+        next_frame += frame_duration;
+        let now = Instant::now();
+        if next_frame > now {
+            std::thread::sleep(next_frame - now);
+        } else {
+            next_frame = now;
+        }
+    }
+}
+
+/// Copy the keys held down in `input` to the VM, recording any changes.
+fn poll_input(
+    input: &mut dyn InputSource,
+    held: &mut [bool; 16],
+    latch: Option<&mut KeyLatch>,
+    vm: &mut VM,
+    recorder: &mut Option<Recorder>,
+) -> Result<(), String> {
+    for event in input::apply_input(input, held, latch, vm) {
+        if let Some(recorder) = recorder {
+            recorder
+                .record(&event)
+                .map_err(|err| format!("Error writing replay file: {}", err))?;
         }
     }
+    Ok(())
+}
 
+/// Everywhere the buzzer plays: the frontend's speakers, if it has
+/// any, and the --record-audio file.
+fn audio_sinks<'a>(
+    frontend: &'a mut dyn frontend::Frontend,
+    wav: &'a mut Option<WavSink<BufWriter<File>>>,
+) -> impl Iterator<Item = &'a mut dyn AudioSink> {
+    let wav = wav.as_mut().map(|wav| wav as &mut dyn AudioSink);
+    frontend.audio().into_iter().chain(wav)
+}
+
+/// Report what --profile and --turbo measured, and write what
+/// --record-audio and --dump-display ask for, once the emulator stops.
+fn finish_run(
+    profiler: Option<&profile::Profiler>,
+    throughput: Option<&status::Throughput>,
+    wav: Option<WavSink<BufWriter<File>>>,
+    options: &RunArgs,
+    vm: &VM,
+    rom: &LoadedRom,
+) -> Result<(), String> {
+    if let (Some(profiler), Some(_)) = (profiler, &options.profile) {
+        finish_profile(profiler, options, vm, rom)?;
+    }
+    if let Some(throughput) = throughput {
+        log::info!("{}", throughput.report());
+    }
+    if let Some(wav) = wav {
+        wav.finish().map_err(|err| format!("Error writing audio: {}", err))?;
+    }
+    if let Some(path) = &options.dump_display {
+        image::save_display(&vm.display, path)?;
+    }
+    if let Some(range) = options.save_ram {
+        save_ram::save(range, &rom.bytes, vm)?;
+    }
     Ok(())
 }
+
+/// Print the --profile report, and write the folded stacks if a file
+/// was given.
+fn finish_profile(profiler: &profile::Profiler, options: &RunArgs, vm: &VM, rom: &LoadedRom) -> Result<(), String> {
+    eprint!("{}", profiler.report(&vm.memory, rom.bytes.len()));
+    match &options.profile {
+        Some(Some(path)) => profiler.write_folded_stacks(path),
+        _ => Ok(()),
+    }
+}
+
+fn paint(frontend: &mut dyn frontend::Frontend, status: &mut status::TitleStatus, display: &mut Display) {
+    if display.needs_repaint() {
+        status.frame_painted();
+    }
+    frontend.paint(display);
+}
+
+#[test]
+fn test_parse_args() {
+    let parse = |args: &[&str]| Cli::try_parse_from(["chip8"].iter().chain(args));
+    let run_args = |args: &[&str]| match parse(args).unwrap().command {
+        Command::Run(args) => args,
+        _ => panic!("expected the run subcommand"),
+    };
+
+    let args = run_args(&["run", "rom.ch8"]);
+    assert_eq!(args.rom, Some(PathBuf::from("rom.ch8")));
+    assert_eq!(parse(&["run", "rom.ch8"]).unwrap().log_level, None);
+    let cli = parse(&["run", "--log-level", "debug", "rom.ch8"]).unwrap();
+    assert_eq!(cli.log_level, Some(log::LevelFilter::Debug));
+    assert!(parse(&["--log-level", "loud", "run", "rom.ch8"]).is_err());
+    assert_eq!(args.speed, None);
+    assert_eq!(args.timer_hz, None);
+    assert_eq!(args.compat, None);
+
+    let args = run_args(&[
+        "run",
+        "--speed",
+        "1000",
+        "--scale",
+        "4",
+        "--compat",
+        "shift-uses-vy",
+        "rom.ch8",
+    ]);
+    assert_eq!(args.speed, Some(1000));
+    assert_eq!(args.scale, Some(4));
+    assert_eq!(
+        args.compat,
+        Some(Quirks {
+            shift_uses_vy: true,
+            ..Quirks::default()
+        })
+    );
+
+    assert_eq!(run_args(&["run"]).rom, None);
+    assert!(parse(&[]).is_err());
+    assert!(parse(&["run", "--speed", "0", "rom.ch8"]).is_err());
+    assert!(parse(&["run", "--timer-hz", "0", "rom.ch8"]).is_err());
+    assert!(parse(&["run", "--fast-forward", "0", "rom.ch8"]).is_err());
+    assert!(parse(&["run", "--compat", "nope", "rom.ch8"]).is_err());
+    assert_eq!(
+        run_args(&["run", "--platform", "vip", "rom.ch8"])
+            .platform
+            .unwrap()
+            .name,
+        "vip"
+    );
+    assert!(parse(&["run", "--platform", "nope", "rom.ch8"]).is_err());
+    assert!(run_args(&["run", "--megachip", "rom.mc8"]).megachip);
+    assert!(parse(&["run", "--megachip", "--platform", "vip", "rom.mc8"]).is_err());
+    assert!(parse(&["run", "--nope", "rom.ch8"]).is_err());
+    assert!(parse(&["run", "a.ch8", "b.ch8"]).is_err());
+    assert!(parse(&["run", "--record", "a", "--replay", "b", "rom.ch8"]).is_err());
+    assert!(run_args(&["run", "--watch", "rom.ch8"]).watch);
+    assert_eq!(
+        run_args(&["run", "--break", "main_loop", "--break", "2F0", "rom.ch8"]).breakpoints,
+        ["main_loop", "2F0"]
+    );
+    assert!(parse(&["run", "--watch", "--replay", "b", "rom.ch8"]).is_err());
+    assert!(parse(&["run", "--watch", "--record", "b", "rom.ch8"]).is_err());
+    assert!(run_args(&["run", "--latch-keys", "rom.ch8"]).latch_keys);
+    assert!(parse(&["run", "--latch-keys", "--replay", "b", "rom.ch8"]).is_err());
+    assert!(parse(&["run", "--save-ram", "E00-EFF", "--record", "b", "rom.ch8"]).is_err());
+    assert!(parse(&["run", "--script", "a.rhai", "--record", "b", "rom.ch8"]).is_err());
+    assert!(parse(&["run", "--cheats", "a.cht", "--record", "b", "rom.ch8"]).is_err());
+    assert!(parse(&["run", "--cheats", "a.cht", "--replay", "b", "rom.ch8"]).is_err());
+    let args = run_args(&["run", "--autofire", "5a", "--autofire-hz", "15", "rom.ch8"]);
+    assert!(args.autofire.unwrap()[0xA]);
+    assert_eq!(args.autofire_hz, Some(15));
+    assert!(parse(&["run", "--autofire", "Z", "rom.ch8"]).is_err());
+    assert!(parse(&["run", "--autofire-hz", "31", "rom.ch8"]).is_err());
+    assert!(parse(&["run", "--seed", "-1", "rom.ch8"]).is_err());
+    for conflicting in [
+        &["--cheats", "a.cht"],
+        &["--script", "a.rhai"],
+        &["--remote", ":8080"],
+        &["--break", "2F0"],
+    ] {
+        let args = ["run", "--netplay", "host:6464"]
+            .iter()
+            .chain(conflicting)
+            .chain(&["rom.ch8"]);
+        assert!(parse(&args.copied().collect::<Vec<_>>()).is_err());
+    }
+    assert!(matches!(
+        parse(&["assemble", "game.8o", "-o", "game.ch8"]).unwrap().command,
+        Command::Assemble { .. }
+    ));
+    assert!(parse(&["assemble", "-o", "game.ch8"]).is_err());
+    assert!(matches!(
+        parse(&["disasm", "rom.ch8"]).unwrap().command,
+        Command::Disasm { .. }
+    ));
+    assert!(matches!(
+        parse(&["info", "rom.ch8"]).unwrap().command,
+        Command::Info { platform: None, .. }
+    ));
+    assert!(matches!(
+        parse(&["info", "--platform", "xochip", "rom.ch8"]).unwrap().command,
+        Command::Info {
+            platform: Some(Platform {
+                memory_bytes: XO_CHIP_MEMORY_BYTES,
+                ..
+            }),
+            ..
+        }
+    ));
+    assert!(matches!(
+        parse(&["debug", "rom.ch8"]).unwrap().command,
+        Command::Debug(_)
+    ));
+
+    let args = run_args(&["run", "--trace", "rom.ch8"]);
+    assert_eq!(args.trace, Some(None));
+    assert_eq!(args.rom, Some(PathBuf::from("rom.ch8")));
+    let args = run_args(&["run", "--trace=trace.log", "rom.ch8"]);
+    assert_eq!(args.trace, Some(Some(PathBuf::from("trace.log"))));
+    let args = run_args(&["run", "--trace", "--trace-range", "200-20F", "rom.ch8"]);
+    assert_eq!(args.trace, Some(None));
+    assert_eq!(args.trace_range, Some(0x200..=0x20F));
+    assert_eq!(
+        run_args(&["run", "--paranoid", "rom.ch8"]).paranoid,
+        Some(paranoid::Policy::default())
+    );
+    let policy = run_args(&["run", "--paranoid=allow-odd-pc", "rom.ch8"])
+        .paranoid
+        .unwrap();
+    assert!(!policy.aligned_pc);
+    assert!(parse(&["run", "--paranoid=nope", "rom.ch8"]).is_err());
+    assert_eq!(run_args(&["run", "--profile", "rom.ch8"]).profile, Some(None));
+    let args = run_args(&["run", "--profile=stacks.folded", "rom.ch8"]);
+    assert_eq!(args.profile, Some(Some(PathBuf::from("stacks.folded"))));
+    let args = run_args(&["run", "--max-cycles", "5000", "--on-idle", "exit", "rom.ch8"]);
+    assert_eq!(args.max_cycles, Some(5000));
+    assert_eq!(args.on_idle, Some(IdleAction::Exit));
+    assert!(parse(&["run", "--on-idle", "stop", "rom.ch8"]).is_err());
+
+    let args = run_args(&["run", "--frontend", "tui", "rom.ch8"]);
+    assert_eq!(args.frontend, FrontendKind::Tui);
+    assert!(parse(&["run", "--frontend", "nope", "rom.ch8"]).is_err());
+    let args = run_args(&["run", "--turbo", "--frontend", "headless", "rom.ch8"]);
+    assert!(args.turbo);
+    assert_eq!(args.frontend, FrontendKind::Headless);
+}
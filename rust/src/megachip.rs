@@ -0,0 +1,280 @@
+//! Experimental Mega-Chip support: a 256x192 display of palette
+//! indexes, sprites whose pixels are colors rather than bits, and a
+//! 24-bit index register so programs can keep sprite and palette data
+//! in a ROM much larger than CHIP-8 memory.
+//!
+//! Code still runs from the normal 4K of memory. `chip8 run --megachip`
+//! loads ROMs with `load_megachip_rom`, and the window frontends draw
+//! `MegaChip::rgba` while Mega-Chip mode is on. The sound and blending
+//! opcodes are accepted but ignored.
+
+use crate::vm::{fetch, max_rom_bytes, PROGRAM_START, VM};
+
+pub const MEGA_WIDTH_PX: usize = 256;
+pub const MEGA_HEIGHT_PX: usize = 192;
+
+/// Instructions that only exist (or behave differently) in Mega-Chip.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MegaInstruction {
+    /// 0x0010: Leave Mega-Chip mode
+    MegaOff,
+    /// 0x0011: Enter Mega-Chip mode
+    MegaOn,
+    /// 0x00BN: Scroll the display up N lines
+    ScrollUp { n: u8 },
+    /// 0x00E0: Clear the display
+    Clear,
+    /// 0x01NN 0xNNNN: Set I to the 24-bit address NNNNNN
+    LoadLongIndex { hi: u8 },
+    /// 0x02NN: Load NN ARGB colors from I into the palette, from index 1
+    LoadPalette { count: u8 },
+    /// 0x03NN: Set the sprite width to NN (0 means 256)
+    SpriteWidth { nn: u8 },
+    /// 0x04NN: Set the sprite height to NN (0 means 256)
+    SpriteHeight { nn: u8 },
+    /// 0x05NN: Set the screen alpha
+    Alpha { nn: u8 },
+    /// 0x060N: Play the digitized sound at I
+    PlaySound { n: u8 },
+    /// 0x0700: Stop the sound
+    StopSound,
+    /// 0x080N: Set the sprite blend mode
+    BlendMode { n: u8 },
+    /// 0x09NN: Set the color that counts as a collision when drawn over
+    CollisionColor { nn: u8 },
+    /// 0xANNN: Set I to NNN
+    SetIndex { nnn: u16 },
+    /// 0xDXYN: Draw the sprite at I at (VX, VY)
+    Draw { x: usize, y: usize },
+}
+
+/// Decode `raw` as a Mega-Chip instruction, or return None if it's an
+/// ordinary CHIP-8 one. Only MegaOn is recognized outside Mega-Chip
+/// mode.
+pub fn parse_mega_instruction(raw: u16, enabled: bool) -> Option<MegaInstruction> {
+    let nn = (raw & 0x00FF) as u8;
+    let instruction = match raw >> 8 {
+        _ if raw == 0x0011 => MegaInstruction::MegaOn,
+        _ if !enabled => return None,
+        0x00 => match nn {
+            0x10 => MegaInstruction::MegaOff,
+            0xB0..=0xBF => MegaInstruction::ScrollUp { n: nn & 0xF },
+            0xE0 => MegaInstruction::Clear,
+            _ => return None,
+        },
+        0x01 => MegaInstruction::LoadLongIndex { hi: nn },
+        0x02 => MegaInstruction::LoadPalette { count: nn },
+        0x03 => MegaInstruction::SpriteWidth { nn },
+        0x04 => MegaInstruction::SpriteHeight { nn },
+        0x05 => MegaInstruction::Alpha { nn },
+        0x06 if nn <= 0xF => MegaInstruction::PlaySound { n: nn },
+        0x07 if nn == 0 => MegaInstruction::StopSound,
+        0x08 if nn <= 0xF => MegaInstruction::BlendMode { n: nn },
+        0x09 => MegaInstruction::CollisionColor { nn },
+        0xA0..=0xAF => MegaInstruction::SetIndex { nnn: raw & 0x0FFF },
+        0xD0..=0xDF => MegaInstruction::Draw {
+            x: ((raw >> 8) & 0xF) as usize,
+            y: ((raw >> 4) & 0xF) as usize,
+        },
+        _ => return None,
+    };
+    Some(instruction)
+}
+
+/// The Mega-Chip state that sits alongside the VM.
+pub struct MegaChip {
+    pub enabled: bool,
+
+    /// The whole ROM, loaded at 0x200 like CHIP-8 memory
+    memory: Vec<u8>,
+    /// Palette indexes, row by row. 0 is transparent.
+    pixels: Vec<u8>,
+    /// ARGB colors
+    palette: [[u8; 4]; 256],
+    index: u32,
+    sprite_width: usize,
+    sprite_height: usize,
+    collision_color: u8,
+}
+
+impl MegaChip {
+    pub fn new(rom: &[u8]) -> MegaChip {
+        let mut memory = vec![0; PROGRAM_START as usize + rom.len()];
+        memory[PROGRAM_START as usize..].copy_from_slice(rom);
+        MegaChip {
+            enabled: false,
+            memory,
+            pixels: vec![0; MEGA_WIDTH_PX * MEGA_HEIGHT_PX],
+            palette: [[0; 4]; 256],
+            index: 0,
+            sprite_width: 0,
+            sprite_height: 0,
+            collision_color: 0,
+        }
+    }
+
+    /// Leave Mega-Chip mode and clear everything but the ROM, as part
+    /// of `VM::reset`.
+    pub fn reset(&mut self) {
+        let memory = std::mem::take(&mut self.memory);
+        *self = MegaChip {
+            memory,
+            ..MegaChip::new(&[])
+        };
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * MEGA_WIDTH_PX + x]
+    }
+
+    /// The display as RGBA bytes, row by row.
+    pub fn rgba(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|&color| {
+                let [a, r, g, b] = self.palette[color as usize];
+                [r, g, b, a]
+            })
+            .collect()
+    }
+
+    fn read(&self, addr: u32) -> Result<u8, String> {
+        self.memory
+            .get(addr as usize)
+            .copied()
+            .ok_or_else(|| format!("Can't read from {:#08X}, which is past the end of the ROM", addr))
+    }
+
+    fn draw(&mut self, x: usize, y: usize) -> Result<bool, String> {
+        let mut collision = false;
+        for row in 0..self.sprite_height {
+            for column in 0..self.sprite_width {
+                let color = self.read(self.index + (row * self.sprite_width + column) as u32)?;
+                let (px, py) = (x + column, y + row);
+                // Sprites are clipped, and color 0 is transparent
+                if color == 0 || px >= MEGA_WIDTH_PX || py >= MEGA_HEIGHT_PX {
+                    continue;
+                }
+                let pixel = &mut self.pixels[py * MEGA_WIDTH_PX + px];
+                collision |= *pixel == self.collision_color;
+                *pixel = color;
+            }
+        }
+        Ok(collision)
+    }
+}
+
+/// Load a Mega-Chip ROM like `VM::reload`: the first part into CHIP-8
+/// memory, where its code runs from, and all of it into `vm.megachip`.
+pub fn load_megachip_rom(vm: &mut VM, rom: &[u8]) -> Result<(), String> {
+    let code_bytes = rom.len().min(max_rom_bytes(vm.memory.len()));
+    vm.reload(&rom[..code_bytes])?;
+    vm.megachip = Some(MegaChip::new(rom));
+    Ok(())
+}
+
+/// Execute the instruction at PC if it's a Mega-Chip one. Returns false
+/// if it's an ordinary CHIP-8 instruction, which the caller should run.
+pub fn execute_mega_instruction(vm: &mut VM) -> Result<bool, String> {
+    let mega = match &mut vm.megachip {
+        Some(mega) => mega,
+        None => return Ok(false),
+    };
+    let instruction = match parse_mega_instruction(fetch(&vm.memory, vm.pc), mega.enabled) {
+        Some(instruction) => instruction,
+        None => return Ok(false),
+    };
+    vm.pc += 2;
+
+    let size = |nn: u8| if nn == 0 { 256 } else { nn as usize };
+    match instruction {
+        MegaInstruction::MegaOff => mega.enabled = false,
+        MegaInstruction::MegaOn => mega.enabled = true,
+        MegaInstruction::ScrollUp { n } => {
+            let lines = (n as usize).min(MEGA_HEIGHT_PX);
+            mega.pixels.drain(..lines * MEGA_WIDTH_PX);
+            mega.pixels.resize(MEGA_WIDTH_PX * MEGA_HEIGHT_PX, 0);
+        }
+        MegaInstruction::Clear => mega.pixels.iter_mut().for_each(|pixel| *pixel = 0),
+        MegaInstruction::LoadLongIndex { hi } => {
+            // The low 16 bits are the next word
+            mega.index = (hi as u32) << 16 | fetch(&vm.memory, vm.pc) as u32;
+            vm.pc += 2;
+        }
+        MegaInstruction::LoadPalette { count } => {
+            for i in 0..count as u32 {
+                let mut color = [0; 4];
+                for (j, channel) in color.iter_mut().enumerate() {
+                    *channel = mega.read(mega.index + i * 4 + j as u32)?;
+                }
+                mega.palette[i as usize + 1] = color;
+            }
+        }
+        MegaInstruction::SpriteWidth { nn } => mega.sprite_width = size(nn),
+        MegaInstruction::SpriteHeight { nn } => mega.sprite_height = size(nn),
+        MegaInstruction::CollisionColor { nn } => mega.collision_color = nn,
+        MegaInstruction::SetIndex { nnn } => {
+            mega.index = nnn as u32;
+            vm.ir = nnn;
+        }
+        MegaInstruction::Draw { x, y } => {
+            let collision = mega.draw(vm.v[x] as usize, vm.v[y] as usize)?;
+            vm.v[0xF] = collision as u8;
+        }
+        // No sound or blending yet
+        MegaInstruction::Alpha { .. }
+        | MegaInstruction::PlaySound { .. }
+        | MegaInstruction::StopSound
+        | MegaInstruction::BlendMode { .. } => {}
+    }
+    Ok(true)
+}
+
+#[test]
+fn test_parse_mega_instruction() {
+    assert_eq!(parse_mega_instruction(0x0011, false), Some(MegaInstruction::MegaOn));
+    assert_eq!(parse_mega_instruction(0x00E0, false), None);
+    assert_eq!(parse_mega_instruction(0x00E0, true), Some(MegaInstruction::Clear));
+    assert_eq!(
+        parse_mega_instruction(0x0123, true),
+        Some(MegaInstruction::LoadLongIndex { hi: 0x23 })
+    );
+    assert_eq!(
+        parse_mega_instruction(0xD120, true),
+        Some(MegaInstruction::Draw { x: 1, y: 2 })
+    );
+    assert_eq!(parse_mega_instruction(0x6123, true), None);
+}
+
+#[test]
+fn test_megachip_draw() {
+    let mut rom = vec![
+        0x00, 0x11, // 200: mega on
+        0x01, 0x00, 0x12, 0x00, // 202: I = 001200
+        0x02, 0x01, // 206: load 1 color
+        0x01, 0x00, 0x12, 0x04, // 208: I = 001204
+        0x03, 0x02, // 20C: sprite width 2
+        0x04, 0x01, // 20E: sprite height 1
+        0x09, 0x01, // 210: collide with color 1
+        0x60, 0x0A, // 212: V0 = 10
+        0xD0, 0x00, // 214: draw at (V0, V0)
+        0xD0, 0x00, // 216: draw again, colliding
+    ];
+    // Data past the end of CHIP-8 memory: an opaque red and a sprite
+    // with one transparent pixel
+    rom.resize(0x1000, 0);
+    rom.extend_from_slice(&[0xFF, 0xFF, 0x00, 0x00, 0x01, 0x00]);
+
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    load_megachip_rom(&mut vm, &rom).unwrap();
+    for _ in 0..9 {
+        crate::vm::processor_cycle(&mut vm).unwrap();
+    }
+    let mega = vm.megachip.as_ref().unwrap();
+    assert_eq!((mega.get_pixel(10, 10), mega.get_pixel(11, 10)), (1, 0));
+    assert_eq!(&mega.rgba()[(10 * MEGA_WIDTH_PX + 10) * 4..][..4], &[0xFF, 0, 0, 0xFF]);
+    assert_eq!(vm.v[0xF], 0);
+
+    crate::vm::processor_cycle(&mut vm).unwrap();
+    assert_eq!(vm.v[0xF], 1);
+}
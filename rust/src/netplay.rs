@@ -0,0 +1,401 @@
+//! Netplay: two machines running the same ROM in lockstep over UDP, for
+//! two-player games like Pong and Tank. Each runs `chip8 run --netplay
+//! PEER ROM`, naming the other's address.
+//!
+//! Only keys go over the network. Both machines start from the same
+//! seed and run the same instructions each frame, so as long as the
+//! same keys are held during every frame, everything else stays the
+//! same too. At the end of each frame, each machine sends the keys its
+//! player is holding, to be pressed `INPUT_DELAY_FRAMES` frames later,
+//! and before starting a frame it waits for the other's keys for it.
+//! The keypad is shared: a key held on either machine is held on both,
+//! so each player uses the keys for their own side, as they would
+//! sitting at the same keyboard.
+//!
+//! Packets start with `MAGIC` and a kind byte, followed by big-endian
+//! numbers:
+//!
+//! ```text
+//! hello  ROM SHA-256 (32 bytes), seed (8), speed (8), timer Hz (4), quirks (2)
+//! keys   first frame (8), count (1), then a 16-bit mask of held keys per frame
+//! bye
+//! ```
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use sha2::{Digest, Sha256};
+
+use chip8::quirks::{Quirks, QUIRK_NAMES};
+
+use crate::frontend::InputEvent;
+use crate::input::{FrontendInput, InputSource};
+use crate::interrupt;
+
+/// The port netplay listens on unless --netplay-port says otherwise.
+pub(crate) const DEFAULT_PORT: u16 = 6464;
+
+/// How many frames after a key is pressed it reaches the program, which
+/// gives it time to get to the other machine. At 60 frames a second,
+/// 3 frames is 50 ms.
+const INPUT_DELAY_FRAMES: u64 = 3;
+
+/// How many frames of keys each packet repeats, so a lost packet
+/// doesn't hold up the other machine.
+const FRAMES_PER_PACKET: u64 = 8;
+
+/// How long to wait for a packet before sending ours again.
+const RESEND_INTERVAL: Duration = Duration::from_millis(15);
+
+/// How long to wait for the other machine's keys during the game before
+/// giving up on it.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+const MAGIC: &[u8; 4] = b"C8NP";
+
+/// What both machines have to agree on to stay in step.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) struct Session {
+    rom_hash: [u8; 32],
+    /// This machine's proposal, until they've agreed on the lower one
+    seed: u64,
+    speed: u64,
+    timer_hz: u32,
+    quirks: u16,
+}
+
+impl Session {
+    pub(crate) fn new(rom: &[u8], seed: u64, speed: u64, timer_hz: u32, quirks: Quirks) -> Session {
+        let quirks = QUIRK_NAMES
+            .iter()
+            .enumerate()
+            .filter(|(_, name)| quirks.get(name) == Some(true))
+            .fold(0, |bits, (n, _)| bits | 1 << n);
+        Session {
+            rom_hash: Sha256::digest(rom).into(),
+            seed,
+            speed,
+            timer_hz,
+            quirks,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Packet {
+    Hello(Session),
+    Keys { first_frame: u64, keys: Vec<u16> },
+    Bye,
+}
+
+impl Packet {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        match self {
+            Packet::Hello(session) => {
+                out.push(0);
+                out.extend_from_slice(&session.rom_hash);
+                out.extend_from_slice(&session.seed.to_be_bytes());
+                out.extend_from_slice(&session.speed.to_be_bytes());
+                out.extend_from_slice(&session.timer_hz.to_be_bytes());
+                out.extend_from_slice(&session.quirks.to_be_bytes());
+            }
+            Packet::Keys { first_frame, keys } => {
+                out.push(1);
+                out.extend_from_slice(&first_frame.to_be_bytes());
+                out.push(keys.len() as u8);
+                for mask in keys {
+                    out.extend_from_slice(&mask.to_be_bytes());
+                }
+            }
+            Packet::Bye => out.push(2),
+        }
+        out
+    }
+
+    /// The packet in `data`, or None if it isn't one, e.g. a stray
+    /// packet from some other program.
+    fn decode(data: &[u8]) -> Option<Packet> {
+        let mut rest = data.strip_prefix(MAGIC)?;
+        let mut take = |n: usize| {
+            let (bytes, tail) = (rest.get(..n)?, &rest[n..]);
+            rest = tail;
+            Some(bytes)
+        };
+        let packet = match take(1)?[0] {
+            0 => Packet::Hello(Session {
+                rom_hash: take(32)?.try_into().ok()?,
+                seed: u64::from_be_bytes(take(8)?.try_into().ok()?),
+                speed: u64::from_be_bytes(take(8)?.try_into().ok()?),
+                timer_hz: u32::from_be_bytes(take(4)?.try_into().ok()?),
+                quirks: u16::from_be_bytes(take(2)?.try_into().ok()?),
+            }),
+            1 => {
+                let first_frame = u64::from_be_bytes(take(8)?.try_into().ok()?);
+                let count = take(1)?[0] as usize;
+                let keys = take(count * 2)?
+                    .chunks(2)
+                    .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                    .collect();
+                Packet::Keys { first_frame, keys }
+            }
+            2 => Packet::Bye,
+            _ => return None,
+        };
+        Some(packet)
+    }
+}
+
+fn key_mask(keys: &[bool; 16]) -> u16 {
+    keys.iter()
+        .enumerate()
+        .filter(|(_, &held)| held)
+        .fold(0, |mask, (key, _)| mask | 1 << key)
+}
+
+/// Keys from this machine's frontend and the other machine, the same on
+/// both.
+pub(crate) struct Netplay {
+    socket: UdpSocket,
+    peer: SocketAddr,
+    session: Session,
+    local: FrontendInput,
+    /// Keys for frames from the one being run, by frame
+    local_keys: BTreeMap<u64, u16>,
+    remote_keys: BTreeMap<u64, u16>,
+    keys: [bool; 16],
+    /// Whether keys have come from the other machine, so it got our hello
+    peer_started: bool,
+    peer_left: bool,
+}
+
+impl Netplay {
+    /// Wait for the machine at `peer` to start netplay too, listening on
+    /// `port`, and agree on the seed: the lower of the two proposed in
+    /// `session`. Fails if the other machine is running a different ROM
+    /// or settings.
+    pub(crate) fn connect(port: u16, peer: &str, session: Session) -> Result<Netplay, String> {
+        let peer = peer
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .ok_or_else(|| format!("Invalid netplay address {:?}, expected HOST:PORT", peer))?;
+        let bind = if peer.is_ipv4() { "0.0.0.0" } else { "::" };
+        let socket = UdpSocket::bind((bind, port)).map_err(|err| format!("Can't listen on port {}: {}", port, err))?;
+        socket
+            .set_read_timeout(Some(RESEND_INTERVAL))
+            .map_err(|err| err.to_string())?;
+        let mut netplay = Netplay {
+            socket,
+            peer,
+            session,
+            local: FrontendInput::default(),
+            // No keys are held until the first ones sent arrive
+            local_keys: (0..=INPUT_DELAY_FRAMES).map(|frame| (frame, 0)).collect(),
+            remote_keys: (0..=INPUT_DELAY_FRAMES).map(|frame| (frame, 0)).collect(),
+            keys: [false; 16],
+            peer_started: false,
+            peer_left: false,
+        };
+
+        log::info!("Waiting for {} to start netplay", peer);
+        let theirs = loop {
+            interrupt::check()?;
+            netplay.send(&Packet::Hello(session))?;
+            if let Some(Packet::Hello(theirs)) = netplay.receive()? {
+                break theirs;
+            }
+        };
+        let mismatch = if theirs.rom_hash != session.rom_hash {
+            Some("a different ROM")
+        } else if (theirs.speed, theirs.timer_hz, theirs.quirks) != (session.speed, session.timer_hz, session.quirks) {
+            Some("with a different speed, timer frequency or quirks")
+        } else {
+            None
+        };
+        if let Some(mismatch) = mismatch {
+            // It may not have our hello yet, and it should stop too
+            netplay.send(&Packet::Hello(session))?;
+            return Err(format!("{} is running {}", peer, mismatch));
+        }
+        netplay.session.seed = session.seed.min(theirs.seed);
+        log::info!("Connected to {}", peer);
+        Ok(netplay)
+    }
+
+    /// The random seed both machines use.
+    pub(crate) fn seed(&self) -> u64 {
+        self.session.seed
+    }
+
+    fn send(&self, packet: &Packet) -> Result<(), String> {
+        match self.socket.send_to(&packet.encode(), self.peer) {
+            Ok(_) => Ok(()),
+            // Until the other machine is listening, some systems report
+            // the last packet as refused
+            Err(err) if err.kind() == ErrorKind::ConnectionRefused => Ok(()),
+            Err(err) => Err(format!("Error sending to {}: {}", self.peer, err)),
+        }
+    }
+
+    /// The next packet from the other machine, or None if there isn't one
+    /// in time.
+    fn receive(&self) -> Result<Option<Packet>, String> {
+        let mut buf = [0; 512];
+        match self.socket.recv_from(&mut buf) {
+            Ok((len, from)) if from == self.peer => Ok(Packet::decode(&buf[..len])),
+            Ok(_) => Ok(None),
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::ConnectionRefused
+                ) =>
+            {
+                Ok(None)
+            }
+            Err(err) => Err(format!("Error receiving from {}: {}", self.peer, err)),
+        }
+    }
+
+    fn send_keys(&self) -> Result<(), String> {
+        let (&oldest, _) = self.local_keys.first_key_value().expect("keys are kept for the delay");
+        let (&newest, _) = self.local_keys.last_key_value().expect("keys are kept for the delay");
+        let first_frame = newest.saturating_sub(FRAMES_PER_PACKET - 1).max(oldest);
+        let keys = self.local_keys.range(first_frame..).map(|(_, &mask)| mask).collect();
+        self.send(&Packet::Keys { first_frame, keys })
+    }
+}
+
+impl InputSource for Netplay {
+    fn handle_event(&mut self, event: &InputEvent) {
+        self.local.handle_event(event);
+    }
+
+    fn poll_keys(&mut self, _cycle: u64) -> [bool; 16] {
+        self.keys
+    }
+
+    fn wants_quit(&self) -> bool {
+        self.local.wants_quit() || self.peer_left
+    }
+
+    fn end_frame(&mut self, frame: u64) -> Result<(), String> {
+        if self.peer_left {
+            return Ok(());
+        }
+        let held = key_mask(&self.local.poll_keys(0));
+        self.local_keys.insert(frame + INPUT_DELAY_FRAMES, held);
+        self.send_keys()?;
+
+        let mut last_heard = Instant::now();
+        while !self.remote_keys.contains_key(&frame) {
+            interrupt::check()?;
+            match self.receive()? {
+                Some(Packet::Keys { first_frame, keys }) => {
+                    for (n, mask) in keys.into_iter().enumerate() {
+                        let frame_keys = first_frame + n as u64;
+                        if frame_keys >= frame {
+                            self.remote_keys.insert(frame_keys, mask);
+                        }
+                    }
+                    self.peer_started = true;
+                    last_heard = Instant::now();
+                }
+                // It missed our hello, so it's still waiting to start.
+                // Once it has started, hellos are ones it sent earlier.
+                Some(Packet::Hello(_)) if !self.peer_started => {
+                    self.send(&Packet::Hello(self.session))?;
+                    last_heard = Instant::now();
+                }
+                Some(Packet::Hello(_)) => {}
+                Some(Packet::Bye) => {
+                    log::info!("{} left", self.peer);
+                    self.peer_left = true;
+                    return Ok(());
+                }
+                None if last_heard.elapsed() > TIMEOUT => {
+                    return Err(format!("Lost the connection to {}", self.peer));
+                }
+                None => self.send_keys()?,
+            }
+        }
+
+        let mask = self.local_keys[&frame] | self.remote_keys[&frame];
+        for (key, held) in self.keys.iter_mut().enumerate() {
+            *held = mask & 1 << key != 0;
+        }
+        self.local_keys = self.local_keys.split_off(&frame);
+        self.remote_keys = self.remote_keys.split_off(&frame);
+        Ok(())
+    }
+}
+
+impl Drop for Netplay {
+    /// Tell the other machine we're going, so it stops too rather than
+    /// waiting to time out. Packets can get lost, so send a few.
+    fn drop(&mut self) {
+        if !self.peer_left {
+            for _ in 0..3 {
+                let _ = self.send(&Packet::Bye);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_packets() {
+    let packets = [
+        Packet::Hello(Session::new(&[0x12, 0x00], 42, 700, 60, Quirks::default())),
+        Packet::Keys {
+            first_frame: 9,
+            keys: vec![0x0001, 0x8000],
+        },
+        Packet::Bye,
+    ];
+    for packet in packets {
+        assert_eq!(Packet::decode(&packet.encode()), Some(packet));
+    }
+    assert_eq!(Packet::decode(b"GET / HTTP/1.1"), None);
+    assert_eq!(
+        Packet::decode(b"C8NP\x01\x00\x00\x00\x00\x00\x00\x00\x09\x02\x00"),
+        None
+    );
+}
+
+#[test]
+fn test_netplay() {
+    fn session(seed: u64) -> Session {
+        Session::new(&[0x12, 0x00], seed, 700, 60, Quirks::default())
+    }
+    fn play(netplay: &mut Netplay, key: u8) -> Vec<[bool; 16]> {
+        netplay.handle_event(&InputEvent::Key { key, pressed: true });
+        (1..8)
+            .map(|frame| {
+                netplay.end_frame(frame).unwrap();
+                netplay.poll_keys(0)
+            })
+            .collect()
+    }
+
+    let other = std::thread::spawn(|| {
+        let mut netplay = Netplay::connect(46465, "127.0.0.1:46464", session(5)).unwrap();
+        (netplay.seed(), play(&mut netplay, 0xC))
+    });
+    let mut netplay = Netplay::connect(46464, "127.0.0.1:46465", session(3)).unwrap();
+    let keys = play(&mut netplay, 0x1);
+    let (seed, other_keys) = other.join().unwrap();
+    assert_eq!((netplay.seed(), seed), (3, 3));
+    assert_eq!(keys, other_keys);
+    // Pressed before frame 1, so held from frame 1 + INPUT_DELAY_FRAMES
+    assert_eq!(keys[2], [false; 16]);
+    let held: Vec<usize> = (0..16).filter(|&key| keys[3][key]).collect();
+    assert_eq!(held, [0x1, 0xC]);
+
+    // The other machine has gone, saying bye
+    assert!(!netplay.wants_quit());
+    netplay.end_frame(20).unwrap();
+    assert!(netplay.wants_quit());
+}
@@ -0,0 +1,1031 @@
+//! An assembler for the Octo dialect, which most modern CHIP-8 homebrew
+//! is written in, e.g.
+//!
+//! ```text
+//! :const SPEED 2
+//! :alias x v0
+//!
+//! : main
+//!     i := sprite
+//!     loop
+//!         sprite x v1 5
+//!         x += SPEED
+//!         if x == 60 then x := 0
+//!     again
+//!
+//! : sprite
+//!     0xF0 0x90 0xF0 0x90 0xF0
+//! ```
+//!
+//! Supported are labels (`: name`, or `:name`), `:const`, `:alias`,
+//! `:macro`, `:byte`, `:call`, `:unpack` and `:next`, the standard
+//! statements, and the `if ... then`, `if ... begin ... else ... end`
+//! and `loop ... while ... again` control structures, with Octo's
+//! comparison pseudo-ops (`<`, `>`, `<=` and `>=`, which use VF).
+//! The SCHIP and XO-CHIP statements are supported too, except for
+//! `saveflags`, `loadflags`, `i := long` and the `save vX - vY` and
+//! `load vX - vY` ranges, which the VM doesn't implement. `:calc`,
+//! `:org`, `:breakpoint`, `:monitor`, `:assert`, `:stringmode` and
+//! `:pointer` aren't supported yet.
+
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+
+use chip8::instruction::{encode_instruction, Instruction};
+use chip8::vm::{max_rom_bytes, XO_CHIP_MEMORY_BYTES};
+
+use crate::assembler::Assembled;
+use crate::symbols::Symbols;
+
+/// Address programs are loaded at, and therefore where the first
+/// assembled byte lives.
+const ORIGIN: u16 = 0x200;
+
+/// Octo directives this assembler doesn't understand, so they're
+/// reported rather than taken for labels.
+const UNSUPPORTED_DIRECTIVES: [&str; 7] = [
+    ":calc",
+    ":org",
+    ":breakpoint",
+    ":monitor",
+    ":assert",
+    ":stringmode",
+    ":pointer",
+];
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    /// Where the token came from, for error messages
+    location: String,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// The operators that can follow a register at the start of a
+/// statement.
+const OPERATORS: [&str; 9] = [":=", "+=", "-=", "=-", "|=", "&=", "^=", ">>=", "<<="];
+
+/// A reference to a label that isn't defined yet, patched in once the
+/// whole program has been read.
+struct Fixup {
+    /// Offset of the instruction to patch
+    offset: usize,
+    label: String,
+    /// Where the reference is, for errors
+    location: String,
+    kind: FixupKind,
+}
+
+enum FixupKind {
+    /// The NNN of an instruction
+    Address,
+    /// The two `vX := NN` of `:unpack`, with its nibble, or None for
+    /// `:unpack long`
+    Unpack(Option<u8>),
+}
+
+/// A jump or loop waiting for its closing keyword.
+enum Block {
+    /// `if ... begin` or `else`: the offset of the jump to patch with
+    /// the address of the matching `else` or `end`
+    Begin(usize),
+    /// `loop`: where it starts, and the offsets of the jumps out of it
+    /// (from `while`) to patch with the address after `again`
+    Loop { start: u16, exits: Vec<usize> },
+}
+
+/// A condition in `if` or `while`.
+#[derive(Clone, Copy)]
+enum Condition {
+    Eq(usize, Operand),
+    Neq(usize, Operand),
+    Less(usize, Operand),
+    Greater(usize, Operand),
+    LessEq(usize, Operand),
+    GreaterEq(usize, Operand),
+    Key(usize),
+    NotKey(usize),
+}
+
+#[derive(Clone, Copy)]
+enum Operand {
+    Register(usize),
+    Byte(u8),
+}
+
+impl Condition {
+    fn negate(self) -> Condition {
+        match self {
+            Condition::Eq(x, operand) => Condition::Neq(x, operand),
+            Condition::Neq(x, operand) => Condition::Eq(x, operand),
+            Condition::Less(x, operand) => Condition::GreaterEq(x, operand),
+            Condition::Greater(x, operand) => Condition::LessEq(x, operand),
+            Condition::LessEq(x, operand) => Condition::Greater(x, operand),
+            Condition::GreaterEq(x, operand) => Condition::Less(x, operand),
+            Condition::Key(x) => Condition::NotKey(x),
+            Condition::NotKey(x) => Condition::Key(x),
+        }
+    }
+
+    /// The instructions that skip the next one unless the condition
+    /// holds. As in Octo, the comparisons subtract in the register
+    /// `temp` (VF unless `compare-temp` is aliased) and check the
+    /// borrow they leave in VF.
+    fn skip_unless(self, temp: usize) -> Vec<Instruction> {
+        let compare = |x: usize, operand: Operand, reversed: bool, or_equal: bool| {
+            let load = match operand {
+                Operand::Register(y) => Instruction::SetVxVy { x: temp, y },
+                Operand::Byte(nn) => Instruction::SetVxNn { x: temp, nn },
+            };
+            // VF is 1 if temp - x (or x - temp, reversed) didn't borrow
+            let subtract = if reversed {
+                Instruction::SetVyMinusVx { x: temp, y: x }
+            } else {
+                Instruction::SetVxMinusVy { x: temp, y: x }
+            };
+            let skip = if or_equal {
+                Instruction::SkipVxNeqNn { x: 0xF, nn: 1 }
+            } else {
+                Instruction::SkipVxEqNn { x: 0xF, nn: 1 }
+            };
+            vec![load, subtract, skip]
+        };
+        match self {
+            Condition::Eq(x, Operand::Byte(nn)) => vec![Instruction::SkipVxNeqNn { x, nn }],
+            Condition::Eq(x, Operand::Register(y)) => vec![Instruction::SkipVxNeqVy { x, y }],
+            Condition::Neq(x, Operand::Byte(nn)) => vec![Instruction::SkipVxEqNn { x, nn }],
+            Condition::Neq(x, Operand::Register(y)) => vec![Instruction::SkipVxEqVy { x, y }],
+            Condition::Greater(x, operand) => compare(x, operand, false, false),
+            Condition::Less(x, operand) => compare(x, operand, true, false),
+            Condition::GreaterEq(x, operand) => compare(x, operand, true, true),
+            Condition::LessEq(x, operand) => compare(x, operand, false, true),
+            Condition::Key(x) => vec![Instruction::SkipIfVxNotPressed { x }],
+            Condition::NotKey(x) => vec![Instruction::SkipIfVxPressed { x }],
+        }
+    }
+}
+
+/// Assemble Octo source files into one program, in order, as if they
+/// were one file. Each file is named by an optional path for error
+/// messages.
+pub(crate) fn assemble_octo(sources: &[(Option<&str>, &str)]) -> Result<Assembled, String> {
+    let mut tokens = VecDeque::new();
+    for (name, source) in sources {
+        for (num, line) in source.lines().enumerate() {
+            let location = match name {
+                Some(name) => format!("{}:{}", name, num + 1),
+                None => format!("line {}", num + 1),
+            };
+            let line = line.split('#').next().unwrap_or("");
+            tokens.extend(line.split_whitespace().map(|text| Token {
+                text: text.to_string(),
+                location: location.clone(),
+            }));
+        }
+    }
+
+    let mut assembler = OctoAssembler {
+        tokens,
+        rom: Vec::new(),
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+        last_location: "end of input".to_string(),
+        last_call: None,
+    };
+    let max = max_rom_bytes(XO_CHIP_MEMORY_BYTES);
+    while let Some(token) = assembler.tokens.pop_front() {
+        assembler.last_location = token.location.clone();
+        assembler
+            .statement(&token)
+            .map_err(|err| format!("{}: {}", token.location, err))?;
+        if assembler.rom.len() > max {
+            return Err(format!(
+                "{}: program too large, more than the {} bytes that fit in memory",
+                token.location, max
+            ));
+        }
+    }
+    assembler.finish()
+}
+
+struct OctoAssembler {
+    tokens: VecDeque<Token>,
+    rom: Vec<u8>,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, u16>,
+    aliases: HashMap<String, usize>,
+    macros: HashMap<String, Macro>,
+    /// Instructions that refer to a label before it's defined
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    /// Location of the latest token, for errors at the end of input
+    last_location: String,
+    /// The previous statement, if it was a name taken for a subroutine
+    /// call, so an unknown statement with arguments can be reported as
+    /// that rather than as its arguments
+    last_call: Option<String>,
+}
+
+impl OctoAssembler {
+    /// The address of the next byte, which is past the end of memory
+    /// once the program fills it.
+    fn here(&self) -> Result<u16, String> {
+        u16::try_from(ORIGIN as usize + self.rom.len()).map_err(|_| "past the end of memory".to_string())
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self.tokens.pop_front().ok_or("unexpected end of input")?;
+        self.last_location = token.location.clone();
+        Ok(token)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), String> {
+        let token = self.next()?;
+        if token.text == text {
+            Ok(())
+        } else {
+            Err(format!("expected {:?}, not {:?}", text, token.text))
+        }
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.rom
+            .extend_from_slice(&encode_instruction(&instruction).to_be_bytes());
+    }
+
+    /// Emit an instruction whose address may be a label that isn't
+    /// defined yet.
+    fn emit_with_address(&mut self, make: fn(u16) -> Instruction, target: &Token) -> Result<(), String> {
+        let address = match self.address(&target.text)? {
+            Some(address) => address,
+            None => {
+                self.add_fixup(target, FixupKind::Address);
+                0
+            }
+        };
+        self.emit(make(address));
+        Ok(())
+    }
+
+    /// Patch the instruction about to be emitted once `target`, a label
+    /// that isn't defined yet, is.
+    fn add_fixup(&mut self, target: &Token, kind: FixupKind) {
+        self.fixups.push(Fixup {
+            offset: self.rom.len(),
+            label: target.text.clone(),
+            location: target.location.clone(),
+            kind,
+        });
+    }
+
+    /// Emit the instructions that skip the next one unless `condition`
+    /// holds.
+    fn emit_skip_unless(&mut self, condition: Condition) {
+        let temp = self.aliases.get("compare-temp").copied().unwrap_or(0xF);
+        for instruction in condition.skip_unless(temp) {
+            self.emit(instruction);
+        }
+    }
+
+    /// Emit `:unpack`'s loads of `address` into v0 and v1 (or whatever
+    /// `unpack-hi` and `unpack-lo` are aliased to): the high byte, with
+    /// `nibble` in its top four bits unless it's `:unpack long`, and
+    /// the low byte.
+    fn emit_unpack(&mut self, nibble: Option<u8>, address: u16) {
+        let hi = match nibble {
+            Some(nibble) => nibble << 4 | (address >> 8) as u8,
+            None => (address >> 8) as u8,
+        };
+        let x = self.aliases.get("unpack-hi").copied().unwrap_or(0);
+        self.emit(Instruction::SetVxNn { x, nn: hi });
+        let x = self.aliases.get("unpack-lo").copied().unwrap_or(1);
+        self.emit(Instruction::SetVxNn { x, nn: address as u8 });
+    }
+
+    /// A jump to be patched later, returning its offset.
+    fn emit_placeholder_jump(&mut self) -> usize {
+        let offset = self.rom.len();
+        self.emit(Instruction::Jump { nnn: 0 });
+        offset
+    }
+
+    /// Point the instruction at `offset` to `address`.
+    fn patch(&mut self, offset: usize, address: u16) {
+        self.rom[offset] = self.rom[offset] & 0xF0 | (address >> 8) as u8;
+        self.rom[offset + 1] = address as u8;
+    }
+
+    fn statement(&mut self, token: &Token) -> Result<(), String> {
+        let text = token.text.as_str();
+        let after_call = self.last_call.take();
+        match text {
+            ":" => {
+                let name = self.next()?;
+                self.define_label(&name.text)
+            }
+            ":next" => {
+                // A label for the second byte of the next instruction,
+                // for code that changes it
+                let name = self.next()?;
+                let address = self.here()?.saturating_add(1);
+                self.define_label_at(&name.text, address)
+            }
+            ":unpack" => {
+                let nibble = self.next()?;
+                let nibble = match nibble.text.as_str() {
+                    "long" => None,
+                    text => match self.number(text)? {
+                        n @ 0..=0xF => Some(n as u8),
+                        n => return Err(format!(":unpack nibble {} is more than 15", n)),
+                    },
+                };
+                let target = self.next()?;
+                let address = match self.labels.get(&target.text) {
+                    Some(&address) => Some(address),
+                    None => match self.number(&target.text) {
+                        Ok(address) => Some(address),
+                        Err(_) if is_identifier(&target.text) => None,
+                        Err(err) => return Err(err),
+                    },
+                };
+                match address {
+                    Some(address) if nibble.is_some() && address > 0xFFF => {
+                        Err(format!("address {:#X} is out of range", address))
+                    }
+                    Some(address) => {
+                        self.emit_unpack(nibble, address);
+                        Ok(())
+                    }
+                    None => {
+                        self.add_fixup(&target, FixupKind::Unpack(nibble));
+                        self.emit_unpack(nibble, 0);
+                        Ok(())
+                    }
+                }
+            }
+            ":const" => {
+                let name = self.next()?.text;
+                let value = self.next()?;
+                let value = self.number(&value.text)?;
+                self.check_name(&name)?;
+                self.constants.insert(name, value);
+                Ok(())
+            }
+            ":alias" => {
+                let name = self.next()?.text;
+                let register = self.next()?;
+                let register = self.register(&register.text)?;
+                self.check_name(&name)?;
+                self.aliases.insert(name, register);
+                Ok(())
+            }
+            ":macro" => self.define_macro(),
+            ":byte" => {
+                let value = self.next()?;
+                let byte = self.byte(&value.text)?;
+                self.rom.push(byte);
+                Ok(())
+            }
+            ":call" => {
+                let target = self.next()?;
+                self.emit_with_address(|nnn| Instruction::SubroutineCall { nnn }, &target)
+            }
+            "clear" => {
+                self.emit(Instruction::ClearScreen);
+                Ok(())
+            }
+            "exit" => {
+                self.emit(Instruction::Exit);
+                Ok(())
+            }
+            "return" | ";" => {
+                self.emit(Instruction::SubroutineReturn);
+                Ok(())
+            }
+            "audio" => {
+                self.emit(Instruction::LoadAudioPattern);
+                Ok(())
+            }
+            "scroll-down" | "scroll-up" => {
+                let n = self.next()?;
+                let n = self.number(&n.text)?;
+                if n > 0xF {
+                    return Err(format!("scroll distance {} is more than 15", n));
+                }
+                let n = n as u8;
+                self.emit(if text == "scroll-down" {
+                    Instruction::ScrollDown { n }
+                } else {
+                    Instruction::ScrollUp { n }
+                });
+                Ok(())
+            }
+            "plane" => {
+                let n = self.next()?;
+                let n = self.number(&n.text)?;
+                if n > 3 {
+                    return Err(format!("plane mask {} is more than 3", n));
+                }
+                self.emit(Instruction::SelectPlanes { n: n as u8 });
+                Ok(())
+            }
+            "lores" => {
+                self.emit(Instruction::LowRes);
+                Ok(())
+            }
+            "hires" => {
+                self.emit(Instruction::HighRes);
+                Ok(())
+            }
+            "scroll-left" => {
+                self.emit(Instruction::ScrollLeft);
+                Ok(())
+            }
+            "scroll-right" => {
+                self.emit(Instruction::ScrollRight);
+                Ok(())
+            }
+            "jump" => {
+                let target = self.next()?;
+                self.emit_with_address(|nnn| Instruction::Jump { nnn }, &target)
+            }
+            "jump0" => {
+                let target = self.next()?;
+                self.emit_with_address(|nnn| Instruction::JumpV0Nnn { nnn }, &target)
+            }
+            "bcd" | "save" | "load" => {
+                let register = self.next()?;
+                let x = self.register(&register.text)?;
+                self.emit(match text {
+                    "bcd" => Instruction::StoreVxDigitsI { x },
+                    "save" => Instruction::StoreVxI { x },
+                    _ => Instruction::StoreIVx { x },
+                });
+                Ok(())
+            }
+            "sprite" => {
+                let (x, y, n) = (self.next()?, self.next()?, self.next()?);
+                let n = self.number(&n.text)?;
+                if n > 0xF {
+                    return Err(format!("sprite height {} is more than 15", n));
+                }
+                let instruction = Instruction::Display {
+                    x: self.register(&x.text)?,
+                    y: self.register(&y.text)?,
+                    n: n as u8,
+                };
+                self.emit(instruction);
+                Ok(())
+            }
+            "i" => self.index_statement(),
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let register = self.next()?;
+                let x = self.register(&register.text)?;
+                self.emit(match text {
+                    "delay" => Instruction::SetDelayVx { x },
+                    "buzzer" => Instruction::SetSoundVx { x },
+                    _ => Instruction::SetPitchVx { x },
+                });
+                Ok(())
+            }
+            "if" => self.if_statement(),
+            "else" => match self.blocks.pop() {
+                Some(Block::Begin(offset)) => {
+                    let jump = self.emit_placeholder_jump();
+                    self.patch(offset, self.here()?);
+                    self.blocks.push(Block::Begin(jump));
+                    Ok(())
+                }
+                _ => Err("else without if ... begin".to_string()),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::Begin(offset)) => {
+                    self.patch(offset, self.here()?);
+                    Ok(())
+                }
+                _ => Err("end without if ... begin".to_string()),
+            },
+            "loop" => {
+                self.blocks.push(Block::Loop {
+                    start: self.here()?,
+                    exits: Vec::new(),
+                });
+                Ok(())
+            }
+            "while" => {
+                let condition = self.condition()?;
+                // Skip the jump out of the loop while the condition holds
+                self.emit_skip_unless(condition.negate());
+                let jump = self.emit_placeholder_jump();
+                match self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|block| matches!(block, Block::Loop { .. }))
+                {
+                    Some(Block::Loop { exits, .. }) => {
+                        exits.push(jump);
+                        Ok(())
+                    }
+                    _ => Err("while outside a loop".to_string()),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, exits }) => {
+                    self.emit(Instruction::Jump { nnn: start });
+                    for exit in exits {
+                        self.patch(exit, self.here()?);
+                    }
+                    Ok(())
+                }
+                _ => Err("again without loop".to_string()),
+            },
+            _ if self.is_register(text) => self.register_statement(token, after_call),
+            _ if self.macros.contains_key(text) => self.expand_macro(token),
+            _ if UNSUPPORTED_DIRECTIVES.contains(&text) => Err(format!("{} isn't supported", text)),
+            _ if text.starts_with(':') && text.len() > 1 => self.define_label(&text[1..]),
+            _ => match self.number(text) {
+                // A number on its own is a byte of data
+                Ok(_) => {
+                    let byte = self.byte(text)?;
+                    self.rom.push(byte);
+                    Ok(())
+                }
+                // Anything else calls a subroutine
+                Err(_) if is_identifier(text) => {
+                    self.last_call = Some(text.to_string());
+                    self.emit_with_address(|nnn| Instruction::SubroutineCall { nnn }, token)
+                }
+                Err(_) => Err(format!("unknown statement {:?}", text)),
+            },
+        }
+    }
+
+    fn define_label(&mut self, name: &str) -> Result<(), String> {
+        let address = self
+            .here()
+            .map_err(|_| format!("label {:?} is past the end of memory", name))?;
+        self.define_label_at(name, address)
+    }
+
+    fn define_label_at(&mut self, name: &str, address: u16) -> Result<(), String> {
+        self.check_name(name)?;
+        if self.labels.insert(name.to_string(), address).is_some() {
+            return Err(format!("duplicate label {:?}", name));
+        }
+        Ok(())
+    }
+
+    fn check_name(&self, name: &str) -> Result<(), String> {
+        if is_identifier(name) && !self.is_register(name) {
+            Ok(())
+        } else {
+            Err(format!("invalid name {:?}", name))
+        }
+    }
+
+    /// `:macro name params... { body }`
+    fn define_macro(&mut self) -> Result<(), String> {
+        let name = self.next()?.text;
+        self.check_name(&name)?;
+        let mut params = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "{" {
+                break;
+            }
+            params.push(token.text);
+        }
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                break;
+            }
+            body.push(token);
+        }
+        self.macros.insert(name, Macro { params, body });
+        Ok(())
+    }
+
+    /// Replace a macro invocation and its arguments with the macro's
+    /// body, with the arguments substituted for its parameters.
+    fn expand_macro(&mut self, token: &Token) -> Result<(), String> {
+        let mut args = HashMap::new();
+        let param_count = self.macros[&token.text].params.len();
+        for i in 0..param_count {
+            let arg = self.next()?;
+            args.insert(self.macros[&token.text].params[i].clone(), arg.text);
+        }
+        let expansion: Vec<Token> = self.macros[&token.text]
+            .body
+            .iter()
+            .map(|body_token| Token {
+                text: args.get(&body_token.text).unwrap_or(&body_token.text).clone(),
+                location: format!("{} (in macro {})", token.location, token.text),
+            })
+            .collect();
+        for body_token in expansion.into_iter().rev() {
+            self.tokens.push_front(body_token);
+        }
+        Ok(())
+    }
+
+    /// `i := ADDR`, `i := hex vX`, `i := bighex vX` or `i += vX`
+    fn index_statement(&mut self) -> Result<(), String> {
+        let op = self.next()?;
+        let operand = self.next()?;
+        match op.text.as_str() {
+            ":=" if operand.text == "hex" => {
+                let register = self.next()?;
+                let x = self.register(&register.text)?;
+                self.emit(Instruction::SetIFontVx { x });
+                Ok(())
+            }
+            ":=" if operand.text == "bighex" => {
+                let register = self.next()?;
+                let x = self.register(&register.text)?;
+                self.emit(Instruction::SetILargeFontVx { x });
+                Ok(())
+            }
+            ":=" => self.emit_with_address(|nnn| Instruction::SetIndexNnn { nnn }, &operand),
+            "+=" => {
+                let x = self.register(&operand.text)?;
+                self.emit(Instruction::AddVxI { x });
+                Ok(())
+            }
+            _ => Err(format!("invalid operator {:?} for i", op.text)),
+        }
+    }
+
+    /// `vX OP operand`. `after_call` is the name of the previous
+    /// statement, if it was taken for a subroutine call.
+    fn register_statement(&mut self, token: &Token, after_call: Option<String>) -> Result<(), String> {
+        let x = self.register(&token.text)?;
+        let op = self.next()?.text;
+        if !OPERATORS.contains(&op.as_str()) {
+            // Most likely a statement this assembler doesn't know, like
+            // `saveflags v3`, rather than a call followed by nonsense
+            return Err(match after_call {
+                Some(name) => format!("unknown statement {:?}", name),
+                None => format!("expected an operator after {}, not {:?}", token.text, op),
+            });
+        }
+        let operand = self.next()?;
+        let operand = operand.text.as_str();
+
+        let instruction = match (op.as_str(), operand) {
+            (":=", "random") => {
+                let mask = self.next()?;
+                Instruction::SetVxRandNn {
+                    x,
+                    nn: self.byte(&mask.text)?,
+                }
+            }
+            (":=", "delay") => Instruction::SetVxDelay { x },
+            (":=", "key") => Instruction::BlockUntilAnyKey { x },
+            _ if self.is_register(operand) => {
+                let y = self.register(operand)?;
+                match op.as_str() {
+                    ":=" => Instruction::SetVxVy { x, y },
+                    "+=" => Instruction::SetVxPlusVy { x, y },
+                    "-=" => Instruction::SetVxMinusVy { x, y },
+                    "=-" => Instruction::SetVyMinusVx { x, y },
+                    "|=" => Instruction::SetVxOrVy { x, y },
+                    "&=" => Instruction::SetVxAndVy { x, y },
+                    "^=" => Instruction::SetVxXorVy { x, y },
+                    ">>=" => Instruction::ShiftVxRight { x, y },
+                    "<<=" => Instruction::ShiftVxLeft { x, y },
+                    _ => return Err(format!("invalid operator {:?} for two registers", op)),
+                }
+            }
+            _ => {
+                let nn = self.byte(operand)?;
+                match op.as_str() {
+                    ":=" => Instruction::SetVxNn { x, nn },
+                    "+=" => Instruction::AddNnVx { x, nn },
+                    // There's no subtract instruction, so add the negation
+                    "-=" => Instruction::AddNnVx {
+                        x,
+                        nn: nn.wrapping_neg(),
+                    },
+                    _ => return Err(format!("invalid operator {:?} for a register and a number", op)),
+                }
+            }
+        };
+        self.emit(instruction);
+        Ok(())
+    }
+
+    /// `if COND then STATEMENT` or `if COND begin ... [else ...] end`
+    fn if_statement(&mut self) -> Result<(), String> {
+        let condition = self.condition()?;
+        let keyword = self.next()?;
+        match keyword.text.as_str() {
+            // The next statement is assembled as usual
+            "then" => self.emit_skip_unless(condition),
+            "begin" => {
+                self.emit_skip_unless(condition.negate());
+                let jump = self.emit_placeholder_jump();
+                self.blocks.push(Block::Begin(jump));
+            }
+            text => return Err(format!("expected then or begin, not {:?}", text)),
+        }
+        Ok(())
+    }
+
+    fn condition(&mut self) -> Result<Condition, String> {
+        let register = self.next()?;
+        let x = self.register(&register.text)?;
+        let op = self.next()?.text;
+        match op.as_str() {
+            "key" => return Ok(Condition::Key(x)),
+            "-key" => return Ok(Condition::NotKey(x)),
+            _ => {}
+        }
+        let operand = self.next()?;
+        let operand = if self.is_register(&operand.text) {
+            Operand::Register(self.register(&operand.text)?)
+        } else {
+            Operand::Byte(self.byte(&operand.text)?)
+        };
+        match op.as_str() {
+            "==" => Ok(Condition::Eq(x, operand)),
+            "!=" => Ok(Condition::Neq(x, operand)),
+            "<" => Ok(Condition::Less(x, operand)),
+            ">" => Ok(Condition::Greater(x, operand)),
+            "<=" => Ok(Condition::LessEq(x, operand)),
+            ">=" => Ok(Condition::GreaterEq(x, operand)),
+            _ => Err(format!("unknown comparison {:?}", op)),
+        }
+    }
+
+    fn is_register(&self, s: &str) -> bool {
+        self.aliases.contains_key(s) || parse_register(s).is_some()
+    }
+
+    fn register(&self, s: &str) -> Result<usize, String> {
+        self.aliases
+            .get(s)
+            .copied()
+            .or_else(|| parse_register(s))
+            .ok_or_else(|| format!("expected a register, not {:?}", s))
+    }
+
+    /// A number or constant.
+    fn number(&self, s: &str) -> Result<u16, String> {
+        if let Some(&value) = self.constants.get(s) {
+            return Ok(value);
+        }
+        parse_number(s).ok_or_else(|| format!("expected a number, not {:?}", s))
+    }
+
+    /// A number or constant that fits in a byte. Negative numbers are
+    /// two's complement, so `v0 += -1` subtracts one.
+    fn byte(&self, s: &str) -> Result<u8, String> {
+        let value = self.number(s)?;
+        if value <= 0xFF || value >= 0xFF80 {
+            Ok(value as u8)
+        } else {
+            Err(format!("value {:#X} does not fit in a byte", value))
+        }
+    }
+
+    /// An address given as a number, constant or label, or None if it's
+    /// a label that isn't defined yet.
+    fn address(&self, s: &str) -> Result<Option<u16>, String> {
+        let address = match self.labels.get(s) {
+            Some(&address) => address,
+            None => match self.number(s) {
+                Ok(address) => address,
+                Err(_) if is_identifier(s) => return Ok(None),
+                Err(err) => return Err(err),
+            },
+        };
+        if address <= 0xFFF {
+            Ok(Some(address))
+        } else {
+            Err(format!("address {:#X} is out of range", address))
+        }
+    }
+
+    /// Resolve forward references and check everything was closed.
+    fn finish(mut self) -> Result<Assembled, String> {
+        if !self.blocks.is_empty() {
+            return Err(format!(
+                "{}: missing end or again at the end of the program",
+                self.last_location
+            ));
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            let address = match self.labels.get(&fixup.label) {
+                Some(&address) => address,
+                None => return Err(format!("{}: unknown label {:?}", fixup.location, fixup.label)),
+            };
+            match fixup.kind {
+                FixupKind::Address => self.patch(fixup.offset, address),
+                FixupKind::Unpack(Some(_)) if address > 0xFFF => {
+                    return Err(format!("{}: address {:#X} is out of range", fixup.location, address))
+                }
+                FixupKind::Unpack(nibble) => {
+                    self.rom[fixup.offset + 1] |= match nibble {
+                        Some(_) => (address >> 8) as u8 & 0xF,
+                        None => (address >> 8) as u8,
+                    };
+                    self.rom[fixup.offset + 3] = address as u8;
+                }
+            }
+        }
+        Ok(Assembled {
+            rom: self.rom,
+            symbols: Symbols::new(self.labels.into_iter().collect()),
+        })
+    }
+}
+
+fn parse_register(s: &str) -> Option<usize> {
+    let digit = s.strip_prefix('v').or_else(|| s.strip_prefix('V'))?;
+    if digit.len() == 1 {
+        usize::from_str_radix(digit, 16).ok()
+    } else {
+        None
+    }
+}
+
+fn parse_number(s: &str) -> Option<u16> {
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s),
+    };
+    let value = if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        u16::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = s.strip_prefix("0b").or_else(|| s.strip_prefix("0B")) {
+        u16::from_str_radix(binary, 2).ok()?
+    } else {
+        s.parse().ok()?
+    };
+    if negative {
+        (value <= 0x80).then_some(value.wrapping_neg())
+    } else {
+        Some(value)
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[test]
+fn test_assemble_octo() {
+    let assemble = |source| assemble_octo(&[(None, source)]).map(|assembled| assembled.rom);
+    let source = "
+        :const SPEED 2
+        :alias x v0
+        :macro step reg amount { reg += amount }
+
+        : main
+            i := sprite        # forward reference
+            loop
+                sprite x v1 5
+                step x SPEED
+                if x == 60 then x := 0
+                while v1 != 0
+            again
+            draw
+        :draw
+            v1 =- x
+            ;
+        : sprite
+            0xF0 -1 :byte 0b101
+    ";
+    assert_eq!(
+        assemble(source),
+        Ok(vec![
+            0xA2, 0x16, // 200: i := sprite
+            0xD0, 0x15, // 202: sprite x v1 5
+            0x70, 0x02, // 204: x += SPEED
+            0x40, 0x3C, // 206: skip unless x == 60
+            0x60, 0x00, // 208: x := 0
+            0x41, 0x00, // 20A: skip the exit while v1 != 0
+            0x12, 0x10, // 20C: exit the loop
+            0x12, 0x02, // 20E: again
+            0x22, 0x12, // 210: call draw
+            0x81, 0x07, // 212: v1 =- x
+            0x00, 0xEE, // 214: return
+            0xF0, 0xFF, 0x05, // 216: sprite
+        ])
+    );
+
+    assert_eq!(
+        assemble("if v2 key begin v0 := 1 else v0 := 2 end"),
+        Ok(vec![0xE2, 0x9E, 0x12, 0x08, 0x60, 0x01, 0x12, 0x0A, 0x60, 0x02])
+    );
+    assert_eq!(assemble("clear exit"), Ok(vec![0x00, 0xE0, 0x00, 0xFD]));
+    assert_eq!(assemble("i := hex v1 i := bighex v2"), Ok(vec![0xF1, 0x29, 0xF2, 0x30]));
+    assert_eq!(
+        assemble("scroll-down 4 scroll-up 2 scroll-left scroll-right"),
+        Ok(vec![0x00, 0xC4, 0x00, 0xD2, 0x00, 0xFC, 0x00, 0xFB])
+    );
+    assert_eq!(assemble("hires lores"), Ok(vec![0x00, 0xFF, 0x00, 0xFE]));
+    assert_eq!(assemble("plane 3"), Ok(vec![0xF3, 0x01]));
+    assert!(assemble("plane 4").is_err());
+
+    let symbols = assemble_octo(&[(None, source)]).unwrap().symbols;
+    assert_eq!(symbols.address("sprite"), Some(0x216));
+
+    assert!(assemble("jump nowhere").unwrap_err().contains("line 1"));
+    assert!(assemble("loop v0 += 1").is_err());
+    assert!(assemble("v0 := 256").is_err());
+    assert!(assemble("if v0 ~ 3 then v0 := 1").is_err());
+    assert!(assemble(": a : a").is_err());
+    assert!(assemble(":org 0x300").unwrap_err().contains("isn't supported"));
+}
+
+#[test]
+fn test_assemble_octo_comparisons() {
+    let assemble = |source| assemble_octo(&[(None, source)]).map(|assembled| assembled.rom);
+    assert_eq!(
+        assemble("if v0 < 3 then v2 := 1"),
+        Ok(vec![0x6F, 0x03, 0x8F, 0x07, 0x3F, 0x01, 0x62, 0x01])
+    );
+    assert_eq!(
+        assemble("if v0 > v1 then v2 := 1"),
+        Ok(vec![0x8F, 0x10, 0x8F, 0x05, 0x3F, 0x01, 0x62, 0x01])
+    );
+    assert_eq!(
+        assemble("if v0 <= 3 then v2 := 1"),
+        Ok(vec![0x6F, 0x03, 0x8F, 0x05, 0x4F, 0x01, 0x62, 0x01])
+    );
+    // The jump out of the loop is taken once v0 < 8
+    assert_eq!(
+        assemble(":alias compare-temp ve loop v0 += 1 while v0 >= 8 again"),
+        Ok(vec![
+            0x70, 0x01, 0x6E, 0x08, 0x8E, 0x07, 0x3F, 0x01, 0x12, 0x0C, 0x12, 0x00
+        ])
+    );
+}
+
+#[test]
+fn test_assemble_octo_unpack_next() {
+    let assemble = |source| assemble_octo(&[(None, source)]).map(|assembled| assembled.rom);
+    assert_eq!(
+        assemble(":unpack 0xA data :unpack long data : data 1"),
+        Ok(vec![0x60, 0xA2, 0x61, 0x08, 0x60, 0x02, 0x61, 0x08, 0x01])
+    );
+    assert_eq!(
+        assemble(": data 1 :unpack 5 data"),
+        Ok(vec![0x01, 0x60, 0x52, 0x61, 0x00])
+    );
+    assert!(assemble(":unpack 16 data : data").is_err());
+
+    let assembled = assemble_octo(&[(None, "clear :next target v0 := 0 i := target")]).unwrap();
+    assert_eq!(assembled.symbols.address("target"), Some(0x203));
+    assert_eq!(assembled.rom, vec![0x00, 0xE0, 0x60, 0x00, 0xA2, 0x03]);
+}
+
+#[test]
+fn test_assemble_octo_unknown_statement() {
+    let assemble = |source| assemble_octo(&[(None, source)]).map(|assembled| assembled.rom);
+    assert_eq!(
+        assemble("loop\n saveflags v3\nagain"),
+        Err("line 2: unknown statement \"saveflags\"".to_string())
+    );
+    assert_eq!(
+        assemble("v3 again"),
+        Err("line 1: expected an operator after v3, not \"again\"".to_string())
+    );
+    // A call followed by an ordinary statement is fine
+    assert_eq!(assemble("draw v3 := 1 : draw"), Ok(vec![0x22, 0x04, 0x63, 0x01]));
+}
+
+#[test]
+fn test_assemble_octo_too_large() {
+    let assemble = |source: &str| assemble_octo(&[(None, source)]).map(|assembled| assembled.rom.len());
+    let source = "clear ".repeat(max_rom_bytes(XO_CHIP_MEMORY_BYTES) / 2);
+    assert_eq!(assemble(&source), Ok(0xFE00));
+    assert_eq!(
+        assemble(&(source.clone() + ": end")),
+        Err("line 1: label \"end\" is past the end of memory".to_string())
+    );
+    assert_eq!(
+        assemble(&(source + "\nclear")),
+        Err("line 2: program too large, more than the 65024 bytes that fit in memory".to_string())
+    );
+}
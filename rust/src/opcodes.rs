@@ -0,0 +1,227 @@
+//! `chip8 opcodes`: list every instruction the emulator supports. The
+//! list is worked out by running every two-byte word through the
+//! decoder, so it can't drift from what's actually implemented.
+//! `--json` prints it for other tools, e.g.
+//!
+//! ```text
+//! {"name":"SetVxNn","pattern":"6XNN","mnemonic":"LD","syntax":"LD VX, NN","octo":"vX := NN",
+//!  "operands":["x","nn"],"quirks":[],"platforms":["vip","chip48","schip-legacy","schip-modern","xochip"]}
+//! ```
+
+use std::collections::BTreeMap;
+
+use chip8::instruction::{parse_instruction, Instruction};
+use chip8::platform::PLATFORMS;
+use chip8::quirks::Quirks;
+
+/// An instruction, with its operands as placeholders.
+pub(crate) struct Opcode {
+    /// The `Instruction` variant
+    name: String,
+    /// The opcode with its operands' nibbles as letters, like 6XNN
+    pattern: String,
+    mnemonic: String,
+    /// The mnemonic with its operands, like `LD VX, NN`
+    syntax: String,
+    /// The Octo statement, like `vX := NN`
+    octo: String,
+    /// Operand names, in the order they appear in the opcode
+    operands: Vec<String>,
+    quirks: &'static [&'static str],
+    /// Names of the platforms with the instruction
+    platforms: Vec<&'static str>,
+}
+
+/// Nibbles that stand for each operand, and what an example value of it
+/// is written as, so it can be replaced with a placeholder.
+const OPERANDS: [(&str, &str, u16, &str, &str); 5] = [
+    ("x", "X", 0x1, "V1", "VX"),
+    ("y", "Y", 0x2, "V2", "VY"),
+    ("n", "N", 0x3, "3", "N"),
+    ("nn", "NN", 0x45, "0x45", "NN"),
+    ("nnn", "NNN", 0x678, "0x678", "NNN"),
+];
+
+/// Every instruction the decoder accepts, in opcode order.
+pub(crate) fn opcodes() -> Vec<Opcode> {
+    // The words each instruction decodes from, by variant name
+    let mut words: BTreeMap<String, (u16, u16, u16)> = BTreeMap::new();
+    for word in 0..=u16::MAX {
+        if let Ok(instruction) = parse_instruction(word) {
+            let (first, and, or) = words.entry(variant(&instruction)).or_insert((word, word, word));
+            *first = (*first).min(word);
+            *and &= word;
+            *or |= word;
+        }
+    }
+
+    let mut by_first: Vec<(String, (u16, u16, u16))> = words.into_iter().collect();
+    by_first.sort_by_key(|&(_, (first, _, _))| first);
+    by_first
+        .into_iter()
+        .map(|(name, (first, and, or))| {
+            // Nibbles that differ between the words are operands
+            let varies: Vec<bool> = (0..4).map(|i| (and ^ or) >> (12 - i * 4) & 0xF != 0).collect();
+            let sample = parse_instruction(first).expect("decoded before");
+            let operands = operand_names(&sample);
+            let mut pattern = String::new();
+            let mut example = first;
+            let mut nibble = 0;
+            for operand in &operands {
+                let &(_, letters, value, _, _) = OPERANDS
+                    .iter()
+                    .find(|(name, ..)| name == operand)
+                    .expect("every operand is in OPERANDS");
+                while !varies[nibble] {
+                    pattern += &format!("{:X}", first >> (12 - nibble * 4) & 0xF);
+                    nibble += 1;
+                }
+                pattern += letters;
+                nibble += letters.len();
+                example |= value << (16 - nibble * 4);
+            }
+            while nibble < 4 {
+                pattern += &format!("{:X}", first >> (12 - nibble * 4) & 0xF);
+                nibble += 1;
+            }
+
+            let example = parse_instruction(example).expect("operands can take any value");
+            let syntax = placeholders(&example.to_string());
+            Opcode {
+                name,
+                pattern,
+                mnemonic: syntax.split(' ').next().unwrap_or_default().to_string(),
+                syntax,
+                octo: placeholders(&format!("{:#}", example)),
+                operands,
+                quirks: Quirks::affecting(&sample),
+                platforms: PLATFORMS
+                    .iter()
+                    .filter(|platform| sample.instruction_set() <= platform.instruction_set)
+                    .map(|platform| platform.name)
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+fn variant(instruction: &Instruction) -> String {
+    let debug = format!("{:?}", instruction);
+    debug.split(' ').next().unwrap_or_default().to_string()
+}
+
+/// The names of an instruction's fields, like `["x", "nn"]`.
+fn operand_names(instruction: &Instruction) -> Vec<String> {
+    let debug = format!("{:?}", instruction);
+    match debug.split_once('{') {
+        Some((_, fields)) => fields
+            .trim_end_matches('}')
+            .split(',')
+            .filter_map(|field| field.split(':').next())
+            .map(|name| name.trim().to_string())
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Replace the example operands in an instruction's text with their
+/// placeholders, keeping the case of registers.
+fn placeholders(text: &str) -> String {
+    let words: Vec<String> = text
+        .split(' ')
+        .map(|word| {
+            let (bare, comma) = match word.strip_suffix(',') {
+                Some(bare) => (bare, ","),
+                None => (word, ""),
+            };
+            let replaced = OPERANDS
+                .iter()
+                .find_map(|&(_, _, _, example, placeholder)| {
+                    if bare == example {
+                        Some(placeholder.to_string())
+                    } else if bare == example.to_lowercase() && example.starts_with('V') {
+                        Some(format!("v{}", &placeholder[1..]))
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or_else(|| bare.to_string());
+            format!("{}{}", replaced, comma)
+        })
+        .collect();
+    words.join(" ")
+}
+
+pub(crate) fn table(opcodes: &[Opcode]) -> String {
+    let mut table = String::new();
+    for opcode in opcodes {
+        let mut line = format!("{:<6}{:<18}{:<22}", opcode.pattern, opcode.syntax, opcode.octo);
+        if opcode.platforms.len() < PLATFORMS.len() {
+            line += &format!("  ({} only)", opcode.platforms.join(", "));
+        }
+        if !opcode.quirks.is_empty() {
+            line += &format!("  quirks: {}", opcode.quirks.join(", "));
+        }
+        table += line.trim_end();
+        table += "\n";
+    }
+    table
+}
+
+/// The opcodes as a JSON array, one object per line.
+pub(crate) fn json(opcodes: &[Opcode]) -> String {
+    let string = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+    let strings = |items: &mut dyn Iterator<Item = &str>| {
+        let items: Vec<String> = items.map(string).collect();
+        format!("[{}]", items.join(","))
+    };
+    let objects: Vec<String> = opcodes
+        .iter()
+        .map(|opcode| {
+            format!(
+                concat!(
+                    "{{\"name\":{},\"pattern\":{},\"mnemonic\":{},\"syntax\":{},\"octo\":{},",
+                    "\"operands\":{},\"quirks\":{},\"platforms\":{}}}"
+                ),
+                string(&opcode.name),
+                string(&opcode.pattern),
+                string(&opcode.mnemonic),
+                string(&opcode.syntax),
+                string(&opcode.octo),
+                strings(&mut opcode.operands.iter().map(String::as_str)),
+                strings(&mut opcode.quirks.iter().copied()),
+                strings(&mut opcode.platforms.iter().copied()),
+            )
+        })
+        .collect();
+    format!("[\n{}\n]\n", objects.join(",\n"))
+}
+
+#[test]
+fn test_opcodes() {
+    let opcodes = opcodes();
+    assert_eq!(opcodes.len(), 45);
+    let find = |pattern: &str| opcodes.iter().find(|opcode| opcode.pattern == pattern).unwrap();
+
+    let set = find("6XNN");
+    assert_eq!(set.name, "SetVxNn");
+    assert_eq!(set.syntax, "LD VX, NN");
+    assert_eq!(set.octo, "vX := NN");
+    assert_eq!(set.operands, ["x", "nn"]);
+    assert_eq!(find("DXYN").syntax, "DRW VX, VY, N");
+    assert_eq!(find("DXYN").quirks, ["display-wait", "wrap-sprites"]);
+    assert_eq!(find("ANNN").octo, "i := NNN");
+    assert_eq!(find("FX55").syntax, "LD [I], VX");
+    assert_eq!(find("F002").platforms, ["xochip"]);
+    assert_eq!(find("00FD").platforms, ["schip-legacy", "schip-modern", "xochip"]);
+    assert_eq!(find("FX30").syntax, "LD HF, VX");
+    assert_eq!(find("00CN").octo, "scroll-down N");
+    assert_eq!(find("00DN").platforms, ["xochip"]);
+    assert_eq!(find("FN01").syntax, "PLANE N");
+    assert_eq!(opcodes[0].pattern, "00CN");
+
+    let json = json(&opcodes);
+    assert!(json.starts_with("[\n{\"name\":\"ScrollDown\",\"pattern\":\"00CN\",\"mnemonic\":\"SCD\""));
+    assert!(json.contains(",\"operands\":[\"x\",\"nn\"],\"quirks\":[],\"platforms\":[\"vip\","));
+    assert_eq!(json.lines().count(), opcodes.len() + 2);
+}
@@ -0,0 +1,300 @@
+//! Invariant checking for `--paranoid` mode. Every cycle the VM state
+//! is checked before the next instruction executes, and violations are
+//! logged to stderr without stopping the program. This is meant for
+//! vetting newly written ROMs, which often do things that happen to
+//! work on one interpreter but not on another.
+//!
+//! What counts as suspicious can be adjusted with a policy, e.g.
+//! `--paranoid=allow-odd-pc,i=200-FFF`.
+
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+
+use chip8::audio::AUDIO_PATTERN_BYTES;
+use chip8::instruction::{parse_instruction, Instruction};
+use chip8::vm::{PROGRAM_START, VM};
+
+/// The parts of `check_invariants` that ROMs can reasonably disagree
+/// about.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Policy {
+    /// PC must be even. Some ROMs deliberately run code at odd
+    /// addresses, squeezed in between data.
+    pub(crate) aligned_pc: bool,
+    /// Where I may point, for ROMs that never read the font or
+    /// anything else below their own code. By default, anywhere in
+    /// memory.
+    pub(crate) i_range: RangeInclusive<u16>,
+}
+
+impl Default for Policy {
+    fn default() -> Policy {
+        Policy {
+            aligned_pc: true,
+            i_range: 0..=u16::MAX,
+        }
+    }
+}
+
+impl Policy {
+    /// Parse a comma-separated list of changes to the default policy:
+    /// `allow-odd-pc`, and `i=START-END` (hex addresses).
+    pub(crate) fn parse(s: &str) -> Result<Policy, String> {
+        let mut policy = Policy::default();
+        for setting in s.split(',').map(str::trim).filter(|setting| !setting.is_empty()) {
+            match setting.split_once('=') {
+                None if setting == "allow-odd-pc" => policy.aligned_pc = false,
+                Some(("i", range)) => {
+                    policy.i_range = parse_range(range)
+                        .ok_or_else(|| format!("Invalid I range {:?}, expected hex addresses like 200-FFF", range))?
+                }
+                _ => {
+                    return Err(format!(
+                        "Unknown paranoid setting {:?}, expected allow-odd-pc or i=START-END",
+                        setting
+                    ))
+                }
+            }
+        }
+        Ok(policy)
+    }
+}
+
+/// Parse a range of hex addresses, like 200-FFF. They can be anywhere
+/// in XO-CHIP's 64 KB of memory.
+fn parse_range(s: &str) -> Option<RangeInclusive<u16>> {
+    let address = |s: &str| u16::from_str_radix(s.trim_start_matches("0x").trim_start_matches("0X"), 16).ok();
+    let (start, end) = s.split_once('-')?;
+    match (address(start)?, address(end)?) {
+        (start, end) if start <= end => Some(start..=end),
+        _ => None,
+    }
+}
+
+pub(crate) struct Paranoid {
+    policy: Policy,
+    /// Violations that have already been logged. A ROM stuck in a loop
+    /// would otherwise report the same problem hundreds of times a
+    /// second.
+    reported: HashSet<String>,
+}
+
+impl Paranoid {
+    pub(crate) fn new(policy: Policy) -> Paranoid {
+        Paranoid {
+            policy,
+            reported: HashSet::new(),
+        }
+    }
+
+    /// Check `vm` and log any violations that haven't been seen before.
+    pub(crate) fn check(&mut self, vm: &VM) {
+        for violation in check_invariants(vm, &self.policy) {
+            let message = format!("PC {:#05X}: {}", vm.pc, violation);
+            if self.reported.insert(message.clone()) {
+                log::warn!("paranoid: cycle {}: {}", vm.cycles, message);
+            }
+        }
+    }
+}
+
+/// Returns a description of every invariant `vm` currently violates.
+pub(crate) fn check_invariants(vm: &VM, policy: &Policy) -> Vec<String> {
+    let mut violations = Vec::new();
+
+    if policy.aligned_pc && !vm.pc.is_multiple_of(2) {
+        violations.push("PC is not aligned to an instruction boundary".to_string());
+    }
+    if vm.pc < PROGRAM_START {
+        violations.push("PC is in interpreter-reserved memory".to_string());
+    }
+    if vm.ir as usize >= vm.memory.len() {
+        violations.push(format!("I ({:#05X}) is outside of memory", vm.ir));
+    } else if !policy.i_range.contains(&vm.ir) {
+        violations.push(format!(
+            "I ({:#05X}) is outside of {:03X}-{:03X}",
+            vm.ir,
+            policy.i_range.start(),
+            policy.i_range.end()
+        ));
+    }
+
+    let pc = vm.pc as usize;
+    if pc + 1 >= vm.memory.len() {
+        violations.push("PC is outside of memory".to_string());
+        return violations;
+    }
+
+    let raw_instruction = (vm.memory[pc] as u16) << 8 | vm.memory[pc + 1] as u16;
+    match parse_instruction(raw_instruction) {
+        Err(err) => violations.push(err),
+        Ok(instruction) => {
+            if let Some(warning) = vf_warning(&instruction) {
+                violations.push(format!("{}: {}", instruction, warning));
+            }
+            if let Some(warning) = memory_warning(vm, &instruction) {
+                violations.push(format!("{}: {}", instruction, warning));
+            }
+        }
+    }
+
+    violations
+}
+
+/// VF doubles as the flag register, so using it as the destination of
+/// an instruction that also sets a flag throws away one of the results.
+fn vf_warning(instruction: &Instruction) -> Option<&'static str> {
+    match *instruction {
+        Instruction::SetVxPlusVy { x: 0xF, .. }
+        | Instruction::SetVxMinusVy { x: 0xF, .. }
+        | Instruction::SetVyMinusVx { x: 0xF, .. }
+        | Instruction::ShiftVxRight { x: 0xF, .. }
+        | Instruction::ShiftVxLeft { x: 0xF, .. } => Some("VF is the destination but is overwritten with a flag"),
+        Instruction::SetVxPlusVy { y: 0xF, .. }
+        | Instruction::SetVxMinusVy { y: 0xF, .. }
+        | Instruction::SetVyMinusVx { y: 0xF, .. } => Some("VF is used as an operand of a flag-setting instruction"),
+        _ => None,
+    }
+}
+
+/// Warn about instructions that would read or write past the end of
+/// memory starting at I.
+fn memory_warning(vm: &VM, instruction: &Instruction) -> Option<&'static str> {
+    // Sprites are drawn once for each selected plane
+    let planes = vm.display.planes().count_ones() as usize;
+    let len = match *instruction {
+        Instruction::Display { n: 0, .. } => 32 * planes,
+        Instruction::Display { n, .. } => n as usize * planes,
+        Instruction::StoreVxDigitsI { .. } => 3,
+        Instruction::StoreVxI { x } | Instruction::StoreIVx { x } => x + 1,
+        Instruction::LoadAudioPattern => AUDIO_PATTERN_BYTES,
+        _ => return None,
+    };
+    if vm.ir as usize + len > vm.memory.len() {
+        Some("accesses memory past the end of memory")
+    } else if vm.ir < PROGRAM_START
+        && matches!(
+            instruction,
+            Instruction::StoreVxDigitsI { .. } | Instruction::StoreVxI { .. }
+        )
+    {
+        Some("writes to interpreter-reserved memory")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+fn violations(vm: &VM) -> Vec<String> {
+    check_invariants(vm, &Policy::default())
+}
+
+#[cfg(test)]
+fn test_vm(program: &[u8]) -> VM {
+    let mut vm = VM::new(Box::new(chip8::random::SeededRandom::new(0)));
+    vm.memory[0x200..0x200 + program.len()].copy_from_slice(program);
+    vm
+}
+
+#[test]
+fn test_parse_policy() {
+    assert_eq!(Policy::parse(""), Ok(Policy::default()));
+    assert_eq!(
+        Policy::parse("allow-odd-pc, i=0x200-FFF"),
+        Ok(Policy {
+            aligned_pc: false,
+            i_range: 0x200..=0xFFF,
+        })
+    );
+    assert!(Policy::parse("i=300-200").is_err());
+    assert!(Policy::parse("i=200-10000").is_err());
+    assert!(Policy::parse("nope").is_err());
+}
+
+#[test]
+fn test_check_invariants_pc() {
+    // 6000: V0 := 0
+    let vm = test_vm(&[0x60, 0x00]);
+    assert_eq!(violations(&vm), Vec::<String>::new());
+
+    let mut vm = test_vm(&[0x00, 0x60, 0x00]);
+    vm.pc = 0x201;
+    assert_eq!(violations(&vm), ["PC is not aligned to an instruction boundary"]);
+    let policy = Policy::parse("allow-odd-pc").unwrap();
+    assert_eq!(check_invariants(&vm, &policy), Vec::<String>::new());
+
+    vm.pc = 0x100;
+    assert!(violations(&vm).contains(&"PC is in interpreter-reserved memory".to_string()));
+
+    vm.pc = 0xFFE;
+    vm.memory[0xFFE..].copy_from_slice(&[0x60, 0x00]);
+    assert_eq!(violations(&vm), Vec::<String>::new());
+    vm.pc = 0x1000;
+    assert!(violations(&vm).contains(&"PC is outside of memory".to_string()));
+
+    // XO-CHIP's memory goes on to FFFF
+    vm.set_memory_bytes(chip8::vm::XO_CHIP_MEMORY_BYTES);
+    vm.memory[0x1000..0x1002].copy_from_slice(&[0x60, 0x00]);
+    assert_eq!(violations(&vm), Vec::<String>::new());
+}
+
+#[test]
+fn test_check_invariants_i() {
+    let mut vm = test_vm(&[0x60, 0x00]);
+    vm.ir = 0x1000;
+    assert_eq!(violations(&vm), ["I (0x1000) is outside of memory"]);
+    let mut xo_chip = test_vm(&[0x60, 0x00]);
+    xo_chip.set_memory_bytes(chip8::vm::XO_CHIP_MEMORY_BYTES);
+    xo_chip.memory[0x200..0x202].copy_from_slice(&[0x60, 0x00]);
+    xo_chip.ir = 0x1000;
+    assert_eq!(violations(&xo_chip), Vec::<String>::new());
+
+    vm.ir = 0x050;
+    assert_eq!(violations(&vm), Vec::<String>::new());
+    let policy = Policy::parse("i=200-FFF").unwrap();
+    assert_eq!(check_invariants(&vm, &policy), ["I (0x050) is outside of 200-FFF"]);
+}
+
+#[test]
+fn test_check_invariants_instructions() {
+    // An opcode that doesn't decode
+    assert_eq!(violations(&test_vm(&[0xFF, 0xFF])).len(), 1);
+
+    // 8FE4: VF += VE, then VF = carry
+    let vm = test_vm(&[0x8F, 0xE4]);
+    assert_eq!(
+        violations(&vm),
+        ["ADD VF, VE: VF is the destination but is overwritten with a flag"]
+    );
+    // 81F5: V1 -= VF, then VF = not borrow
+    let vm = test_vm(&[0x81, 0xF5]);
+    assert_eq!(
+        violations(&vm),
+        ["SUB V1, VF: VF is used as an operand of a flag-setting instruction"]
+    );
+
+    // D015: a 5-byte sprite at FFE runs off the end of memory
+    let mut vm = test_vm(&[0xD0, 0x15]);
+    vm.ir = 0xFFE;
+    assert_eq!(
+        violations(&vm),
+        ["DRW V0, V1, 5: accesses memory past the end of memory"]
+    );
+    // F033: BCD into the font
+    let mut vm = test_vm(&[0xF0, 0x33]);
+    vm.ir = 0x050;
+    assert_eq!(violations(&vm), ["LD B, V0: writes to interpreter-reserved memory"]);
+}
+
+#[test]
+fn test_paranoid_reports_once() {
+    let mut paranoid = Paranoid::new(Policy::default());
+    let mut vm = test_vm(&[0x8F, 0xE4, 0x8F, 0xE4]);
+    paranoid.check(&vm);
+    paranoid.check(&vm);
+    assert_eq!(paranoid.reported.len(), 1);
+    // The same problem somewhere else is reported again
+    vm.pc = 0x202;
+    paranoid.check(&vm);
+    assert_eq!(paranoid.reported.len(), 2);
+}
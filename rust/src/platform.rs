@@ -0,0 +1,176 @@
+//! Named CHIP-8 platforms, so that users can pick the machine a ROM
+//! was written for instead of setting each quirk. The quirks follow the
+//! tables in Timendus' CHIP-8 test suite.
+
+use std::fmt;
+
+use crate::display::Display;
+use crate::instruction::InstructionSet;
+use crate::quirks::Quirks;
+use crate::vm::{FONT_BYTES, MEMORY_BYTES, VIP_FONT_BYTES};
+
+#[derive(Debug, PartialEq)]
+pub struct Platform {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub quirks: Quirks,
+    /// The instructions programs can use
+    pub instruction_set: InstructionSet,
+
+    /// Bytes of memory programs can address
+    pub memory_bytes: usize,
+    /// The largest display resolution, as (width, height)
+    pub display_size: (usize, usize),
+    /// The small font, for FX29
+    pub font: &'static [u8; 80],
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.description)
+    }
+}
+
+pub const PLATFORMS: &[Platform] = &[
+    Platform {
+        name: "vip",
+        description: "the original COSMAC VIP interpreter",
+        quirks: Quirks {
+            shift_uses_vy: true,
+            display_wait: true,
+            wrap_sprites: false,
+            increment_i: true,
+            index_overflow: false,
+            jump_uses_vx: false,
+            vf_reset: true,
+            half_scroll: false,
+        },
+        instruction_set: InstructionSet::Chip8,
+        memory_bytes: 4096,
+        display_size: (64, 32),
+        font: &VIP_FONT_BYTES,
+    },
+    Platform {
+        name: "chip48",
+        description: "CHIP-48 on the HP-48 calculators",
+        // CHIP-48 actually increments I by X rather than X + 1, which
+        // isn't emulated
+        quirks: Quirks {
+            shift_uses_vy: false,
+            display_wait: false,
+            wrap_sprites: false,
+            increment_i: false,
+            index_overflow: false,
+            jump_uses_vx: true,
+            vf_reset: false,
+            half_scroll: false,
+        },
+        instruction_set: InstructionSet::Chip8,
+        memory_bytes: 4096,
+        display_size: (64, 32),
+        font: &FONT_BYTES,
+    },
+    Platform {
+        name: "schip-legacy",
+        description: "SUPER-CHIP 1.1 on the HP-48 calculators",
+        quirks: Quirks {
+            shift_uses_vy: false,
+            display_wait: true,
+            wrap_sprites: false,
+            increment_i: false,
+            index_overflow: false,
+            jump_uses_vx: true,
+            vf_reset: false,
+            half_scroll: true,
+        },
+        instruction_set: InstructionSet::SuperChip,
+        memory_bytes: 4096,
+        display_size: (128, 64),
+        font: &FONT_BYTES,
+    },
+    Platform {
+        name: "schip-modern",
+        description: "SUPER-CHIP as modern interpreters run it",
+        quirks: Quirks {
+            shift_uses_vy: false,
+            display_wait: false,
+            wrap_sprites: false,
+            increment_i: false,
+            index_overflow: false,
+            jump_uses_vx: true,
+            vf_reset: false,
+            half_scroll: false,
+        },
+        instruction_set: InstructionSet::SuperChip,
+        memory_bytes: 4096,
+        display_size: (128, 64),
+        font: &FONT_BYTES,
+    },
+    Platform {
+        name: "xochip",
+        description: "XO-CHIP, as in Octo",
+        quirks: Quirks {
+            shift_uses_vy: true,
+            display_wait: false,
+            wrap_sprites: true,
+            increment_i: true,
+            index_overflow: false,
+            jump_uses_vx: false,
+            vf_reset: false,
+            half_scroll: false,
+        },
+        instruction_set: InstructionSet::XoChip,
+        memory_bytes: 65536,
+        display_size: (128, 64),
+        font: &FONT_BYTES,
+    },
+];
+
+/// Look up a platform by name.
+pub fn find(name: &str) -> Result<&'static Platform, String> {
+    PLATFORMS
+        .iter()
+        .find(|platform| platform.name == name)
+        .ok_or_else(|| format!("Unknown platform {:?}, expected one of: {}", name, names().join(", ")))
+}
+
+pub fn names() -> Vec<&'static str> {
+    PLATFORMS.iter().map(|platform| platform.name).collect()
+}
+
+impl Platform {
+    /// Features of this platform that the emulator doesn't support yet,
+    /// so programs that use them won't run correctly.
+    pub fn unsupported_features(&self) -> Vec<String> {
+        let mut features = Vec::new();
+        // Memory past the first 4 KB is there, but I can only be
+        // pointed at it by adding to it
+        if self.memory_bytes > MEMORY_BYTES {
+            features.push("F000 NNNN to point I anywhere in memory".to_string());
+        }
+        if Display::with_size(self.display_size.0, self.display_size.1).is_err() {
+            features.push(format!("a {}x{} display", self.display_size.0, self.display_size.1));
+        }
+        features
+    }
+}
+
+#[test]
+fn test_platforms() {
+    assert!(find("vip").unwrap().quirks.shift_uses_vy);
+    assert!(find("vip").unwrap().unsupported_features().is_empty());
+    // Modern SUPER-CHIP only differs from the default quirks in BXNN
+    assert_eq!(
+        find("schip-modern").unwrap().quirks,
+        Quirks {
+            jump_uses_vx: true,
+            ..Quirks::default()
+        }
+    );
+    assert_eq!(find("xochip").unwrap().unsupported_features().len(), 1);
+    assert!(find("schip-modern").unwrap().unsupported_features().is_empty());
+    assert!(find("nope").is_err());
+    assert!(find("schip-legacy").unwrap().instruction_set > InstructionSet::Chip8);
+    assert_eq!(find("vip").unwrap().font, &VIP_FONT_BYTES);
+    assert_eq!(find("chip48").unwrap().font, &FONT_BYTES);
+}
@@ -0,0 +1,279 @@
+//! `--profile`: count how many times each instruction runs, then report
+//! the hot spots and the parts of the ROM that never ran. It can also
+//! write the counts as folded stacks, one line per call chain, which
+//! flamegraph tools (e.g. inferno or flamegraph.pl) can draw:
+//!
+//! ```text
+//! 0x200;0x2A0 1520
+//! ```
+//!
+//! With `--remote`, the profiler also runs so clients can fetch its
+//! counts and recent frame timings while the ROM runs (see `Stats`).
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use serde::Serialize;
+
+use chip8::instruction::parse_instruction;
+use chip8::vm::{fetch, PROGRAM_START, VM};
+
+/// How many of the hottest addresses to report.
+const HOT_SPOTS: usize = 10;
+
+/// How many frames' timings to keep, two seconds' worth.
+const RECENT_FRAMES: usize = 120;
+
+pub(crate) struct Profiler {
+    /// Executions of the instruction at each address
+    counts: Vec<u64>,
+    /// Executions per call chain (the entry point, then each subroutine
+    /// called), if folded stacks are wanted
+    stacks: Option<HashMap<Vec<u16>, u64>>,
+    instructions: u64,
+    frames: u64,
+    /// When the last frame ended, and how many instructions had run then
+    last_frame: Option<(Instant, u64)>,
+    recent_frames: VecDeque<FrameTiming>,
+}
+
+/// How long a frame took from the end of the one before, in real time
+/// (including any time paused), and how many instructions it ran.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+pub(crate) struct FrameTiming {
+    pub(crate) ms: f64,
+    pub(crate) instructions: u64,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[cfg_attr(not(feature = "remote"), allow(dead_code))]
+pub(crate) struct HotSpot {
+    pub(crate) addr: u16,
+    pub(crate) count: u64,
+}
+
+/// What the profiler has counted so far, for clients of the remote API
+/// to graph.
+#[derive(Debug, PartialEq, Serialize)]
+#[cfg_attr(not(feature = "remote"), allow(dead_code))]
+pub(crate) struct Stats {
+    pub(crate) instructions: u64,
+    pub(crate) frames: u64,
+    /// Sprites drawn (DXYN)
+    pub(crate) draws: u64,
+    /// Executions by instruction type, as in the report
+    pub(crate) opcodes: BTreeMap<String, u64>,
+    /// The hottest instructions, hottest first
+    pub(crate) hot: Vec<HotSpot>,
+    /// The last frames, oldest first
+    pub(crate) recent_frames: Vec<FrameTiming>,
+}
+
+impl Profiler {
+    /// A profiler for a VM with `memory_bytes` of memory.
+    pub(crate) fn new(memory_bytes: usize, folded_stacks: bool) -> Profiler {
+        Profiler {
+            counts: vec![0; memory_bytes],
+            stacks: if folded_stacks { Some(HashMap::new()) } else { None },
+            instructions: 0,
+            frames: 0,
+            last_frame: None,
+            recent_frames: VecDeque::new(),
+        }
+    }
+
+    /// Count the instruction `vm` is about to execute.
+    pub(crate) fn record(&mut self, vm: &VM) {
+        self.counts[vm.pc as usize % vm.memory.len()] += 1;
+        self.instructions += 1;
+        if let Some(stacks) = &mut self.stacks {
+            let chain = std::iter::once(PROGRAM_START)
+                .chain(vm.stack.frames().iter().map(|frame| frame.subroutine))
+                .collect();
+            *stacks.entry(chain).or_insert(0) += 1;
+        }
+    }
+
+    /// Time the frame that just ended.
+    pub(crate) fn end_frame(&mut self) {
+        let now = Instant::now();
+        if let Some((then, instructions)) = self.last_frame {
+            if self.recent_frames.len() == RECENT_FRAMES {
+                self.recent_frames.pop_front();
+            }
+            self.recent_frames.push_back(FrameTiming {
+                ms: now.duration_since(then).as_secs_f64() * 1000.0,
+                instructions: self.instructions - instructions,
+            });
+        }
+        self.last_frame = Some((now, self.instructions));
+        self.frames += 1;
+    }
+
+    /// The counts so far, with instructions decoded from `memory` as in
+    /// `report`.
+    #[cfg_attr(not(feature = "remote"), allow(dead_code))]
+    pub(crate) fn stats(&self, memory: &[u8]) -> Stats {
+        let opcodes: BTreeMap<String, u64> = self.counts_by_type(memory).into_iter().collect();
+        Stats {
+            instructions: self.instructions,
+            frames: self.frames,
+            draws: opcodes.get("Display").copied().unwrap_or(0),
+            opcodes,
+            hot: self
+                .hottest()
+                .into_iter()
+                .take(HOT_SPOTS)
+                .map(|addr| HotSpot {
+                    addr: addr as u16,
+                    count: self.counts[addr],
+                })
+                .collect(),
+            recent_frames: self.recent_frames.iter().copied().collect(),
+        }
+    }
+
+    /// The addresses of the instructions that ran, hottest first.
+    fn hottest(&self) -> Vec<usize> {
+        let mut hot: Vec<usize> = (0..self.counts.len()).filter(|&addr| self.counts[addr] > 0).collect();
+        hot.sort_by_key(|&addr| std::cmp::Reverse(self.counts[addr]));
+        hot
+    }
+
+    /// Executions by instruction type, most first. Types are the
+    /// instructions' variant names, which tell apart the many kinds of
+    /// LD.
+    fn counts_by_type(&self, memory: &[u8]) -> Vec<(String, u64)> {
+        let mut by_type: BTreeMap<String, u64> = BTreeMap::new();
+        for addr in (0..self.counts.len()).filter(|&addr| self.counts[addr] > 0) {
+            let name = match parse_instruction(fetch(memory, addr as u16)) {
+                Ok(instruction) => format!("{:?}", instruction),
+                Err(err) => err,
+            };
+            let name = name.split([' ', '{']).next().unwrap_or_default();
+            *by_type.entry(name.to_string()).or_insert(0) += self.counts[addr];
+        }
+        let mut by_type: Vec<(String, u64)> = by_type.into_iter().collect();
+        by_type.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+        by_type
+    }
+
+    /// A report of the hottest instructions, the instruction types that
+    /// ran, and the bytes of a `rom_len`-byte ROM that were never
+    /// executed (which includes any data). Instructions are decoded
+    /// from `memory` as it is now, so code the program modified shows
+    /// its latest version.
+    pub(crate) fn report(&self, memory: &[u8], rom_len: usize) -> String {
+        let total: u64 = self.counts.iter().sum();
+        let percent = |count: u64| count as f64 * 100.0 / total.max(1) as f64;
+        let describe = |addr: usize| match parse_instruction(fetch(memory, addr as u16)) {
+            Ok(instruction) => instruction.to_string(),
+            Err(err) => err,
+        };
+        let mut report = format!("Profile of {} instructions\n", total);
+
+        report += "\nHottest instructions:\n";
+        for addr in self.hottest().into_iter().take(HOT_SPOTS) {
+            let count = self.counts[addr];
+            report += &format!(
+                "  {:#05X}  {:>10}  {:5.1}%  {}\n",
+                addr,
+                count,
+                percent(count),
+                describe(addr)
+            );
+        }
+
+        report += "\nInstructions by type:\n";
+        for (name, count) in self.counts_by_type(memory) {
+            report += &format!("  {:<20}  {:>10}  {:5.1}%\n", name, count, percent(count));
+        }
+
+        let unexecuted = self.unexecuted(rom_len);
+        if unexecuted.is_empty() {
+            report += "\nEvery byte of the ROM was executed\n";
+        } else {
+            report += "\nNever executed (code or data):\n";
+            for (start, end) in unexecuted {
+                report += &format!("  {:#05X}-{:#05X}  {} bytes\n", start, end, end - start + 1);
+            }
+        }
+        report
+    }
+
+    /// Inclusive ranges of ROM addresses that weren't part of any
+    /// executed instruction.
+    fn unexecuted(&self, rom_len: usize) -> Vec<(usize, usize)> {
+        let start = PROGRAM_START as usize;
+        let end = (start + rom_len).min(self.counts.len());
+        let executed = |addr: usize| self.counts[addr] > 0 || (addr > 0 && self.counts[addr - 1] > 0);
+
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+        for addr in (start..end).filter(|&addr| !executed(addr)) {
+            match ranges.last_mut() {
+                Some((_, last)) if *last + 1 == addr => *last = addr,
+                _ => ranges.push((addr, addr)),
+            }
+        }
+        ranges
+    }
+
+    /// The folded stacks, sorted so the output is stable.
+    fn folded_stacks(&self) -> String {
+        let mut lines: Vec<String> = self
+            .stacks
+            .iter()
+            .flatten()
+            .map(|(chain, count)| {
+                let chain: Vec<String> = chain.iter().map(|addr| format!("{:#05X}", addr)).collect();
+                format!("{} {}\n", chain.join(";"), count)
+            })
+            .collect();
+        lines.sort();
+        lines.concat()
+    }
+
+    pub(crate) fn write_folded_stacks(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, self.folded_stacks()).map_err(|err| format!("Error writing {}: {}", path.display(), err))
+    }
+}
+
+#[test]
+fn test_profiler() {
+    let mut vm = VM::new(Box::new(chip8::random::SeededRandom::new(0)));
+    chip8::vm::load_rom(
+        &mut vm.memory,
+        &[
+            0x22, 0x06, // 200: call 206
+            0x12, 0x02, // 202: loop forever
+            0x00, 0x00, // 204: never executed
+            0x60, 0x01, // 206: V0 = 1
+            0x00, 0xEE, // 208: return
+        ],
+    )
+    .unwrap();
+    let mut profiler = Profiler::new(vm.memory.len(), true);
+    for _ in 0..7 {
+        profiler.record(&vm);
+        chip8::vm::processor_cycle(&mut vm).unwrap();
+    }
+
+    let report = profiler.report(&vm.memory, 10);
+    assert!(report.starts_with("Profile of 7 instructions"));
+    assert!(report.contains("0x202           4   57.1%  JP 0x202"), "{}", report);
+    assert!(report.contains("Jump                           4"), "{}", report);
+    assert_eq!(profiler.unexecuted(10), vec![(0x204, 0x205)]);
+    assert_eq!(profiler.folded_stacks(), "0x200 5\n0x200;0x206 2\n");
+
+    profiler.end_frame();
+    profiler.record(&vm);
+    profiler.end_frame();
+    let stats = profiler.stats(&vm.memory);
+    assert_eq!((stats.instructions, stats.frames, stats.draws), (8, 2, 0));
+    assert_eq!(stats.opcodes["Jump"], 5);
+    assert_eq!(stats.hot[0], HotSpot { addr: 0x202, count: 5 });
+    assert_eq!(stats.recent_frames.len(), 1);
+    assert_eq!(stats.recent_frames[0].instructions, 1);
+}
@@ -0,0 +1,262 @@
+//! Homebrew project support for `chip8 new`, `chip8 build` and
+//! `chip8 run`. A project is a directory laid out like:
+//!
+//! ```text
+//! mygame/
+//!   chip8.toml      manifest: ROM name and emulator settings
+//!   src/main.asm    program entry point, assembled first
+//!   sprites/*.asm   sprite data, assembled after main.asm in name order
+//!   test.sh         builds the ROM and runs it with --paranoid
+//!   build/          output ROM (mygame.ch8) and its symbols (mygame.sym)
+//! ```
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chip8::vm::{check_rom_size, XO_CHIP_MEMORY_BYTES};
+
+use crate::assembler::{assemble_files, Assembled};
+use crate::octo::assemble_octo;
+use crate::symbols::Symbols;
+
+pub(crate) const MANIFEST_FILE: &str = "chip8.toml";
+
+const SOURCE_DIR: &str = "src";
+const MAIN_SOURCE: &str = "src/main.asm";
+/// Used instead of `MAIN_SOURCE` for projects written in Octo (see
+/// `octo.rs`), along with `sprites/*.8o`
+const OCTO_MAIN_SOURCE: &str = "src/main.8o";
+const OCTO_EXTENSION: &str = "8o";
+const SPRITES_DIR: &str = "sprites";
+const BUILD_DIR: &str = "build";
+
+/// Project settings read from `chip8.toml`. Only a small subset of TOML
+/// is understood: `key = value` lines where the value is a quoted
+/// string or an integer.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Manifest {
+    pub(crate) name: String,
+    pub(crate) timer_hz: Option<u32>,
+    pub(crate) seed: Option<u64>,
+}
+
+impl Manifest {
+    pub(crate) fn load(project_dir: &Path) -> Result<Manifest, String> {
+        let path = project_dir.join(MANIFEST_FILE);
+        let contents = fs::read_to_string(&path).map_err(|err| format!("Error reading {}: {}", path.display(), err))?;
+        Manifest::parse(&contents).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    pub(crate) fn parse(contents: &str) -> Result<Manifest, String> {
+        let mut name = None;
+        let mut timer_hz = None;
+        let mut seed = None;
+
+        for (num, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || format!("line {}: invalid setting {:?}", num + 1, line);
+            let (key, value) = line.split_once('=').ok_or_else(invalid)?;
+            let value = value.trim();
+            match key.trim() {
+                "name" => {
+                    let unquoted = value.strip_prefix('"').and_then(|value| value.strip_suffix('"'));
+                    name = Some(unquoted.ok_or_else(invalid)?.to_string());
+                }
+                "timer_hz" => timer_hz = Some(value.parse().map_err(|_| invalid())?),
+                "seed" => seed = Some(value.parse().map_err(|_| invalid())?),
+                key => return Err(format!("line {}: unknown setting {:?}", num + 1, key)),
+            }
+        }
+
+        Ok(Manifest {
+            name: name.ok_or("missing name setting")?,
+            timer_hz,
+            seed,
+        })
+    }
+}
+
+/// Create a new project directory named `name` with a small program
+/// that draws a sprite.
+pub(crate) fn new_project(name: &str) -> Result<(), String> {
+    let dir = Path::new(name);
+    if dir.exists() {
+        return Err(format!("{} already exists", dir.display()));
+    }
+    let rom_name = dir
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format!("Invalid project name {:?}", name))?;
+
+    let files = [
+        (
+            MANIFEST_FILE,
+            format!("name = \"{}\"\ntimer_hz = 60\n", rom_name),
+        ),
+        (
+            MAIN_SOURCE,
+            "; Entry point. Sprites are defined in the sprites/ directory.\n\
+             start:\n    CLS\n    LD V0, 28\n    LD V1, 13\n    LD I, logo\n    DRW V0, V1, 5\n\
+             loop:\n    JP loop\n"
+                .to_string(),
+        ),
+        (
+            "sprites/logo.asm",
+            "logo:\n    DB 0b11110000\n    DB 0b10010000\n    DB 0b11110000\n    DB 0b10010000\n    DB 0b11110000\n"
+                .to_string(),
+        ),
+        (
+            "test.sh",
+            "#!/bin/sh\n# Build the ROM and run it, logging anything suspicious.\nset -e\ncd \"$(dirname \"$0\")\"\nchip8 run --paranoid \"$@\"\n"
+                .to_string(),
+        ),
+        (".gitignore", format!("/{}/\n", BUILD_DIR)),
+    ];
+
+    for (file, contents) in files.iter() {
+        let path = dir.join(file);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|err| format!("Error creating {}: {}", parent.display(), err))?;
+        }
+        fs::write(&path, contents).map_err(|err| format!("Error writing {}: {}", path.display(), err))?;
+    }
+    make_executable(&dir.join("test.sh"))?;
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+        .map_err(|err| format!("Error setting permissions on {}: {}", path.display(), err))
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> Result<(), String> {
+    Ok(())
+}
+
+/// Assemble the project in `project_dir` and write the ROM into the
+/// build directory. Returns the path of the ROM.
+pub(crate) fn build(project_dir: &Path, manifest: &Manifest) -> Result<PathBuf, String> {
+    let assembled = assemble_paths(&source_paths(project_dir)?)?;
+    let build_dir = project_dir.join(BUILD_DIR);
+    fs::create_dir_all(&build_dir).map_err(|err| format!("Error creating {}: {}", build_dir.display(), err))?;
+    let rom_path = build_dir.join(format!("{}.ch8", manifest.name));
+    write_rom(&rom_path, assembled)?;
+
+    Ok(rom_path)
+}
+
+/// What `chip8 run --watch` watches in a project: its sources, and the
+/// directories they're in, so added and removed sprite files count too.
+pub(crate) fn watch_paths(project_dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut paths = vec![project_dir.join(SOURCE_DIR), project_dir.join(SPRITES_DIR)];
+    paths.extend(source_paths(project_dir)?);
+    Ok(paths)
+}
+
+/// The project's source files, in the order they're assembled.
+fn source_paths(project_dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let octo_main = project_dir.join(OCTO_MAIN_SOURCE);
+    let (main_source, extension) = if octo_main.exists() {
+        (octo_main, OCTO_EXTENSION)
+    } else {
+        (project_dir.join(MAIN_SOURCE), "asm")
+    };
+    let mut source_paths = vec![main_source];
+
+    let sprites_dir = project_dir.join(SPRITES_DIR);
+    if sprites_dir.is_dir() {
+        let entries =
+            fs::read_dir(&sprites_dir).map_err(|err| format!("Error reading {}: {}", sprites_dir.display(), err))?;
+        let mut sprite_paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == extension))
+            .collect();
+        sprite_paths.sort();
+        source_paths.extend(sprite_paths);
+    }
+    Ok(source_paths)
+}
+
+/// Assemble source files into one program, in order. They're Octo if
+/// the first one has the `.8o` extension, and otherwise use the classic
+/// mnemonics.
+pub(crate) fn assemble_paths(source_paths: &[PathBuf]) -> Result<Assembled, String> {
+    let mut sources = Vec::new();
+    for path in source_paths {
+        let contents = fs::read_to_string(path).map_err(|err| format!("Error reading {}: {}", path.display(), err))?;
+        sources.push((path.display().to_string(), contents));
+    }
+    let sources: Vec<(Option<&str>, &str)> = sources
+        .iter()
+        .map(|(name, contents)| (Some(name.as_str()), contents.as_str()))
+        .collect();
+    let is_octo = source_paths
+        .first()
+        .and_then(|path| path.extension())
+        .is_some_and(|ext| ext == OCTO_EXTENSION);
+    if is_octo {
+        assemble_octo(&sources)
+    } else {
+        assemble_files(&sources)
+    }
+}
+
+/// Write an assembled ROM to `rom_path`, and its symbols next to it,
+/// unless it's too large to load.
+pub(crate) fn write_rom(rom_path: &Path, assembled: Assembled) -> Result<(), String> {
+    check_rom_size(&assembled.rom, XO_CHIP_MEMORY_BYTES).map_err(|err| format!("{}: {}", rom_path.display(), err))?;
+    fs::write(rom_path, assembled.rom).map_err(|err| format!("Error writing {}: {}", rom_path.display(), err))?;
+    let symbols_path = Symbols::path_for_rom(rom_path);
+    fs::write(&symbols_path, assembled.symbols.to_file())
+        .map_err(|err| format!("Error writing {}: {}", symbols_path.display(), err))
+}
+
+#[test]
+fn test_parse_manifest() {
+    assert_eq!(
+        Manifest::parse("# My game\nname = \"pong\"\ntimer_hz = 50 # PAL\n"),
+        Ok(Manifest {
+            name: "pong".to_string(),
+            timer_hz: Some(50),
+            seed: None,
+        })
+    );
+    assert!(Manifest::parse("timer_hz = 60\n").is_err());
+    assert!(Manifest::parse("name = pong\n").is_err());
+    assert!(Manifest::parse("name = \"pong\"\ncolor = 1\n").is_err());
+}
+
+#[test]
+fn test_watch_paths() {
+    let dir = std::env::temp_dir().join(format!("chip8-project-test-{}", std::process::id()));
+    new_project(dir.to_str().unwrap()).unwrap();
+    assert_eq!(
+        watch_paths(&dir).unwrap(),
+        [
+            dir.join("src"),
+            dir.join("sprites"),
+            dir.join("src/main.asm"),
+            dir.join("sprites/logo.asm")
+        ]
+    );
+    let manifest = Manifest::load(&dir).unwrap();
+    let rom_path = build(&dir, &manifest).unwrap();
+    assert_eq!(rom_path, dir.join("build").join(format!("{}.ch8", manifest.name)));
+
+    // ROMs too large to load aren't written
+    let too_large = Assembled {
+        rom: vec![0; 0x10000],
+        symbols: Symbols::default(),
+    };
+    let big_path = dir.join("build/big.ch8");
+    assert!(write_rom(&big_path, too_large).is_err());
+    assert!(!big_path.exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
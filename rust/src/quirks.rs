@@ -0,0 +1,156 @@
+//! Behaviors that differ between CHIP-8 interpreters. ROMs written for
+//! one interpreter can misbehave on another, so each difference can be
+//! switched on when running a ROM that depends on it.
+
+use crate::instruction::Instruction;
+use crate::platform;
+
+/// The quirks that are enabled. The default matches most modern
+/// interpreters.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct Quirks {
+    /// 8XY6 and 8XYE shift VY into VX, like the original COSMAC VIP
+    /// interpreter, instead of shifting VX in place
+    pub shift_uses_vy: bool,
+
+    /// DXYN waits for the next frame before continuing, like the
+    /// COSMAC VIP waiting for vertical blank. This limits games to one
+    /// sprite per frame.
+    pub display_wait: bool,
+
+    /// Sprites that cross the edge of the screen wrap around to the
+    /// other side instead of being clipped
+    pub wrap_sprites: bool,
+
+    /// FX55 and FX65 leave I pointing just past the registers they
+    /// stored or loaded, like the COSMAC VIP, instead of unchanged
+    pub increment_i: bool,
+
+    /// FX1E sets VF to 1 when I goes past 0xFFF and to 0 otherwise,
+    /// like the Amiga interpreter that Spaceflight 2091! relies on,
+    /// instead of leaving VF alone. None of the platforms do this.
+    pub index_overflow: bool,
+
+    /// BNNN jumps to NNN + VX, where X is the top nibble of NNN, like
+    /// CHIP-48 and SUPER-CHIP ("BXNN"), instead of to NNN + V0
+    pub jump_uses_vx: bool,
+
+    /// 8XY1, 8XY2 and 8XY3 set VF to 0 afterwards, like the COSMAC VIP,
+    /// instead of leaving it alone
+    pub vf_reset: bool,
+
+    /// Scrolling in lo-res moves half as far, like SUPER-CHIP 1.1,
+    /// which scrolled by hi-res pixels in either mode
+    pub half_scroll: bool,
+}
+
+/// Every quirk name accepted by `Quirks::set`.
+pub const QUIRK_NAMES: &[&str] = &[
+    "shift-uses-vy",
+    "display-wait",
+    "wrap-sprites",
+    "increment-i",
+    "index-overflow",
+    "jump-uses-vx",
+    "vf-reset",
+    "half-scroll",
+];
+
+impl Quirks {
+    /// Enable or disable the quirk called `name`.
+    pub fn set(&mut self, name: &str, enabled: bool) -> Result<(), String> {
+        match name {
+            "shift-uses-vy" => self.shift_uses_vy = enabled,
+            "display-wait" => self.display_wait = enabled,
+            "wrap-sprites" => self.wrap_sprites = enabled,
+            "increment-i" => self.increment_i = enabled,
+            "index-overflow" => self.index_overflow = enabled,
+            "jump-uses-vx" => self.jump_uses_vx = enabled,
+            "vf-reset" => self.vf_reset = enabled,
+            "half-scroll" => self.half_scroll = enabled,
+            _ => {
+                return Err(format!(
+                    "Unknown quirk {:?}, expected one of: {}",
+                    name,
+                    QUIRK_NAMES.join(", ")
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the quirk called `name` is enabled, or `None` if there's
+    /// no such quirk.
+    pub fn get(&self, name: &str) -> Option<bool> {
+        match name {
+            "shift-uses-vy" => Some(self.shift_uses_vy),
+            "display-wait" => Some(self.display_wait),
+            "wrap-sprites" => Some(self.wrap_sprites),
+            "increment-i" => Some(self.increment_i),
+            "index-overflow" => Some(self.index_overflow),
+            "jump-uses-vx" => Some(self.jump_uses_vx),
+            "vf-reset" => Some(self.vf_reset),
+            "half-scroll" => Some(self.half_scroll),
+            _ => None,
+        }
+    }
+
+    /// The names of the quirks that change what `instruction` does.
+    pub fn affecting(instruction: &Instruction) -> &'static [&'static str] {
+        match instruction {
+            Instruction::ShiftVxRight { .. } | Instruction::ShiftVxLeft { .. } => &["shift-uses-vy"],
+            Instruction::Display { .. } => &["display-wait", "wrap-sprites"],
+            Instruction::StoreVxI { .. } | Instruction::StoreIVx { .. } => &["increment-i"],
+            Instruction::AddVxI { .. } => &["index-overflow"],
+            Instruction::JumpV0Nnn { .. } => &["jump-uses-vx"],
+            Instruction::SetVxOrVy { .. } | Instruction::SetVxAndVy { .. } | Instruction::SetVxXorVy { .. } => {
+                &["vf-reset"]
+            }
+            Instruction::ScrollDown { .. }
+            | Instruction::ScrollUp { .. }
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft => &["half-scroll"],
+            _ => &[],
+        }
+    }
+
+    /// Parse a comma-separated list of quirks to enable, like
+    /// `shift-uses-vy`. A platform name (see `platform::PLATFORMS`)
+    /// replaces the quirks before it with the platform's, so
+    /// `vip,wrap-sprites` adds a quirk to a platform's.
+    pub fn parse(s: &str) -> Result<Quirks, String> {
+        let mut quirks = Quirks::default();
+        for name in s.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            match platform::find(name) {
+                Ok(platform) => quirks = platform.quirks,
+                Err(_) => quirks
+                    .set(name, true)
+                    .map_err(|err| format!("{}, or a platform: {}", err, platform::names().join(", ")))?,
+            }
+        }
+        Ok(quirks)
+    }
+}
+
+#[test]
+fn test_parse_quirks() {
+    assert_eq!(Quirks::parse(""), Ok(Quirks::default()));
+    assert_eq!(
+        Quirks::parse("shift-uses-vy, display-wait"),
+        Ok(Quirks {
+            shift_uses_vy: true,
+            display_wait: true,
+            ..Quirks::default()
+        })
+    );
+    assert!(Quirks::parse("shift-uses-vy,nope").is_err());
+
+    let vip = Quirks::parse("vip,wrap-sprites").unwrap();
+    assert!(vip.shift_uses_vy && vip.wrap_sprites);
+    assert_eq!(vip.get("wrap-sprites"), Some(true));
+    assert_eq!(vip.get("nope"), None);
+    assert_eq!(
+        Quirks::parse("shift-uses-vy,schip-modern"),
+        Ok(platform::find("schip-modern").unwrap().quirks)
+    );
+}
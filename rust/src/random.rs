@@ -0,0 +1,82 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Source of random bytes for the CXNN instruction. This is a trait so
+/// tests and replays can inject a deterministic sequence.
+pub trait RandomSource {
+    fn next_u8(&mut self) -> u8;
+
+    /// A copy that gives out the same bytes from here on, so a saved
+    /// state draws the same numbers again when it's restored.
+    fn clone_box(&self) -> Box<dyn RandomSource>;
+}
+
+impl Clone for Box<dyn RandomSource> {
+    fn clone(&self) -> Box<dyn RandomSource> {
+        self.clone_box()
+    }
+}
+
+/// Pseudo-random bytes from a seeded RNG. The same seed always
+/// produces the same sequence.
+#[derive(Clone)]
+pub struct SeededRandom {
+    rng: StdRng,
+}
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> SeededRandom {
+        SeededRandom {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn next_u8(&mut self) -> u8 {
+        self.rng.gen()
+    }
+
+    fn clone_box(&self) -> Box<dyn RandomSource> {
+        Box::new(self.clone())
+    }
+}
+
+/// Repeats a fixed sequence of bytes forever. Used to inject known
+/// "random" values in tests.
+#[cfg(test)]
+#[derive(Clone)]
+pub struct SequenceRandom {
+    bytes: Vec<u8>,
+    next: usize,
+}
+
+#[cfg(test)]
+impl SequenceRandom {
+    pub fn new(bytes: Vec<u8>) -> SequenceRandom {
+        assert!(!bytes.is_empty(), "random sequence must not be empty");
+        SequenceRandom { bytes, next: 0 }
+    }
+}
+
+#[cfg(test)]
+impl RandomSource for SequenceRandom {
+    fn next_u8(&mut self) -> u8 {
+        let byte = self.bytes[self.next];
+        self.next = (self.next + 1) % self.bytes.len();
+        byte
+    }
+
+    fn clone_box(&self) -> Box<dyn RandomSource> {
+        Box::new(self.clone())
+    }
+}
+
+#[test]
+fn test_seeded_random_is_deterministic() {
+    let mut a = SeededRandom::new(1234);
+    let mut b = SeededRandom::new(1234);
+    for _ in 0..100 {
+        assert_eq!(a.next_u8(), b.next_u8());
+    }
+}
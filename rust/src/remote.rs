@@ -0,0 +1,399 @@
+//! A WebSocket server for driving the emulator from other programs, such
+//! as dashboards, web debuggers or chat bots, started with `--remote`.
+//! Clients send JSON requests as text messages:
+//!
+//! ```text
+//! {"cmd": "state"}                        registers, timers and stack
+//! {"cmd": "pause"}, {"cmd": "resume"}
+//! {"cmd": "step", "count": 10}            run instructions while paused
+//! {"cmd": "key", "key": 5, "pressed": true}
+//! {"cmd": "peek", "addr": 768, "len": 16} read memory
+//! {"cmd": "frames", "enabled": true}      stream the display
+//! {"cmd": "stats"}                        profiler counts and timings
+//! ```
+//!
+//! Every request but `frames` gets one reply: `{"type": "state", ...}`
+//! for `state`, `pause`, `resume`, `step` and `key`, `{"type":
+//! "memory", ...}` for `peek`, `{"type": "stats", ...}` for `stats`, or
+//! `{"type": "error", "message": ...}`. Stats are the instructions and
+//! frames run, sprites drawn, counts by instruction type, the hottest
+//! addresses and the last two seconds of frame timings (see
+//! src/profile.rs), for dashboards to poll.
+//! Clients streaming the display get a `{"type": "frame", ...}` message
+//! whenever it changes, with a string of `#` and `.` per row.
+//!
+//! Connections are served on their own threads, like the debugger's
+//! command reader, and requests are handled between frames. So that one
+//! request can't hold up the emulator for long, `step` runs at most a
+//! second's worth of instructions at the emulator's speed.
+//!
+//! While recording or replaying, `pause`, `resume`, `step` and `key` are
+//! refused: the replay file only has the keypad, so anything else would
+//! make the run diverge from it. Netplay can't be used with remote
+//! control at all.
+
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tungstenite::{Error, Message};
+
+use chip8::display::Display;
+use chip8::vm::VM;
+
+use crate::profile::{Profiler, Stats};
+
+/// How long connection threads wait for a request before checking for
+/// replies to send.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+enum Request {
+    State,
+    Pause,
+    Resume,
+    Step {
+        #[serde(default = "one")]
+        count: u32,
+    },
+    Key {
+        key: usize,
+        pressed: bool,
+    },
+    Peek {
+        addr: usize,
+        len: usize,
+    },
+    Frames {
+        enabled: bool,
+    },
+    Stats,
+}
+
+fn one() -> u32 {
+    1
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Response {
+    State {
+        pc: u16,
+        i: u16,
+        v: [u8; 16],
+        delay_timer: u8,
+        sound_timer: u8,
+        /// Return addresses, innermost call last
+        stack: Vec<u16>,
+        cycles: u64,
+        paused: bool,
+    },
+    Memory {
+        addr: usize,
+        bytes: Vec<u8>,
+    },
+    Frame {
+        width: usize,
+        height: usize,
+        rows: Vec<String>,
+    },
+    Stats(Stats),
+    Error {
+        message: String,
+    },
+}
+
+impl Response {
+    fn state(vm: &VM, paused: bool) -> Response {
+        Response::State {
+            pc: vm.pc,
+            i: vm.ir,
+            v: vm.v,
+            delay_timer: vm.delay_timer,
+            sound_timer: vm.sound_timer,
+            stack: vm.stack.frames().iter().map(|frame| frame.return_address()).collect(),
+            cycles: vm.cycles,
+            paused,
+        }
+    }
+
+    fn frame(display: &Display) -> Response {
+        Response::Frame {
+            width: display.width(),
+            height: display.height(),
+            rows: display.to_ascii().lines().map(str::to_string).collect(),
+        }
+    }
+
+    fn error(message: String) -> Response {
+        Response::Error { message }
+    }
+
+    fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("responses can always be serialized")
+    }
+}
+
+/// A request from a client, with where to send the reply.
+struct Envelope {
+    /// Which connection it came from
+    client: u64,
+    request: Request,
+    reply: Sender<String>,
+}
+
+pub(crate) struct Remote {
+    requests: Receiver<Envelope>,
+    addr: SocketAddr,
+    /// The most instructions one `step` can run
+    max_step: u64,
+    /// Clients streaming the display, by connection
+    frame_subscribers: Vec<(u64, Sender<String>)>,
+    /// The hash of the last display sent to them
+    last_frame: Option<u64>,
+}
+
+impl Remote {
+    /// Start listening on `addr`, e.g. `127.0.0.1:8080`. A bare `:8080`
+    /// only accepts connections from this machine. `speed` is how many
+    /// instructions the emulator runs a second.
+    pub(crate) fn start(addr: &str, speed: u64) -> Result<Remote, String> {
+        let addr = match addr.strip_prefix(':') {
+            Some(port) => format!("127.0.0.1:{}", port),
+            None => addr.to_string(),
+        };
+        let listener = TcpListener::bind(&addr).map_err(|err| format!("Error listening on {}: {}", addr, err))?;
+        let addr = listener.local_addr().map_err(|err| err.to_string())?;
+
+        let (sender, requests) = channel();
+        std::thread::spawn(move || {
+            for (client, stream) in listener.incoming().map_while(Result::ok).enumerate() {
+                let sender = sender.clone();
+                std::thread::spawn(move || {
+                    if let Err(err) = serve(client as u64, stream, sender) {
+                        log::warn!("Remote control: {}", err);
+                    }
+                });
+            }
+        });
+
+        Ok(Remote {
+            requests,
+            addr,
+            max_step: speed,
+            frame_subscribers: Vec::new(),
+            last_frame: None,
+        })
+    }
+
+    pub(crate) fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Handle the requests that have arrived since the last call.
+    /// `paused` is whether the user paused the emulator, which clients
+    /// can change too. Instructions run by `step` aren't traced. Stats
+    /// come from `profiler`. `locked` says why clients can't control the
+    /// machine, e.g. "recording", if they can't.
+    pub(crate) fn poll(&mut self, vm: &mut VM, paused: &mut bool, profiler: &Profiler, locked: Option<&str>) {
+        while let Ok(Envelope { client, request, reply }) = self.requests.try_recv() {
+            let response = match request {
+                Request::State => Response::state(vm, *paused),
+                Request::Pause | Request::Resume | Request::Step { .. } | Request::Key { .. } if locked.is_some() => {
+                    Response::error(format!(
+                        "Can't control the emulator while {}",
+                        locked.unwrap_or_default()
+                    ))
+                }
+                Request::Pause | Request::Resume => {
+                    *paused = request == Request::Pause;
+                    Response::state(vm, *paused)
+                }
+                Request::Step { .. } if !*paused => Response::error("Pause before stepping".to_string()),
+                Request::Step { count } if count as u64 > self.max_step => {
+                    Response::error(format!("Can't step more than {} instructions at once", self.max_step))
+                }
+                Request::Step { count } => match (0..count).try_for_each(|_| step(vm)) {
+                    Ok(()) => Response::state(vm, *paused),
+                    Err(err) => Response::error(err),
+                },
+                Request::Key { key, pressed } => match vm.keys_pressed.get_mut(key) {
+                    Some(state) => {
+                        *state = pressed;
+                        Response::state(vm, *paused)
+                    }
+                    None => Response::error(format!("{} isn't a key", key)),
+                },
+                Request::Peek { addr, len } if addr.checked_add(len).is_some_and(|end| end <= vm.memory.len()) => {
+                    Response::Memory {
+                        addr,
+                        bytes: vm.memory[addr..addr + len].to_vec(),
+                    }
+                }
+                Request::Peek { .. } => Response::error("Can't read outside of memory".to_string()),
+                Request::Stats => Response::Stats(profiler.stats(&vm.memory)),
+                Request::Frames { enabled } => {
+                    if enabled {
+                        self.frame_subscribers.push((client, reply));
+                        // Send the current display to the new subscriber
+                        self.last_frame = None;
+                    } else {
+                        self.frame_subscribers.retain(|&(subscriber, _)| subscriber != client);
+                    }
+                    continue;
+                }
+            };
+            let _ = reply.send(response.to_json());
+        }
+    }
+
+    /// Send the display to streaming clients if it's changed.
+    pub(crate) fn send_frame(&mut self, display: &Display) {
+        if self.frame_subscribers.is_empty() {
+            return;
+        }
+        let hash = display.hash();
+        if self.last_frame == Some(hash) {
+            return;
+        }
+        self.last_frame = Some(hash);
+        let message = Response::frame(display).to_json();
+        // Clients that have gone away are dropped
+        self.frame_subscribers
+            .retain(|(_, subscriber)| subscriber.send(message.clone()).is_ok());
+    }
+}
+
+fn step(vm: &mut VM) -> Result<(), String> {
+    chip8::vm::processor_cycle(vm)?;
+    vm.cycles += 1;
+    Ok(())
+}
+
+/// Pass a client's requests on to the emulator and send back the
+/// replies, until either side goes away.
+fn serve(client: u64, stream: TcpStream, requests: Sender<Envelope>) -> Result<(), String> {
+    let peer = stream.peer_addr().map_err(|err| err.to_string())?;
+    let mut socket = tungstenite::accept(stream).map_err(|err| format!("{}: {}", peer, err))?;
+    socket
+        .get_ref()
+        .set_read_timeout(Some(POLL_INTERVAL))
+        .map_err(|err| err.to_string())?;
+    let (reply, replies) = channel();
+    loop {
+        for message in replies.try_iter() {
+            socket
+                .send(Message::Text(message))
+                .map_err(|err| format!("{}: {}", peer, err))?;
+        }
+        let request = match socket.read() {
+            Ok(Message::Text(text)) => serde_json::from_str(&text),
+            Ok(_) => continue,
+            Err(Error::Io(err)) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(Error::ConnectionClosed) | Err(Error::AlreadyClosed) => return Ok(()),
+            Err(err) => return Err(format!("{}: {}", peer, err)),
+        };
+        match request {
+            Ok(request) => {
+                let envelope = Envelope {
+                    client,
+                    request,
+                    reply: reply.clone(),
+                };
+                if requests.send(envelope).is_err() {
+                    // The emulator has exited
+                    return Ok(());
+                }
+            }
+            Err(err) => {
+                let response = Response::error(format!("Invalid request: {}", err));
+                socket
+                    .send(Message::Text(response.to_json()))
+                    .map_err(|err| format!("{}: {}", peer, err))?;
+            }
+        }
+    }
+}
+
+/// Send `requests` to a remote for a VM running a small program,
+/// returning the replies and the VM afterwards.
+#[cfg(test)]
+fn exchange(requests: &'static [&'static str], locked: Option<&str>) -> (Vec<serde_json::Value>, VM) {
+    let mut remote = Remote::start("127.0.0.1:0", 1000).unwrap();
+    let url = format!("ws://{}", remote.addr());
+    let client = std::thread::spawn(move || {
+        let (mut socket, _) = tungstenite::connect(url).unwrap();
+        let mut replies = Vec::new();
+        for request in requests.iter() {
+            socket.send(Message::Text(request.to_string())).unwrap();
+            let reply: serde_json::Value = serde_json::from_str(&socket.read().unwrap().into_text().unwrap()).unwrap();
+            replies.push(reply);
+        }
+        replies
+    });
+
+    let mut vm = VM::new(Box::new(chip8::random::SeededRandom::new(0)));
+    vm.reload(&[0x60, 0x07, 0x71, 0x02]).unwrap();
+    let mut paused = false;
+    let mut profiler = Profiler::new(vm.memory.len(), false);
+    profiler.record(&vm);
+    while !client.is_finished() {
+        remote.poll(&mut vm, &mut paused, &profiler, locked);
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    (client.join().unwrap(), vm)
+}
+
+#[test]
+fn test_remote() {
+    let (replies, vm) = exchange(
+        &[
+            r#"{"cmd": "pause"}"#,
+            r#"{"cmd": "key", "key": 3, "pressed": true}"#,
+            r#"{"cmd": "step", "count": 2}"#,
+            r#"{"cmd": "step", "count": 4294967295}"#,
+            r#"{"cmd": "peek", "addr": 512, "len": 2}"#,
+            r#"{"cmd": "peek", "addr": 4095, "len": 2}"#,
+            r#"{"cmd": "jump"}"#,
+            r#"{"cmd": "stats"}"#,
+        ],
+        None,
+    );
+
+    assert_eq!(replies[0]["type"], "state");
+    assert_eq!(replies[0]["paused"], true);
+    assert_eq!(replies[1]["pc"], 0x200);
+    assert!(vm.keys_pressed[3]);
+    assert_eq!(replies[2]["pc"], 0x204);
+    assert_eq!(replies[2]["v"][0], 7);
+    assert_eq!(replies[2]["v"][1], 2);
+    assert_eq!(replies[3]["message"], "Can't step more than 1000 instructions at once");
+    assert_eq!(replies[4]["bytes"], serde_json::json!([0x60, 0x07]));
+    assert_eq!(replies[5]["type"], "error");
+    assert_eq!(replies[6]["type"], "error");
+    assert_eq!(replies[7]["type"], "stats");
+    assert_eq!(replies[7]["instructions"], 1);
+    assert_eq!(replies[7]["opcodes"]["SetVxNn"], 1);
+}
+
+#[test]
+fn test_remote_locked() {
+    let (replies, vm) = exchange(
+        &[
+            r#"{"cmd": "pause"}"#,
+            r#"{"cmd": "key", "key": 3, "pressed": true}"#,
+            r#"{"cmd": "step"}"#,
+            r#"{"cmd": "resume"}"#,
+            r#"{"cmd": "state"}"#,
+        ],
+        Some("recording"),
+    );
+    for reply in &replies[..4] {
+        assert_eq!(reply["message"], "Can't control the emulator while recording");
+    }
+    assert_eq!(replies[4]["paused"], false);
+    assert_eq!((vm.pc, vm.keys_pressed[3]), (0x200, false));
+}
@@ -0,0 +1,279 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use chip8::platform::{self, Platform};
+use chip8::quirks::{Quirks, QUIRK_NAMES};
+
+/// A change in the state of a single keypad key, tagged with the
+/// cycle number before which it was applied.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) struct KeyEvent {
+    pub(crate) cycle: u64,
+    pub(crate) key: u8,
+    pub(crate) pressed: bool,
+}
+
+/// The settings a run depends on, besides the seed and the keys, which
+/// a recording saves so the replay runs the same.
+pub(crate) struct RecordedSettings<'a> {
+    pub(crate) speed: u64,
+    pub(crate) timer_hz: u32,
+    pub(crate) platform: Option<&'a Platform>,
+    pub(crate) quirks: Quirks,
+    pub(crate) stack_depth: usize,
+    pub(crate) strict: bool,
+    pub(crate) exit_opcode: Option<u16>,
+    pub(crate) megachip: bool,
+}
+
+/// Writes keypad events to a replay file as they happen.
+///
+/// The file format is line-based text: the first line is `seed N`,
+/// then the settings the run depends on, `speed N`, `timer-hz N`,
+/// `platform NAME`, `quirks NAME,NAME`, `stack-depth N`,
+/// `strict true|false`, `exit-opcode XXXX` and `megachip true|false`,
+/// where an empty platform or exit opcode means there wasn't one. They
+/// are followed by one `CYCLE KEY down|up` line per event, with the key
+/// written in hex. Files from before the settings were recorded only
+/// have the seed, and older ones with settings only have some of them.
+pub(crate) struct Recorder {
+    out: BufWriter<File>,
+}
+
+impl Recorder {
+    pub(crate) fn create(path: &Path, seed: u64, settings: &RecordedSettings) -> io::Result<Recorder> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "seed {}", seed)?;
+        writeln!(out, "speed {}", settings.speed)?;
+        writeln!(out, "timer-hz {}", settings.timer_hz)?;
+        writeln!(
+            out,
+            "platform {}",
+            settings.platform.map_or("", |platform| platform.name)
+        )?;
+        let enabled: Vec<&str> = QUIRK_NAMES
+            .iter()
+            .copied()
+            .filter(|name| settings.quirks.get(name) == Some(true))
+            .collect();
+        writeln!(out, "quirks {}", enabled.join(","))?;
+        writeln!(out, "stack-depth {}", settings.stack_depth)?;
+        writeln!(out, "strict {}", settings.strict)?;
+        let exit_opcode = settings
+            .exit_opcode
+            .map_or(String::new(), |opcode| format!("{:04X}", opcode));
+        writeln!(out, "exit-opcode {}", exit_opcode)?;
+        writeln!(out, "megachip {}", settings.megachip)?;
+        Ok(Recorder { out })
+    }
+
+    pub(crate) fn record(&mut self, event: &KeyEvent) -> io::Result<()> {
+        let state = if event.pressed { "down" } else { "up" };
+        writeln!(self.out, "{} {:X} {}", event.cycle, event.key, state)?;
+        // Flush eagerly so the recording survives the process exiting.
+        self.out.flush()
+    }
+}
+
+/// A recording loaded from a replay file, played back in cycle order.
+#[derive(Clone)]
+pub(crate) struct Replay {
+    pub(crate) seed: u64,
+    /// The settings it was recorded with, if the file has them
+    pub(crate) speed: Option<u64>,
+    pub(crate) timer_hz: Option<u32>,
+    /// `Some(None)` if it was recorded without a platform
+    pub(crate) platform: Option<Option<&'static Platform>>,
+    pub(crate) quirks: Option<Quirks>,
+    pub(crate) stack_depth: Option<usize>,
+    pub(crate) strict: Option<bool>,
+    /// `Some(None)` if it was recorded without an exit opcode
+    pub(crate) exit_opcode: Option<Option<u16>>,
+    pub(crate) megachip: Option<bool>,
+    events: VecDeque<KeyEvent>,
+}
+
+impl Replay {
+    pub(crate) fn load(path: &Path) -> Result<Replay, String> {
+        let contents = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+        Replay::parse(&contents)
+    }
+
+    pub(crate) fn parse(contents: &str) -> Result<Replay, String> {
+        let mut lines = contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .peekable();
+
+        let seed = match lines
+            .next()
+            .map(|(_, line)| line.split_whitespace().collect::<Vec<_>>())
+        {
+            Some(words) if words.len() == 2 && words[0] == "seed" => {
+                words[1].parse().map_err(|_| format!("Invalid seed {:?}", words[1]))?
+            }
+            _ => return Err("Replay file must start with a seed line".to_string()),
+        };
+
+        let (mut speed, mut timer_hz, mut platform, mut quirks) = (None, None, None, None);
+        let (mut stack_depth, mut strict, mut exit_opcode, mut megachip) = (None, None, None, None);
+        while let Some(&(num, line)) = lines.peek() {
+            let invalid = || format!("Invalid replay setting on line {}: {:?}", num + 1, line);
+            let (name, value) = match line.trim().split_once(' ') {
+                Some((name, value)) => (name, value.trim()),
+                None => (line.trim(), ""),
+            };
+            match name {
+                "speed" => speed = Some(value.parse().map_err(|_| invalid())?),
+                "timer-hz" => timer_hz = Some(value.parse().map_err(|_| invalid())?),
+                "platform" if value.is_empty() => platform = Some(None),
+                "platform" => platform = Some(Some(platform::find(value).map_err(|_| invalid())?)),
+                "quirks" => quirks = Some(Quirks::parse(value).map_err(|_| invalid())?),
+                "stack-depth" => stack_depth = Some(value.parse().map_err(|_| invalid())?),
+                "strict" => strict = Some(value.parse().map_err(|_| invalid())?),
+                "exit-opcode" if value.is_empty() => exit_opcode = Some(None),
+                "exit-opcode" => exit_opcode = Some(Some(u16::from_str_radix(value, 16).map_err(|_| invalid())?)),
+                "megachip" => megachip = Some(value.parse().map_err(|_| invalid())?),
+                _ => break,
+            }
+            lines.next();
+        }
+
+        let mut events = VecDeque::new();
+        for (num, line) in lines {
+            let invalid = || format!("Invalid replay event on line {}: {:?}", num + 1, line);
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.len() != 3 {
+                return Err(invalid());
+            }
+            let cycle = words[0].parse().map_err(|_| invalid())?;
+            let key = u8::from_str_radix(words[1], 16).map_err(|_| invalid())?;
+            let pressed = match words[2] {
+                "down" => true,
+                "up" => false,
+                _ => return Err(invalid()),
+            };
+            if key > 0xF {
+                return Err(invalid());
+            }
+            events.push_back(KeyEvent { cycle, key, pressed });
+        }
+
+        Ok(Replay {
+            seed,
+            speed,
+            timer_hz,
+            platform,
+            quirks,
+            stack_depth,
+            strict,
+            exit_opcode,
+            megachip,
+            events,
+        })
+    }
+
+    /// Remove and return the next event if it is due at or before
+    /// `cycle`.
+    pub(crate) fn next_due(&mut self, cycle: u64) -> Option<KeyEvent> {
+        match self.events.front() {
+            Some(event) if event.cycle <= cycle => self.events.pop_front(),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn test_parse_replay() {
+    let mut replay = Replay::parse("seed 42\n10 A down\n25 A up\n").unwrap();
+    assert_eq!(replay.seed, 42);
+    assert_eq!(replay.next_due(9), None);
+    assert_eq!(
+        replay.next_due(10),
+        Some(KeyEvent {
+            cycle: 10,
+            key: 0xA,
+            pressed: true
+        })
+    );
+    assert_eq!(replay.next_due(10), None);
+    assert_eq!(replay.next_due(30).map(|event| event.pressed), Some(false));
+
+    assert_eq!((replay.speed, replay.timer_hz, replay.quirks), (None, None, None));
+    assert!(replay.platform.is_none());
+    assert_eq!(
+        (replay.stack_depth, replay.strict, replay.exit_opcode, replay.megachip),
+        (None, None, None, None)
+    );
+
+    let replay = Replay::parse("seed 1\nspeed 700\ntimer-hz 50\nquirks vf-reset,shift-uses-vy\n10 A down\n").unwrap();
+    assert_eq!((replay.speed, replay.timer_hz), (Some(700), Some(50)));
+    assert_eq!(replay.quirks, Quirks::parse("shift-uses-vy,vf-reset").ok());
+    assert_eq!(replay.events.len(), 1);
+    assert_eq!(
+        Replay::parse("seed 1\nquirks\n").unwrap().quirks,
+        Some(Quirks::default())
+    );
+    assert_eq!(Replay::parse("seed 1\nplatform\n").unwrap().platform, Some(None));
+    assert_eq!(Replay::parse("seed 1\nexit-opcode\n").unwrap().exit_opcode, Some(None));
+    assert_eq!(
+        Replay::parse("seed 1\nplatform xochip\n")
+            .unwrap()
+            .platform
+            .map(|platform| platform.map(|platform| platform.name)),
+        Some(Some("xochip"))
+    );
+
+    assert!(Replay::parse("").is_err());
+    assert!(Replay::parse("seed 1\nspeed fast\n").is_err());
+    assert!(Replay::parse("seed 1\nquirks nope\n").is_err());
+    assert!(Replay::parse("seed 1\nplatform nope\n").is_err());
+    assert!(Replay::parse("seed 1\nstrict maybe\n").is_err());
+    assert!(Replay::parse("seed 1\nexit-opcode 10000\n").is_err());
+    assert!(Replay::parse("seed 1\n10 10 down\n").is_err());
+    assert!(Replay::parse("seed 1\n10 A sideways\n").is_err());
+}
+
+#[test]
+fn test_record_settings() {
+    let path = std::env::temp_dir().join(format!("chip8-replay-test-{}", std::process::id()));
+    let quirks = Quirks {
+        vf_reset: true,
+        ..Quirks::default()
+    };
+    let platform = platform::find("schip-modern").unwrap();
+    let settings = RecordedSettings {
+        speed: 1000,
+        timer_hz: 50,
+        platform: Some(platform),
+        quirks,
+        stack_depth: 16,
+        strict: true,
+        exit_opcode: Some(0x00FD),
+        megachip: false,
+    };
+    let mut recorder = Recorder::create(&path, 7, &settings).unwrap();
+    recorder
+        .record(&KeyEvent {
+            cycle: 3,
+            key: 0xF,
+            pressed: true,
+        })
+        .unwrap();
+    let replay = Replay::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        (replay.seed, replay.speed, replay.timer_hz, replay.quirks),
+        (7, Some(1000), Some(50), Some(quirks))
+    );
+    assert_eq!(replay.platform, Some(Some(platform)));
+    assert_eq!(
+        (replay.stack_depth, replay.strict, replay.exit_opcode, replay.megachip),
+        (Some(16), Some(true), Some(Some(0x00FD)), Some(false))
+    );
+    assert_eq!(replay.events.len(), 1);
+}
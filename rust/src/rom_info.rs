@@ -0,0 +1,138 @@
+//! `chip8 info`: what a ROM is without running it: its size, SHA-256
+//! (the hash config files match ROMs by), how much of the program area
+//! it fills and how it starts. `chip8 run` logs the short version when
+//! it loads a ROM.
+
+use chip8::instruction::parse_instruction;
+use chip8::vm::{check_rom_size, is_hires_rom, max_rom_bytes, LoadError, HIRES_PROGRAM_START, PROGRAM_START};
+
+use crate::config::sha256_hex;
+use crate::romdb;
+
+pub(crate) struct RomInfo {
+    /// The title from the ROM database, if it's a known ROM
+    pub(crate) title: Option<String>,
+    pub(crate) size: usize,
+    pub(crate) sha256: String,
+    /// The first instruction, if the ROM starts with a valid one
+    entry: Option<String>,
+    hires: bool,
+    /// The size of the program area: the rest of memory from where
+    /// ROMs are loaded
+    max_size: usize,
+    /// Whether it fits in memory
+    size_check: Result<(), LoadError>,
+}
+
+impl RomInfo {
+    /// What `rom` is, and whether it fits in `memory_bytes` of memory.
+    pub(crate) fn new(rom: &[u8], memory_bytes: usize) -> Result<RomInfo, String> {
+        let entry = match rom {
+            [hi, lo, ..] => parse_instruction((*hi as u16) << 8 | *lo as u16)
+                .ok()
+                .map(|instruction| instruction.to_string()),
+            _ => None,
+        };
+        Ok(RomInfo {
+            title: romdb::lookup(rom)?.map(|info| info.title),
+            size: rom.len(),
+            sha256: sha256_hex(rom),
+            entry,
+            hires: is_hires_rom(rom),
+            max_size: max_rom_bytes(memory_bytes),
+            size_check: check_rom_size(rom, memory_bytes),
+        })
+    }
+
+    /// How much of the program area the ROM fills, as a percentage.
+    fn usage(&self) -> f64 {
+        self.size as f64 * 100.0 / self.max_size as f64
+    }
+
+    /// Why the ROM can't be loaded, if it can't.
+    pub(crate) fn error(&self) -> Option<&LoadError> {
+        self.size_check.as_ref().err()
+    }
+
+    /// One line for logging when the ROM loads.
+    pub(crate) fn summary(&self) -> String {
+        format!(
+            "{} bytes ({:.0}% of the program area), SHA-256 {}",
+            self.size,
+            self.usage(),
+            self.sha256
+        )
+    }
+
+    /// The full report `chip8 info` prints.
+    pub(crate) fn report(&self) -> String {
+        let mut report = String::new();
+        if let Some(title) = &self.title {
+            report += &format!("Title:   {}\n", title);
+        }
+        report += &format!("Size:    {} bytes\n", self.size);
+        report += &format!("SHA-256: {}\n", self.sha256);
+        let end = PROGRAM_START as usize + self.size.max(1) - 1;
+        report += &format!(
+            "Memory:  {:03X}-{:03X}, {} of {} bytes ({:.1}%)\n",
+            PROGRAM_START,
+            end,
+            self.size,
+            self.max_size,
+            self.usage()
+        );
+        let entry = self.entry.as_deref().unwrap_or("(data)");
+        report += &format!("Entry:   {:03X}: {}\n", PROGRAM_START, entry);
+        if self.hires {
+            report += &format!(
+                "         hi-res ROM: the program proper starts at {:03X}\n",
+                HIRES_PROGRAM_START
+            );
+        }
+        report
+    }
+}
+
+#[test]
+fn test_rom_info() {
+    use chip8::vm::{MAX_ROM_BYTES, MEMORY_BYTES, XO_CHIP_MEMORY_BYTES};
+
+    let info = RomInfo::new(&[0x00, 0xE0, 0x12, 0x00], MEMORY_BYTES).unwrap();
+    assert_eq!(info.title, None);
+    assert_eq!(info.error(), None);
+    let report = info.report();
+    assert!(report.contains("Size:    4 bytes\n"), "{}", report);
+    assert!(
+        report.contains("Memory:  200-203, 4 of 3584 bytes (0.1%)\n"),
+        "{}",
+        report
+    );
+    assert!(report.contains("Entry:   200: CLS\n"), "{}", report);
+    assert!(!report.contains("hi-res"));
+    assert_eq!(info.sha256.len(), 64);
+    assert!(info.summary().starts_with("4 bytes (0% of the program area), SHA-256 "));
+
+    assert!(RomInfo::new(&[0x12, 0x60], MEMORY_BYTES)
+        .unwrap()
+        .report()
+        .contains("hi-res ROM"));
+    assert!(RomInfo::new(&[0xFF], MEMORY_BYTES)
+        .unwrap()
+        .report()
+        .contains("Entry:   200: (data)\n"));
+
+    assert_eq!(RomInfo::new(&[0; MAX_ROM_BYTES], MEMORY_BYTES).unwrap().error(), None);
+    let too_big = RomInfo::new(&[0; MAX_ROM_BYTES + 2], MEMORY_BYTES).unwrap();
+    assert_eq!(too_big.error(), Some(&LoadError::RomTooLarge { size: 3586, max: 3584 }));
+
+    // XO-CHIP's 64 KB fits much bigger ROMs
+    let xo_chip = RomInfo::new(&[0; MAX_ROM_BYTES + 2], XO_CHIP_MEMORY_BYTES).unwrap();
+    assert_eq!(xo_chip.error(), None);
+    assert!(
+        xo_chip
+            .report()
+            .contains("Memory:  200-1001, 3586 of 65024 bytes (5.5%)\n"),
+        "{}",
+        xo_chip.report()
+    );
+}
@@ -0,0 +1,106 @@
+//! A database of known ROMs (see `data/roms.toml`), used to pick the
+//! right settings for a ROM automatically and to show its title.
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+
+use chip8::platform;
+use chip8::vm::FRAMES_PER_SECOND;
+
+use crate::config::Settings;
+use crate::symbols::{parse_range, Symbols};
+
+const DATABASE: &str = include_str!("../data/roms.toml");
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RomInfo {
+    pub(crate) title: String,
+    /// The machine the ROM was written for (see `chip8::platform`)
+    platform: Option<String>,
+    /// Instructions per 60 Hz frame
+    tickrate: Option<u64>,
+    #[serde(default)]
+    quirks: BTreeMap<String, bool>,
+    #[serde(default)]
+    keymap: BTreeMap<char, String>,
+    /// Named regions of memory, as hex ranges
+    #[serde(default)]
+    regions: BTreeMap<String, String>,
+}
+
+impl RomInfo {
+    /// The settings this ROM wants, to be overridden by the config file.
+    pub(crate) fn settings(&self) -> Settings {
+        let mut settings = Settings::default();
+        settings.speed = self.tickrate.map(|tickrate| tickrate * FRAMES_PER_SECOND);
+        settings.platform = self.platform.clone();
+        settings.quirks = self.quirks.clone();
+        settings.keymap = self.keymap.clone();
+        settings
+    }
+
+    /// Add the ROM's named regions to `symbols`, unless its symbol file
+    /// already names them.
+    pub(crate) fn add_regions(&self, symbols: &mut Symbols) {
+        for (name, range) in &self.regions {
+            if let (None, Some((start, end))) = (symbols.region_named(name), parse_range(range)) {
+                symbols.add_region(start, end, name.clone());
+            }
+        }
+    }
+}
+
+/// Look up a ROM by its contents.
+pub(crate) fn lookup(rom: &[u8]) -> Result<Option<RomInfo>, String> {
+    let mut database = parse(DATABASE).map_err(|err| format!("Error in the ROM database: {}", err))?;
+    Ok(database.remove(&sha1_hex(rom)))
+}
+
+fn parse(contents: &str) -> Result<BTreeMap<String, RomInfo>, String> {
+    let database: BTreeMap<String, RomInfo> = toml::from_str(contents).map_err(|err| err.to_string())?;
+    for (hash, info) in &database {
+        if let Some(name) = &info.platform {
+            platform::find(name).map_err(|err| format!("{}: {}", hash, err))?;
+        }
+        if info.tickrate == Some(0) {
+            return Err(format!("{}: tickrate must be greater than 0", hash));
+        }
+        for (name, range) in &info.regions {
+            if parse_range(range).is_none() {
+                return Err(format!("{}: invalid range {:?} for region {}", hash, range, name));
+            }
+        }
+    }
+    Ok(database)
+}
+
+fn sha1_hex(data: &[u8]) -> String {
+    Sha1::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[test]
+fn test_database() {
+    let database = parse(DATABASE).unwrap();
+    assert!(database.keys().all(|hash| hash.len() == 40));
+    for info in database.values() {
+        assert!(info.settings().quirks(chip8::quirks::Quirks::default()).is_ok());
+        assert!(info.settings().keymap().is_ok());
+    }
+
+    let info = parse("[\"da39a3ee5e6b4b0d3255bfef95601890afd80709\"]\ntitle = \"Empty\"\ntickrate = 20\n").unwrap();
+    assert_eq!(sha1_hex(&[]), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+    let info = &info[&sha1_hex(&[])];
+    assert_eq!(info.platform, None);
+    assert_eq!(info.settings().speed, Some(1200));
+    assert!(parse("[a]\ntitle = \"A\"\nplatform = \"nope\"\n").is_err());
+
+    let info = parse("[a]\ntitle = \"A\"\nregions = { score = \"3A0-3A3\", ship = \"300-30F\" }\n").unwrap();
+    let mut symbols = Symbols::parse("310-31F ship").unwrap();
+    info["a"].add_regions(&mut symbols);
+    assert_eq!(symbols.name(0x3A1).as_deref(), Some("score+1"));
+    assert_eq!(symbols.name(0x300), None);
+    assert!(parse("[a]\ntitle = \"A\"\nregions = { score = \"3A3-3A0\" }\n").is_err());
+}
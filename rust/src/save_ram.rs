@@ -0,0 +1,114 @@
+//! Save RAM, like a cartridge's battery-backed RAM: a range of memory,
+//! set with `--save-ram START-END` or `save-ram` in the config file,
+//! that's kept between runs so homebrew games can keep high scores and
+//! save files.
+//!
+//! The convention for ROMs is simple: keep the data to save in the
+//! range, e.g. writing it with FX55, and read it back at startup. The
+//! range starts zeroed the first time a ROM runs. When the emulator
+//! exits, the range is written to `saves/HASH.sav` in the config
+//! directory, keyed by the SHA-256 hash of the ROM, and it's copied back
+//! into memory when the ROM is next loaded, before it starts. Resetting
+//! keeps it, as a battery would.
+
+use std::fs;
+use std::path::PathBuf;
+
+use chip8::vm::{MEMORY_BYTES, VM};
+
+use crate::config;
+
+/// The addresses of a save RAM range, both included.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) struct SaveRange {
+    start: u16,
+    end: u16,
+}
+
+impl SaveRange {
+    /// Parse a range like `E00-EFF`, in hex.
+    pub(crate) fn parse(s: &str) -> Result<SaveRange, String> {
+        let error = || format!("Invalid save RAM range {:?}, expected hex addresses like E00-EFF", s);
+        let address = |s: &str| {
+            u16::from_str_radix(s.trim_start_matches("0x").trim_start_matches("0X"), 16)
+                .ok()
+                .filter(|&addr| (addr as usize) < MEMORY_BYTES)
+        };
+        let (start, end) = s.split_once('-').ok_or_else(error)?;
+        match (address(start), address(end)) {
+            (Some(start), Some(end)) if start <= end => Ok(SaveRange { start, end }),
+            _ => Err(error()),
+        }
+    }
+
+    fn bytes(self) -> std::ops::RangeInclusive<usize> {
+        self.start as usize..=self.end as usize
+    }
+
+    /// The range's bytes in `vm`'s memory.
+    pub(crate) fn read(self, vm: &VM) -> Vec<u8> {
+        vm.memory[self.bytes()].to_vec()
+    }
+
+    /// Copy saved bytes into the range. A save from a smaller range only
+    /// fills the start, and one from a bigger range is cut short.
+    pub(crate) fn write(self, vm: &mut VM, saved: &[u8]) {
+        for (addr, &byte) in self.bytes().zip(saved) {
+            vm.poke(addr, byte);
+        }
+    }
+}
+
+/// Where the save RAM for `rom` is kept.
+fn save_path(rom: &[u8]) -> Option<PathBuf> {
+    Some(
+        config::config_dir()?
+            .join("saves")
+            .join(format!("{}.sav", config::sha256_hex(rom))),
+    )
+}
+
+/// Restore `rom`'s save RAM, if it has been saved before.
+pub(crate) fn restore(range: SaveRange, rom: &[u8], vm: &mut VM) -> Result<(), String> {
+    let path = match save_path(rom) {
+        Some(path) if path.exists() => path,
+        _ => return Ok(()),
+    };
+    let saved = fs::read(&path).map_err(|err| format!("Error reading save RAM {}: {}", path.display(), err))?;
+    range.write(vm, &saved);
+    log::info!("Restored save RAM from {}", path.display());
+    Ok(())
+}
+
+/// Write `rom`'s save RAM to disk.
+pub(crate) fn save(range: SaveRange, rom: &[u8], vm: &VM) -> Result<(), String> {
+    let path = save_path(rom).ok_or("Can't find the config directory to keep save RAM in")?;
+    let error = |err: std::io::Error| format!("Error writing save RAM {}: {}", path.display(), err);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(error)?;
+    }
+    fs::write(&path, range.read(vm)).map_err(error)
+}
+
+#[test]
+fn test_save_range() {
+    assert_eq!(
+        SaveRange::parse("E00-EFF"),
+        Ok(SaveRange {
+            start: 0xE00,
+            end: 0xEFF
+        })
+    );
+    assert_eq!(SaveRange::parse("0xFFE-0xFFF").map(SaveRange::bytes), Ok(0xFFE..=0xFFF));
+    for invalid in ["E00", "EFF-E00", "E00-1000", "x-y"] {
+        assert!(SaveRange::parse(invalid).is_err(), "{}", invalid);
+    }
+
+    let mut vm = VM::new(Box::new(chip8::random::SeededRandom::new(0)));
+    let range = SaveRange::parse("E00-E03").unwrap();
+    range.write(&mut vm, &[1, 2, 3, 4, 5]);
+    assert_eq!(range.read(&vm), [1, 2, 3, 4]);
+    assert_eq!(vm.memory[0xE04], 0);
+    range.write(&mut vm, &[9]);
+    assert_eq!(range.read(&vm), [9, 2, 3, 4]);
+}
@@ -0,0 +1,229 @@
+//! Rhai scripts that drive the emulator, loaded with `--script`, for
+//! bots, cheats and test scenarios. The script's top level runs once
+//! after the ROM is loaded, and then its `on_frame` function, if it has
+//! one, runs at the end of every frame. For example:
+//!
+//! ```text
+//! poke(0x3A0, 9);          // start with 9 lives
+//!
+//! fn on_frame() {
+//!     if frame() % 30 == 0 { press(5) } else { release(5) }
+//!     if frame() == 3600 { quit() }
+//! }
+//! ```
+//!
+//! Scripts can call:
+//!
+//! - `v(x)` and `set_v(x, value)` for the registers, `i()` and
+//!   `set_i(value)` for I, and `pc()`
+//! - `peek(addr)` and `poke(addr, value)` for memory
+//! - `press(key)` and `release(key)` for the keypad
+//! - `frame()`, the number of frames run so far
+//! - `quit()`, to stop the emulator after this frame
+//!
+//! `print` writes to stderr, out of the way of the terminal frontend.
+
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use rhai::{Engine, EvalAltResult, Scope, AST};
+
+use chip8::vm::{Memory, VM};
+
+const FRAME_FUNCTION: &str = "on_frame";
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// The parts of the VM scripts can see, copied in before the script
+/// runs and back out after.
+struct Machine {
+    memory: Memory,
+    v: [u8; 16],
+    ir: u16,
+    pc: u16,
+    keys_pressed: [bool; 16],
+    frame: u64,
+    quit: bool,
+}
+
+impl Machine {
+    fn load(&mut self, vm: &VM, frame: u64) {
+        self.memory.clone_from(&vm.memory);
+        self.v = vm.v;
+        self.ir = vm.ir;
+        self.pc = vm.pc;
+        self.keys_pressed = vm.keys_pressed;
+        self.frame = frame;
+    }
+
+    fn store(&self, vm: &mut VM) {
+        // Only write memory back if the script changed it, so the
+        // caches aren't flushed every frame
+        if vm.memory != self.memory {
+            vm.memory.clone_from(&self.memory);
+            vm.clear_caches();
+        }
+        vm.v = self.v;
+        vm.ir = self.ir;
+        vm.keys_pressed = self.keys_pressed;
+    }
+}
+
+pub(crate) struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    machine: Rc<RefCell<Machine>>,
+    has_frame_function: bool,
+}
+
+impl Script {
+    pub(crate) fn load(path: &Path, vm: &mut VM) -> Result<Script, String> {
+        let source =
+            std::fs::read_to_string(path).map_err(|err| format!("Error reading script {}: {}", path.display(), err))?;
+        Script::new(&source, vm).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    /// Compile `source` and run its top level.
+    pub(crate) fn new(source: &str, vm: &mut VM) -> Result<Script, String> {
+        let machine = Rc::new(RefCell::new(Machine {
+            memory: vm.memory.clone(),
+            v: vm.v,
+            ir: vm.ir,
+            pc: vm.pc,
+            keys_pressed: vm.keys_pressed,
+            frame: 0,
+            quit: false,
+        }));
+        let mut engine = Engine::new();
+        engine.on_print(|text| eprintln!("{}", text));
+        register_functions(&mut engine, &machine);
+
+        let ast = engine.compile(source).map_err(|err| err.to_string())?;
+        let has_frame_function = ast.iter_functions().any(|function| function.name == FRAME_FUNCTION);
+        let mut script = Script {
+            engine,
+            ast,
+            scope: Scope::new(),
+            machine,
+            has_frame_function,
+        };
+        script
+            .engine
+            .run_ast_with_scope(&mut script.scope, &script.ast)
+            .map_err(|err| err.to_string())?;
+        script.machine.borrow().store(vm);
+        Ok(script)
+    }
+
+    /// Run the script's `on_frame` function. Returns false once the
+    /// script has called `quit()`.
+    pub(crate) fn on_frame(&mut self, vm: &mut VM, frame: u64) -> Result<bool, String> {
+        if !self.has_frame_function {
+            return Ok(true);
+        }
+        self.machine.borrow_mut().load(vm, frame);
+        // Whatever on_frame returns is ignored
+        let _ = self
+            .engine
+            .call_fn::<rhai::Dynamic>(&mut self.scope, &self.ast, FRAME_FUNCTION, ())
+            .map_err(|err| format!("Error in script: {}", err))?;
+        let machine = self.machine.borrow();
+        machine.store(vm);
+        Ok(!machine.quit)
+    }
+}
+
+fn register_functions(engine: &mut Engine, machine: &Rc<RefCell<Machine>>) {
+    let m = machine.clone();
+    engine.register_fn("v", move |x: i64| -> ScriptResult<i64> {
+        Ok(m.borrow().v[register(x)?] as i64)
+    });
+    let m = machine.clone();
+    engine.register_fn("set_v", move |x: i64, value: i64| -> ScriptResult<()> {
+        m.borrow_mut().v[register(x)?] = in_range(value, 0xFF, "register value")? as u8;
+        Ok(())
+    });
+    let m = machine.clone();
+    engine.register_fn("i", move || m.borrow().ir as i64);
+    let m = machine.clone();
+    engine.register_fn("set_i", move |value: i64| -> ScriptResult<()> {
+        m.borrow_mut().ir = in_range(value, 0xFFFF, "I")? as u16;
+        Ok(())
+    });
+    let m = machine.clone();
+    engine.register_fn("pc", move || m.borrow().pc as i64);
+    let m = machine.clone();
+    engine.register_fn("peek", move |addr: i64| -> ScriptResult<i64> {
+        let machine = m.borrow();
+        Ok(machine.memory[address(addr, &machine.memory)?] as i64)
+    });
+    let m = machine.clone();
+    engine.register_fn("poke", move |addr: i64, value: i64| -> ScriptResult<()> {
+        let mut machine = m.borrow_mut();
+        let addr = address(addr, &machine.memory)?;
+        machine.memory[addr] = in_range(value, 0xFF, "byte")? as u8;
+        Ok(())
+    });
+    let m = machine.clone();
+    engine.register_fn("press", move |key: i64| -> ScriptResult<()> {
+        m.borrow_mut().keys_pressed[keypad_key(key)?] = true;
+        Ok(())
+    });
+    let m = machine.clone();
+    engine.register_fn("release", move |key: i64| -> ScriptResult<()> {
+        m.borrow_mut().keys_pressed[keypad_key(key)?] = false;
+        Ok(())
+    });
+    let m = machine.clone();
+    engine.register_fn("frame", move || m.borrow().frame as i64);
+    let m = machine.clone();
+    engine.register_fn("quit", move || m.borrow_mut().quit = true);
+}
+
+fn in_range(value: i64, max: i64, what: &str) -> ScriptResult<i64> {
+    if (0..=max).contains(&value) {
+        Ok(value)
+    } else {
+        Err(format!("{} {} out of range (0-{:#X})", what, value, max).into())
+    }
+}
+
+fn register(x: i64) -> ScriptResult<usize> {
+    in_range(x, 0xF, "register").map(|x| x as usize)
+}
+
+fn keypad_key(key: i64) -> ScriptResult<usize> {
+    in_range(key, 0xF, "key").map(|key| key as usize)
+}
+
+fn address(addr: i64, memory: &[u8]) -> ScriptResult<usize> {
+    in_range(addr, memory.len() as i64 - 1, "address").map(|addr| addr as usize)
+}
+
+#[test]
+fn test_script() {
+    let mut vm = VM::new(Box::new(chip8::random::SeededRandom::new(0)));
+    vm.v[3] = 7;
+    let source = "
+        poke(0x300, v(3) + 1);
+
+        fn on_frame() {
+            if frame() % 2 == 0 { press(0xA) } else { release(0xA) }
+            set_i(peek(0x300));
+            if frame() == 3 { quit() }
+        }
+    ";
+    let mut script = Script::new(source, &mut vm).unwrap();
+    assert_eq!(vm.memory[0x300], 8);
+
+    assert_eq!(script.on_frame(&mut vm, 2), Ok(true));
+    assert!(vm.keys_pressed[0xA]);
+    assert_eq!(vm.ir, 8);
+    assert_eq!(script.on_frame(&mut vm, 3), Ok(false));
+    assert!(!vm.keys_pressed[0xA]);
+
+    let err = Script::new("set_v(16, 0)", &mut vm).err().unwrap();
+    assert!(err.contains("register 16 out of range"), "{}", err);
+}
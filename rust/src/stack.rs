@@ -0,0 +1,121 @@
+use std::fmt;
+
+/// Most interpreters allow 16 levels of subroutine nesting (the
+/// original COSMAC VIP interpreter allowed 12).
+pub const DEFAULT_STACK_DEPTH: usize = 16;
+
+/// A subroutine call that hasn't returned yet.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Frame {
+    /// Address of the 2NNN instruction that made the call
+    pub call_site: u16,
+    /// Address of the subroutine that was called
+    pub subroutine: u16,
+}
+
+impl Frame {
+    pub fn return_address(&self) -> u16 {
+        self.call_site.wrapping_add(2)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StackError {
+    /// A subroutine call when the stack is already at its maximum depth
+    Overflow { depth: usize },
+    /// A return with no subroutine call to return from
+    Underflow,
+}
+
+impl fmt::Display for StackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StackError::Overflow { depth } => write!(f, "stack overflow: more than {} nested subroutine calls", depth),
+            StackError::Underflow => write!(f, "stack underflow: return without a subroutine call"),
+        }
+    }
+}
+
+/// The subroutine call stack, limited to a fixed depth like the stack
+/// of a real interpreter.
+#[derive(Clone)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+    max_depth: usize,
+}
+
+impl CallStack {
+    pub fn new(max_depth: usize) -> CallStack {
+        CallStack {
+            frames: Vec::with_capacity(max_depth),
+            max_depth,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn push(&mut self, frame: Frame) -> Result<(), StackError> {
+        if self.frames.len() >= self.max_depth {
+            return Err(StackError::Overflow { depth: self.max_depth });
+        }
+        self.frames.push(frame);
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    pub fn pop(&mut self) -> Result<Frame, StackError> {
+        self.frames.pop().ok_or(StackError::Underflow)
+    }
+
+    /// The current call chain, outermost call first.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// The addresses the current subroutine was called from, outermost
+    /// call first, like "246 -> 302 -> 410", or None outside any
+    /// subroutine.
+    pub fn call_chain(&self) -> Option<String> {
+        if self.frames.is_empty() {
+            return None;
+        }
+        let call_sites: Vec<String> = self
+            .frames
+            .iter()
+            .map(|frame| format!("{:03X}", frame.call_site))
+            .collect();
+        Some(call_sites.join(" -> "))
+    }
+}
+
+#[test]
+fn test_call_stack() {
+    let frame = Frame {
+        call_site: 0x200,
+        subroutine: 0x300,
+    };
+    let mut stack = CallStack::new(2);
+    assert_eq!(stack.pop(), Err(StackError::Underflow));
+    assert_eq!(stack.call_chain(), None);
+    assert_eq!(stack.push(frame), Ok(()));
+    assert_eq!(
+        stack.push(Frame {
+            call_site: 0x302,
+            subroutine: 0x410,
+        }),
+        Ok(())
+    );
+    assert_eq!(stack.call_chain().as_deref(), Some("200 -> 302"));
+    assert_eq!(stack.push(frame), Err(StackError::Overflow { depth: 2 }));
+    assert_eq!(stack.len(), 2);
+    assert_eq!(stack.pop().map(|frame| frame.return_address()), Ok(0x304));
+}
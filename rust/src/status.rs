@@ -0,0 +1,119 @@
+//! The window title shows the ROM's name and how fast the emulator is
+//! actually running, refreshed about once a second. With --turbo, the
+//! speed reached over the whole run is reported on exit too.
+
+use std::time::{Duration, Instant};
+
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+pub(crate) struct TitleStatus {
+    rom_name: String,
+    last_update: Instant,
+    cycles_at_update: u64,
+    frames_since_update: u32,
+}
+
+impl TitleStatus {
+    pub(crate) fn new(rom_name: String) -> TitleStatus {
+        TitleStatus {
+            rom_name,
+            last_update: Instant::now(),
+            cycles_at_update: 0,
+            frames_since_update: 0,
+        }
+    }
+
+    /// The title to use before any speed has been measured.
+    pub(crate) fn initial_title(&self) -> String {
+        format!("CHIP-8 - {}", self.rom_name)
+    }
+
+    /// Count a repaint of the display.
+    pub(crate) fn frame_painted(&mut self) {
+        self.frames_since_update += 1;
+    }
+
+    /// Returns a new title if it's time to refresh it.
+    pub(crate) fn update(&mut self, cycles: u64, paused: bool) -> Option<String> {
+        let elapsed = self.last_update.elapsed();
+        if elapsed < UPDATE_INTERVAL {
+            return None;
+        }
+
+        let per_second = |count: u64| (count as f64 / elapsed.as_secs_f64()).round() as u64;
+        let title = format_title(
+            &self.rom_name,
+            per_second(cycles - self.cycles_at_update),
+            per_second(self.frames_since_update as u64),
+            paused,
+        );
+        self.last_update = Instant::now();
+        self.cycles_at_update = cycles;
+        self.frames_since_update = 0;
+        Some(title)
+    }
+}
+
+/// Counts the instructions run with --turbo, to report the speed
+/// reached.
+pub(crate) struct Throughput {
+    started: Instant,
+    instructions: u64,
+}
+
+impl Throughput {
+    pub(crate) fn new() -> Throughput {
+        Throughput {
+            started: Instant::now(),
+            instructions: 0,
+        }
+    }
+
+    /// Count an instruction run.
+    pub(crate) fn count(&mut self) {
+        self.instructions += 1;
+    }
+
+    pub(crate) fn report(&self) -> String {
+        format_throughput(self.instructions, self.started.elapsed())
+    }
+}
+
+/// The speed in millions of instructions per second.
+fn format_throughput(instructions: u64, elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+    format!(
+        "Ran {} instructions in {:.3}s: {:.2} MIPS",
+        instructions,
+        seconds,
+        instructions as f64 / seconds / 1_000_000.0
+    )
+}
+
+fn format_title(rom_name: &str, instructions_per_second: u64, frames_per_second: u64, paused: bool) -> String {
+    let mut title = format!(
+        "CHIP-8 - {} - {} IPS, {} FPS",
+        rom_name, instructions_per_second, frames_per_second
+    );
+    if paused {
+        title += " [paused]";
+    }
+    title
+}
+
+#[test]
+fn test_format_title() {
+    assert_eq!(format_title("Pong", 700, 60, false), "CHIP-8 - Pong - 700 IPS, 60 FPS");
+    assert_eq!(
+        format_title("Pong", 0, 0, true),
+        "CHIP-8 - Pong - 0 IPS, 0 FPS [paused]"
+    );
+}
+
+#[test]
+fn test_format_throughput() {
+    assert_eq!(
+        format_throughput(150_000_000, Duration::from_millis(2500)),
+        "Ran 150000000 instructions in 2.500s: 60.00 MIPS"
+    );
+}
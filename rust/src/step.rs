@@ -0,0 +1,113 @@
+//! Single-stepping with a record of what each instruction did, for
+//! tools built on the interpreter (tracers, coverage analyzers,
+//! debuggers) that need more than `processor_cycle` tells them.
+
+use crate::instruction::Instruction;
+use crate::vm::{execute, VM};
+
+/// One executed instruction and its effects.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Step {
+    /// Where the instruction was fetched from
+    pub pc: u16,
+    /// None for Mega-Chip instructions
+    pub instruction: Option<Instruction>,
+    /// Where execution continues
+    pub next_pc: u16,
+    pub diff: StateDiff,
+}
+
+/// The state an instruction changed, as (before, after) pairs. Only
+/// things that changed are included.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct StateDiff {
+    /// (register, before, after)
+    pub registers: Vec<(usize, u8, u8)>,
+    pub index: Option<(u16, u16)>,
+    pub delay_timer: Option<(u8, u8)>,
+    pub sound_timer: Option<(u8, u8)>,
+    pub stack_depth: Option<(usize, usize)>,
+    /// (address, before, after)
+    pub memory: Vec<(u16, u8, u8)>,
+    pub display_changed: bool,
+}
+
+impl StateDiff {
+    pub fn is_empty(&self) -> bool {
+        *self == StateDiff::default()
+    }
+}
+
+/// Execute the instruction at PC, like `processor_cycle`, and report
+/// what it did. This copies memory to find writes, so it's slower than
+/// `processor_cycle`.
+pub fn step(vm: &mut VM) -> Result<Step, String> {
+    let (pc, v, ir, delay_timer, sound_timer, stack_depth) =
+        (vm.pc, vm.v, vm.ir, vm.delay_timer, vm.sound_timer, vm.stack.len());
+    let memory = vm.memory.clone();
+    let display_hash = vm.display.hash();
+
+    let instruction = execute(vm)?;
+
+    let diff = StateDiff {
+        registers: (0..16)
+            .filter(|&i| v[i] != vm.v[i])
+            .map(|i| (i, v[i], vm.v[i]))
+            .collect(),
+        index: changed(ir, vm.ir),
+        delay_timer: changed(delay_timer, vm.delay_timer),
+        sound_timer: changed(sound_timer, vm.sound_timer),
+        stack_depth: changed(stack_depth, vm.stack.len()),
+        memory: (0..memory.len())
+            .filter(|&addr| memory[addr] != vm.memory[addr])
+            .map(|addr| (addr as u16, memory[addr], vm.memory[addr]))
+            .collect(),
+        display_changed: vm.display.hash() != display_hash,
+    };
+    Ok(Step {
+        pc,
+        instruction,
+        next_pc: vm.pc,
+        diff,
+    })
+}
+
+fn changed<T: PartialEq>(before: T, after: T) -> Option<(T, T)> {
+    if before != after {
+        Some((before, after))
+    } else {
+        None
+    }
+}
+
+#[test]
+fn test_step() {
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    crate::vm::load_rom(
+        &mut vm.memory,
+        &[
+            0x6A, 0x05, // 200: VA = 5
+            0xA3, 0x00, // 202: I = 300
+            0xFA, 0x33, // 204: store the digits of VA at I
+            0x22, 0x0A, // 206: call 20A
+            0x00, 0x00, // 208
+            0x00, 0xE0, // 20A: clear the screen
+        ],
+    )
+    .unwrap();
+
+    let step1 = step(&mut vm).unwrap();
+    assert_eq!(
+        (step1.pc, step1.instruction, step1.next_pc),
+        (0x200, Some(Instruction::SetVxNn { x: 0xA, nn: 5 }), 0x202)
+    );
+    assert_eq!(step1.diff.registers, vec![(0xA, 0, 5)]);
+
+    assert_eq!(step(&mut vm).unwrap().diff.index, Some((0, 0x300)));
+    assert_eq!(step(&mut vm).unwrap().diff.memory, vec![(0x302, 0, 5)]);
+    assert_eq!(step(&mut vm).unwrap().diff.stack_depth, Some((0, 1)));
+
+    // Clearing a blank screen changes nothing
+    let clear = step(&mut vm).unwrap();
+    assert!(!clear.diff.display_changed && clear.diff.is_empty());
+}
@@ -0,0 +1,217 @@
+//! Symbol files map the assembler's labels to addresses so the
+//! debugger and disassembler can use them. `chip8 build` writes one
+//! next to the ROM, with the same name and a `.sym` extension, and
+//! anything that reads the ROM picks it up from there. Each line is a
+//! hex address and a label, or a hex range of addresses and the name
+//! of what's stored there:
+//!
+//! ```text
+//! 200 start
+//! 20A main_loop
+//! 3A0-3A3 score
+//! ```
+//!
+//! Named ranges (regions) can also come from the ROM database or the
+//! debugger's `annotate` command, and addresses inside them are shown
+//! as e.g. `score+2`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A named range of memory, like a sprite or a score.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct Region {
+    pub(crate) start: u16,
+    /// The last address in the region
+    pub(crate) end: u16,
+    pub(crate) name: String,
+}
+
+impl Region {
+    pub(crate) fn contains(&self, addr: u16) -> bool {
+        (self.start..=self.end).contains(&addr)
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:03X}-{:03X} {}", self.start, self.end, self.name)
+    }
+}
+
+/// Parse a hex range like `3A0-3A3`, or a single address.
+pub(crate) fn parse_range(s: &str) -> Option<(u16, u16)> {
+    let parse = |addr: &str| {
+        let hex = addr.trim_start_matches("0x").trim_start_matches("0X");
+        u16::from_str_radix(hex, 16).ok().filter(|&addr| addr <= 0xFFF)
+    };
+    let (start, end) = match s.split_once('-') {
+        Some((start, end)) => (parse(start)?, parse(end)?),
+        None => (parse(s)?, parse(s)?),
+    };
+    Some((start, end)).filter(|(start, end)| start <= end)
+}
+
+#[derive(Debug, Default, PartialEq, Clone)]
+pub(crate) struct Symbols {
+    addresses: BTreeMap<String, u16>,
+    regions: Vec<Region>,
+}
+
+impl Symbols {
+    pub(crate) fn new(addresses: BTreeMap<String, u16>) -> Symbols {
+        Symbols {
+            addresses,
+            regions: Vec::new(),
+        }
+    }
+
+    /// The symbol file that goes with the ROM at `rom_path`.
+    pub(crate) fn path_for_rom(rom_path: &Path) -> PathBuf {
+        rom_path.with_extension("sym")
+    }
+
+    /// Load the symbols for the ROM at `rom_path`, or none if it
+    /// doesn't have a symbol file.
+    pub(crate) fn load_for_rom(rom_path: &Path) -> Result<Symbols, String> {
+        let path = Symbols::path_for_rom(rom_path);
+        if !path.exists() {
+            return Ok(Symbols::default());
+        }
+        let contents = fs::read_to_string(&path).map_err(|err| format!("Error reading {}: {}", path.display(), err))?;
+        Symbols::parse(&contents).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    pub(crate) fn parse(contents: &str) -> Result<Symbols, String> {
+        let mut symbols = Symbols::default();
+        for (num, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || format!("line {}: expected an address and a label, not {:?}", num + 1, line);
+            let (address, label) = line.split_once(' ').ok_or_else(invalid)?;
+            let label = label.trim().to_string();
+            if address.contains('-') {
+                let (start, end) = parse_range(address).ok_or_else(invalid)?;
+                symbols.add_region(start, end, label);
+            } else {
+                let address = u16::from_str_radix(address, 16).map_err(|_| invalid())?;
+                symbols.addresses.insert(label, address);
+            }
+        }
+        Ok(symbols)
+    }
+
+    /// The symbol file's contents, in address order.
+    pub(crate) fn to_file(&self) -> String {
+        let mut symbols: Vec<(&String, &u16)> = self.addresses.iter().collect();
+        symbols.sort_by_key(|&(label, address)| (*address, label));
+        symbols
+            .iter()
+            .map(|(label, address)| format!("{:03X} {}\n", address, label))
+            .chain(self.regions.iter().map(|region| format!("{}\n", region)))
+            .collect()
+    }
+
+    /// Name the addresses from `start` to `end`, replacing any region
+    /// with the same name.
+    pub(crate) fn add_region(&mut self, start: u16, end: u16, name: String) {
+        self.regions.retain(|region| region.name != name);
+        self.regions.push(Region { start, end, name });
+        self.regions.sort_by_key(|region| (region.start, region.end));
+    }
+
+    /// Regions in address order.
+    pub(crate) fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    pub(crate) fn region_named(&self, name: &str) -> Option<&Region> {
+        self.regions.iter().find(|region| region.name == name)
+    }
+
+    /// The smallest region containing `addr`, if any.
+    pub(crate) fn region(&self, addr: u16) -> Option<&Region> {
+        self.regions
+            .iter()
+            .filter(|region| region.contains(addr))
+            .min_by_key(|region| region.end - region.start)
+    }
+
+    /// A name for `addr`: its label, or where it is in a region like
+    /// `score+2`.
+    pub(crate) fn name(&self, addr: u16) -> Option<String> {
+        if let Some(label) = self.label(addr) {
+            return Some(label.to_string());
+        }
+        self.region(addr).map(|region| match addr - region.start {
+            0 => region.name.clone(),
+            offset => format!("{}+{}", region.name, offset),
+        })
+    }
+
+    pub(crate) fn address(&self, label: &str) -> Option<u16> {
+        self.addresses.get(label).copied()
+    }
+
+    /// The first label at `address`, if any.
+    pub(crate) fn label(&self, address: u16) -> Option<&str> {
+        self.addresses
+            .iter()
+            .filter(|&(_, &label_address)| label_address == address)
+            .map(|(label, _)| label.as_str())
+            .next()
+    }
+
+    /// Parse a location given as a label, a region's name (for its
+    /// start) or a hex address.
+    pub(crate) fn parse_location(&self, s: &str) -> Result<u16, String> {
+        if let Some(address) = self.address(s) {
+            return Ok(address);
+        }
+        if let Some(region) = self.region_named(s) {
+            return Ok(region.start);
+        }
+        let hex = s.trim_start_matches("0x").trim_start_matches("0X");
+        u16::from_str_radix(hex, 16)
+            .ok()
+            .filter(|&address| address <= 0xFFF)
+            .ok_or_else(|| format!("{:?} is neither a label nor an address", s))
+    }
+}
+
+#[test]
+fn test_symbols() {
+    let symbols = Symbols::parse("20A main_loop\n200 start\n\n").unwrap();
+    assert_eq!(symbols.address("main_loop"), Some(0x20A));
+    assert_eq!(symbols.label(0x200), Some("start"));
+    assert_eq!(symbols.label(0x202), None);
+    assert_eq!(symbols.to_file(), "200 start\n20A main_loop\n");
+    assert_eq!(Symbols::parse(&symbols.to_file()), Ok(symbols.clone()));
+
+    assert_eq!(symbols.parse_location("main_loop"), Ok(0x20A));
+    assert_eq!(symbols.parse_location("0x2F0"), Ok(0x2F0));
+    assert!(symbols.parse_location("nowhere").is_err());
+    assert!(Symbols::parse("start").is_err());
+}
+
+#[test]
+fn test_regions() {
+    let mut symbols = Symbols::parse("200 start\n300-33F sprites\n").unwrap();
+    symbols.add_region(0x310, 0x317, "ship".to_string());
+    assert_eq!(symbols.name(0x200).as_deref(), Some("start"));
+    assert_eq!(symbols.name(0x302).as_deref(), Some("sprites+2"));
+    assert_eq!(symbols.name(0x311).as_deref(), Some("ship+1"));
+    assert_eq!(symbols.name(0x340), None);
+    assert_eq!(symbols.parse_location("ship"), Ok(0x310));
+    assert_eq!(symbols.to_file(), "200 start\n300-33F sprites\n310-317 ship\n");
+
+    symbols.add_region(0x320, 0x327, "ship".to_string());
+    assert_eq!(symbols.regions().len(), 2);
+    assert_eq!(parse_range("3A0"), Some((0x3A0, 0x3A0)));
+    assert_eq!(parse_range("3A3-3A0"), None);
+    assert!(Symbols::parse("33F-300 sprites").is_err());
+}
@@ -0,0 +1,102 @@
+//! Text drawn on a CHIP-8 display with a tiny font, for the ROM menu
+//! and the keypad panel, so they look the same in every frontend. The
+//! SDL frontend's debug views use the same font.
+
+use chip8::display::Display;
+
+/// Glyphs are 3x5 pixels, drawn with a pixel of space after each
+/// character and each line.
+pub(crate) const GLYPH_WIDTH: usize = 3;
+pub(crate) const GLYPH_HEIGHT: usize = 5;
+pub(crate) const CHAR_WIDTH: usize = GLYPH_WIDTH + 1;
+pub(crate) const LINE_HEIGHT: usize = GLYPH_HEIGHT + 1;
+
+/// The built-in CHIP-8 font only has hex digits, so this covers the
+/// characters that commonly appear in ROM file names, on keyboard keys
+/// and in disassembly. Each row is 3 bits, most significant on the
+/// left. Anything else is drawn as '?'.
+const GLYPHS: [(char, [u8; GLYPH_HEIGHT]); 53] = [
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('K', [0b101, 0b101, 0b110, 0b101, 0b101]),
+    ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('O', [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('W', [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('Y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b110, 0b001, 0b010, 0b100, 0b111]),
+    ('3', [0b110, 0b001, 0b010, 0b001, 0b110]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b110, 0b001, 0b110]),
+    ('6', [0b011, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b010, 0b010, 0b010]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b110]),
+    (' ', [0b000, 0b000, 0b000, 0b000, 0b000]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    ('_', [0b000, 0b000, 0b000, 0b000, 0b111]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    (',', [0b000, 0b000, 0b000, 0b010, 0b100]),
+    ('(', [0b001, 0b010, 0b010, 0b010, 0b001]),
+    (')', [0b100, 0b010, 0b010, 0b010, 0b100]),
+    ('[', [0b011, 0b010, 0b010, 0b010, 0b011]),
+    (']', [0b110, 0b010, 0b010, 0b010, 0b110]),
+    ('{', [0b011, 0b010, 0b100, 0b010, 0b011]),
+    ('}', [0b110, 0b010, 0b001, 0b010, 0b110]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    ('=', [0b000, 0b111, 0b000, 0b111, 0b000]),
+    ('+', [0b000, 0b010, 0b111, 0b010, 0b000]),
+    ('>', [0b100, 0b010, 0b001, 0b010, 0b100]),
+    ('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+    ('*', [0b000, 0b101, 0b010, 0b101, 0b000]),
+];
+
+const UNKNOWN_GLYPH: [u8; GLYPH_HEIGHT] = [0b110, 0b001, 0b010, 0b000, 0b010];
+
+/// Draw a line of text with its top left corner at (`x`, `y`),
+/// truncated to the display's width. Inverted text is drawn by turning
+/// pixels off, for drawing on a highlighted background.
+pub(crate) fn draw_text(display: &mut Display, x: usize, y: usize, text: &str, inverted: bool) {
+    let columns = (display.width() + 1).saturating_sub(x) / CHAR_WIDTH;
+    let text: String = text.chars().take(columns).collect();
+    text_pixels(&text, |dx, dy| display.set_pixel(x + dx, y + dy, !inverted));
+}
+
+/// Call `lit` with the position of each lit pixel of a line of text,
+/// relative to its top left corner.
+pub(crate) fn text_pixels(text: &str, mut lit: impl FnMut(usize, usize)) {
+    for (column, c) in text.chars().enumerate() {
+        let c = c.to_ascii_uppercase();
+        let glyph = GLYPHS
+            .iter()
+            .find(|(glyph_char, _)| *glyph_char == c)
+            .map_or(UNKNOWN_GLYPH, |(_, glyph)| *glyph);
+        for (dy, bits) in glyph.iter().enumerate() {
+            for dx in 0..GLYPH_WIDTH {
+                if bits >> (GLYPH_WIDTH - 1 - dx) & 1 == 1 {
+                    lit(column * CHAR_WIDTH + dx, dy);
+                }
+            }
+        }
+    }
+}
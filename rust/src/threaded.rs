@@ -0,0 +1,251 @@
+//! Threaded-code dispatch. Rather than decoding each instruction and
+//! matching on it every time it runs, runs of straight-line code
+//! ("blocks") are compiled the first time PC reaches them into a table
+//! of ops, one per instruction: a function pointer with the operands
+//! already pulled out of the opcode. Running an instruction is then a
+//! table lookup and a call. Simple instructions get functions of their
+//! own, while the rest call back into the interpreter, so quirks and
+//! errors work the same either way.
+//!
+//! Like the decode cache, blocks are thrown away when memory they cover
+//! is written, so programs can still modify their own code.
+
+use crate::instruction::{parse_instruction, Instruction};
+use crate::vm::{fetch, run_instruction, MEMORY_BYTES, VM};
+
+/// The most instructions compiled into one block. Blocks also end at
+/// jumps, calls and returns.
+const MAX_BLOCK_INSTRUCTIONS: usize = 32;
+
+/// An instruction compiled to the function that runs it, once PC has
+/// been moved past it.
+#[derive(Clone, Copy)]
+pub(crate) struct Op {
+    pub(crate) instruction: Instruction,
+    function: fn(&mut VM, &Op) -> Result<(), String>,
+    x: u8,
+    y: u8,
+    nn: u8,
+    nnn: u16,
+    /// Where the block the op was compiled in starts
+    block: u16,
+}
+
+impl Op {
+    pub(crate) fn run(&self, vm: &mut VM) -> Result<(), String> {
+        (self.function)(vm, self)
+    }
+}
+
+pub struct BlockCache {
+    /// The op at each address, if a block covers it
+    ops: Box<[Option<Op>]>,
+    /// The number of instructions in the block starting at each address
+    block_lengths: Box<[u8]>,
+}
+
+impl Default for BlockCache {
+    fn default() -> BlockCache {
+        BlockCache::new(MEMORY_BYTES)
+    }
+}
+
+impl BlockCache {
+    /// A cache for a VM with `memory_bytes` of memory.
+    pub fn new(memory_bytes: usize) -> BlockCache {
+        BlockCache {
+            ops: vec![None; memory_bytes].into_boxed_slice(),
+            block_lengths: vec![0; memory_bytes].into_boxed_slice(),
+        }
+    }
+
+    /// The op at `addr`, if a block covering it has been compiled.
+    pub(crate) fn lookup(&self, addr: u16) -> Option<Op> {
+        self.ops[addr as usize % self.ops.len()]
+    }
+
+    /// Compile a block starting at `addr`, which no block covers yet, and
+    /// return its first op.
+    #[cold]
+    pub(crate) fn compile(&mut self, addr: u16, memory: &[u8]) -> Result<Op, String> {
+        let addr = addr as usize % memory.len();
+        let block = compile(addr as u16, memory)?;
+        self.block_lengths[addr] = block.len() as u8;
+        for (index, op) in block.into_iter().enumerate() {
+            // Keep the blocks that already cover addresses, so jumping
+            // into the middle of a block doesn't compile over it
+            self.ops[addr + index * 2].get_or_insert(op);
+        }
+        Ok(self.ops[addr].expect("just compiled"))
+    }
+
+    /// Throw away any block with an instruction that includes the byte
+    /// at `addr`.
+    pub fn invalidate(&mut self, addr: usize) {
+        let len = self.ops.len();
+        for addr in [addr % len, (addr + len - 1) % len] {
+            if let Some(op) = self.ops[addr] {
+                let start = op.block as usize;
+                let end = start + self.block_lengths[start] as usize * 2;
+                for entry in &mut self.ops[start..end] {
+                    if entry.is_some_and(|other| other.block == op.block) {
+                        *entry = None;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.ops.iter_mut().for_each(|op| *op = None);
+    }
+}
+
+/// Compile the block starting at `start`. It ends before an invalid
+/// instruction, or if the first one is invalid, that's the error.
+fn compile(start: u16, memory: &[u8]) -> Result<Vec<Op>, String> {
+    let mut ops = Vec::new();
+    let mut addr = start as usize;
+    while ops.len() < MAX_BLOCK_INSTRUCTIONS && addr + 1 < memory.len() {
+        let word = fetch(memory, addr as u16);
+        let instruction = match parse_instruction(word) {
+            Ok(instruction) => instruction,
+            Err(err) if ops.is_empty() => return Err(err),
+            Err(_) => break,
+        };
+        ops.push(Op {
+            instruction,
+            function: function(instruction),
+            x: (word >> 8 & 0xF) as u8,
+            y: (word >> 4 & 0xF) as u8,
+            nn: (word & 0xFF) as u8,
+            nnn: word & 0xFFF,
+            block: start,
+        });
+        if ends_block(instruction) {
+            break;
+        }
+        addr += 2;
+    }
+    Ok(ops)
+}
+
+/// Whether PC never goes on to the next instruction after this one.
+fn ends_block(instruction: Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Jump { .. }
+            | Instruction::JumpV0Nnn { .. }
+            | Instruction::SubroutineCall { .. }
+            | Instruction::SubroutineReturn
+            | Instruction::Exit
+    )
+}
+
+/// The function that runs `instruction`. These must do just what
+/// `run_instruction` does, which `VM::verify_caches` checks.
+fn function(instruction: Instruction) -> fn(&mut VM, &Op) -> Result<(), String> {
+    match instruction {
+        Instruction::Jump { .. } => |vm, op| {
+            vm.pc = op.nnn;
+            Ok(())
+        },
+        Instruction::SkipVxEqNn { .. } => |vm, op| {
+            if vm.v[op.x as usize] == op.nn {
+                vm.pc = vm.pc.wrapping_add(2);
+            }
+            Ok(())
+        },
+        Instruction::SkipVxNeqNn { .. } => |vm, op| {
+            if vm.v[op.x as usize] != op.nn {
+                vm.pc = vm.pc.wrapping_add(2);
+            }
+            Ok(())
+        },
+        Instruction::SkipVxEqVy { .. } => |vm, op| {
+            if vm.v[op.x as usize] == vm.v[op.y as usize] {
+                vm.pc = vm.pc.wrapping_add(2);
+            }
+            Ok(())
+        },
+        Instruction::SkipVxNeqVy { .. } => |vm, op| {
+            if vm.v[op.x as usize] != vm.v[op.y as usize] {
+                vm.pc = vm.pc.wrapping_add(2);
+            }
+            Ok(())
+        },
+        Instruction::SetVxNn { .. } => |vm, op| {
+            vm.v[op.x as usize] = op.nn;
+            Ok(())
+        },
+        Instruction::AddNnVx { .. } => |vm, op| {
+            vm.v[op.x as usize] = vm.v[op.x as usize].wrapping_add(op.nn);
+            Ok(())
+        },
+        Instruction::SetVxVy { .. } => |vm, op| {
+            vm.v[op.x as usize] = vm.v[op.y as usize];
+            Ok(())
+        },
+        Instruction::SetVxPlusVy { .. } => |vm, op| {
+            let (sum, carry) = vm.v[op.x as usize].overflowing_add(vm.v[op.y as usize]);
+            vm.v[op.x as usize] = sum;
+            vm.v[0xF] = carry as u8;
+            Ok(())
+        },
+        Instruction::SetVxMinusVy { .. } => |vm, op| {
+            let (difference, borrow) = vm.v[op.x as usize].overflowing_sub(vm.v[op.y as usize]);
+            vm.v[op.x as usize] = difference;
+            vm.v[0xF] = !borrow as u8;
+            Ok(())
+        },
+        Instruction::SetIndexNnn { .. } => |vm, op| {
+            vm.ir = op.nnn;
+            Ok(())
+        },
+        Instruction::SetVxDelay { .. } => |vm, op| {
+            vm.v[op.x as usize] = vm.delay_timer;
+            Ok(())
+        },
+        Instruction::SetDelayVx { .. } => |vm, op| {
+            vm.delay_timer = vm.v[op.x as usize];
+            Ok(())
+        },
+        Instruction::SetSoundVx { .. } => |vm, op| {
+            vm.sound_timer = vm.v[op.x as usize];
+            Ok(())
+        },
+        _ => |vm, op| run_instruction(vm, op.instruction),
+    }
+}
+
+#[test]
+fn test_blocks() {
+    let mut memory = [0; MEMORY_BYTES];
+    memory[0x200..0x208].copy_from_slice(&[0x60, 0x01, 0x70, 0x02, 0x12, 0x00, 0x00, 0xE0]);
+    let mut cache = BlockCache::new(MEMORY_BYTES);
+    assert!(cache.lookup(0x200).is_none());
+    let op = cache.compile(0x200, &memory).unwrap();
+    assert_eq!(op.instruction, Instruction::SetVxNn { x: 0, nn: 1 });
+    // The block ends at the jump
+    assert_eq!(cache.block_lengths[0x200], 3);
+    assert!(cache.ops[0x206].is_none());
+    let op = cache.lookup(0x202).unwrap();
+    assert_eq!(
+        (op.instruction, op.block),
+        (Instruction::AddNnVx { x: 0, nn: 2 }, 0x200)
+    );
+
+    // Writing to the block throws all of it away
+    memory[0x203] = 0x05;
+    cache.invalidate(0x203);
+    assert!(cache.ops[0x200].is_none() && cache.ops[0x204].is_none());
+    assert!(cache.lookup(0x202).is_none());
+    let op = cache.compile(0x202, &memory).unwrap();
+    assert_eq!(
+        (op.instruction, op.block),
+        (Instruction::AddNnVx { x: 0, nn: 5 }, 0x202)
+    );
+
+    memory[0x300] = 0xFF;
+    assert!(cache.compile(0x300, &memory).is_err());
+}
@@ -0,0 +1,251 @@
+//! Execution tracing. Every executed instruction can be logged with its
+//! address, the decoded instruction, and the registers it changed, e.g.
+//!
+//! ```text
+//! 000001 PC 0x202: 610D LD V1, 0x0D  V1=0D
+//! ```
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::rc::Rc;
+
+use chip8::instruction::parse_instruction;
+use chip8::vm::{fetch, VM};
+
+/// Destination for trace lines.
+pub(crate) trait TraceSink {
+    fn write_line(&mut self, line: &str) -> io::Result<()>;
+}
+
+/// Lets a sink be shared, so it can be read back while the tracer is
+/// still writing to it.
+impl<T: TraceSink> TraceSink for Rc<RefCell<T>> {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.borrow_mut().write_line(line)
+    }
+}
+
+pub(crate) struct StderrSink;
+
+impl TraceSink for StderrSink {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        writeln!(io::stderr(), "{}", line)
+    }
+}
+
+/// Writes trace lines to a file. They're buffered, so the file is only
+/// complete once the sink is dropped.
+pub(crate) struct FileSink {
+    out: BufWriter<File>,
+}
+
+impl FileSink {
+    pub(crate) fn create(path: &Path) -> io::Result<FileSink> {
+        Ok(FileSink {
+            out: BufWriter::new(File::create(path)?),
+        })
+    }
+}
+
+impl TraceSink for FileSink {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.out, "{}", line)
+    }
+}
+
+/// Keeps only the most recent `capacity` lines in memory, so the
+/// instructions leading up to a problem can be shown after the fact.
+pub(crate) struct RingBufferSink {
+    lines: VecDeque<String>,
+    capacity: usize,
+}
+
+impl RingBufferSink {
+    pub(crate) fn new(capacity: usize) -> RingBufferSink {
+        RingBufferSink {
+            lines: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub(crate) fn lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().map(String::as_str)
+    }
+}
+
+impl TraceSink for RingBufferSink {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.capacity == 0 {
+            return Ok(());
+        }
+        if self.lines.len() == self.capacity {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line.to_string());
+        Ok(())
+    }
+}
+
+/// The registers an instruction can change, captured before it runs so
+/// the changes can be logged afterwards.
+pub(crate) struct Registers {
+    pc: u16,
+    /// The instruction about to run, read now in case it overwrites
+    /// itself
+    opcode: u16,
+    ir: u16,
+    v: [u8; 16],
+    delay_timer: u8,
+    sound_timer: u8,
+    stack_depth: usize,
+}
+
+impl Registers {
+    pub(crate) fn capture(vm: &VM) -> Registers {
+        Registers {
+            pc: vm.pc,
+            opcode: fetch(&vm.memory, vm.pc),
+            ir: vm.ir,
+            v: vm.v,
+            delay_timer: vm.delay_timer,
+            sound_timer: vm.sound_timer,
+            stack_depth: vm.stack.len(),
+        }
+    }
+
+    /// Describe every register that differs between `self` and `vm`,
+    /// other than the usual advance of the program counter.
+    fn changes(&self, vm: &VM) -> Vec<String> {
+        let mut changes = Vec::new();
+        for (i, (&before, &after)) in self.v.iter().zip(vm.v.iter()).enumerate() {
+            if before != after {
+                changes.push(format!("V{:X}={:02X}", i, after));
+            }
+        }
+        if self.ir != vm.ir {
+            changes.push(format!("I={:03X}", vm.ir));
+        }
+        if self.delay_timer != vm.delay_timer {
+            changes.push(format!("DT={:02X}", vm.delay_timer));
+        }
+        if self.sound_timer != vm.sound_timer {
+            changes.push(format!("ST={:02X}", vm.sound_timer));
+        }
+        if self.stack_depth != vm.stack.len() {
+            changes.push(format!("SP={}", vm.stack.len()));
+        }
+        if vm.pc != self.pc.wrapping_add(2) {
+            changes.push(format!("PC={:03X}", vm.pc));
+        }
+        changes
+    }
+}
+
+pub(crate) struct Tracer {
+    sinks: Vec<Box<dyn TraceSink>>,
+
+    /// Only instructions at these addresses are traced, if set
+    pc_range: Option<RangeInclusive<u16>>,
+}
+
+impl Tracer {
+    pub(crate) fn new(sinks: Vec<Box<dyn TraceSink>>, pc_range: Option<RangeInclusive<u16>>) -> Tracer {
+        Tracer { sinks, pc_range }
+    }
+
+    /// Log the instruction that was just executed, given the registers
+    /// from before it ran.
+    pub(crate) fn trace(&mut self, before: &Registers, vm: &VM) -> Result<(), String> {
+        if let Some(pc_range) = &self.pc_range {
+            if !pc_range.contains(&before.pc) {
+                return Ok(());
+            }
+        }
+
+        let decoded = match parse_instruction(before.opcode) {
+            Ok(instruction) => instruction.to_string(),
+            Err(err) => err,
+        };
+        let line = format!(
+            "{:06} PC {:#05X}: {:04X} {}  {}",
+            vm.cycles,
+            before.pc,
+            before.opcode,
+            decoded,
+            before.changes(vm).join(" ")
+        );
+        for sink in &mut self.sinks {
+            sink.write_line(line.trim_end())
+                .map_err(|err| format!("Error writing trace: {}", err))?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse a PC range like `200-2FF` (hex, inclusive).
+pub(crate) fn parse_pc_range(s: &str) -> Result<RangeInclusive<u16>, String> {
+    let invalid = || format!("Invalid PC range {:?}, expected e.g. 200-2FF", s);
+    let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+    let parse = |addr: &str| {
+        let addr = addr.trim_start_matches("0x").trim_start_matches("0X");
+        u16::from_str_radix(addr, 16).map_err(|_| invalid())
+    };
+    let (start, end) = (parse(start)?, parse(end)?);
+    if start > end {
+        return Err(invalid());
+    }
+    Ok(start..=end)
+}
+
+#[test]
+fn test_parse_pc_range() {
+    assert_eq!(parse_pc_range("200-2FF"), Ok(0x200..=0x2FF));
+    assert_eq!(parse_pc_range("0x300-0x310"), Ok(0x300..=0x310));
+    assert!(parse_pc_range("300").is_err());
+    assert!(parse_pc_range("300-200").is_err());
+}
+
+#[test]
+fn test_ring_buffer_sink() {
+    let mut sink = RingBufferSink::new(2);
+    sink.write_line("a").unwrap();
+    sink.write_line("b").unwrap();
+    sink.write_line("c").unwrap();
+    assert_eq!(sink.lines().collect::<Vec<_>>(), vec!["b", "c"]);
+}
+
+#[test]
+fn test_trace() {
+    let sink = Rc::new(RefCell::new(RingBufferSink::new(2)));
+    let mut tracer = Tracer::new(vec![Box::new(sink.clone())], None);
+    let mut vm = VM::new(Box::new(chip8::random::SeededRandom::new(0)));
+    let mut step = |vm: &mut VM| {
+        let before = Registers::capture(vm);
+        chip8::vm::processor_cycle(vm).unwrap();
+        tracer.trace(&before, vm).unwrap();
+    };
+
+    // F055 at 200 stores V0 over itself, but the line shows what ran
+    vm.v[0] = 0x12;
+    vm.ir = 0x200;
+    vm.memory[0x200..0x202].copy_from_slice(&[0xF0, 0x55]);
+    step(&mut vm);
+    // An instruction at FFF wraps around to 000 for its second byte
+    vm.pc = 0xFFF;
+    vm.memory[0xFFF] = 0x61;
+    vm.memory[0x000] = 0x05;
+    step(&mut vm);
+
+    let lines: Vec<String> = sink.borrow().lines().map(str::to_string).collect();
+    assert!(lines[0].starts_with("000000 PC 0x200: F055 "), "{}", lines[0]);
+    assert!(
+        lines[1].starts_with("000000 PC 0xFFF: 6105 LD V1, 0x05  V1=05"),
+        "{}",
+        lines[1]
+    );
+}
@@ -0,0 +1,434 @@
+//! Debug views shown in windows of their own beside the display with
+//! `--views`: the registers, the stack and the code around PC in one,
+//! the memory around I in another, and the breakpoints and quirks in
+//! two more. They're plain text, rebuilt every frame, and only the SDL
+//! frontend shows them.
+//!
+//! Clicking some of the lines does something: an instruction in the CPU
+//! view adds or removes a breakpoint there (or with the right button,
+//! runs until PC gets there, without adding one), a breakpoint in the
+//! Breakpoints view deletes it, and a quirk in the Quirks view turns it
+//! on or off. While paused, clicking a register or a byte of memory
+//! edits it: typing hex digits replaces its value, which is set once
+//! they're all typed or on Enter.
+
+use std::ops::Range;
+
+use chip8::instruction::parse_instruction;
+use chip8::quirks::{Quirks, QUIRK_NAMES};
+use chip8::vm::{MEMORY_BYTES, VM};
+
+use crate::frontend::EditKey;
+
+use crate::disassembler::describe;
+use crate::symbols::Symbols;
+
+/// How many instructions to show before and after PC.
+const CODE_BEFORE: usize = 4;
+const CODE_AFTER: usize = 12;
+
+/// How many rows of memory to show, and how many of them before the one
+/// I points into.
+const MEMORY_ROWS: usize = 16;
+const MEMORY_ROWS_BEFORE: usize = 4;
+const BYTES_PER_ROW: usize = 8;
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct View {
+    /// Also the title of the view's window
+    pub(crate) title: &'static str,
+    pub(crate) lines: Vec<String>,
+    /// What clicking parts of the view does: the line number, the
+    /// columns and the action
+    actions: Vec<(usize, Range<usize>, ViewAction)>,
+}
+
+impl View {
+    fn new(title: &'static str, lines: Vec<String>) -> View {
+        View {
+            title,
+            lines,
+            actions: Vec::new(),
+        }
+    }
+
+    /// What clicking `column` of line number `line` does, if anything.
+    pub(crate) fn action(&self, line: usize, column: usize) -> Option<ViewAction> {
+        self.actions
+            .iter()
+            .find(|(action_line, columns, _)| *action_line == line && columns.contains(&column))
+            .map(|&(_, _, action)| action)
+    }
+}
+
+/// Something the views can edit.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum Field {
+    Memory(u16),
+    V(u8),
+    Pc,
+    I,
+    DelayTimer,
+    SoundTimer,
+}
+
+impl Field {
+    /// How many hex digits its values have in `vm`, whose PC and I need
+    /// four if it has more than 4 KB of memory, like XO-CHIP
+    fn digits(self, vm: &VM) -> usize {
+        match self {
+            Field::Pc | Field::I if vm.memory.len() > MEMORY_BYTES => 4,
+            Field::Pc | Field::I => 3,
+            _ => 2,
+        }
+    }
+
+    fn set(self, vm: &mut VM, value: u16) {
+        match self {
+            Field::Memory(addr) => vm.poke(addr as usize, value as u8),
+            Field::V(x) => vm.v[x as usize] = value as u8,
+            Field::Pc => vm.pc = value,
+            Field::I => vm.ir = value,
+            Field::DelayTimer => vm.delay_timer = value as u8,
+            Field::SoundTimer => vm.sound_timer = value as u8,
+        }
+    }
+}
+
+/// A field being edited, with the digits typed so far.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Edit {
+    field: Field,
+    digits: String,
+}
+
+impl Edit {
+    pub(crate) fn new(field: Field) -> Edit {
+        Edit {
+            field,
+            digits: String::new(),
+        }
+    }
+
+    /// Handle a key typed while editing, setting the field in `vm` once
+    /// it's done. Returns whether the edit is still going.
+    pub(crate) fn type_key(&mut self, key: EditKey, vm: &mut VM) -> bool {
+        match key {
+            EditKey::Digit(digit) => self.digits.push_str(&format!("{:X}", digit)),
+            EditKey::Backspace => {
+                self.digits.pop();
+            }
+            EditKey::Enter if self.digits.is_empty() => return false,
+            EditKey::Enter => {}
+            EditKey::Escape => return false,
+        }
+        if key == EditKey::Enter || self.digits.len() == self.field.digits(vm) {
+            // Only hex digits are typed, and not too many for a u16
+            let value = u16::from_str_radix(&self.digits, 16).unwrap();
+            self.field.set(vm, value);
+            return false;
+        }
+        true
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum ViewAction {
+    /// Add or remove a breakpoint at an instruction's address
+    ToggleBreakpoint(u16),
+    /// Delete a breakpoint, numbered as the debugger's `breaks` lists
+    /// them
+    DeleteBreakpoint(usize),
+    /// Turn a quirk on or off
+    ToggleQuirk(&'static str),
+    /// Start editing a register or a byte of memory
+    Edit(Field),
+}
+
+/// Build every view. `breakpoints` are the debugger's, as returned by
+/// `Debugger::breakpoints`, and `edit` is the field being edited, if
+/// any.
+pub(crate) fn views(
+    vm: &VM,
+    symbols: &Symbols,
+    breakpoints: &[(Option<u16>, String)],
+    edit: Option<&Edit>,
+) -> Vec<View> {
+    vec![
+        cpu_view(vm, symbols, breakpoints, edit),
+        memory_view(vm, symbols, edit),
+        breakpoints_view(breakpoints),
+        quirks_view(&vm.quirks),
+    ]
+}
+
+/// Add `field`'s value in `vm` to the end of `line`, or the digits
+/// typed so far if it's being edited, and make clicking it edit it.
+fn push_field(
+    line: &mut String,
+    actions: &mut Vec<(usize, Range<usize>, ViewAction)>,
+    line_number: usize,
+    (field, value): (Field, u16),
+    vm: &VM,
+    edit: Option<&Edit>,
+) {
+    let start = line.len();
+    let width = field.digits(vm);
+    match edit.filter(|edit| edit.field == field) {
+        Some(edit) => *line += &format!("{:_<width$}", edit.digits, width = width),
+        None => *line += &format!("{:0width$X}", value, width = width),
+    }
+    actions.push((line_number, start..line.len(), ViewAction::Edit(field)));
+}
+
+/// The registers, return addresses and the code around PC, with PC's
+/// instruction marked by `>` and those with a breakpoint by `*`.
+fn cpu_view(vm: &VM, symbols: &Symbols, breakpoints: &[(Option<u16>, String)], edit: Option<&Edit>) -> View {
+    let mut actions = Vec::new();
+    let mut timers = String::new();
+    let fields = [
+        ("PC ", Field::Pc, vm.pc),
+        ("  I ", Field::I, vm.ir),
+        ("  DT ", Field::DelayTimer, vm.delay_timer as u16),
+        ("  ST ", Field::SoundTimer, vm.sound_timer as u16),
+    ];
+    for (name, field, value) in fields {
+        timers += name;
+        push_field(&mut timers, &mut actions, 0, (field, value), vm, edit);
+    }
+    let mut registers = |range: Range<usize>, line_number| {
+        let mut line = format!("V{:X}-V{:X}", range.start, range.end - 1);
+        for x in range {
+            line.push(' ');
+            let field = (Field::V(x as u8), vm.v[x] as u16);
+            push_field(&mut line, &mut actions, line_number, field, vm, edit);
+        }
+        line
+    };
+    let (low, high) = (registers(0..8, 1), registers(8..16, 2));
+    let stack: Vec<String> = vm
+        .stack
+        .frames()
+        .iter()
+        .map(|frame| format!("{:03X}", frame.return_address()))
+        .collect();
+    let stack = if stack.is_empty() {
+        "-".to_string()
+    } else {
+        stack.join(" ")
+    };
+    let mut lines = vec![timers, low, high, format!("STACK {}", stack), String::new()];
+
+    let pc = vm.pc as usize;
+    let start = pc.saturating_sub(CODE_BEFORE * 2);
+    for addr in (start..pc + CODE_AFTER * 2).step_by(2) {
+        if addr + 1 >= vm.memory.len() {
+            break;
+        }
+        if let Some(label) = symbols.label(addr as u16) {
+            lines.push(format!("{}:", label));
+        }
+        let raw = (vm.memory[addr] as u16) << 8 | vm.memory[addr + 1] as u16;
+        let instruction = match parse_instruction(raw) {
+            Ok(instruction) => describe(&instruction, symbols),
+            Err(_) => "(data)".to_string(),
+        };
+        let marker = if addr == pc {
+            '>'
+        } else if breakpoints.iter().any(|&(bp_addr, _)| bp_addr == Some(addr as u16)) {
+            '*'
+        } else {
+            ' '
+        };
+        actions.push((lines.len(), 0..usize::MAX, ViewAction::ToggleBreakpoint(addr as u16)));
+        lines.push(format!("{}{:03X} {:04X} {}", marker, addr, raw, instruction));
+    }
+    View {
+        title: "CPU",
+        lines,
+        actions,
+    }
+}
+
+/// A hex dump of the memory around I, with the byte at I marked by `>`
+/// and rows ending with the names of the regions they overlap.
+fn memory_view(vm: &VM, symbols: &Symbols, edit: Option<&Edit>) -> View {
+    let mut actions = Vec::new();
+    let ir = vm.ir as usize;
+    let last_start = vm.memory.len() - MEMORY_ROWS * BYTES_PER_ROW;
+    let start = (ir / BYTES_PER_ROW)
+        .saturating_sub(MEMORY_ROWS_BEFORE)
+        .saturating_mul(BYTES_PER_ROW)
+        .min(last_start);
+    let lines = (start..start + MEMORY_ROWS * BYTES_PER_ROW)
+        .step_by(BYTES_PER_ROW)
+        .enumerate()
+        .map(|(line_number, row_start)| {
+            let mut line = format!("{:03X}:", row_start);
+            for addr in row_start..row_start + BYTES_PER_ROW {
+                line.push(if addr == ir { '>' } else { ' ' });
+                let field = (Field::Memory(addr as u16), vm.memory[addr] as u16);
+                push_field(&mut line, &mut actions, line_number, field, vm, edit);
+            }
+            let row_end = (row_start + BYTES_PER_ROW - 1) as u16;
+            let regions: Vec<&str> = symbols
+                .regions()
+                .iter()
+                .filter(|region| region.start <= row_end && region.end >= row_start as u16)
+                .map(|region| region.name.as_str())
+                .collect();
+            if !regions.is_empty() {
+                line += &format!("  {}", regions.join(", "));
+            }
+            line
+        })
+        .collect();
+    View {
+        title: "Memory",
+        lines,
+        actions,
+    }
+}
+
+/// The debugger's breakpoints, numbered as `breaks` lists them.
+fn breakpoints_view(breakpoints: &[(Option<u16>, String)]) -> View {
+    if breakpoints.is_empty() {
+        return View::new("Breakpoints", vec!["None, click an instruction".to_string()]);
+    }
+    let lines = breakpoints
+        .iter()
+        .enumerate()
+        .map(|(i, (_, description))| format!("{}: {}", i, description))
+        .collect();
+    View {
+        title: "Breakpoints",
+        lines,
+        actions: (0..breakpoints.len())
+            .map(|i| (i, 0..usize::MAX, ViewAction::DeleteBreakpoint(i)))
+            .collect(),
+    }
+}
+
+/// Every quirk, with `[X]` beside those that are on.
+fn quirks_view(quirks: &Quirks) -> View {
+    let lines = QUIRK_NAMES
+        .iter()
+        .map(|&name| {
+            let on = if quirks.get(name) == Some(true) { 'X' } else { ' ' };
+            format!("[{}] {}", on, name)
+        })
+        .collect();
+    View {
+        title: "Quirks",
+        lines,
+        actions: QUIRK_NAMES
+            .iter()
+            .enumerate()
+            .map(|(i, &name)| (i, 0..usize::MAX, ViewAction::ToggleQuirk(name)))
+            .collect(),
+    }
+}
+
+#[test]
+fn test_views() {
+    let mut vm = VM::new(Box::new(chip8::random::SeededRandom::new(0)));
+    vm.reload(&[0x60, 0x07, 0xA3, 0x00, 0x12, 0x00]).unwrap();
+    vm.ir = 0x301;
+    vm.memory[0x301] = 0xAB;
+    let symbols = Symbols::parse("200 start\n300-303 score").unwrap();
+    let breakpoints = vec![(Some(0x204), "204".to_string())];
+    let views = views(&vm, &symbols, &breakpoints, None);
+
+    let cpu = &views[0];
+    assert_eq!(cpu.title, "CPU");
+    assert_eq!(cpu.lines[0], "PC 200  I 301  DT 00  ST 00");
+    assert_eq!(cpu.lines[1], "V0-V7 00 00 00 00 00 00 00 00");
+    assert_eq!(cpu.lines[3], "STACK -");
+    let code: Vec<&str> = cpu
+        .lines
+        .iter()
+        .map(String::as_str)
+        .skip_while(|line| !line.is_empty())
+        .collect();
+    // The first four instructions are in the font's memory
+    assert_eq!(code[5], "start:");
+    assert!(code[6].starts_with(">200 6007 "), "{}", code[6]);
+    assert!(code[8].starts_with("*204 1200 JP start"), "{}", code[8]);
+    let line = cpu.lines.iter().position(|line| line.starts_with(">200")).unwrap();
+    assert_eq!(cpu.action(line, 3), Some(ViewAction::ToggleBreakpoint(0x200)));
+    assert_eq!(cpu.action(0, 1), None);
+    assert_eq!(cpu.action(0, 10), Some(ViewAction::Edit(Field::I)));
+    assert_eq!(cpu.action(2, 10), Some(ViewAction::Edit(Field::V(9))));
+
+    let memory = &views[1];
+    assert_eq!(memory.lines.len(), MEMORY_ROWS);
+    assert_eq!(memory.lines[4], "300: 00>AB 00 00 00 00 00 00  score");
+
+    let breakpoints = &views[2];
+    assert_eq!(breakpoints.lines, ["0: 204"]);
+    assert_eq!(breakpoints.action(0, 0), Some(ViewAction::DeleteBreakpoint(0)));
+
+    let quirks = &views[3];
+    assert_eq!(quirks.lines[0], "[ ] shift-uses-vy");
+    assert_eq!(quirks.action(0, 0), Some(ViewAction::ToggleQuirk("shift-uses-vy")));
+}
+
+#[test]
+fn test_edit() {
+    let mut vm = VM::new(Box::new(chip8::random::SeededRandom::new(0)));
+    vm.reload(&[0x12, 0x00]).unwrap();
+    vm.ir = 0x300;
+    let symbols = Symbols::parse("").unwrap();
+    let memory = |vm: &VM, edit| views(vm, &symbols, &[], edit).remove(1);
+
+    // Clicking the byte at I starts editing it
+    let line = memory(&vm, None)
+        .lines
+        .iter()
+        .position(|line| line.starts_with("300:"))
+        .unwrap();
+    let Some(ViewAction::Edit(field)) = memory(&vm, None).action(line, 6) else {
+        panic!("not editable");
+    };
+    assert_eq!(field, Field::Memory(0x300));
+    let mut edit = Edit::new(field);
+    assert!(edit.type_key(EditKey::Digit(0xA), &mut vm));
+    assert!(memory(&vm, Some(&edit)).lines[line].starts_with("300:>A_ 00"));
+    assert!(!edit.type_key(EditKey::Digit(7), &mut vm));
+    assert_eq!(vm.memory[0x300], 0xA7);
+
+    // Enter sets fewer digits, and Escape sets nothing
+    let mut edit = Edit::new(Field::Pc);
+    edit.type_key(EditKey::Digit(4), &mut vm);
+    edit.type_key(EditKey::Digit(2), &mut vm);
+    edit.type_key(EditKey::Backspace, &mut vm);
+    assert!(!edit.type_key(EditKey::Enter, &mut vm));
+    assert_eq!(vm.pc, 4);
+    let mut edit = Edit::new(Field::V(3));
+    edit.type_key(EditKey::Digit(1), &mut vm);
+    assert!(!edit.type_key(EditKey::Escape, &mut vm));
+    assert_eq!(vm.v[3], 0);
+}
+
+#[test]
+fn test_edit_xo_chip() {
+    let mut vm = VM::new(Box::new(chip8::random::SeededRandom::new(0)));
+    vm.set_memory_bytes(chip8::vm::XO_CHIP_MEMORY_BYTES);
+    vm.reload(&[0x12, 0x00]).unwrap();
+    let symbols = Symbols::parse("").unwrap();
+    let cpu = |vm: &VM, edit: Option<&Edit>| views(vm, &symbols, &[], edit).remove(0);
+    assert_eq!(cpu(&vm, None).lines[0], "PC 0200  I 0000  DT 00  ST 00");
+
+    // I takes four digits, and the fields after it stay put
+    let mut edit = Edit::new(Field::I);
+    for digit in [1, 2, 3] {
+        assert!(edit.type_key(EditKey::Digit(digit), &mut vm));
+    }
+    assert_eq!(cpu(&vm, Some(&edit)).lines[0], "PC 0200  I 123_  DT 00  ST 00");
+    assert!(!edit.type_key(EditKey::Digit(4), &mut vm));
+    assert_eq!(vm.ir, 0x1234);
+    let view = cpu(&vm, None);
+    assert_eq!(view.lines[0], "PC 0200  I 1234  DT 00  ST 00");
+    assert_eq!(view.action(0, 12), Some(ViewAction::Edit(Field::I)));
+    assert_eq!(view.action(0, 20), Some(ViewAction::Edit(Field::DelayTimer)));
+}
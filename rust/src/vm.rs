@@ -0,0 +1,2046 @@
+//! The CHIP-8 virtual machine: memory, registers, and instruction
+//! execution.
+
+use std::cell::RefCell;
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::audio::{AudioPattern, AUDIO_PATTERN_BYTES, DEFAULT_PITCH};
+use crate::cheats::{CheatMode, CheatTable};
+use crate::decode_cache::DecodeCache;
+use crate::display;
+use crate::instruction::{parse_instruction, Instruction};
+use crate::quirks::Quirks;
+use crate::random::RandomSource;
+use crate::stack;
+use crate::threaded::BlockCache;
+use crate::watch;
+
+/// CHIP-8's memory, which every platform has except XO-CHIP.
+pub const MEMORY_BYTES: usize = 4096;
+
+/// XO-CHIP's memory. Past the first 4 KB, programs reach it by adding
+/// to I with FX1E, since XO-CHIP's `F000 NNNN` isn't supported yet.
+pub const XO_CHIP_MEMORY_BYTES: usize = 0x10000;
+
+/// Address ROMs are loaded at and execution starts from.
+pub const PROGRAM_START: u16 = 0x200;
+
+/// Where execution starts for hi-res ROMs (see `is_hires_rom`).
+pub const HIRES_PROGRAM_START: u16 = 0x2C0;
+
+/// Clears the screen in hi-res mode, instead of calling machine code.
+const HIRES_CLEAR_SCREEN: u16 = 0x0230;
+
+/// How the error from `VM::verify_caches` finding a difference starts,
+/// so fuzzers can tell it from the program's own errors.
+pub const CACHE_MISMATCH: &str = "The caches and the interpreter disagree";
+
+/// Default number of instructions executed per second.
+pub const PROCESSOR_SPEED_HZ: u64 = 700;
+
+/// Rate at which the delay and sound timers are decremented. Most
+/// machines used 60 Hz, but some (PAL) machines ticked at 50 Hz.
+pub const DEFAULT_TIMER_HZ: u32 = 60;
+
+/// Display refreshes per second.
+pub const FRAMES_PER_SECOND: u64 = 60;
+
+/// Number of instructions to execute in the given frame, spreading
+/// `speed_hz` instructions evenly over each second of frames.
+pub fn cycles_in_frame(frame: u64, speed_hz: u64) -> u64 {
+    let cycles = |frames: u64| frames * speed_hz / FRAMES_PER_SECOND;
+    cycles(frame + 1) - cycles(frame)
+}
+
+/// Number of timer ticks due after the given number of frames have
+/// executed, spreading `timer_hz` ticks evenly over each second of
+/// frames. Above 60 Hz, some frames get more than one.
+pub fn timer_ticks_due(frames: u64, timer_hz: u32) -> u64 {
+    let ticks = |frames: u64| frames * timer_hz as u64 / FRAMES_PER_SECOND;
+    if frames == 0 {
+        0
+    } else {
+        ticks(frames) - ticks(frames - 1)
+    }
+}
+
+pub struct VM {
+    pub memory: Memory,
+    pub display: display::Display,
+
+    // Program counter
+    pub pc: u16,
+
+    // Index register
+    pub ir: u16,
+
+    // Stack is for subroutines
+    pub stack: stack::CallStack,
+
+    // General purpose registers
+    pub v: [u8; 16],
+
+    pub keys_pressed: [bool; 16],
+
+    // Timers decremented at 60 Hz
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+
+    // Number of processor cycles executed so far
+    pub cycles: u64,
+
+    // Source for CXNN; seeded so runs can be replayed deterministically
+    pub rng: Box<dyn RandomSource>,
+
+    // Debugger watchpoints, checked on every instruction
+    pub watches: watch::WatchTable,
+    pub quirks: Quirks,
+    /// The small font `reload` loads, which differs between platforms
+    pub font: &'static [u8; 80],
+    /// Quirks the program has done something that depends on, which
+    /// are logged the first time
+    quirks_relied_on: Vec<&'static str>,
+
+    /// Memory pokes applied when the ROM is loaded and each frame
+    pub cheats: CheatTable,
+
+    /// Return an error when PC leaves the program area, instead of
+    /// running interpreter memory or wrapping around
+    pub strict: bool,
+
+    /// An instruction that stops the program like 00FD does, such as
+    /// 0000 for test ROMs that end by running into empty memory
+    pub exit_opcode: Option<u16>,
+
+    /// Set once the program has stopped with 00FD (or `exit_opcode`).
+    /// PC stays at that instruction, so running on stops it again.
+    pub exited: bool,
+
+    /// Decoded instructions, if caching is enabled. Only writes made
+    /// by the program invalidate it, so enable it after loading the
+    /// ROM.
+    pub decode_cache: Option<DecodeCache>,
+
+    /// Instructions compiled to threaded code (see `threaded`), if
+    /// enabled, which takes the place of the decode cache. As with the
+    /// decode cache, enable it after loading the ROM.
+    pub block_cache: Option<BlockCache>,
+
+    /// Run every instruction twice from the same state, once through
+    /// `decode_cache` or `block_cache` and once decoding it afresh, and
+    /// fail if they end up in different states. Slow, but it catches
+    /// the caches going stale or compiling instructions wrongly.
+    pub verify_caches: bool,
+
+    /// Set by DXYN with the display-wait quirk. Whatever runs the frames
+    /// should skip the rest of the current one and clear this.
+    pub waiting_for_frame: bool,
+
+    /// The XO-CHIP audio pattern, played instead of the buzzer's tone
+    /// once a program has loaded one
+    pub audio_pattern: Option<[u8; AUDIO_PATTERN_BYTES]>,
+    pub pitch: u8,
+
+    /// Running in the two-page 64x64 hi-res mode, which starts at
+    /// HIRES_PROGRAM_START
+    pub hires: bool,
+
+    /// Mega-Chip state, if the ROM was loaded as a Mega-Chip one
+    #[cfg(feature = "megachip")]
+    pub megachip: Option<crate::megachip::MegaChip>,
+}
+
+pub type Memory = Box<[u8]>;
+
+/// A copy of the machine state, so the debugger can step backwards.
+/// Settings like quirks, cheats and watchpoints aren't part of it, and
+/// neither are the keypad, which follows the host's keys, or Mega-Chip
+/// state. The random number generator is, so a restored state draws the
+/// same numbers again.
+#[derive(Clone)]
+pub struct SavedState {
+    memory: Memory,
+    display: display::Display,
+    pc: u16,
+    ir: u16,
+    stack: stack::CallStack,
+    v: [u8; 16],
+    delay_timer: u8,
+    sound_timer: u8,
+    cycles: u64,
+    exited: bool,
+    waiting_for_frame: bool,
+    audio_pattern: Option<[u8; AUDIO_PATTERN_BYTES]>,
+    pitch: u8,
+    hires: bool,
+    rng: Box<dyn RandomSource>,
+}
+
+impl SavedState {
+    /// Roughly how many bytes the state takes up: mostly its copies of
+    /// memory and the display.
+    pub fn size(&self) -> usize {
+        std::mem::size_of::<SavedState>() + self.memory.len() + self.display.pixels().len()
+    }
+
+    /// The names of the parts of the state that differ from `other`.
+    pub fn differences(&self, other: &SavedState) -> Vec<&'static str> {
+        let mut differences = Vec::new();
+        let mut differ = |name, differs| {
+            if differs {
+                differences.push(name);
+            }
+        };
+        differ("memory", self.memory[..] != other.memory[..]);
+        differ("display", self.display != other.display);
+        differ("PC", self.pc != other.pc);
+        differ("I", self.ir != other.ir);
+        differ("stack", self.stack.frames() != other.stack.frames());
+        differ("V", self.v != other.v);
+        differ("DT", self.delay_timer != other.delay_timer);
+        differ("ST", self.sound_timer != other.sound_timer);
+        differ("cycles", self.cycles != other.cycles);
+        differ("exited", self.exited != other.exited);
+        differ("waiting_for_frame", self.waiting_for_frame != other.waiting_for_frame);
+        differ("audio_pattern", self.audio_pattern != other.audio_pattern);
+        differ("pitch", self.pitch != other.pitch);
+        differ("hires", self.hires != other.hires);
+        differences
+    }
+}
+
+impl VM {
+    pub fn new(rng: Box<dyn RandomSource>) -> VM {
+        VM {
+            memory: vec![0; MEMORY_BYTES].into_boxed_slice(),
+            display: display::Display::new(),
+            pc: PROGRAM_START,
+            ir: 0,
+            stack: stack::CallStack::new(stack::DEFAULT_STACK_DEPTH),
+            v: [0; 16],
+            keys_pressed: [false; 16],
+            delay_timer: 0,
+            sound_timer: 0,
+            cycles: 0,
+            rng,
+            watches: watch::WatchTable::default(),
+            cheats: CheatTable::default(),
+            quirks: Quirks::default(),
+            font: &FONT_BYTES,
+            quirks_relied_on: Vec::new(),
+            strict: false,
+            exit_opcode: None,
+            exited: false,
+            decode_cache: None,
+            block_cache: None,
+            verify_caches: false,
+            waiting_for_frame: false,
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            hires: false,
+            #[cfg(feature = "megachip")]
+            megachip: None,
+        }
+    }
+
+    /// Soft reset: restart the program from the beginning, clearing the
+    /// registers, stack, timers, keypad and display, which goes back
+    /// to lo-res unless it's a two-page hi-res ROM. Memory is kept as it
+    /// is, including any changes the program made to itself.
+    pub fn reset(&mut self) {
+        self.display.set_hires(self.hires);
+        self.display.set_planes(1);
+        self.pc = if self.hires { HIRES_PROGRAM_START } else { PROGRAM_START };
+        self.ir = 0;
+        self.stack.clear();
+        self.v = [0; 16];
+        self.keys_pressed = [false; 16];
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.exited = false;
+        self.waiting_for_frame = false;
+        self.audio_pattern = None;
+        self.pitch = DEFAULT_PITCH;
+        #[cfg(feature = "megachip")]
+        if let Some(mega) = &mut self.megachip {
+            mega.reset();
+        }
+    }
+
+    /// Hard reset: clear memory, load `rom` into it, apply any cheats
+    /// and reset. This switches to hi-res mode if it's a hi-res ROM.
+    pub fn reload(&mut self, rom: &[u8]) -> Result<(), String> {
+        let mut memory = vec![0; self.memory.len()].into_boxed_slice();
+        load_rom(&mut memory, rom).map_err(|err| err.to_string())?;
+        memory[FONT_MEMORY_START..FONT_MEMORY_START + self.font.len()].copy_from_slice(self.font);
+        self.memory = memory;
+        self.clear_caches();
+        self.apply_cheats(&[CheatMode::Load, CheatMode::Always]);
+        self.set_hires(is_hires_rom(rom));
+        Ok(())
+    }
+
+    /// Change how much memory there is, e.g. to XO-CHIP's 64 KB,
+    /// clearing it. `reload` loads the ROM into the new memory.
+    pub fn set_memory_bytes(&mut self, bytes: usize) {
+        self.memory = vec![0; bytes].into_boxed_slice();
+        if let Some(cache) = &mut self.decode_cache {
+            *cache = DecodeCache::new(bytes);
+        }
+        if let Some(cache) = &mut self.block_cache {
+            *cache = BlockCache::new(bytes);
+        }
+    }
+
+    /// Apply the cheats that hold values in place. Called at the end
+    /// of every frame.
+    pub fn apply_frame_cheats(&mut self) {
+        self.apply_cheats(&[CheatMode::Always]);
+    }
+
+    fn apply_cheats(&mut self, modes: &[CheatMode]) {
+        let applied = self.cheats.apply(&mut self.memory, modes);
+        let addresses: Vec<usize> = applied.iter().flat_map(|cheat| cheat.addresses()).collect();
+        for addr in addresses {
+            self.invalidate_caches(addr);
+        }
+    }
+
+    /// Enable or disable cheat number `index`. A cheat is applied
+    /// straight away when enabled, but disabling one doesn't undo it.
+    /// Returns whether it's now enabled.
+    pub fn toggle_cheat(&mut self, index: usize) -> Option<bool> {
+        let cheat = self.cheats.toggle(index)?;
+        let enabled = cheat.enabled;
+        if enabled {
+            cheat.apply(&mut self.memory);
+            let addresses: Vec<usize> = cheat.addresses().collect();
+            for addr in addresses {
+                self.invalidate_caches(addr);
+            }
+        }
+        Some(enabled)
+    }
+
+    /// Copy the machine state, to go back to with `restore_state`.
+    pub fn save_state(&self) -> SavedState {
+        SavedState {
+            memory: self.memory.clone(),
+            display: self.display.clone(),
+            pc: self.pc,
+            ir: self.ir,
+            stack: self.stack.clone(),
+            v: self.v,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            cycles: self.cycles,
+            exited: self.exited,
+            waiting_for_frame: self.waiting_for_frame,
+            audio_pattern: self.audio_pattern,
+            pitch: self.pitch,
+            hires: self.hires,
+            rng: self.rng.clone(),
+        }
+    }
+
+    /// Go back to a state from `save_state`, forgetting decoded and
+    /// compiled instructions since memory may have changed since.
+    pub fn restore_state(&mut self, state: &SavedState) {
+        self.memory = state.memory.clone();
+        self.display.copy_from(&state.display);
+        self.pc = state.pc;
+        self.ir = state.ir;
+        self.stack = state.stack.clone();
+        self.v = state.v;
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+        self.cycles = state.cycles;
+        self.exited = state.exited;
+        self.waiting_for_frame = state.waiting_for_frame;
+        self.audio_pattern = state.audio_pattern;
+        self.pitch = state.pitch;
+        self.hires = state.hires;
+        self.rng = state.rng.clone();
+        self.clear_caches();
+    }
+
+    /// Switch to or from the 64x64 hi-res mode and reset.
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.display.set_hires(hires);
+        self.reset();
+    }
+
+    /// Change a byte of memory from outside the program, as the debug
+    /// views do, keeping any decoded instructions up to date. Unlike the
+    /// program's own writes, this doesn't trigger watchpoints.
+    pub fn poke(&mut self, addr: usize, value: u8) {
+        self.memory[addr] = value;
+        self.invalidate_caches(addr);
+    }
+
+    /// Forget the decoded and compiled instructions that include the
+    /// byte at `addr`, after it's been written.
+    fn invalidate_caches(&mut self, addr: usize) {
+        if let Some(cache) = &mut self.decode_cache {
+            cache.invalidate(addr);
+        }
+        if let Some(cache) = &mut self.block_cache {
+            cache.invalidate(addr);
+        }
+    }
+
+    /// Forget all decoded and compiled instructions, e.g. after
+    /// replacing memory wholesale.
+    pub fn clear_caches(&mut self) {
+        if let Some(cache) = &mut self.decode_cache {
+            cache.clear();
+        }
+        if let Some(cache) = &mut self.block_cache {
+            cache.clear();
+        }
+    }
+
+    /// Write a byte to memory, checking it against any watchpoints.
+    fn write_memory(&mut self, addr: usize, value: u8) -> Result<(), String> {
+        if addr >= self.memory.len() {
+            return Err(format!("Can't write to {:#05X}, which is outside of memory", addr));
+        }
+        if !self.watches.is_empty() {
+            self.watches.check_memory_write(addr as u16, self.memory[addr], value);
+        }
+        self.memory[addr] = value;
+        self.invalidate_caches(addr);
+        Ok(())
+    }
+
+    fn read_memory(&self, addr: usize) -> Result<u8, String> {
+        self.memory
+            .get(addr)
+            .copied()
+            .ok_or_else(|| format!("Can't read from {:#05X}, which is outside of memory", addr))
+    }
+
+    fn key_pressed(&self, key: u8) -> Result<bool, String> {
+        self.keys_pressed
+            .get(key as usize)
+            .copied()
+            .ok_or_else(|| format!("{:#04X} isn't a key", key))
+    }
+
+    /// The XO-CHIP audio pattern to play while the sound timer is
+    /// running, if the program has loaded one.
+    pub fn audio_pattern(&self) -> Option<AudioPattern> {
+        self.audio_pattern.map(|bits| AudioPattern {
+            bits,
+            pitch: self.pitch,
+        })
+    }
+
+    /// Whether the program is done: the instruction at PC is a jump to
+    /// itself (1NNN, the usual way to end a CHIP-8 program), the timers
+    /// have run out and no keys are held, so nothing would change by
+    /// running it further.
+    pub fn is_idle(&self) -> bool {
+        let addr = self.pc as usize;
+        if addr + 1 >= self.memory.len() {
+            return false;
+        }
+        let raw = (self.memory[addr] as u16) << 8 | self.memory[addr + 1] as u16;
+        raw == 0x1000 | self.pc && self.delay_timer == 0 && self.sound_timer == 0 && !self.keys_pressed.contains(&true)
+    }
+
+    /// Decrement the delay and sound timers by one tick. Called at
+    /// the configured timer frequency (see `DEFAULT_TIMER_HZ`).
+    pub fn tick_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+}
+
+pub const FONT_MEMORY_START: usize = 0x050;
+
+pub static FONT_BYTES: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+/// The COSMAC VIP's small font, which draws 1, 4, 7, B and D
+/// differently from the CHIP-48 one in `FONT_BYTES` that later
+/// interpreters copied.
+pub static VIP_FONT_BYTES: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x60, 0x20, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0xA0, 0xA0, 0xF0, 0x20, 0x20, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x10, 0x10, 0x10, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xF0, 0x50, 0x70, 0x50, 0xF0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xF0, 0x50, 0x50, 0x50, 0xF0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+/// SUPER-CHIP's large font, 8x10 pixels per digit, for FX30. It follows
+/// the small font in memory. SUPER-CHIP only had 0-9, so A-F are
+/// XO-CHIP's.
+pub const LARGE_FONT_MEMORY_START: usize = FONT_MEMORY_START + FONT_BYTES.len();
+
+pub static LARGE_FONT_BYTES: [u8; 160] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+/// The most bytes a ROM can have: from where it's loaded to the end of
+/// CHIP-8 memory. XO-CHIP ROMs can have up to
+/// `max_rom_bytes(XO_CHIP_MEMORY_BYTES)`.
+pub const MAX_ROM_BYTES: usize = MEMORY_BYTES - PROGRAM_START as usize;
+
+/// The most bytes a ROM can have with `memory_bytes` of memory.
+pub const fn max_rom_bytes(memory_bytes: usize) -> usize {
+    memory_bytes - PROGRAM_START as usize
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LoadError {
+    /// A ROM that doesn't fit between PROGRAM_START and the end of memory
+    RomTooLarge { size: usize, max: usize },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::RomTooLarge { size, max } => write!(
+                f,
+                "ROM is {} bytes, {} more than the {} bytes that fit in memory",
+                size,
+                size - max,
+                max
+            ),
+        }
+    }
+}
+
+/// Check that `rom` fits in `memory_bytes` of memory, before loading
+/// any of it.
+pub fn check_rom_size(rom: &[u8], memory_bytes: usize) -> Result<(), LoadError> {
+    if rom.len() > max_rom_bytes(memory_bytes) {
+        return Err(LoadError::RomTooLarge {
+            size: rom.len(),
+            max: max_rom_bytes(memory_bytes),
+        });
+    }
+    Ok(())
+}
+
+/// Load a ROM into memory at 0x200, along with the fonts. Memory is left
+/// alone if the ROM doesn't fit.
+pub fn load_rom(memory: &mut [u8], rom: &[u8]) -> Result<(), LoadError> {
+    check_rom_size(rom, memory.len())?;
+    let start = PROGRAM_START as usize;
+    memory[start..start + rom.len()].copy_from_slice(rom);
+
+    // Load the fonts into 0x050–0x09F and 0x0A0–0x13F
+    memory[FONT_MEMORY_START..FONT_MEMORY_START + FONT_BYTES.len()].copy_from_slice(&FONT_BYTES);
+    memory[LARGE_FONT_MEMORY_START..LARGE_FONT_MEMORY_START + LARGE_FONT_BYTES.len()]
+        .copy_from_slice(&LARGE_FONT_BYTES);
+
+    Ok(())
+}
+
+pub fn load_rom_file(memory: &mut [u8], path: &Path) -> Result<(), String> {
+    load_rom(memory, &read_rom_file(path)?).map_err(|err| err.to_string())
+}
+
+/// The biggest file `read_rom_file` reads: the most a Mega-Chip
+/// program, the biggest kind, can address. Anything larger isn't a ROM,
+/// so there's no point reading it all into memory.
+pub const MAX_ROM_FILE_BYTES: u64 = 1 << 24;
+
+/// Read the ROM file at `path`, refusing files too big to be a ROM.
+pub fn read_rom_file(path: &Path) -> Result<Vec<u8>, String> {
+    let error = |err: io::Error| format!("Error reading ROM file {}: {}", path.display(), err);
+    let mut rom = Vec::new();
+    File::open(path)
+        .and_then(|file| file.take(MAX_ROM_FILE_BYTES + 1).read_to_end(&mut rom))
+        .map_err(error)?;
+    if rom.len() as u64 > MAX_ROM_FILE_BYTES {
+        return Err(format!(
+            "ROM file {} is bigger than {} bytes, too big to be a ROM",
+            path.display(),
+            MAX_ROM_FILE_BYTES
+        ));
+    }
+    Ok(rom)
+}
+
+/// Returns true if `rom` is written for the two-page 64x64 hi-res mode.
+/// These ROMs came with their own interpreter patch: they start by
+/// jumping to 0x260 to set it up, and the program proper starts at
+/// HIRES_PROGRAM_START.
+pub fn is_hires_rom(rom: &[u8]) -> bool {
+    rom.starts_with(&[0x12, 0x60])
+}
+
+/// Read the two-byte instruction at `addr`.
+pub fn fetch(memory: &[u8], addr: u16) -> u16 {
+    let addr = addr as usize;
+    (memory[addr % memory.len()] as u16) << 8 | memory[(addr + 1) % memory.len()] as u16
+}
+
+pub fn processor_cycle(vm: &mut VM) -> Result<(), String> {
+    if vm.verify_caches && (vm.decode_cache.is_some() || vm.block_cache.is_some()) {
+        return verified_cycle(vm);
+    }
+    execute(vm).map(|_| ())
+}
+
+/// Run frame number `frame` at the default speeds: its share of
+/// instructions, or fewer if the program waits for the next frame, and
+/// then any timer ticks that are due. Embedders call this once per
+/// frame, 60 times a second.
+pub fn run_frame(vm: &mut VM, frame: u64) -> Result<(), String> {
+    run_frame_at(vm, frame, PROCESSOR_SPEED_HZ, DEFAULT_TIMER_HZ)
+}
+
+/// `run_frame`, at `speed_hz` instructions and `timer_hz` timer ticks a
+/// second. A program waiting for the display runs nothing until a frame
+/// ends with a timer tick, which is when the display refreshes.
+pub fn run_frame_at(vm: &mut VM, frame: u64, speed_hz: u64, timer_hz: u32) -> Result<(), String> {
+    if !vm.waiting_for_frame {
+        for _ in 0..cycles_in_frame(frame, speed_hz) {
+            processor_cycle(vm)?;
+            vm.cycles += 1;
+            if vm.waiting_for_frame {
+                break;
+            }
+        }
+    }
+    end_frame(vm, frame + 1, timer_hz);
+    Ok(())
+}
+
+/// Tick the timers as many times as are due after `frames` frames at
+/// `timer_hz`. The ticks are the display's vertical blank, so a program
+/// waiting for the display stops waiting if there are any.
+pub fn end_frame(vm: &mut VM, frames: u64, timer_hz: u32) {
+    let ticks = timer_ticks_due(frames, timer_hz);
+    if ticks > 0 {
+        vm.waiting_for_frame = false;
+    }
+    for _ in 0..ticks {
+        vm.tick_timers();
+    }
+}
+
+/// Run the next instruction without the caches, then from the same
+/// state again with them, keeping the second run's result (see
+/// `VM::verify_caches`).
+fn verified_cycle(vm: &mut VM) -> Result<(), String> {
+    // Mega-Chip state isn't saved, so its instructions can't be run twice
+    #[cfg(feature = "megachip")]
+    if vm.megachip.is_some() {
+        return execute(vm).map(|_| ());
+    }
+
+    let before = vm.save_state();
+    let pc = vm.pc;
+    // Both runs get the same random bytes, and watchpoints only see the
+    // second
+    let source = Rc::new(RefCell::new(None));
+    let drawn = Rc::new(RefCell::new(VecDeque::new()));
+    let random = |replaying| DrawTwice {
+        source: source.clone(),
+        drawn: drawn.clone(),
+        replaying,
+    };
+    *source.borrow_mut() = Some(std::mem::replace(&mut vm.rng, Box::new(random(false))));
+    let watches = std::mem::take(&mut vm.watches);
+    let caches = (vm.decode_cache.take(), vm.block_cache.take());
+
+    let uncached = execute(vm).map(|_| ());
+    let uncached_state = vm.save_state();
+
+    vm.restore_state(&before);
+    (vm.decode_cache, vm.block_cache) = caches;
+    vm.watches = watches;
+    vm.rng = Box::new(random(true));
+    let cached = execute(vm).map(|_| ());
+    vm.rng = source.borrow_mut().take().expect("the random source is put back once");
+
+    let mut differences = vm.save_state().differences(&uncached_state);
+    if cached != uncached {
+        differences.insert(0, "result");
+    }
+    if differences.is_empty() {
+        cached
+    } else {
+        Err(format!(
+            "{} on the instruction at {:03X} ({}): {} differ",
+            CACHE_MISMATCH,
+            pc,
+            parse_instruction(fetch(&before.memory, pc)).map_or_else(|err| err, |instruction| instruction.to_string()),
+            differences.join(", ")
+        ))
+    }
+}
+
+/// Gives out the bytes `source` does, remembering them, and when
+/// `replaying`, gives out the remembered ones again instead, so an
+/// instruction run twice gets the same random numbers both times.
+#[derive(Clone)]
+struct DrawTwice {
+    source: Rc<RefCell<Option<Box<dyn RandomSource>>>>,
+    drawn: Rc<RefCell<VecDeque<u8>>>,
+    replaying: bool,
+}
+
+impl RandomSource for DrawTwice {
+    fn next_u8(&mut self) -> u8 {
+        if self.replaying {
+            if let Some(byte) = self.drawn.borrow_mut().pop_front() {
+                return byte;
+            }
+        }
+        let byte = self
+            .source
+            .borrow_mut()
+            .as_mut()
+            .expect("the random source is set before running")
+            .next_u8();
+        if !self.replaying {
+            self.drawn.borrow_mut().push_back(byte);
+        }
+        byte
+    }
+
+    fn clone_box(&self) -> Box<dyn RandomSource> {
+        Box::new(self.clone())
+    }
+}
+
+/// Log a warning, the first time for each quirk, that the instruction
+/// being run acts differently depending on `quirk`, since that's a
+/// likely reason for a ROM to misbehave.
+fn relies_on_quirk(vm: &mut VM, quirk: &'static str) {
+    if vm.quirks_relied_on.contains(&quirk) {
+        return;
+    }
+    vm.quirks_relied_on.push(quirk);
+    let state = if vm.quirks.get(quirk) == Some(true) {
+        "on"
+    } else {
+        "off"
+    };
+    log::warn!(
+        "The instruction at {:03X} depends on the {} quirk, which is {}",
+        vm.pc.wrapping_sub(2),
+        quirk,
+        state
+    );
+}
+
+/// Update VF after 8XY1, 8XY2 or 8XY3, which only the vf-reset quirk
+/// touches.
+fn logic_vf(vm: &mut VM) {
+    if vm.v[0xF] != 0 {
+        relies_on_quirk(vm, "vf-reset");
+    }
+    if vm.quirks.vf_reset {
+        vm.v[0xF] = 0;
+    }
+}
+
+/// Scroll the display with `scroll`, which moves half as far in lo-res
+/// with the half-scroll quirk.
+fn scroll(vm: &mut VM, scroll: impl FnOnce(&mut display::Display)) {
+    if vm.display.is_lores() {
+        relies_on_quirk(vm, "half-scroll");
+    }
+    vm.display.set_half_scroll(vm.quirks.half_scroll);
+    scroll(&mut vm.display);
+}
+
+/// Execute the instruction at PC, returning it (or None for Mega-Chip
+/// instructions, which aren't `Instruction`s).
+pub(crate) fn execute(vm: &mut VM) -> Result<Option<Instruction>, String> {
+    if vm.strict {
+        if vm.pc < PROGRAM_START {
+            return Err(format!("PC {:#05X} is in interpreter-reserved memory", vm.pc));
+        }
+        if vm.pc as usize + 1 >= vm.memory.len() {
+            return Err(format!("PC {:#05X} ran past the end of memory", vm.pc));
+        }
+    }
+    // Otherwise PC wraps around at the end of memory
+    vm.pc = (vm.pc as usize % vm.memory.len()) as u16;
+
+    #[cfg(feature = "megachip")]
+    if crate::megachip::execute_mega_instruction(vm)? {
+        return Ok(None);
+    }
+
+    // Instructions are fetched from memory as it is now (the cache is
+    // kept up to date with writes), so programs can modify their own
+    // code.
+    let word = fetch(&vm.memory, vm.pc);
+    let special = vm.exit_opcode == Some(word) || vm.hires && word == HIRES_CLEAR_SCREEN;
+    // Threaded code skips decoding altogether. What the exit opcode and
+    // 0230 do depends on the VM's settings, so they're never compiled.
+    if let (Some(cache), false) = (&mut vm.block_cache, special) {
+        let op = match cache.lookup(vm.pc) {
+            Some(op) => op,
+            None => cache.compile(vm.pc, &vm.memory)?,
+        };
+        log::debug!(target: "chip8::cpu", "{:03X}: {}", vm.pc, op.instruction);
+        vm.pc = vm.pc.wrapping_add(2);
+        let (v_before, ir_before) = (vm.v, vm.ir);
+        op.run(vm)?;
+        if !vm.watches.is_empty() {
+            vm.watches.check_registers(&v_before, &vm.v, ir_before, vm.ir);
+        }
+        return Ok(Some(op.instruction));
+    }
+    let instruction = match &mut vm.decode_cache {
+        _ if vm.exit_opcode == Some(word) => Instruction::Exit,
+        _ if vm.hires && word == HIRES_CLEAR_SCREEN => Instruction::ClearScreen,
+        Some(cache) => cache.decode(vm.pc, &vm.memory)?,
+        None => parse_instruction(word)?,
+    };
+
+    log::debug!(target: "chip8::cpu", "{:03X}: {}", vm.pc, instruction);
+
+    // Increment program counter here instead of in each instruction
+    // so we don't forget.
+    vm.pc = vm.pc.wrapping_add(2);
+
+    let (v_before, ir_before) = (vm.v, vm.ir);
+
+    run_instruction(vm, instruction)?;
+
+    if !vm.watches.is_empty() {
+        vm.watches.check_registers(&v_before, &vm.v, ir_before, vm.ir);
+    }
+
+    Ok(Some(instruction))
+}
+
+/// Run `instruction`, once PC has been moved past it.
+pub(crate) fn run_instruction(vm: &mut VM, instruction: Instruction) -> Result<(), String> {
+    match instruction {
+        Instruction::ClearScreen => vm.display.clear(),
+        Instruction::SubroutineReturn => {
+            let frame = vm.stack.pop().map_err(|err| err.to_string())?;
+            vm.pc = frame.return_address();
+        }
+        Instruction::Exit => {
+            vm.pc = vm.pc.wrapping_sub(2);
+            vm.exited = true;
+        }
+        Instruction::ScrollDown { n } => scroll(vm, |display| display.scroll_down(n as usize)),
+        Instruction::ScrollUp { n } => scroll(vm, |display| display.scroll_up(n as usize)),
+        Instruction::ScrollRight => scroll(vm, display::Display::scroll_right),
+        Instruction::ScrollLeft => scroll(vm, display::Display::scroll_left),
+        Instruction::LowRes => vm.display.set_schip_hires(false),
+        Instruction::HighRes => vm.display.set_schip_hires(true),
+        Instruction::SelectPlanes { n } => vm.display.set_planes(n),
+        Instruction::Jump { nnn } => vm.pc = nnn,
+        Instruction::SubroutineCall { nnn } => {
+            let frame = stack::Frame {
+                call_site: vm.pc.wrapping_sub(2),
+                subroutine: nnn,
+            };
+            vm.stack.push(frame).map_err(|err| err.to_string())?;
+            vm.pc = nnn; // Jump to NNN
+        }
+        Instruction::SkipVxEqNn { x, nn } => {
+            if vm.v[x] == nn {
+                vm.pc = vm.pc.wrapping_add(2)
+            }
+        }
+        Instruction::SkipVxNeqNn { x, nn } => {
+            if vm.v[x] != nn {
+                vm.pc = vm.pc.wrapping_add(2)
+            }
+        }
+        Instruction::SkipVxEqVy { x, y } => {
+            if vm.v[x] == vm.v[y] {
+                vm.pc = vm.pc.wrapping_add(2)
+            }
+        }
+        Instruction::SkipVxNeqVy { x, y } => {
+            if vm.v[x] != vm.v[y] {
+                vm.pc = vm.pc.wrapping_add(2)
+            }
+        }
+        Instruction::SetVxNn { x, nn } => vm.v[x] = nn,
+        Instruction::AddNnVx { x, nn } => vm.v[x] = vm.v[x].wrapping_add(nn),
+        Instruction::SetVxVy { x, y } => vm.v[x] = vm.v[y],
+        Instruction::SetVxOrVy { x, y } => {
+            vm.v[x] |= vm.v[y];
+            logic_vf(vm);
+        }
+        Instruction::SetVxAndVy { x, y } => {
+            vm.v[x] &= vm.v[y];
+            logic_vf(vm);
+        }
+        Instruction::SetVxXorVy { x, y } => {
+            vm.v[x] ^= vm.v[y];
+            logic_vf(vm);
+        }
+        // The flag is written after the result, so when X is F the
+        // flag wins. Both are computed first in case Y is F.
+        Instruction::SetVxPlusVy { x, y } => {
+            let (sum, carry) = vm.v[x].overflowing_add(vm.v[y]);
+            vm.v[x] = sum;
+            vm.v[0xF] = carry as u8;
+        }
+        Instruction::SetVxMinusVy { x, y } => {
+            let (difference, borrow) = vm.v[x].overflowing_sub(vm.v[y]);
+            vm.v[x] = difference;
+            vm.v[0xF] = !borrow as u8;
+        }
+        Instruction::ShiftVxRight { x, y } => {
+            if vm.v[x] != vm.v[y] {
+                relies_on_quirk(vm, "shift-uses-vy");
+            }
+            let value = if vm.quirks.shift_uses_vy { vm.v[y] } else { vm.v[x] };
+            vm.v[x] = value >> 1;
+            vm.v[0xF] = value & 0x1;
+        }
+        Instruction::SetVyMinusVx { x, y } => {
+            let (difference, borrow) = vm.v[y].overflowing_sub(vm.v[x]);
+            vm.v[x] = difference;
+            vm.v[0xF] = !borrow as u8;
+        }
+        Instruction::ShiftVxLeft { x, y } => {
+            if vm.v[x] != vm.v[y] {
+                relies_on_quirk(vm, "shift-uses-vy");
+            }
+            let value = if vm.quirks.shift_uses_vy { vm.v[y] } else { vm.v[x] };
+            vm.v[x] = value << 1;
+            vm.v[0xF] = value >> 7;
+        }
+        Instruction::SetIndexNnn { nnn } => vm.ir = nnn,
+        Instruction::JumpV0Nnn { nnn } => {
+            let x = (nnn >> 8) as usize;
+            if vm.v[x] != vm.v[0] {
+                relies_on_quirk(vm, "jump-uses-vx");
+            }
+            let offset = if vm.quirks.jump_uses_vx { vm.v[x] } else { vm.v[0] };
+            vm.pc = offset as u16 + nnn;
+        }
+        Instruction::SetVxRandNn { x, nn } => vm.v[x] = vm.rng.next_u8() & nn,
+        Instruction::Display { x, y, n } => {
+            // Display the sprite at memory location I at (VX, VY), and
+            // set VF = collision. Sprites are 8 pixels wide and n rows
+            // tall, except that DXY0 draws a 16x16 sprite stored as two
+            // bytes per row, like SUPER-CHIP and XO-CHIP.
+            let (sprite_width, sprite_height) = if n == 0 { (16, 16) } else { (8, n as usize) };
+            let bytes_per_row = sprite_width / 8;
+            // Each selected plane gets its own sprite, one after the
+            // other, as in XO-CHIP. The whole of them is read, even the
+            // parts that end up clipped, so it has to be in memory.
+            let sprite_bytes = sprite_height * bytes_per_row;
+            let planes: Vec<u8> = [1, 2]
+                .iter()
+                .copied()
+                .filter(|plane| vm.display.planes() & plane != 0)
+                .collect();
+            let total_bytes = sprite_bytes * planes.len();
+            if total_bytes > 0 {
+                vm.read_memory(vm.ir as usize + total_bytes - 1)?;
+            }
+            let mut sprites = [0; 64];
+            sprites[..total_bytes].copy_from_slice(&vm.memory[vm.ir as usize..vm.ir as usize + total_bytes]);
+
+            // The starting position wraps around the screen
+            let (width, height) = (vm.display.width(), vm.display.height());
+            let dx = vm.v[x] as usize % width;
+            let dy = vm.v[y] as usize % height;
+
+            // Reset collision flag
+            vm.v[0xF] = 0;
+
+            // The rest of the sprite is clipped at the right and bottom
+            // edges, unless it wraps around to the left and top
+            if dy + sprite_height > height || dx + sprite_width > width {
+                relies_on_quirk(vm, "wrap-sprites");
+            }
+            let (rows, columns) = if vm.quirks.wrap_sprites {
+                (sprite_height, sprite_width)
+            } else {
+                (min(sprite_height, height - dy), min(sprite_width, width - dx))
+            };
+
+            for (plane, sprite) in planes.into_iter().zip(sprites.chunks(sprite_bytes)) {
+                // j is the y value
+                for j in 0..rows {
+                    // i is the x value we use to iterate over bits
+                    for i in 0..columns {
+                        // Bit shift to get the current row bit
+                        let sprite_byte = sprite[j * bytes_per_row + i / 8];
+                        if (sprite_byte >> (7 - i % 8)) & 0b1 == 0 {
+                            continue;
+                        }
+
+                        let x = (dx + i) % width;
+                        let y = (dy + j) % height;
+                        let pixel = vm.display.pixel_planes(x, y);
+                        if pixel & plane != 0 {
+                            // Set collision register
+                            vm.v[0xF] = 1;
+                        }
+
+                        // XOR with current bit
+                        vm.display.set_pixel_planes(x, y, pixel ^ plane);
+                    }
+                }
+            }
+            vm.waiting_for_frame = vm.quirks.display_wait;
+        }
+        Instruction::SkipIfVxPressed { x } => {
+            if vm.key_pressed(vm.v[x])? {
+                vm.pc = vm.pc.wrapping_add(2);
+            }
+        }
+        Instruction::SkipIfVxNotPressed { x } => {
+            if !vm.key_pressed(vm.v[x])? {
+                vm.pc = vm.pc.wrapping_add(2);
+            }
+        }
+        Instruction::SetVxDelay { x } => vm.v[x] = vm.delay_timer,
+        Instruction::SetDelayVx { x } => vm.delay_timer = vm.v[x],
+        Instruction::SetSoundVx { x } => vm.sound_timer = vm.v[x],
+        Instruction::AddVxI { x } => {
+            let sum = vm.ir.wrapping_add(vm.v[x] as u16);
+            let overflow = vm.ir <= 0xFFF && sum > 0xFFF;
+            if overflow {
+                relies_on_quirk(vm, "index-overflow");
+            }
+            if vm.quirks.index_overflow {
+                vm.v[0xF] = overflow as u8;
+            }
+            vm.ir = sum;
+        }
+        Instruction::BlockUntilAnyKey { x } => {
+            // Repeat this instruction until a key is pressed, taking
+            // the lowest one if several are
+            match vm.keys_pressed.iter().position(|&pressed| pressed) {
+                Some(key) => vm.v[x] = key as u8,
+                None => vm.pc = vm.pc.wrapping_sub(2),
+            }
+        }
+        Instruction::SetIFontVx { x } => vm.ir = FONT_MEMORY_START as u16 + vm.v[x] as u16 * 5, // Fonts are 5 bytes wide
+        Instruction::SetILargeFontVx { x } => vm.ir = LARGE_FONT_MEMORY_START as u16 + vm.v[x] as u16 * 10,
+        Instruction::StoreVxDigitsI { x } => {
+            vm.write_memory(vm.ir as usize, vm.v[x] / 100)?;
+            vm.write_memory(vm.ir as usize + 1, (vm.v[x] % 100) / 10)?;
+            vm.write_memory(vm.ir as usize + 2, vm.v[x] % 10)?;
+        }
+        Instruction::StoreVxI { x } => {
+            for i in 0..=x {
+                vm.write_memory(vm.ir as usize + i, vm.v[i])?;
+            }
+            if vm.quirks.increment_i {
+                vm.ir = vm.ir.wrapping_add(x as u16 + 1);
+            }
+        }
+        Instruction::StoreIVx { x } => {
+            for i in 0..=x {
+                vm.v[i] = vm.read_memory(vm.ir as usize + i)?;
+            }
+            if vm.quirks.increment_i {
+                vm.ir = vm.ir.wrapping_add(x as u16 + 1);
+            }
+        }
+        Instruction::LoadAudioPattern => {
+            let mut pattern = [0; AUDIO_PATTERN_BYTES];
+            for (i, byte) in pattern.iter_mut().enumerate() {
+                *byte = vm.read_memory(vm.ir as usize + i)?;
+            }
+            vm.audio_pattern = Some(pattern);
+        }
+        Instruction::SetPitchVx { x } => vm.pitch = vm.v[x],
+    }
+    Ok(())
+}
+
+#[test]
+fn test_save_state() {
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    // V0 += 1, call 206, clear, I := 300, save V0, I := 300, draw it
+    vm.reload(&[
+        0x70, 0x01, 0x22, 0x06, 0x00, 0xE0, 0xA3, 0x00, 0xF0, 0x55, 0xA3, 0x00, 0xD0, 0x01,
+    ])
+    .unwrap();
+    processor_cycle(&mut vm).unwrap();
+    let state = vm.save_state();
+    for _ in 0..5 {
+        processor_cycle(&mut vm).unwrap();
+    }
+    assert_eq!(vm.memory[0x300], 1);
+    assert!(vm.display.get_pixel(8, 1));
+
+    vm.restore_state(&state);
+    assert_eq!((vm.pc, vm.ir, vm.v[0], vm.stack.len()), (0x202, 0, 1, 0));
+    assert_eq!(vm.memory[0x300], 0);
+    assert!(!vm.display.get_pixel(8, 1));
+
+    // V1 := random FF, run again after restoring
+    vm.reload(&[0xC1, 0xFF]).unwrap();
+    let state = vm.save_state();
+    processor_cycle(&mut vm).unwrap();
+    let drawn = vm.v[1];
+    vm.restore_state(&state);
+    processor_cycle(&mut vm).unwrap();
+    assert_eq!(vm.v[1], drawn);
+}
+
+#[test]
+fn test_timer_ticks_due() {
+    let ticks = |timer_hz| -> u64 {
+        (1..=FRAMES_PER_SECOND)
+            .map(|frames| timer_ticks_due(frames, timer_hz))
+            .sum()
+    };
+    assert_eq!(ticks(60), 60);
+    assert_eq!(ticks(50), 50);
+    assert_eq!(ticks(120), 120);
+    assert_eq!(ticks(1000), 1000);
+    assert!((1..=FRAMES_PER_SECOND).all(|frames| timer_ticks_due(frames, 120) == 2));
+    assert!((1..=FRAMES_PER_SECOND).all(|frames| (1..=2).contains(&timer_ticks_due(frames, 90))));
+    assert_eq!(timer_ticks_due(0, 120), 0);
+}
+
+#[test]
+fn test_cycles_in_frame() {
+    let cycles: u64 = (0..FRAMES_PER_SECOND).map(|frame| cycles_in_frame(frame, 700)).sum();
+    assert_eq!(cycles, 700);
+    assert!((0..FRAMES_PER_SECOND).all(|frame| (11..=12).contains(&cycles_in_frame(frame, 700))));
+}
+
+#[test]
+fn test_rand_uses_injected_source() {
+    let mut vm = VM::new(Box::new(crate::random::SequenceRandom::new(vec![0xAB, 0xFF])));
+    // C10F: V1 = rand & 0x0F, then C2F0: V2 = rand & 0xF0
+    vm.memory[0x200..0x204].copy_from_slice(&[0xC1, 0x0F, 0xC2, 0xF0]);
+    processor_cycle(&mut vm).unwrap();
+    processor_cycle(&mut vm).unwrap();
+    assert_eq!(vm.v[1], 0x0B);
+    assert_eq!(vm.v[2], 0xF0);
+}
+
+#[test]
+fn test_memory_watchpoint() {
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    vm.watches.add(watch::Watch::Memory(0x300..=0x301));
+    vm.ir = 0x300;
+    // F155: store V0 and V1 at I
+    vm.memory[0x200..0x202].copy_from_slice(&[0xF1, 0x55]);
+    processor_cycle(&mut vm).unwrap();
+    assert_eq!(vm.watches.take_hits().len(), 2);
+}
+
+#[test]
+fn test_subroutine_call_and_return() {
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    // 0x200: call 0x300, 0x300: return
+    vm.memory[0x200..0x202].copy_from_slice(&[0x23, 0x00]);
+    vm.memory[0x300..0x302].copy_from_slice(&[0x00, 0xEE]);
+    processor_cycle(&mut vm).unwrap();
+    assert_eq!(vm.pc, 0x300);
+    assert_eq!(
+        vm.stack.frames(),
+        &[stack::Frame {
+            call_site: 0x200,
+            subroutine: 0x300
+        }]
+    );
+    processor_cycle(&mut vm).unwrap();
+    assert_eq!(vm.pc, 0x202);
+    assert_eq!(vm.stack.len(), 0);
+
+    // Returning again underflows
+    vm.pc = 0x300;
+    assert!(processor_cycle(&mut vm).unwrap_err().contains("underflow"));
+}
+
+#[test]
+fn test_shift_quirk() {
+    let shift = |quirks: Quirks| {
+        let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+        vm.quirks = quirks;
+        vm.v[1] = 0b0100;
+        vm.v[2] = 0b0011;
+        // 8126: shift right
+        vm.memory[0x200..0x202].copy_from_slice(&[0x81, 0x26]);
+        processor_cycle(&mut vm).unwrap();
+        (vm.v[1], vm.v[0xF])
+    };
+    assert_eq!(shift(Quirks::default()), (0b0010, 0));
+    assert_eq!(
+        shift(Quirks {
+            shift_uses_vy: true,
+            ..Quirks::default()
+        }),
+        (0b0001, 1)
+    );
+
+    // 81 2E: shift left, leaving VY alone
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    vm.quirks = crate::platform::find("vip").unwrap().quirks;
+    vm.v[2] = 0b1000_0001;
+    vm.memory[0x200..0x202].copy_from_slice(&[0x81, 0x2E]);
+    processor_cycle(&mut vm).unwrap();
+    assert_eq!((vm.v[1], vm.v[2], vm.v[0xF]), (0b0000_0010, 0b1000_0001, 1));
+    // VX and VY differed, so the result depended on the quirk
+    assert_eq!(vm.quirks_relied_on, ["shift-uses-vy"]);
+
+    // It doesn't when they're the same
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    vm.memory[0x200..0x202].copy_from_slice(&[0x81, 0x1E]);
+    processor_cycle(&mut vm).unwrap();
+    assert!(vm.quirks_relied_on.is_empty());
+}
+
+#[test]
+fn test_reset() {
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    load_rom(&mut vm.memory, &[0x22, 0x04, 0x00, 0x00, 0x6A, 0x01]).unwrap();
+    processor_cycle(&mut vm).unwrap(); // 2204: call 204
+    processor_cycle(&mut vm).unwrap(); // 6A01: VA = 1
+    vm.memory[0x202] = 0xFF;
+    vm.reset();
+    assert_eq!((vm.pc, vm.v[0xA], vm.stack.len()), (PROGRAM_START, 0, 0));
+    assert_eq!(vm.memory[0x202], 0xFF);
+
+    vm.reload(&[0x00, 0xE0]).unwrap();
+    assert_eq!(&vm.memory[0x200..0x203], &[0x00, 0xE0, 0x00]);
+    assert!(vm.reload(&[0; MEMORY_BYTES]).is_err());
+}
+
+#[test]
+fn test_exit() {
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    vm.reload(&[0x60, 0x01, 0x00, 0xFD]).unwrap();
+    processor_cycle(&mut vm).unwrap();
+    assert!(!vm.exited);
+    processor_cycle(&mut vm).unwrap();
+    assert!(vm.exited);
+    assert_eq!(vm.pc, 0x202);
+
+    // 0000 only stops the program when it's the exit opcode
+    vm.reload(&[0x00, 0x00]).unwrap();
+    assert!(!vm.exited);
+    assert!(processor_cycle(&mut vm).is_err());
+    vm.exit_opcode = Some(0x0000);
+    processor_cycle(&mut vm).unwrap();
+    assert!(vm.exited);
+}
+
+#[test]
+fn test_is_idle() {
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    vm.reload(&[0x60, 0x01, 0x12, 0x02]).unwrap();
+    assert!(!vm.is_idle());
+    processor_cycle(&mut vm).unwrap(); // 6001: V0 = 1
+    assert!(vm.is_idle());
+    vm.sound_timer = 1;
+    assert!(!vm.is_idle());
+    vm.sound_timer = 0;
+    vm.keys_pressed[3] = true;
+    assert!(!vm.is_idle());
+}
+
+#[test]
+fn test_wait_for_key() {
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    // V0 := key
+    vm.reload(&[0xF0, 0x0A]).unwrap();
+    processor_cycle(&mut vm).unwrap();
+    assert_eq!(vm.pc, 0x200);
+
+    vm.keys_pressed[0xF] = true;
+    processor_cycle(&mut vm).unwrap();
+    assert_eq!((vm.pc, vm.v[0]), (0x202, 0xF));
+
+    vm.reload(&[0xF0, 0x0A]).unwrap();
+    vm.keys_pressed[0x4] = true;
+    vm.keys_pressed[0x9] = true;
+    processor_cycle(&mut vm).unwrap();
+    assert_eq!((vm.pc, vm.v[0]), (0x202, 0x4));
+}
+
+#[test]
+fn test_hires() {
+    let mut rom = vec![0x12, 0x60];
+    rom.resize((HIRES_PROGRAM_START - PROGRAM_START) as usize, 0);
+    rom.extend_from_slice(&[
+        0xA0, 0x50, // 2C0: I = font 0
+        0x60, 0x3F, // 2C2: V0 = 63
+        0xD1, 0x01, // 2C4: draw 1 row at (V1, V0)
+        0x02, 0x30, // 2C6: clear
+    ]);
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    vm.reload(&rom).unwrap();
+    assert!(vm.hires);
+    assert_eq!((vm.pc, vm.display.height()), (HIRES_PROGRAM_START, 64));
+
+    for _ in 0..3 {
+        processor_cycle(&mut vm).unwrap();
+    }
+    assert!(vm.display.get_pixel(0, 63));
+    processor_cycle(&mut vm).unwrap();
+    assert!(!vm.display.get_pixel(0, 63));
+
+    vm.reset();
+    assert_eq!(vm.pc, HIRES_PROGRAM_START);
+    vm.reload(&[0x00, 0xE0]).unwrap();
+    assert_eq!((vm.hires, vm.pc, vm.display.height()), (false, PROGRAM_START, 32));
+}
+
+#[test]
+fn test_audio_pattern() {
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    load_rom(
+        &mut vm.memory,
+        &[
+            0xA2, 0x08, // 200: I = 208
+            0xF0, 0x02, // 202: load the audio pattern
+            0x60, 0x70, // 204: V0 = 112
+            0xF0, 0x3A, // 206: pitch = V0
+            0xFF, 0x00, // 208: pattern data
+        ],
+    )
+    .unwrap();
+    assert_eq!(vm.audio_pattern(), None);
+    for _ in 0..4 {
+        processor_cycle(&mut vm).unwrap();
+    }
+    let pattern = vm.audio_pattern().unwrap();
+    assert_eq!(
+        (pattern.bits[..3].to_vec(), pattern.pitch),
+        (vec![0xFF, 0x00, 0x00], 112)
+    );
+
+    vm.reset();
+    assert_eq!((vm.audio_pattern(), vm.pitch), (None, DEFAULT_PITCH));
+}
+
+#[test]
+fn test_display_wait_quirk() {
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    // D001: draw a 1-byte sprite
+    vm.memory[0x200..0x202].copy_from_slice(&[0xD0, 0x01]);
+    processor_cycle(&mut vm).unwrap();
+    assert!(!vm.waiting_for_frame);
+
+    vm.pc = 0x200;
+    vm.quirks.display_wait = true;
+    processor_cycle(&mut vm).unwrap();
+    assert!(vm.waiting_for_frame);
+}
+
+#[test]
+fn test_display_wait_timer_hz() {
+    // Draw, count the draw in V1 and go round again
+    let draws = |timer_hz| {
+        let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+        vm.quirks.display_wait = true;
+        vm.memory[0x200..0x206].copy_from_slice(&[0xD0, 0x01, 0x71, 0x01, 0x12, 0x00]);
+        for frame in 0..FRAMES_PER_SECOND {
+            run_frame_at(&mut vm, frame, PROCESSOR_SPEED_HZ, timer_hz).unwrap();
+        }
+        vm.v[1]
+    };
+    // One draw per timer tick, less the last, whose count is still to come
+    assert_eq!(draws(60), 59);
+    assert_eq!(draws(50), 49);
+}
+
+#[test]
+fn test_wrap_sprites_quirk() {
+    let draw = |quirks: Quirks| {
+        let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+        vm.quirks = quirks;
+        // D012: draw a 2-byte sprite of solid rows at (62, 31)
+        vm.v[0] = 62;
+        vm.v[1] = 31;
+        vm.ir = 0x300;
+        vm.memory[0x300..0x302].copy_from_slice(&[0xFF, 0xFF]);
+        vm.memory[0x200..0x202].copy_from_slice(&[0xD0, 0x12]);
+        processor_cycle(&mut vm).unwrap();
+        vm.display
+    };
+    let display = draw(Quirks::default());
+    assert!(display.get_pixel(63, 31));
+    assert!(!display.get_pixel(0, 31));
+    assert!(!display.get_pixel(0, 0));
+
+    let display = draw(Quirks {
+        wrap_sprites: true,
+        ..Quirks::default()
+    });
+    assert!(display.get_pixel(63, 31));
+    assert!(display.get_pixel(0, 31));
+    assert!(display.get_pixel(5, 0));
+}
+
+/// Draw a sprite of solid rows at (vx, vy) with DXYN, returning the
+/// pixels that are on, row by row, and VF.
+#[cfg(test)]
+fn draw_solid_sprite(wrap_sprites: bool, vx: u8, vy: u8, n: u8) -> (Vec<(usize, usize)>, u8) {
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    vm.quirks.wrap_sprites = wrap_sprites;
+    vm.v[0] = vx;
+    vm.v[1] = vy;
+    vm.ir = 0x300;
+    vm.memory[0x300..0x320].fill(0xFF);
+    vm.memory[0x200..0x202].copy_from_slice(&[0xD0, 0x10 | n]);
+    processor_cycle(&mut vm).unwrap();
+    let on = (0..vm.display.height())
+        .flat_map(|y| (0..vm.display.width()).map(move |x| (x, y)))
+        .filter(|&(x, y)| vm.display.get_pixel(x, y))
+        .collect();
+    (on, vm.v[0xF])
+}
+
+#[test]
+fn test_sprite_edges() {
+    let square = |xs: std::ops::Range<usize>, ys: std::ops::Range<usize>| -> Vec<(usize, usize)> {
+        ys.flat_map(|y| xs.clone().map(move |x| (x, y))).collect()
+    };
+    let both = |vx, vy, n| {
+        (
+            draw_solid_sprite(false, vx, vy, n).0,
+            draw_solid_sprite(true, vx, vy, n).0,
+        )
+    };
+
+    // Top-left corner: nothing to clip
+    assert_eq!(both(0, 0, 2), (square(0..8, 0..2), square(0..8, 0..2)));
+    // Right edge: columns past it are clipped or wrap to the left edge
+    let (clipped, wrapped) = both(60, 10, 1);
+    assert_eq!(clipped, square(60..64, 10..11));
+    assert_eq!(wrapped, [square(0..4, 10..11), square(60..64, 10..11)].concat());
+    // Bottom edge: a sprite taller than the rows left is clipped, or
+    // wraps to the top edge
+    let (clipped, wrapped) = both(8, 30, 15);
+    assert_eq!(clipped, square(8..16, 30..32));
+    assert_eq!(wrapped, [square(8..16, 0..13), square(8..16, 30..32)].concat());
+    // Bottom-right corner
+    let (clipped, wrapped) = both(62, 31, 2);
+    assert_eq!(clipped, square(62..64, 31..32));
+    assert_eq!(
+        wrapped,
+        [
+            square(0..6, 0..1),
+            square(62..64, 0..1),
+            square(0..6, 31..32),
+            square(62..64, 31..32)
+        ]
+        .concat()
+    );
+    // Starting positions past the left or top edge wrap around before
+    // anything is drawn, so they're never clipped
+    assert_eq!(both(64 + 3, 32 + 5, 1), (square(3..11, 5..6), square(3..11, 5..6)));
+    assert_eq!(both(255, 255, 1).0, square(63..64, 31..32));
+}
+
+#[test]
+fn test_16x16_sprites() {
+    // DXY0 draws 16x16 wherever there's room
+    let (on, vf) = draw_solid_sprite(false, 4, 2, 0);
+    assert_eq!(on.len(), 256);
+    assert_eq!(on.first(), Some(&(4, 2)));
+    assert_eq!(on.last(), Some(&(19, 17)));
+    assert_eq!(vf, 0);
+    // and is clipped or wraps at the edges like any other sprite
+    let (clipped, _) = draw_solid_sprite(false, 56, 24, 0);
+    assert_eq!(clipped.len(), 8 * 8);
+    let (wrapped, _) = draw_solid_sprite(true, 56, 24, 0);
+    assert_eq!(wrapped.len(), 256);
+    assert!(wrapped.contains(&(7, 7)) && !wrapped.contains(&(8, 8)));
+}
+
+#[test]
+fn test_sprite_must_be_in_memory() {
+    let draw = |ir: u16, n: u8| {
+        let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+        // Rows past the bottom edge would be clipped, but still have
+        // to be in memory
+        vm.v[0] = 30;
+        vm.ir = ir;
+        vm.memory[0x200..0x202].copy_from_slice(&[0xD0, n]);
+        processor_cycle(&mut vm)
+    };
+    assert!(draw(0xFFB, 5).is_ok());
+    assert!(draw(0xFFC, 5).unwrap_err().contains("0x1000"));
+    assert!(draw(0xFE0, 0).is_ok());
+    assert!(draw(0xFE1, 0).is_err());
+}
+
+#[test]
+fn test_arithmetic_flags() {
+    // Run 8XYN with the given register values, returning (VX, VF)
+    let run = |n: u8, x: usize, y: usize, vx: u8, vy: u8| {
+        let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+        vm.v[x] = vx;
+        vm.v[y] = vy;
+        vm.memory[0x200..0x202].copy_from_slice(&[0x80 | x as u8, (y as u8) << 4 | n]);
+        processor_cycle(&mut vm).unwrap();
+        (vm.v[x], vm.v[0xF])
+    };
+
+    // 8XY4: add, VF = carry
+    assert_eq!(run(0x4, 1, 2, 0xFF, 0x02), (0x01, 1));
+    assert_eq!(run(0x4, 1, 2, 0x01, 0x02), (0x03, 0));
+    // 8XY5: subtract, VF = not borrow (equal operands don't borrow)
+    assert_eq!(run(0x5, 1, 2, 0x05, 0x05), (0x00, 1));
+    assert_eq!(run(0x5, 1, 2, 0x01, 0x02), (0xFF, 0));
+    // 8XY7: reverse subtract
+    assert_eq!(run(0x7, 1, 2, 0x02, 0x01), (0xFF, 0));
+    // 8XY6 and 8XYE: shifts
+    assert_eq!(run(0x6, 1, 2, 0x03, 0), (0x01, 1));
+    assert_eq!(run(0xE, 1, 2, 0x81, 0), (0x02, 1));
+
+    // With VF as X, the flag overwrites the result
+    assert_eq!(run(0x4, 0xF, 1, 0xFF, 0x02).1, 1);
+    assert_eq!(run(0x5, 0xF, 1, 0x01, 0x02).1, 0);
+    assert_eq!(run(0xE, 0xF, 1, 0x40, 0).1, 0);
+
+    // With VF as Y, its value is used before the flag is written
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    vm.v[1] = 0x10;
+    vm.v[0xF] = 0x01;
+    // 81F5: V1 -= VF
+    vm.memory[0x200..0x202].copy_from_slice(&[0x81, 0xF5]);
+    processor_cycle(&mut vm).unwrap();
+    assert_eq!((vm.v[1], vm.v[0xF]), (0x0F, 1));
+}
+
+#[test]
+fn test_increment_i_quirk() {
+    let run = |quirks: Quirks| {
+        let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+        vm.quirks = quirks;
+        vm.ir = 0x300;
+        vm.v[..3].copy_from_slice(&[1, 2, 3]);
+        // F255: store V0-V2 at I, then F265: load them back
+        vm.memory[0x200..0x204].copy_from_slice(&[0xF2, 0x55, 0xF2, 0x65]);
+        processor_cycle(&mut vm).unwrap();
+        let ir_after_store = vm.ir;
+        processor_cycle(&mut vm).unwrap();
+        (ir_after_store, vm.ir, vm.memory[0x300..0x303].to_vec())
+    };
+    assert_eq!(run(Quirks::default()), (0x300, 0x300, vec![1, 2, 3]));
+    let quirks = Quirks {
+        increment_i: true,
+        ..Quirks::default()
+    };
+    assert_eq!(run(quirks), (0x303, 0x306, vec![1, 2, 3]));
+}
+
+#[test]
+fn test_index_overflow_quirk() {
+    let run = |quirks: Quirks, ir: u16| {
+        let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+        vm.quirks = quirks;
+        vm.ir = ir;
+        vm.v[0] = 0x10;
+        vm.v[0xF] = 0xAA;
+        // F01E: I += V0
+        vm.memory[0x200..0x202].copy_from_slice(&[0xF0, 0x1E]);
+        processor_cycle(&mut vm).unwrap();
+        (vm.ir, vm.v[0xF])
+    };
+    assert_eq!(run(Quirks::default(), 0xFF8), (0x1008, 0xAA));
+    let quirks = Quirks {
+        index_overflow: true,
+        ..Quirks::default()
+    };
+    assert_eq!(run(quirks, 0xFF8), (0x1008, 1));
+    assert_eq!(run(quirks, 0xFE0), (0xFF0, 0));
+}
+
+#[test]
+fn test_jump_uses_vx_quirk() {
+    let run = |quirks: Quirks| {
+        let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+        vm.quirks = quirks;
+        vm.v[0] = 0x10;
+        vm.v[3] = 0x20;
+        // B340: jump to 340 + V0, or + V3
+        vm.memory[0x200..0x202].copy_from_slice(&[0xB3, 0x40]);
+        processor_cycle(&mut vm).unwrap();
+        vm.pc
+    };
+    assert_eq!(run(Quirks::default()), 0x350);
+    assert_eq!(run(crate::platform::find("chip48").unwrap().quirks), 0x360);
+}
+
+#[test]
+fn test_vf_reset_quirk() {
+    let run = |quirks: Quirks, opcode: u8| {
+        let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+        vm.quirks = quirks;
+        vm.v[1] = 0b1100;
+        vm.v[2] = 0b1010;
+        vm.v[0xF] = 7;
+        // 812N: V1 = V1 OR/AND/XOR V2
+        vm.memory[0x200..0x202].copy_from_slice(&[0x81, 0x20 | opcode]);
+        processor_cycle(&mut vm).unwrap();
+        (vm.v[1], vm.v[0xF])
+    };
+    let vip = crate::platform::find("vip").unwrap().quirks;
+    assert_eq!(run(Quirks::default(), 1), (0b1110, 7));
+    assert_eq!(run(vip, 1), (0b1110, 0));
+    assert_eq!(run(vip, 2), (0b1000, 0));
+    assert_eq!(run(vip, 3), (0b0110, 0));
+}
+
+#[test]
+fn test_scroll_instructions() {
+    let run = |quirks: Quirks, hires: bool| {
+        let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+        vm.quirks = quirks;
+        vm.set_hires(hires);
+        vm.display.set_pixel(8, 8, true);
+        // 00C3 down 3, 00FB right, 00D1 up 1, 00FB right, 00FC left
+        let pc = vm.pc as usize;
+        vm.memory[pc..pc + 10].copy_from_slice(&[0x00, 0xC3, 0x00, 0xFB, 0x00, 0xD1, 0x00, 0xFB, 0x00, 0xFC]);
+        for _ in 0..5 {
+            processor_cycle(&mut vm).unwrap();
+        }
+        let lit: Vec<(usize, usize)> = (0..vm.display.width())
+            .flat_map(|x| (0..vm.display.height()).map(move |y| (x, y)))
+            .filter(|&(x, y)| vm.display.get_pixel(x, y))
+            .collect();
+        lit
+    };
+    let legacy = crate::platform::find("schip-legacy").unwrap().quirks;
+    assert_eq!(run(Quirks::default(), false), [(12, 10)]);
+    assert_eq!(run(legacy, false), [(10, 9)]);
+    assert_eq!(run(legacy, true), [(12, 10)]);
+}
+
+#[test]
+fn test_schip_hires() {
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    // 00FF hi-res, D011 draw at (V0, V1), 00FE lo-res
+    load_rom(&mut vm.memory, &[0x00, 0xFF, 0xD0, 0x11, 0x00, 0xFE]).unwrap();
+    vm.v[0] = 100;
+    vm.v[1] = 50;
+    vm.ir = FONT_MEMORY_START as u16;
+    processor_cycle(&mut vm).unwrap();
+    assert_eq!((vm.display.width(), vm.display.height()), (128, 64));
+    // Positions only wrap at the edges of the bigger display
+    processor_cycle(&mut vm).unwrap();
+    assert!(vm.display.get_pixel(100, 50) && vm.display.get_pixel(103, 50));
+    processor_cycle(&mut vm).unwrap();
+    assert!(vm.display.is_lores());
+
+    // Resetting goes back to lo-res
+    vm.display.set_schip_hires(true);
+    vm.reset();
+    assert!(vm.display.is_lores());
+}
+
+#[test]
+fn test_planes() {
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    // F301 select both planes, D012 draw, F201 select plane 2, 00E0
+    load_rom(&mut vm.memory, &[0xF3, 0x01, 0xD0, 0x12, 0xF2, 0x01, 0x00, 0xE0]).unwrap();
+    // Each plane's sprite follows the other's: plane 1 gets 0xC0 0x00,
+    // plane 2 0x80 0x80
+    vm.memory[0x300..0x304].copy_from_slice(&[0xC0, 0x00, 0x80, 0x80]);
+    vm.ir = 0x300;
+    processor_cycle(&mut vm).unwrap();
+    processor_cycle(&mut vm).unwrap();
+    let planes = |vm: &VM| [(0, 0), (1, 0), (0, 1)].map(|(x, y)| vm.display.pixel_planes(x, y));
+    assert_eq!(planes(&vm), [3, 1, 2]);
+    assert_eq!(vm.v[0xF], 0);
+
+    // Drawing again turns pixels off in both planes
+    vm.pc -= 2;
+    processor_cycle(&mut vm).unwrap();
+    assert_eq!((planes(&vm), vm.v[0xF]), ([0, 0, 0], 1));
+    vm.pc -= 2;
+    processor_cycle(&mut vm).unwrap();
+
+    // 00E0 only clears the selected plane
+    processor_cycle(&mut vm).unwrap();
+    processor_cycle(&mut vm).unwrap();
+    assert_eq!(planes(&vm), [1, 1, 0]);
+
+    // A sprite for each plane has to fit in memory
+    vm.display.set_planes(3);
+    vm.ir = 0xFFE;
+    vm.memory[0x300..0x302].copy_from_slice(&[0xD0, 0x12]);
+    vm.pc = 0x300;
+    assert!(processor_cycle(&mut vm).is_err());
+}
+
+#[test]
+fn test_verify_caches() {
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(7)));
+    load_rom(
+        &mut vm.memory,
+        &[
+            0xC0, 0xFF, // 200: V0 = random
+            0xA3, 0x00, // 202: I = 300
+            0xF0, 0x55, // 204: store V0 at 300
+            0x12, 0x00, // 206: jump to 200
+        ],
+    )
+    .unwrap();
+    vm.decode_cache = Some(DecodeCache::new(vm.memory.len()));
+    vm.verify_caches = true;
+    let mut expected = crate::random::SeededRandom::new(7);
+    for _ in 0..8 {
+        processor_cycle(&mut vm).unwrap();
+    }
+    // Each CXNN drew one random number, not one per run
+    expected.next_u8();
+    assert_eq!(vm.v[0], expected.next_u8());
+    assert!(vm.decode_cache.is_some());
+
+    // Changing memory behind the cache's back makes it stale
+    vm.memory[0x201] = 0x00;
+    let err = processor_cycle(&mut vm).unwrap_err();
+    assert!(err.starts_with(CACHE_MISMATCH), "{}", err);
+    assert!(err.ends_with("(RND V0, 0x00): V differ"), "{}", err);
+}
+
+#[test]
+fn test_fonts() {
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    // F129: I = small digit V1, then F130: I = large digit V1
+    load_rom(&mut vm.memory, &[0xF1, 0x29, 0xF1, 0x30]).unwrap();
+    vm.v[1] = 0xA;
+    processor_cycle(&mut vm).unwrap();
+    assert_eq!(vm.ir as usize, FONT_MEMORY_START + 50);
+    assert_eq!(vm.memory[vm.ir as usize..vm.ir as usize + 5], FONT_BYTES[50..55]);
+    processor_cycle(&mut vm).unwrap();
+    assert_eq!(vm.ir as usize, LARGE_FONT_MEMORY_START + 100);
+    assert_eq!(
+        vm.memory[vm.ir as usize..vm.ir as usize + 10],
+        LARGE_FONT_BYTES[100..110]
+    );
+
+    // The fonts sit side by side below the program, and both survive
+    // loading a ROM
+    let small = FONT_MEMORY_START..FONT_MEMORY_START + FONT_BYTES.len();
+    let large = LARGE_FONT_MEMORY_START..LARGE_FONT_MEMORY_START + LARGE_FONT_BYTES.len();
+    assert_eq!(small.end, large.start);
+    assert!(large.end <= PROGRAM_START as usize);
+    assert_eq!(vm.memory[small.clone()], FONT_BYTES);
+    assert_eq!(vm.memory[large], LARGE_FONT_BYTES);
+
+    // reload loads the VM's small font
+    vm.font = &VIP_FONT_BYTES;
+    vm.reload(&[0x00, 0xE0]).unwrap();
+    assert_eq!(vm.memory[small], VIP_FONT_BYTES);
+}
+
+#[test]
+fn test_strict_pc_bounds() {
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    // 00E0 at 0x100, and split across the end of memory
+    vm.memory[0x100..0x102].copy_from_slice(&[0x00, 0xE0]);
+    vm.memory[0xFFF] = 0x00;
+    vm.memory[0x000] = 0xE0;
+    vm.pc = 0x100;
+    assert!(processor_cycle(&mut vm).is_ok());
+    vm.pc = 0xFFF;
+    assert!(processor_cycle(&mut vm).is_ok());
+
+    vm.strict = true;
+    vm.pc = 0x100;
+    assert!(processor_cycle(&mut vm).unwrap_err().contains("reserved"));
+    vm.pc = 0xFFF;
+    assert!(processor_cycle(&mut vm).unwrap_err().contains("end of memory"));
+}
+
+#[test]
+fn test_self_modifying_code() {
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    load_rom(
+        &mut vm.memory,
+        &[
+            0xA2, 0x08, // 200: I = 208
+            0x60, 0x61, // 202: V0 = 61
+            0x61, 0x2A, // 204: V1 = 2A
+            0xF1, 0x55, // 206: store V0-V1 at I, overwriting 208
+            0x00, 0xE0, // 208: becomes 612A: V1 = 2A
+        ],
+    )
+    .unwrap();
+    for _ in 0..4 {
+        processor_cycle(&mut vm).unwrap();
+    }
+    vm.v[1] = 0;
+    processor_cycle(&mut vm).unwrap();
+    assert_eq!(vm.v[1], 0x2A);
+}
+
+#[test]
+fn test_self_modifying_code_with_decode_cache() {
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    load_rom(
+        &mut vm.memory,
+        &[
+            0x61, 0x01, // 200: V1 = 01
+            0xA2, 0x01, // 202: I = 201
+            0x60, 0x2A, // 204: V0 = 2A
+            0xF0, 0x55, // 206: store V0 at 201, making 200 612A
+            0x12, 0x00, // 208: jump to 200
+        ],
+    )
+    .unwrap();
+    vm.decode_cache = Some(DecodeCache::new(vm.memory.len()));
+    for _ in 0..6 {
+        processor_cycle(&mut vm).unwrap();
+    }
+    assert_eq!(vm.v[1], 0x2A);
+}
+
+#[test]
+fn test_self_modifying_code_with_threaded_code() {
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    load_rom(
+        &mut vm.memory,
+        &[
+            0x61, 0x01, // 200: V1 = 01
+            0xA2, 0x01, // 202: I = 201
+            0x60, 0x2A, // 204: V0 = 2A
+            0xF0, 0x55, // 206: store V0 at 201, making 200 612A
+            0x12, 0x00, // 208: jump to 200
+        ],
+    )
+    .unwrap();
+    vm.block_cache = Some(BlockCache::new(vm.memory.len()));
+    vm.verify_caches = true;
+    for _ in 0..6 {
+        processor_cycle(&mut vm).unwrap();
+    }
+    assert_eq!(vm.v[1], 0x2A);
+
+    // Every instruction runs the same compiled or not, quirks and all
+    let roms: [&[u8]; 2] = [
+        include_bytes!("../../roms/corex89_test_opcode.ch8"),
+        include_bytes!("../../roms/bc_test.ch8"),
+    ];
+    for rom in roms {
+        for platform in crate::platform::PLATFORMS.iter() {
+            let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+            vm.quirks = platform.quirks;
+            vm.reload(rom).unwrap();
+            vm.block_cache = Some(BlockCache::new(vm.memory.len()));
+            vm.verify_caches = true;
+            for _ in 0..2000 {
+                processor_cycle(&mut vm).unwrap();
+            }
+        }
+    }
+}
+
+#[test]
+fn test_poke_with_decode_cache() {
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    load_rom(&mut vm.memory, &[0x61, 0x01, 0x12, 0x00]).unwrap();
+    vm.decode_cache = Some(DecodeCache::new(vm.memory.len()));
+    processor_cycle(&mut vm).unwrap();
+    vm.poke(0x201, 0x2A);
+    processor_cycle(&mut vm).unwrap();
+    processor_cycle(&mut vm).unwrap();
+    assert_eq!(vm.v[1], 0x2A);
+}
+
+#[test]
+fn test_out_of_bounds_errors() {
+    let run = |instruction: [u8; 2], ir: u16, v0: u8| {
+        let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+        vm.ir = ir;
+        vm.v[0] = v0;
+        vm.memory[0x200..0x202].copy_from_slice(&instruction);
+        processor_cycle(&mut vm)
+    };
+    assert!(run([0xFF, 0x55], 0xFFA, 0).is_err()); // store past the end
+    assert!(run([0xFF, 0x65], 0xFFA, 0).is_err()); // load past the end
+    assert!(run([0xF0, 0x33], 0xFFE, 0).is_err()); // BCD past the end
+    assert!(run([0xD0, 0x0F], 0xFFA, 0).is_err()); // sprite past the end
+    assert!(run([0xE0, 0x9E], 0, 0x10).is_err()); // not a key
+    assert!(run([0xE0, 0x9E], 0, 0x0F).is_ok());
+}
+
+#[test]
+fn test_random_roms_dont_panic() {
+    use crate::random::RandomSource;
+
+    let mut rng = crate::random::SeededRandom::new(0);
+    for _ in 0..200 {
+        let rom: Vec<u8> = (0..256).map(|_| rng.next_u8()).collect();
+        let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+        vm.quirks.increment_i = rng.next_u8() & 1 == 1;
+        vm.quirks.wrap_sprites = rng.next_u8() & 1 == 1;
+        load_rom(&mut vm.memory, &rom).unwrap();
+        for _ in 0..1000 {
+            if processor_cycle(&mut vm).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_cheats() {
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    vm.cheats = CheatTable::new(crate::cheats::parse_cheats("jump load 200=1204\nlives always 300=09 off").unwrap());
+    vm.reload(&[0x12, 0x00, 0x00, 0x00]).unwrap();
+    assert_eq!(vm.memory[0x200..0x202], [0x12, 0x04]);
+
+    assert_eq!(vm.toggle_cheat(1), Some(true));
+    assert_eq!(vm.memory[0x300], 9);
+    vm.memory[0x300] = 2;
+    vm.apply_frame_cheats();
+    assert_eq!(vm.memory[0x300], 9);
+    assert_eq!(vm.toggle_cheat(2), None);
+}
+
+#[test]
+fn test_read_rom_file() {
+    let path = std::env::temp_dir().join(format!("chip8-read-rom-test-{}.ch8", std::process::id()));
+    std::fs::write(&path, [0x12, 0x00]).unwrap();
+    assert_eq!(read_rom_file(&path), Ok(vec![0x12, 0x00]));
+
+    let file = File::create(&path).unwrap();
+    file.set_len(MAX_ROM_FILE_BYTES + 1).unwrap();
+    let err = read_rom_file(&path).unwrap_err();
+    assert!(
+        err.ends_with("is bigger than 16777216 bytes, too big to be a ROM"),
+        "{}",
+        err
+    );
+
+    std::fs::remove_file(&path).unwrap();
+    assert!(read_rom_file(&path).unwrap_err().starts_with("Error reading ROM file "));
+}
+
+#[test]
+fn test_load_rom_size() {
+    let mut memory = [0; MEMORY_BYTES];
+    assert_eq!(load_rom(&mut memory, &[]), Ok(()));
+    assert_eq!(MAX_ROM_BYTES, 0xE00);
+
+    // Right up to the last byte of memory
+    let mut rom = vec![0xAB; MAX_ROM_BYTES];
+    assert_eq!(load_rom(&mut memory, &rom), Ok(()));
+    assert_eq!(memory[MEMORY_BYTES - 1], 0xAB);
+
+    // One byte over is refused without touching memory
+    let mut memory = [0; MEMORY_BYTES];
+    rom.push(0xCD);
+    let err = load_rom(&mut memory, &rom).unwrap_err();
+    assert_eq!(
+        err,
+        LoadError::RomTooLarge {
+            size: 0xE01,
+            max: 0xE00
+        }
+    );
+    assert_eq!(
+        err.to_string(),
+        "ROM is 3585 bytes, 1 more than the 3584 bytes that fit in memory"
+    );
+    assert!(memory.iter().all(|&byte| byte == 0));
+
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    vm.reload(&[0x12, 0x00]).unwrap();
+    assert!(vm.reload(&rom).is_err());
+    // A failed reload keeps the old program
+    assert_eq!(fetch(&vm.memory, PROGRAM_START), 0x1200);
+
+    // XO-CHIP's 64 KB holds ROMs right up to FFFF
+    assert_eq!(max_rom_bytes(XO_CHIP_MEMORY_BYTES), 0xFE00);
+    vm.set_memory_bytes(XO_CHIP_MEMORY_BYTES);
+    assert!(vm.reload(&rom).is_ok());
+    let mut rom = vec![0xAB; 0xFE00];
+    assert!(vm.reload(&rom).is_ok());
+    assert_eq!(vm.memory[0xFFFF], 0xAB);
+    rom.push(0xCD);
+    assert_eq!(
+        vm.reload(&rom),
+        Err("ROM is 65025 bytes, 1 more than the 65024 bytes that fit in memory".to_string())
+    );
+    assert_eq!(vm.memory[0xFFFF], 0xAB);
+}
+
+#[test]
+fn test_xo_chip_memory() {
+    let mut vm = VM::new(Box::new(crate::random::SeededRandom::new(0)));
+    vm.set_memory_bytes(XO_CHIP_MEMORY_BYTES);
+    vm.decode_cache = Some(DecodeCache::new(vm.memory.len()));
+    let mut rom = vec![0; 0xE00];
+    // 200: V0 := FF; I := FFF; I += V0 (I is now 10FE); save V0
+    rom[..8].copy_from_slice(&[0x60, 0xFF, 0xAF, 0xFF, 0xF0, 0x1E, 0xF0, 0x55]);
+    // 200 + E00 = 1000: code past the first 4 KB, reached by running on
+    rom[8..10].copy_from_slice(&[0x1F, 0xFE]); // 208: jump FFE
+    rom[0xDFE..].copy_from_slice(&[0x61, 0x01]); // FFE: V1 := 1
+    rom.extend_from_slice(&[0x62, 0x02]); // 1000: V2 := 2
+    vm.reload(&rom).unwrap();
+    for _ in 0..7 {
+        processor_cycle(&mut vm).unwrap();
+    }
+    assert_eq!(vm.ir, 0x10FE);
+    assert_eq!(vm.memory[0x10FE], 0xFF);
+    assert_eq!((vm.v[1], vm.v[2]), (1, 2));
+    assert_eq!(vm.pc, 0x1002);
+
+    // PC wraps around at the end of memory rather than at 4 KB
+    vm.pc = 0xFFFE;
+    vm.memory[0xFFFE..].copy_from_slice(&[0x63, 0x03]);
+    processor_cycle(&mut vm).unwrap();
+    assert_eq!((vm.v[3], vm.pc), (3, 0));
+}
@@ -0,0 +1,182 @@
+//! Watchpoints: pause the debugger when a register changes or when a
+//! range of memory is written to.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Watch {
+    /// Break when VX changes value
+    Register(usize),
+    /// Break when I changes value
+    Index,
+    /// Break on any write to these addresses, even if the value is
+    /// unchanged
+    Memory(RangeInclusive<u16>),
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Watch::Register(x) => write!(f, "V{:X}", x),
+            Watch::Index => write!(f, "I"),
+            Watch::Memory(range) if range.start() == range.end() => write!(f, "{:03X}", range.start()),
+            Watch::Memory(range) => write!(f, "{:03X}-{:03X}", range.start(), range.end()),
+        }
+    }
+}
+
+/// A watchpoint being triggered.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Hit {
+    Write { addr: u16, old: u8, new: u8 },
+    Register { x: usize, old: u8, new: u8 },
+    Index { old: u16, new: u16 },
+}
+
+impl fmt::Display for Hit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Hit::Write { addr, old, new } => write!(f, "write to {:03X}: {:02X} -> {:02X}", addr, old, new),
+            Hit::Register { x, old, new } => write!(f, "V{:X} changed: {:02X} -> {:02X}", x, old, new),
+            Hit::Index { old, new } => write!(f, "I changed: {:03X} -> {:03X}", old, new),
+        }
+    }
+}
+
+/// Parse a watch like `v3`, `i`, `300` or `300-30F` (addresses in hex).
+pub fn parse_watch(s: &str) -> Result<Watch, String> {
+    let upper = s.to_ascii_uppercase();
+    if upper == "I" {
+        return Ok(Watch::Index);
+    }
+    if let Some(x) = upper.strip_prefix('V') {
+        return match usize::from_str_radix(x, 16) {
+            Ok(x) if x < 16 => Ok(Watch::Register(x)),
+            _ => Err(format!("Invalid register {:?}", s)),
+        };
+    }
+
+    let parse = |addr: &str| u16::from_str_radix(addr.trim_start_matches("0X"), 16);
+    let range = match upper.split_once('-') {
+        Some((start, end)) => parse(start).and_then(|start| parse(end).map(|end| start..=end)),
+        None => parse(&upper).map(|addr| addr..=addr),
+    };
+    match range {
+        Ok(range) if range.start() <= range.end() => Ok(Watch::Memory(range)),
+        _ => Err(format!("Invalid watch {:?}, expected e.g. V3, I, 300 or 300-30F", s)),
+    }
+}
+
+/// The active watchpoints, checked by `processor_cycle` on every
+/// instruction. Hits are collected until the debugger takes them.
+#[derive(Default)]
+pub struct WatchTable {
+    watches: Vec<Watch>,
+    hits: Vec<Hit>,
+}
+
+impl WatchTable {
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    pub fn watches(&self) -> &[Watch] {
+        &self.watches
+    }
+
+    pub fn add(&mut self, watch: Watch) {
+        if !self.watches.contains(&watch) {
+            self.watches.push(watch);
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Watch> {
+        if index < self.watches.len() {
+            Some(self.watches.remove(index))
+        } else {
+            None
+        }
+    }
+
+    /// Record a hit if `addr` is being watched.
+    pub fn check_memory_write(&mut self, addr: u16, old: u8, new: u8) {
+        for watch in &self.watches {
+            if let Watch::Memory(range) = watch {
+                if range.contains(&addr) {
+                    self.hits.push(Hit::Write { addr, old, new });
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Record hits for any watched registers that differ.
+    pub fn check_registers(&mut self, v_before: &[u8; 16], v_after: &[u8; 16], ir_before: u16, ir_after: u16) {
+        for watch in &self.watches {
+            match *watch {
+                Watch::Register(x) if v_before[x] != v_after[x] => {
+                    self.hits.push(Hit::Register {
+                        x,
+                        old: v_before[x],
+                        new: v_after[x],
+                    });
+                }
+                Watch::Index if ir_before != ir_after => {
+                    self.hits.push(Hit::Index {
+                        old: ir_before,
+                        new: ir_after,
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Remove and return the hits recorded since the last call.
+    pub fn take_hits(&mut self) -> Vec<Hit> {
+        std::mem::take(&mut self.hits)
+    }
+}
+
+#[test]
+fn test_parse_watch() {
+    assert_eq!(parse_watch("v3"), Ok(Watch::Register(3)));
+    assert_eq!(parse_watch("VF"), Ok(Watch::Register(0xF)));
+    assert_eq!(parse_watch("i"), Ok(Watch::Index));
+    assert_eq!(parse_watch("300"), Ok(Watch::Memory(0x300..=0x300)));
+    assert_eq!(parse_watch("300-30F"), Ok(Watch::Memory(0x300..=0x30F)));
+    assert!(parse_watch("V10").is_err());
+    assert!(parse_watch("30F-300").is_err());
+    assert!(parse_watch("nope").is_err());
+}
+
+#[test]
+fn test_watch_table() {
+    let mut table = WatchTable::default();
+    table.add(Watch::Register(3));
+    table.add(Watch::Memory(0x300..=0x30F));
+
+    let before = [0; 16];
+    let mut after = [0; 16];
+    after[2] = 1;
+    table.check_registers(&before, &after, 0, 0);
+    table.check_memory_write(0x2FF, 0, 1);
+    assert!(table.take_hits().is_empty());
+
+    after[3] = 1;
+    table.check_registers(&before, &after, 0, 0);
+    table.check_memory_write(0x305, 0, 0);
+    let hits = table.take_hits();
+    assert_eq!(
+        hits[1],
+        Hit::Write {
+            addr: 0x305,
+            old: 0,
+            new: 0
+        }
+    );
+    assert_eq!(hits[1].to_string(), "write to 305: 00 -> 00");
+    assert_eq!(hits.len(), 2);
+    assert!(table.take_hits().is_empty());
+}
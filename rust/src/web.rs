@@ -0,0 +1,95 @@
+//! Browser frontend. The page (see `www/`) calls `Emulator::run_frame`
+//! from `requestAnimationFrame`, draws `Emulator::pixels` to a canvas,
+//! and forwards `KeyboardEvent.code` values from key events.
+
+use wasm_bindgen::prelude::*;
+
+use crate::random::SeededRandom;
+use crate::vm::{run_frame, VM};
+
+#[wasm_bindgen]
+pub struct Emulator {
+    vm: VM,
+    frames: u64,
+}
+
+#[wasm_bindgen]
+impl Emulator {
+    #[wasm_bindgen(constructor)]
+    pub fn new(rom: &[u8], seed: u32) -> Result<Emulator, JsValue> {
+        let mut vm = VM::new(Box::new(SeededRandom::new(seed as u64)));
+        vm.reload(rom).map_err(|err| JsValue::from_str(&err))?;
+        Ok(Emulator { vm, frames: 0 })
+    }
+
+    pub fn width(&self) -> usize {
+        self.vm.display.width()
+    }
+
+    /// 32, or 64 for hi-res ROMs.
+    pub fn height(&self) -> usize {
+        self.vm.display.height()
+    }
+
+    /// Run one frame's worth of cycles. Browsers repaint at (roughly)
+    /// 60 Hz, matching the emulator's frame rate.
+    pub fn run_frame(&mut self) -> Result<(), JsValue> {
+        run_frame(&mut self.vm, self.frames).map_err(|err| JsValue::from_str(&err))?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// The display as RGBA bytes, row by row, ready for `ImageData`.
+    pub fn pixels(&mut self) -> Vec<u8> {
+        let rgba = self.vm.display.to_rgba_bytes();
+        self.vm.display.mark_painted();
+        rgba
+    }
+
+    /// The rectangle of the display that changed since the last call,
+    /// as `[x, y, width, height]`, for `putImageData`'s dirty rectangle.
+    pub fn take_damage(&mut self) -> Vec<u32> {
+        let damage = self.vm.display.take_damage();
+        [damage.x, damage.y, damage.width, damage.height]
+            .iter()
+            .map(|&n| n as u32)
+            .collect()
+    }
+
+    pub fn key_down(&mut self, code: &str) {
+        if let Some(key) = key_code_to_key(code) {
+            self.vm.keys_pressed[key as usize] = true;
+        }
+    }
+
+    pub fn key_up(&mut self, code: &str) {
+        if let Some(key) = key_code_to_key(code) {
+            self.vm.keys_pressed[key as usize] = false;
+        }
+    }
+}
+
+/// Map a `KeyboardEvent.code` to a CHIP-8 keypad key, using the same
+/// layout as the SDL frontend. Codes are physical key positions, so
+/// this works regardless of keyboard layout.
+fn key_code_to_key(code: &str) -> Option<u8> {
+    match code {
+        "Digit1" => Some(0x1),
+        "Digit2" => Some(0x2),
+        "Digit3" => Some(0x3),
+        "Digit4" => Some(0xC),
+        "KeyQ" => Some(0x4),
+        "KeyW" => Some(0x5),
+        "KeyE" => Some(0x6),
+        "KeyR" => Some(0xD),
+        "KeyA" => Some(0x7),
+        "KeyS" => Some(0x8),
+        "KeyD" => Some(0x9),
+        "KeyF" => Some(0xE),
+        "KeyZ" => Some(0xA),
+        "KeyX" => Some(0x0),
+        "KeyC" => Some(0xB),
+        "KeyV" => Some(0xF),
+        _ => None,
+    }
+}
@@ -0,0 +1,108 @@
+//! Runs the test ROMs in `roms/` headless and compares the display
+//! against the snapshots in `tests/golden/`. `quirks.ch8`, assembled
+//! from `roms/quirks.8o`, stands in for Timendus' quirks test, which
+//! isn't in `roms/`: it's run under several platforms, as what it draws
+//! depends on their quirks. After an intended change
+//! to what a ROM draws, regenerate the snapshots with
+//!
+//! ```sh
+//! $ UPDATE_GOLDEN=1 cargo test --test test_roms
+//! ```
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use chip8::platform::{self, Platform};
+use chip8::random::SeededRandom;
+use chip8::vm::{cycles_in_frame, processor_cycle, timer_ticks_due, DEFAULT_TIMER_HZ, PROCESSOR_SPEED_HZ, VM};
+
+/// Run `rom` for `cycles` instructions, ticking the timers once a frame,
+/// with `platform`'s quirks and font if given.
+fn run_rom(rom: &[u8], cycles: u64, platform: Option<&Platform>) -> VM {
+    let mut vm = VM::new(Box::new(SeededRandom::new(0)));
+    if let Some(platform) = platform {
+        vm.quirks = platform.quirks;
+        vm.font = platform.font;
+    }
+    vm.reload(rom).unwrap();
+    let mut frames = 0;
+    let mut frame_cycles_left = cycles_in_frame(frames, PROCESSOR_SPEED_HZ);
+    for _ in 0..cycles {
+        processor_cycle(&mut vm).unwrap_or_else(|err| panic!("cycle {}: {}", vm.cycles, err));
+        vm.cycles += 1;
+        frame_cycles_left -= 1;
+        if vm.waiting_for_frame {
+            // The draw takes up the rest of the frame, as in chip8 run
+            vm.waiting_for_frame = false;
+            vm.cycles += frame_cycles_left;
+            frame_cycles_left = 0;
+        }
+        if frame_cycles_left == 0 {
+            frames += 1;
+            for _ in 0..timer_ticks_due(frames, DEFAULT_TIMER_HZ) {
+                vm.tick_timers();
+            }
+            frame_cycles_left = cycles_in_frame(frames, PROCESSOR_SPEED_HZ);
+        }
+    }
+    vm
+}
+
+fn check_rom(name: &str, cycles: u64) {
+    check_rom_on(name, None, cycles);
+}
+
+/// Like `check_rom`, under `platform` if given, which has a snapshot of
+/// its own named after it.
+fn check_rom_on(name: &str, platform: Option<&str>, cycles: u64) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let rom = fs::read(root.join("../roms").join(format!("{}.ch8", name))).unwrap();
+    let platform = platform.map(|platform| platform::find(platform).unwrap());
+    let actual = run_rom(&rom, cycles, platform).display.to_ascii();
+
+    let golden_name = match platform {
+        Some(platform) => format!("{}-{}", name, platform.name),
+        None => name.to_string(),
+    };
+    let golden = root.join("tests/golden").join(format!("{}.txt", golden_name));
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&golden, &actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&golden).unwrap_or_else(|err| panic!("{}: {}", golden.display(), err));
+    assert!(
+        actual == expected,
+        "{} drew something different after {} cycles:\n{}",
+        golden_name,
+        cycles,
+        actual
+    );
+}
+
+#[test]
+fn test_ibm_logo() {
+    check_rom("ibm-logo", 1000);
+}
+
+#[test]
+fn test_bc_test() {
+    check_rom("bc_test", 10_000);
+}
+
+#[test]
+fn test_corex89_test_opcode() {
+    check_rom("corex89_test_opcode", 10_000);
+}
+
+#[test]
+fn test_keypad_test() {
+    check_rom("Keypad Test [Hap, 2006]", 10_000);
+}
+
+#[test]
+fn test_quirks() {
+    for platform in ["vip", "schip-legacy", "schip-modern", "xochip"] {
+        check_rom_on("quirks", Some(platform), 10_000);
+    }
+}
@@ -0,0 +1,50 @@
+// Build the package first with:
+//   wasm-pack build --target web --out-dir www/pkg -- --no-default-features --features web
+// and then serve this directory with any static file server.
+import init, { Emulator } from "./pkg/chip8.js";
+
+await init();
+
+const canvas = document.getElementById("screen");
+const context = canvas.getContext("2d");
+
+let emulator = null;
+
+document.getElementById("rom").addEventListener("change", async (event) => {
+  const rom = new Uint8Array(await event.target.files[0].arrayBuffer());
+  const seed = Math.floor(Math.random() * 0xFFFFFFFF);
+  emulator = new Emulator(rom, seed);
+  canvas.width = emulator.width();
+  canvas.height = emulator.height();
+});
+
+document.addEventListener("keydown", (event) => emulator && emulator.key_down(event.code));
+document.addEventListener("keyup", (event) => emulator && emulator.key_up(event.code));
+
+function frame() {
+  if (emulator) {
+    try {
+      emulator.run_frame();
+    } catch (err) {
+      console.error(err);
+      emulator = null;
+    }
+  }
+  if (emulator) {
+    // SUPER-CHIP programs can switch resolution as they run. The
+    // canvas's CSS width stays the same, so pixels get smaller instead
+    if (canvas.width !== emulator.width() || canvas.height !== emulator.height()) {
+      canvas.width = emulator.width();
+      canvas.height = emulator.height();
+    }
+    // Only redraw what changed. Resizing blanks the canvas, but it
+    // also clears the display, so all of it is damaged then
+    const [x, y, width, height] = emulator.take_damage();
+    if (width > 0 && height > 0) {
+      const pixels = new Uint8ClampedArray(emulator.pixels());
+      context.putImageData(new ImageData(pixels, canvas.width, canvas.height), 0, 0, x, y, width, height);
+    }
+  }
+  requestAnimationFrame(frame);
+}
+requestAnimationFrame(frame);